@@ -0,0 +1,137 @@
+/*
+Archive mode: ship the raw contents of every searched file to the server,
+not just the matches, so new searches can be run over history later.
+How far each file has been sent is kept in <state_dir>/archive_offsets.json.
+*/
+use crate::models::Search;
+use crate::{conf, webclient};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+// largest chunk of a file sent in one segment, before compression
+const SEGMENT_SIZE: u64 = 8 * 1024 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("ArchiveError(IO({0}))")]
+    IO(#[from] std::io::Error),
+
+    #[error("ArchiveError(Json({0}))")]
+    Json(#[from] serde_json::Error),
+
+    #[error("ArchiveError(Web({0}))")]
+    Web(#[from] crate::webclient::WebError),
+}
+type Result<T> = std::result::Result<T, ArchiveError>;
+
+pub fn is_enabled() -> bool {
+    conf::get_archive().unwrap_or(false)
+}
+
+fn state_file() -> PathBuf {
+    let dir = conf::get_state_dir().unwrap_or_else(|_| String::from("state/"));
+
+    PathBuf::from(dir).join("archive_offsets.json")
+}
+
+fn load_offsets() -> Result<HashMap<String, u64>> {
+    match fs::read_to_string(state_file()) {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_offsets(offsets: &HashMap<String, u64>) -> Result<()> {
+    let path = state_file();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string(offsets)?)?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+/**
+ * Send everything appended to the searched files since the last run.
+ */
+pub fn archive_once(searches: &[Search]) -> Result<()> {
+    // the journal keeps itself, command output is not kept
+    let locations: BTreeSet<&String> = searches
+        .iter()
+        .flat_map(|search| search.locations.iter())
+        .filter(|location| securelog_search::is_file(location))
+        .collect();
+
+    let mut offsets = load_offsets()?;
+
+    for location in locations {
+        let offset = offsets.get(location).copied().unwrap_or(0);
+        match archive_file(location, offset) {
+            Ok(Some(offset)) => {
+                offsets.insert(location.to_string(), offset);
+                save_offsets(&offsets)?;
+            }
+            Ok(None) => {
+                // no point sending the rest if the server won't take them
+                warn!("server refused archive segment, skipping archive");
+                break;
+            }
+            Err(e) => {
+                warn!("error archiving {}: {}", location, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Send a file from `offset` in segments. Returns the new offset, or None if
+ * the server refused the segment.
+ */
+fn archive_file(path: &str, mut offset: u64) -> Result<Option<u64>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    if len < offset {
+        // file got smaller, it was rotated or truncated
+        info!("{} was truncated, archiving from the start", path);
+        offset = 0;
+    }
+
+    while offset < len {
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut data: Vec<u8> = Vec::new();
+        (&mut file).take(SEGMENT_SIZE).read_to_end(&mut data)?;
+
+        // only send whole lines, the rest goes out next time
+        let end = match data.iter().rposition(|b| *b == b'\n') {
+            Some(pos) => pos + 1,
+            None if data.len() as u64 == SEGMENT_SIZE => data.len(),
+            None => break,
+        };
+        data.truncate(end);
+
+        let compressed = zstd::encode_all(data.as_slice(), ZSTD_LEVEL)?;
+        if !webclient::send_archive_segment(path, offset, &compressed)? {
+            return Ok(None);
+        }
+        debug!(
+            "archived {} bytes of {} at offset {}",
+            data.len(),
+            path,
+            offset
+        );
+
+        offset += data.len() as u64;
+    }
+
+    Ok(Some(offset))
+}
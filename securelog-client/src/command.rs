@@ -0,0 +1,230 @@
+/*
+Command locations, see securelog_search::command.
+
+The commands a client may run are set in its own config under [commands]
+and nowhere else, the server can't add or change them. A command://
+location only names one, a name that is not in the config is refused. They
+run without a shell and with stdin closed, searches match their stdout. A
+command that runs past its timeout is killed, one that exits with an error
+is reported like a file that could not be read.
+*/
+use crate::conf;
+use std::io::{self, Read};
+use std::process::{ChildStderr, ChildStdout, Command, Stdio};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// output past this is thrown away
+const MAX_OUTPUT: u64 = 64 * 1024 * 1024;
+// of stderr, kept for the error of a failed command
+const MAX_ERROR_OUTPUT: u64 = 1024;
+// how often a running command is checked on
+const WAIT_STEP: Duration = Duration::from_millis(50);
+// how long the output is read once a command is killed, processes it
+// started can keep its pipes open
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+/**
+ * A command from the config, `run` is the program and its arguments.
+ */
+#[derive(Debug, Deserialize)]
+pub struct CommandConfig {
+    pub run: Vec<String>,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("CommandError(Location({0}))")]
+    Location(#[from] securelog_search::CommandError),
+
+    // the name is not in the config
+    #[error("CommandError(Not allowed: {0})")]
+    NotAllowed(String),
+
+    #[error("CommandError(Config({0}))")]
+    Config(#[from] config::ConfigError),
+
+    #[error("CommandError(IO({0}))")]
+    IO(#[from] io::Error),
+
+    #[error("CommandError(Timeout({0} after {1} seconds))")]
+    Timeout(String, u64),
+
+    // the exit status and the start of stderr
+    #[error("CommandError(Failed({0}))")]
+    Failed(String),
+}
+type Result<T> = std::result::Result<T, CommandError>;
+
+// read all of `pipe` so the command does not block on it, keep up to `max`
+fn read_capped(mut pipe: impl Read, max: u64) -> io::Result<Vec<u8>> {
+    let mut kept = Vec::new();
+    (&mut pipe).take(max).read_to_end(&mut kept)?;
+    io::copy(&mut pipe, &mut io::sink())?;
+
+    Ok(kept)
+}
+
+fn join(reader: thread::JoinHandle<io::Result<Vec<u8>>>) -> io::Result<Vec<u8>> {
+    reader
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("output reader panicked")))
+}
+
+/**
+ * Join the output readers of a killed command. Readers still blocked after
+ * KILL_GRACE are left to end with the processes holding their pipe.
+ */
+fn join_killed(name: &str, readers: Vec<thread::JoinHandle<io::Result<Vec<u8>>>>) {
+    let deadline = Instant::now() + KILL_GRACE;
+    for reader in readers {
+        while !reader.is_finished() && Instant::now() < deadline {
+            sleep(WAIT_STEP);
+        }
+        if reader.is_finished() {
+            let _ = join(reader);
+        } else {
+            warn!("{} left processes behind that keep its output open", name);
+        }
+    }
+}
+
+/**
+ * Run the command `location` names, its stdout.
+ */
+pub fn run(location: &str) -> Result<Vec<u8>> {
+    let name = securelog_search::command_name(location)?;
+    let commands = conf::get_commands()?;
+    let command = commands
+        .get(name)
+        .ok_or_else(|| CommandError::NotAllowed(name.to_string()))?;
+
+    run_command(name, command)
+}
+
+fn run_command(name: &str, command: &CommandConfig) -> Result<Vec<u8>> {
+    let (program, args) = command
+        .run
+        .split_first()
+        .ok_or_else(|| CommandError::NotAllowed(format!("{} runs nothing", name)))?;
+    let timeout = command
+        .timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TIMEOUT);
+
+    debug!("running {}: {:?}", name, command.run);
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // read on their own threads, a command blocks once a pipe is full
+    let stdout: ChildStdout = child.stdout.take().expect("stdout is piped");
+    let stderr: ChildStderr = child.stderr.take().expect("stderr is piped");
+    let stdout = thread::spawn(move || read_capped(stdout, MAX_OUTPUT));
+    let stderr = thread::spawn(move || read_capped(stderr, MAX_ERROR_OUTPUT));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            child.wait()?;
+            join_killed(name, vec![stdout, stderr]);
+            return Err(CommandError::Timeout(name.to_string(), timeout.as_secs()));
+        }
+        sleep(WAIT_STEP);
+    };
+
+    let output = join(stdout)?;
+    let errors = join(stderr)?;
+    if !status.success() {
+        return Err(CommandError::Failed(format!(
+            "{} {}: {}",
+            name,
+            status,
+            String::from_utf8_lossy(&errors).trim()
+        )));
+    }
+    if output.len() as u64 == MAX_OUTPUT {
+        warn!(
+            "{} printed more than {} bytes, searching the start",
+            name, MAX_OUTPUT
+        );
+    }
+
+    Ok(output)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn command(run: &[&str], timeout_secs: Option<u64>) -> CommandConfig {
+        CommandConfig {
+            run: run.iter().map(|arg| arg.to_string()).collect(),
+            timeout_secs,
+        }
+    }
+
+    #[test]
+    fn output_is_returned() {
+        let output = run_command("echo", &command(&["echo", "one", "two"], None)).unwrap();
+        assert_eq!(output, b"one two\n");
+
+        // no shell
+        let output = run_command("echo", &command(&["echo", "$HOME;", "|"], None)).unwrap();
+        assert_eq!(output, b"$HOME; |\n");
+    }
+
+    #[test]
+    fn failures_keep_the_start_of_stderr() {
+        let failed = run_command(
+            "fails",
+            &command(&["sh", "-c", "echo no such thing >&2; exit 3"], None),
+        );
+        match failed {
+            Err(CommandError::Failed(message)) => {
+                assert!(message.starts_with("fails "));
+                assert!(message.ends_with("no such thing"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert!(matches!(
+            run_command("empty", &command(&[], None)),
+            Err(CommandError::NotAllowed(_))
+        ));
+        assert!(matches!(
+            run_command("missing", &command(&["/nonexistent/command"], None)),
+            Err(CommandError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn commands_past_their_timeout_are_killed() {
+        let started = Instant::now();
+        assert!(matches!(
+            run_command("sleeps", &command(&["sleep", "30"], Some(1))),
+            Err(CommandError::Timeout(_, 1))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // a process the command started keeps the pipes open
+        let started = Instant::now();
+        assert!(matches!(
+            run_command(
+                "forks",
+                &command(&["sh", "-c", "sleep 30 & sleep 30"], Some(1))
+            ),
+            Err(CommandError::Timeout(_, 1))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,5 +1,8 @@
+use crate::command::CommandConfig;
 use crate::constants;
 use config::{Config, ConfigError};
+use securelog_proto::ResourceLimits;
+use std::collections::HashMap;
 use std::sync::RwLock;
 
 // see contants.rs for config option names
@@ -63,3 +66,104 @@ pub fn get_log_stdout() -> Result<bool, ConfigError> {
 
     config.get_bool(constants::CONFIG_LOG_STDOUT)
 }
+
+pub fn get_signing_key() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_SIGNING_KEY)
+}
+
+pub fn get_archive() -> Result<bool, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_bool(constants::CONFIG_ARCHIVE)
+}
+
+pub fn get_state_dir() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_STATE_DIR)
+}
+
+/**
+ * Files and directories to watch for changes, see integrity.rs.
+ */
+pub fn get_integrity_paths() -> Result<Vec<String>, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    match config.get(constants::CONFIG_INTEGRITY_PATHS) {
+        Ok(paths) => Ok(paths),
+        Err(ConfigError::NotFound(_)) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+ * The commands command:// locations may run, by name. Only the config sets
+ * them, never the server. Empty if not set.
+ */
+pub fn get_commands() -> Result<HashMap<String, CommandConfig>, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    match config.get(constants::CONFIG_COMMANDS) {
+        Ok(commands) => Ok(commands),
+        Err(ConfigError::NotFound(_)) => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+ * Where journalctl reads the journal from, the system journal if not set.
+ */
+pub fn get_journal_dir() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_JOURNAL_DIR)
+}
+
+/**
+ * Tags the client reports to the server, a list of "key=value" strings.
+ * No tags if not set.
+ */
+pub fn get_tags() -> Result<Vec<(String, String)>, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    let tags: Vec<String> = match config.get(constants::CONFIG_TAGS) {
+        Ok(tags) => tags,
+        Err(ConfigError::NotFound(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    securelog_proto::parse_tags(&tags.join(","))
+        .map_err(|e| ConfigError::Message(format!("{}: {}", constants::CONFIG_TAGS, e)))
+}
+
+/**
+ * Resource limits from the config, limits the server sets can only tighten them.
+ * No limits if not set.
+ */
+pub fn get_limits() -> Result<ResourceLimits, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    match config.get(constants::CONFIG_LIMITS) {
+        Ok(limits) => Ok(limits),
+        Err(ConfigError::NotFound(_)) => Ok(ResourceLimits::default()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn get_search_threads() -> Result<usize, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config
+        .get_int(constants::CONFIG_SEARCH_THREADS)
+        .map(|threads| threads.max(1) as usize)
+}
+
+pub fn get_realtime_lines_per_minute() -> Result<u64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config
+        .get_int(constants::CONFIG_REALTIME_LINES_PER_MINUTE)
+        .map(|lines| lines.max(1) as u64)
+}
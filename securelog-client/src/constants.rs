@@ -5,3 +5,13 @@ pub const CONFIG_ID: &str = "id";
 pub const CONFIG_LOG_DIR: &str = "log_dir";
 pub const CONFIG_LOG_LEVEL: &str = "log_level";
 pub const CONFIG_LOG_STDOUT: &str = "log_stdout";
+pub const CONFIG_SIGNING_KEY: &str = "signing_key";
+pub const CONFIG_ARCHIVE: &str = "archive";
+pub const CONFIG_STATE_DIR: &str = "state_dir";
+pub const CONFIG_TAGS: &str = "tags";
+pub const CONFIG_LIMITS: &str = "limits";
+pub const CONFIG_SEARCH_THREADS: &str = "search_threads";
+pub const CONFIG_COMMANDS: &str = "commands";
+pub const CONFIG_INTEGRITY_PATHS: &str = "integrity_paths";
+pub const CONFIG_JOURNAL_DIR: &str = "journal_dir";
+pub const CONFIG_REALTIME_LINES_PER_MINUTE: &str = "realtime_lines_per_minute";
@@ -0,0 +1,201 @@
+/*
+Dry runs, `securelog-client run --search-file` or `--search-id`.
+
+The searches run here once, within the same limits and through the same
+scanning as scheduled runs, and what they find is printed instead of sent.
+Nothing is sent to the server and no journal cursors are kept, a search
+from --search-id is only fetched from it. Problems with a location go to
+stderr in text output and into the reports in JSON.
+*/
+use crate::models::{ClientSearchResult, Search, SearchRunReport};
+use crate::searchrunner::{self, RunScan, Start};
+use crate::webclient;
+use chrono::{DateTime, Local};
+use securelog_proto::SearchType;
+use std::fs;
+use std::io::{self, Write};
+
+#[derive(Debug, Error)]
+pub enum DryRunError {
+    #[error("DryRunError(IO({0}))")]
+    IO(#[from] io::Error),
+
+    #[error("DryRunError(Json({0}))")]
+    Json(#[from] serde_json::Error),
+
+    #[error("DryRunError(Web({0}))")]
+    Web(#[from] webclient::WebError),
+
+    // the server does not send the search to this client
+    #[error("DryRunError(No search {0} for this client)")]
+    NoSuchSearch(i32),
+
+    #[error("DryRunError(Login failed)")]
+    Login,
+}
+type Result<T> = std::result::Result<T, DryRunError>;
+
+/**
+ * A search as written in a --search-file, the fields of a search on the
+ * server without the ones a dry run does not use.
+ */
+#[derive(Debug, Deserialize)]
+struct SearchFile {
+    // the position in the file if not set
+    #[serde(default)]
+    id: i32,
+    #[serde(default)]
+    name: String,
+    stype: SearchType,
+    search: String,
+    locations: Vec<String>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SearchFiles {
+    One(SearchFile),
+    Many(Vec<SearchFile>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/**
+ * Where the searches of a dry run come from.
+ */
+pub enum Searches {
+    File(String),
+    Server(i32),
+}
+
+/**
+ * Read the searches of a --search-file, one search or a list of them.
+ */
+fn read_search_file(path: &str) -> Result<Vec<Search>> {
+    let files = match serde_json::from_str(&fs::read_to_string(path)?)? {
+        SearchFiles::One(search) => vec![search],
+        SearchFiles::Many(searches) => searches,
+    };
+
+    Ok(files
+        .into_iter()
+        .enumerate()
+        .map(|(position, search)| {
+            let id = if search.id == 0 {
+                position as i32 + 1
+            } else {
+                search.id
+            };
+            Search::new(
+                id,
+                search.name,
+                search.stype,
+                search.search,
+                search.locations,
+                true,
+                None,
+            )
+        })
+        .collect())
+}
+
+fn fetch_search(id: i32) -> Result<Vec<Search>> {
+    if !webclient::login()? {
+        return Err(DryRunError::Login);
+    }
+    // runs within the limits the server set for this client
+    webclient::get_should_run()?;
+    let searches = webclient::get_searches();
+    if let Err(e) = webclient::logout() {
+        warn!("failed to log out: {}", e);
+    }
+    let search = searches?
+        .into_iter()
+        .find(|search| search.id == id)
+        .ok_or(DryRunError::NoSuchSearch(id))?;
+
+    Ok(vec![search])
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    results: Vec<&'a ClientSearchResult>,
+    reports: &'a [SearchRunReport],
+}
+
+fn print_text(scan: &RunScan, out: &mut impl Write) -> io::Result<()> {
+    for result in scan.results.iter().flatten() {
+        writeln!(
+            out,
+            "search {} ({}) on {}: {} matches{}",
+            result.search_id,
+            result.search_name,
+            result.location,
+            result.found.len(),
+            if result.truncated {
+                ", stopped by a limit"
+            } else {
+                ""
+            }
+        )?;
+        for line in &result.found {
+            writeln!(out, "  {}", line)?;
+        }
+    }
+    for report in &scan.reports {
+        if let Some(error) = &report.error {
+            eprintln!(
+                "search {} on {}: {:?}: {}",
+                report.search_id, report.location, error.kind, error.message
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Run the searches once and print what they find. Returns whether every
+ * location could be searched.
+ */
+pub fn run(searches: Searches, since: Option<DateTime<Local>>, format: Format) -> Result<bool> {
+    let mut searches = match searches {
+        Searches::File(path) => read_search_file(&path)?,
+        Searches::Server(id) => fetch_search(id)?,
+    };
+    for search in &mut searches {
+        if search
+            .locations
+            .iter()
+            .any(|location| securelog_search::is_syslog(location))
+        {
+            eprintln!(
+                "search {}: leaving out syslog:// locations, the server searches those",
+                search.id
+            );
+            search
+                .locations
+                .retain(|location| !securelog_search::is_syslog(location));
+        }
+    }
+
+    let scan = searchrunner::scan_searches(&searches, Start::Since(since));
+
+    let mut out = io::stdout().lock();
+    match format {
+        Format::Text => print_text(&scan, &mut out)?,
+        Format::Json => {
+            let output = JsonOutput {
+                results: scan.results.iter().flatten().collect(),
+                reports: &scan.reports,
+            };
+            serde_json::to_writer_pretty(&mut out, &output)?;
+            writeln!(out)?;
+        }
+    }
+
+    Ok(scan.reports.iter().all(|report| report.error.is_none()))
+}
@@ -0,0 +1,282 @@
+/*
+File integrity monitoring.
+
+After each run the files under integrity_paths are hashed and their
+metadata sent to the server as an IntegritySnapshot, the server compares it
+with the previous one and reports what changed. Directories are walked
+without following symlinks, only regular files are read, through the same
+read rate limit as searches.
+*/
+use crate::models::{FileState, IntegritySnapshot};
+use crate::settings;
+use crate::throttle::{Throttle, Throttled};
+use crate::{conf, webclient};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, Metadata};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+// files in one snapshot, what is past it is left out as unreadable
+const MAX_FILES: usize = 100_000;
+
+#[derive(Debug, Error)]
+pub enum IntegrityError {
+    #[error("IntegrityError(Config({0}))")]
+    Config(#[from] config::ConfigError),
+
+    #[error("IntegrityError(Web({0}))")]
+    Web(#[from] webclient::WebError),
+}
+type Result<T> = std::result::Result<T, IntegrityError>;
+
+pub fn is_enabled() -> bool {
+    conf::get_integrity_paths()
+        .map(|paths| !paths.is_empty())
+        .unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn owner(meta: &Metadata) -> (u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+
+    (meta.mode(), meta.uid(), meta.gid())
+}
+#[cfg(not(unix))]
+fn owner(meta: &Metadata) -> (u32, u32, u32) {
+    // no owners, a mode with the type and whether it is read only
+    let kind = if meta.is_dir() { 0o040000 } else { 0o100000 };
+    let permissions = if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    };
+
+    (kind | permissions, 0, 0)
+}
+
+fn hash(path: &Path, throttle: &Throttle) -> io::Result<String> {
+    let mut file = Throttled::new(File::open(path)?, throttle);
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn file_state(path: &Path, meta: &Metadata, throttle: &Throttle) -> io::Result<FileState> {
+    let (mode, uid, gid) = owner(meta);
+    let sha256 = if meta.is_file() {
+        Some(hash(path, throttle)?)
+    } else {
+        None
+    };
+    let link = if meta.is_symlink() {
+        Some(fs::read_link(path)?.to_string_lossy().to_string())
+    } else {
+        None
+    };
+
+    Ok(FileState {
+        path: path.to_string_lossy().to_string(),
+        mode,
+        uid,
+        gid,
+        size: meta.len(),
+        mtime: DateTime::<Utc>::from(meta.modified()?),
+        sha256,
+        link,
+    })
+}
+
+fn walk(path: &Path, snapshot: &mut IntegritySnapshot, throttle: &Throttle) {
+    let name = path.to_string_lossy().to_string();
+    if snapshot.files.len() >= MAX_FILES {
+        warn!(
+            "more than {} files to watch, leaving out {}",
+            MAX_FILES, name
+        );
+        snapshot.unreadable.push(name);
+        return;
+    }
+
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        // reported as removed
+        Err(e) if e.kind() == ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("error checking integrity of {}: {}", name, e);
+            snapshot.unreadable.push(name);
+            return;
+        }
+    };
+    match file_state(path, &meta, throttle) {
+        Ok(state) => snapshot.files.push(state),
+        Err(e) => {
+            warn!("error checking integrity of {}: {}", name, e);
+            snapshot.unreadable.push(name);
+            return;
+        }
+    }
+
+    if meta.is_dir() {
+        let entries =
+            match fs::read_dir(path).and_then(|entries| entries.collect::<io::Result<Vec<_>>>()) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("error listing {}: {}", name, e);
+                    snapshot.unreadable.push(name);
+                    return;
+                }
+            };
+        let mut paths: Vec<_> = entries.iter().map(|entry| entry.path()).collect();
+        paths.sort();
+        for path in paths {
+            walk(&path, snapshot, throttle);
+        }
+    }
+}
+
+/**
+ * The state of the files under `paths`.
+ */
+pub fn snapshot(paths: &[String]) -> IntegritySnapshot {
+    let throttle = Throttle::new(settings::limits().max_read_rate);
+    let mut snapshot = IntegritySnapshot {
+        started: Utc::now(),
+        paths: paths.to_vec(),
+        files: Vec::new(),
+        unreadable: Vec::new(),
+    };
+
+    for path in paths {
+        walk(Path::new(path), &mut snapshot, &throttle);
+    }
+
+    snapshot
+}
+
+/**
+ * Take a snapshot of integrity_paths and send it.
+ */
+pub fn check_once() -> Result<()> {
+    let paths = conf::get_integrity_paths()?;
+    let snapshot = snapshot(&paths);
+    info!(
+        "checked the integrity of {} files, {} unreadable",
+        snapshot.files.len(),
+        snapshot.unreadable.len()
+    );
+
+    webclient::send_integrity_snapshot(&snapshot)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::time::SystemTime;
+
+    fn state(path: &Path) -> FileState {
+        let meta = fs::symlink_metadata(path).unwrap();
+        file_state(path, &meta, &Throttle::new(None)).unwrap()
+    }
+
+    #[test]
+    fn files_are_hashed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        fs::write(&path, "hello\n").unwrap();
+        let empty = dir.path().join("empty");
+        fs::write(&empty, "").unwrap();
+
+        assert_eq!(
+            state(&path).sha256.unwrap(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        assert_eq!(
+            state(&empty).sha256.unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(state(&path).size, 6);
+        // directories have no content
+        assert_eq!(state(dir.path()).sha256, None);
+    }
+
+    #[test]
+    fn mtime_is_the_modification_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sshd_config");
+        fs::write(&path, "PermitRootLogin no\n").unwrap();
+
+        let mtime = Utc.with_ymd_and_hms(2026, 10, 19, 6, 0, 0).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::from(mtime))
+            .unwrap();
+
+        assert_eq!(state(&path).mtime, mtime);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_and_owner_are_kept() {
+        use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shadow");
+        fs::write(&path, "root:*:19000::::::\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.path().join("shadow.link");
+        symlink(&path, &link).unwrap();
+
+        let meta = fs::metadata(&path).unwrap();
+        let file = state(&path);
+        assert_eq!(file.mode, 0o100640);
+        assert_eq!((file.uid, file.gid), (meta.uid(), meta.gid()));
+        assert!(!file.is_dir());
+
+        assert_eq!(state(dir.path()).mode & 0o170000, 0o040000);
+        assert!(state(dir.path()).is_dir());
+
+        // links are not followed
+        let file = state(&link);
+        assert_eq!(file.mode & 0o170000, 0o120000);
+        assert_eq!(file.link.unwrap(), path.to_string_lossy());
+        assert_eq!(file.sha256, None);
+    }
+
+    #[test]
+    fn snapshots_walk_directories_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("conf.d")).unwrap();
+        fs::write(dir.path().join("conf.d").join("b.conf"), "b").unwrap();
+        fs::write(dir.path().join("conf.d").join("a.conf"), "a").unwrap();
+        fs::write(dir.path().join("main.conf"), "main").unwrap();
+
+        let root = dir.path().to_string_lossy().to_string();
+        let missing = dir.path().join("missing").to_string_lossy().to_string();
+        let snapshot = snapshot(&[root.clone(), missing]);
+
+        let paths: Vec<&str> = snapshot
+            .files
+            .iter()
+            .map(|file| file.path.strip_prefix(&root).unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "",
+                "/conf.d",
+                "/conf.d/a.conf",
+                "/conf.d/b.conf",
+                "/main.conf"
+            ]
+        );
+        // missing files are reported as removed by the server, not unreadable
+        assert!(snapshot.unreadable.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+/*
+Journal locations, see securelog_search::journal.
+
+A run reads the entries after the cursor the last run got to, the cursor of
+each location is kept in <state_dir>/journal_cursors.json once the run's
+results are sent, so every entry is searched once. A location without a
+cursor is read from the start of the journal. Dry runs read from the start
+or from --since and keep no cursors.
+*/
+use crate::conf;
+use chrono::{DateTime, Local};
+use securelog_search::{JournalLines, JournalQuery};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("JournalError(Location({0}))")]
+    Location(#[from] securelog_search::JournalError),
+
+    #[error("JournalError(IO({0}))")]
+    IO(#[from] io::Error),
+
+    #[error("JournalError(Json({0}))")]
+    Json(#[from] serde_json::Error),
+
+    // journalctl failed, with what it printed
+    #[error("JournalError(Journalctl({0}))")]
+    Journalctl(String),
+}
+type Result<T> = std::result::Result<T, JournalError>;
+
+fn state_file() -> PathBuf {
+    let dir = conf::get_state_dir().unwrap_or_else(|_| String::from("state/"));
+
+    PathBuf::from(dir).join("journal_cursors.json")
+}
+
+fn load_cursors(path: &Path) -> Result<HashMap<String, String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/**
+ * The cursor the last run got to in `location`.
+ */
+pub fn cursor(location: &str) -> Result<Option<String>> {
+    Ok(load_cursors(&state_file())?.remove(location))
+}
+
+/**
+ * Keep how far each location was searched, `cursors` are locations and
+ * the cursor of the last entry searched.
+ */
+pub fn save_cursors(cursors: &[(String, String)]) -> Result<()> {
+    save_cursors_to(&state_file(), cursors)
+}
+
+fn save_cursors_to(path: &Path, cursors: &[(String, String)]) -> Result<()> {
+    if cursors.is_empty() {
+        return Ok(());
+    }
+
+    let mut saved = load_cursors(path)?;
+    for (location, cursor) in cursors {
+        saved.insert(location.to_string(), cursor.to_string());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string(&saved)?)?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+/**
+ * Where reading the journal starts, at its start without one.
+ */
+#[derive(Debug)]
+pub enum JournalStart {
+    // after the entry with this cursor
+    After(String),
+    Since(DateTime<Local>),
+}
+
+/**
+ * The journalctl arguments that read the entries `query` selects from
+ * `start`, in the journal in `dir` or the system's.
+ */
+fn journalctl_args(
+    query: &JournalQuery,
+    dir: Option<&str>,
+    start: Option<JournalStart>,
+) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(dir) = dir {
+        args.push(format!("--directory={}", dir));
+    }
+    match start {
+        Some(JournalStart::After(cursor)) => args.push(format!("--after-cursor={}", cursor)),
+        Some(JournalStart::Since(since)) => {
+            args.push(format!("--since={}", since.format("%Y-%m-%d %H:%M:%S")))
+        }
+        None => {}
+    }
+    args.extend(query.journalctl_args());
+    args
+}
+
+/**
+ * The entries of a journal location as lines, read from journalctl.
+ */
+pub struct JournalReader {
+    child: Child,
+    lines: JournalLines<BufReader<ChildStdout>>,
+}
+impl JournalReader {
+    /**
+     * Start reading `location` from `start`.
+     */
+    pub fn open(location: &str, start: Option<JournalStart>) -> Result<JournalReader> {
+        let query = JournalQuery::parse(location)?;
+
+        let dir = conf::get_journal_dir().ok();
+
+        let mut command = Command::new("journalctl");
+        command
+            .args(journalctl_args(&query, dir.as_deref(), start))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        debug!("reading {} with {:?}", location, command);
+
+        let mut child = command.spawn()?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| JournalError::Journalctl(String::from("no stdout")))?;
+
+        Ok(JournalReader {
+            child,
+            lines: JournalLines::new(BufReader::new(stdout)),
+        })
+    }
+
+    pub fn lines(&mut self) -> &mut JournalLines<BufReader<ChildStdout>> {
+        &mut self.lines
+    }
+
+    /**
+     * Stop journalctl if it is still running. The cursor of the last entry
+     * read whole, None if none was.
+     */
+    pub fn finish(mut self) -> Result<Option<String>> {
+        let cursor = self.lines.cursor().map(str::to_string);
+
+        // entries left unread are read by the next run
+        if !self.lines.fill_buf()?.is_empty() {
+            let _ = self.child.kill();
+            self.child.wait()?;
+            return Ok(cursor);
+        }
+
+        let status = self.child.wait()?;
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = self.child.stderr.take() {
+                pipe.read_to_string(&mut stderr)?;
+            }
+            return Err(JournalError::Journalctl(format!(
+                "{}: {}",
+                status,
+                stderr.trim()
+            )));
+        }
+
+        Ok(cursor)
+    }
+}
+impl Drop for JournalReader {
+    fn drop(&mut self) {
+        // journalctl is done or waited for already unless the read failed
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn cursors_are_kept_per_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("journal_cursors.json");
+        assert!(load_cursors(&path).unwrap().is_empty());
+
+        // nothing to keep writes nothing
+        save_cursors_to(&path, &[]).unwrap();
+        assert!(!path.exists());
+
+        let cursor = |location: &str, cursor: &str| (location.to_string(), cursor.to_string());
+        save_cursors_to(
+            &path,
+            &[
+                cursor("journal://unit=sshd.service", "s=1;i=10"),
+                cursor("journal://priority<=err", "s=1;i=12"),
+            ],
+        )
+        .unwrap();
+        // a later run only moves the locations it read
+        save_cursors_to(&path, &[cursor("journal://unit=sshd.service", "s=1;i=20")]).unwrap();
+
+        let saved = load_cursors(&path).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved["journal://unit=sshd.service"], "s=1;i=20");
+        assert_eq!(saved["journal://priority<=err"], "s=1;i=12");
+        assert!(!path.with_extension("tmp").exists());
+
+        fs::write(&path, "not json").unwrap();
+        assert!(matches!(load_cursors(&path), Err(JournalError::Json(_))));
+    }
+
+    #[test]
+    fn journalctl_reads_the_selected_entries() {
+        let query = JournalQuery::parse("journal://unit=sshd.service,priority<=warning").unwrap();
+
+        assert_eq!(
+            journalctl_args(&query, None, None),
+            vec![
+                "--output=export",
+                "--no-pager",
+                "--priority=4",
+                "_SYSTEMD_UNIT=sshd.service"
+            ]
+        );
+        assert_eq!(
+            journalctl_args(
+                &query,
+                Some("/var/log/journal/remote"),
+                Some(JournalStart::After("s=1;i=20".to_string()))
+            )[..2],
+            [
+                "--directory=/var/log/journal/remote",
+                "--after-cursor=s=1;i=20"
+            ]
+        );
+
+        let since = Local.with_ymd_and_hms(2026, 10, 19, 8, 30, 0).unwrap();
+        let args = journalctl_args(
+            &JournalQuery::parse("journal://").unwrap(),
+            None,
+            Some(JournalStart::Since(since)),
+        );
+        assert_eq!(
+            args,
+            vec![
+                "--since=2026-10-19 08:30:00",
+                "--output=export",
+                "--no-pager"
+            ]
+        );
+    }
+}
@@ -18,19 +18,109 @@ extern crate serde_json;
 #[macro_use]
 extern crate clap;
 
+mod archiver;
+mod command;
 mod conf;
 mod constants;
+mod dryrun;
+mod integrity;
+mod journal;
 mod models;
+mod realtime;
 mod searchrunner;
+mod settings;
+mod signing;
+mod since;
+mod throttle;
 mod webclient;
 
 use std::thread::sleep;
-use std::time::Duration;
 
 fn main() {
-    use clap::Arg;
+    use clap::{Arg, ArgAction, ArgGroup, Command};
 
     let matches = command!()
+        .subcommand(
+            Command::new("run")
+                .about("Run the searches, what the client does without a command")
+                .arg(
+                    Arg::new("once")
+                        .long("once")
+                        .help("Run once now and exit, dry runs always run once")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("search-file")
+                        .long("search-file")
+                        .help("Dry run the searches in this JSON file, nothing is sent")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("search-id")
+                        .long("search-id")
+                        .help("Dry run this search from the server, nothing is sent")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i32)),
+                )
+                .group(ArgGroup::new("dry-run").args(["search-file", "search-id"]))
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Only scan what was written since, i.e. 30m, 12h, 7d or 2026-10-19 06:00:00")
+                        .num_args(1)
+                        .requires("dry-run"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("How dry runs print what they find")
+                        .num_args(1)
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .requires("dry-run"),
+                ),
+        )
+        .subcommand(
+            Command::new("login-test").about("Log in to the server with the config and log out"),
+        )
+        .subcommand(Command::new("show-config").about("Print the config without its secrets"))
+        .subcommand(Command::new("init").about("Create a config, asking for what it needs"))
+        .subcommand(
+            Command::new("register-key")
+                .about("Give a client from before result signing a signing key, saved to the config file"),
+        )
+        .subcommand(
+            Command::new("enroll")
+                .about("Create a client and its config with an enrollment token, without asking")
+                .arg(
+                    Arg::new("server")
+                        .long("server")
+                        .help("Server base url")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("token")
+                        .long("token")
+                        .help("Enrollment token from the server")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .help("Client name")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .help("File to save the config to, it must not exist")
+                        .required(true)
+                        .num_args(1),
+                ),
+        )
         .arg(
             Arg::new("config")
                 .short('c')
@@ -43,7 +133,28 @@ fn main() {
     if let Some(loc) = matches.get_one::<String>("config") {
         std::env::set_var("CONFIG_LOCATION", loc);
     }
+    if matches.subcommand_matches("init").is_some() {
+        init_script().unwrap();
+        std::process::exit(0);
+    }
+    if let Some(args) = matches.subcommand_matches("enroll") {
+        std::process::exit(enroll(args));
+    }
     conf::initialize_config().unwrap();
+
+    match matches.subcommand() {
+        Some(("show-config", _)) => {
+            show_config().unwrap();
+            std::process::exit(0);
+        }
+        Some(("login-test", _)) => std::process::exit(login_test()),
+        Some(("register-key", _)) => std::process::exit(register_key()),
+        Some(("run", run)) if run.contains_id("dry-run") => std::process::exit(dry_run(run)),
+        _ => (),
+    }
+    let once = matches
+        .subcommand_matches("run")
+        .is_some_and(|run| run.get_flag("once"));
     check_initialized();
 
     setup_log().unwrap();
@@ -57,8 +168,27 @@ fn main() {
     );
     info!("build rust version: {:?}", env::var("VERGEN_RUSTC_SEMVER"));
 
-    if !webclient::login().unwrap() {
-        panic!("failed to login!");
+    if conf::get_signing_key().is_err() {
+        warn!("no signing_key configured, search results will be sent unsigned, see register-key");
+    }
+
+    match webclient::login() {
+        Ok(true) => (),
+        Ok(false) => panic!("failed to login!"),
+        Err(e) => panic!("failed to login: {}", e),
+    }
+
+    if once {
+        // settings ride along with should_run, the run is now either way
+        if let Err(e) = webclient::get_should_run() {
+            warn!("error getting settings: {}", e);
+        }
+        webclient::notify_running().unwrap();
+        if let Err(e) = searchrunner::run_once() {
+            error!("error running search: {}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
     }
 
     loop {
@@ -71,7 +201,11 @@ fn main() {
                 }
             }
         }
-        sleep(Duration::from_secs(60));
+        match webclient::get_searches() {
+            Ok(searches) => realtime::refresh(searches),
+            Err(e) => warn!("error getting realtime searches: {}", e),
+        }
+        sleep(settings::poll_interval());
     }
 
     //webclient::logout().unwrap();
@@ -86,13 +220,7 @@ fn setup_log() -> anyhow::Result<()> {
         String::from("logs/")
     };
 
-    let log_level = if let Ok(level) = conf::get_log_level() {
-        level
-    } else {
-        String::from("info")
-    };
-
-    let mut logger = Logger::try_with_str(log_level)?.format(opt_format);
+    let mut logger = Logger::try_with_str(settings::config_log_level())?.format(opt_format);
 
     if let Ok(true) = conf::get_log_stdout() {
         logger = logger.duplicate_to_stderr(Duplicate::All);
@@ -117,11 +245,180 @@ fn setup_log() -> anyhow::Result<()> {
         logger = logger.use_windows_line_ending();
     }
 
-    logger.start()?;
+    settings::set_logger(logger.start()?);
 
     Ok(())
 }
 
+/**
+ * Log errors to stderr, for the commands that are run by hand.
+ */
+fn setup_command_log() {
+    match flexi_logger::Logger::try_with_str("error").and_then(|logger| logger.start()) {
+        Ok(handle) => settings::set_logger(handle),
+        Err(e) => eprintln!("failed to set up logging: {}", e),
+    }
+}
+
+/**
+ * Dry run the searches `run` names, the exit code: 0 if every location
+ * was searched, 1 if some could not be and 2 if the run failed.
+ */
+fn dry_run(run: &clap::ArgMatches) -> i32 {
+    setup_command_log();
+
+    let since = match run
+        .get_one::<String>("since")
+        .map(|since| since::parse_since(since, chrono::Local::now()))
+        .transpose()
+    {
+        Ok(since) => since,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let searches = match (
+        run.get_one::<String>("search-file"),
+        run.get_one::<i32>("search-id"),
+    ) {
+        (Some(path), _) => dryrun::Searches::File(path.clone()),
+        (None, Some(id)) => dryrun::Searches::Server(*id),
+        (None, None) => unreachable!("dry runs have a search file or id"),
+    };
+    let format = match run.get_one::<String>("format").map(String::as_str) {
+        Some("json") => dryrun::Format::Json,
+        _ => dryrun::Format::Text,
+    };
+
+    match dryrun::run(searches, since, format) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("dry run failed: {}", e);
+            2
+        }
+    }
+}
+
+/**
+ * Log in with the config and say what the server agreed to, the exit code.
+ */
+fn login_test() -> i32 {
+    setup_command_log();
+    if config_missing() {
+        return 1;
+    }
+
+    let server = conf::get_server().unwrap_or_default();
+    match webclient::login() {
+        Ok(true) => (),
+        Ok(false) => {
+            eprintln!("login to {} failed, check id and token", server);
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("login to {} failed: {}", server, e);
+            return 1;
+        }
+    }
+    println!(
+        "logged in to {} as {}, protocol version {}",
+        server,
+        conf::get_id().unwrap_or_default(),
+        webclient::protocol()
+    );
+
+    let result = match webclient::get_searches() {
+        Ok(searches) => {
+            println!("the server sends {} searches", searches.len());
+            0
+        }
+        Err(e) => {
+            eprintln!("failed to get searches: {}", e);
+            1
+        }
+    };
+    if let Err(e) = webclient::logout() {
+        eprintln!("failed to log out: {}", e);
+    }
+    result
+}
+
+/**
+ * Register a signing key for a client created before result signing and
+ * add it to the config file, the exit code.
+ */
+fn register_key() -> i32 {
+    setup_command_log();
+    if config_missing() {
+        return 1;
+    }
+    if conf::get_signing_key().is_ok() {
+        eprintln!("a signing_key is already configured");
+        return 1;
+    }
+    let path = match std::env::var("CONFIG_LOCATION") {
+        Ok(path) if std::path::Path::new(&path).is_file() => path,
+        _ => {
+            eprintln!("register-key saves the key to the config file, give it with -c");
+            return 1;
+        }
+    };
+
+    let server = conf::get_server().unwrap_or_default();
+    let signing_key = signing::generate_signing_key();
+    if let Err(e) = webclient::register_key(&signing::encode_public_key(&signing_key)) {
+        eprintln!("registering the key with {} failed: {}", server, e);
+        return 1;
+    }
+
+    // top level options go before any table, the rest of the file is kept as is
+    let line = format!(
+        "{} = \"{}\"\n",
+        constants::CONFIG_SIGNING_KEY,
+        signing::encode_signing_key(&signing_key)
+    );
+    match std::fs::read_to_string(&path)
+        .and_then(|config| write_config(&path, &format!("{}{}", line, config)))
+    {
+        Ok(()) => {
+            println!(
+                "registered the key with {} and saved it to {}",
+                server, path
+            );
+            0
+        }
+        Err(e) => {
+            // the server won't take another key, this one must not be lost
+            eprintln!(
+                "the key is registered but saving it to {} failed: {}, add this to the config:\n{}",
+                path, e, line
+            );
+            1
+        }
+    }
+}
+
+// options that let anyone act as the client
+const SECRET_OPTIONS: &[&str] = &[constants::CONFIG_TOKEN, constants::CONFIG_SIGNING_KEY];
+
+/**
+ * Print the config as read from the file and environment, secrets hidden.
+ */
+fn show_config() -> anyhow::Result<()> {
+    let config = conf::CONFIG.read().unwrap().clone();
+    let mut values: toml::Table = config.try_deserialize()?;
+    for option in SECRET_OPTIONS {
+        if let Some(value) = values.get_mut(*option) {
+            *value = toml::Value::String(String::from("<hidden>"));
+        }
+    }
+
+    print!("{}", toml::to_string_pretty(&values)?);
+    Ok(())
+}
+
 /**
  * Checks if any important config options are missing.
  * Will print missing config options to console
@@ -129,16 +426,14 @@ fn setup_log() -> anyhow::Result<()> {
 fn config_missing() -> bool {
     let config = conf::CONFIG.read().unwrap();
 
-    if config.get_string(constants::CONFIG_SERVER).is_err() {
-        println!("config missing!");
-        true
-    } else if config.get_string(constants::CONFIG_NAME).is_err() {
-        println!("config missing!");
-        true
-    } else if config.get_string(constants::CONFIG_ID).is_err() {
-        println!("config missing!");
-        true
-    } else if config.get_string(constants::CONFIG_TOKEN).is_err() {
+    let required = [
+        constants::CONFIG_SERVER,
+        constants::CONFIG_NAME,
+        constants::CONFIG_ID,
+        constants::CONFIG_TOKEN,
+    ];
+
+    if required.iter().any(|key| config.get_string(key).is_err()) {
         println!("config missing!");
         true
     } else {
@@ -160,6 +455,7 @@ struct TomlConfig {
     pub name: String,
     pub id: String,
     pub token: String,
+    pub signing_key: String,
     pub log_dir: String,
     pub log_level: String,
     pub log_stdout: bool,
@@ -183,13 +479,20 @@ fn init_script() -> anyhow::Result<()> {
     let username = prompt_user_input("Username: ")?;
     let password = rpassword::prompt_password("Password: ")?;
 
-    let client_auth = webclient::create_client_token(&server, &name, &username, &password)?;
+    // results are signed with this key so the server can tell them apart
+    // from anything posted with a stolen session cookie
+    let signing_key = signing::generate_signing_key();
+    let pubkey = signing::encode_public_key(&signing_key);
+
+    let client_auth =
+        webclient::create_client_token(&server, &name, &username, &password, &pubkey)?;
 
     let config = TomlConfig {
         server,
         name,
         id: client_auth.id,
         token: client_auth.token,
+        signing_key: signing::encode_signing_key(&signing_key),
         log_dir: String::from("logs"),
         log_level: String::from("info"),
         log_stdout: true,
@@ -199,7 +502,7 @@ fn init_script() -> anyhow::Result<()> {
 
     let toml = toml::to_string_pretty(&config)?;
 
-    match std::fs::write(&outfile, &toml) {
+    match write_config(&outfile, &toml) {
         Ok(_) => {
             println!("file saved successfully!");
         }
@@ -212,6 +515,97 @@ fn init_script() -> anyhow::Result<()> {
     Ok(())
 }
 
+// the config holds the token and signing key, only its owner may read it
+fn config_options() -> std::fs::OpenOptions {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+fn write_config(path: &str, toml: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    config_options()
+        .create(true)
+        .truncate(true)
+        .open(path)?
+        .write_all(toml.as_bytes())
+}
+
+// fails with AlreadyExists instead of replacing a config
+fn create_config(path: &str) -> std::io::Result<std::fs::File> {
+    config_options().create_new(true).open(path)
+}
+
+/**
+ * Create the client with an enrollment token and write its config to
+ * --out, for config management. Returns the exit code.
+ */
+fn enroll(args: &clap::ArgMatches) -> i32 {
+    let arg = |name: &str| args.get_one::<String>(name).cloned().unwrap_or_default();
+    let server = arg("server").trim_end_matches('/').to_string();
+    let name = arg("name");
+    let outfile = arg("out");
+
+    // enrolling again would leave the client in the old config orphaned,
+    // the file is created first so two enrollments can't both write it
+    let mut file = match create_config(&outfile) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            eprintln!("{} already exists, not enrolling again", outfile);
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("cannot create {}: {}", outfile, e);
+            return 1;
+        }
+    };
+
+    let signing_key = signing::generate_signing_key();
+    let pubkey = signing::encode_public_key(&signing_key);
+    let client_auth = match webclient::enroll_client(&server, &arg("token"), &name, &pubkey) {
+        Ok(client_auth) => client_auth,
+        Err(e) => {
+            eprintln!("enrolling {} with {} failed: {}", name, server, e);
+            drop(file);
+            let _ = std::fs::remove_file(&outfile);
+            return 1;
+        }
+    };
+
+    let id = client_auth.id.clone();
+    let config = TomlConfig {
+        server,
+        name,
+        id: client_auth.id,
+        token: client_auth.token,
+        signing_key: signing::encode_signing_key(&signing_key),
+        log_dir: String::from("logs"),
+        log_level: String::from("info"),
+        log_stdout: true,
+    };
+    let written = toml::to_string_pretty(&config)
+        .map_err(|e| e.to_string())
+        .and_then(|toml| {
+            std::io::Write::write_all(&mut file, toml.as_bytes()).map_err(|e| e.to_string())
+        });
+    match written {
+        Ok(()) => {
+            println!("enrolled as client {}, config saved to {}", id, outfile);
+            0
+        }
+        Err(e) => {
+            eprintln!(
+                "enrolled as client {} but failed to save the config to {}: {}",
+                id, outfile, e
+            );
+            1
+        }
+    }
+}
+
 fn prompt_user_input(prompt: &str) -> std::io::Result<String> {
     use std::io;
     use std::io::Write;
@@ -231,3 +625,29 @@ fn prompt_user_input(prompt: &str) -> std::io::Result<String> {
 
     Ok(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_are_never_replaced_by_enrolling() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+        let path = path.to_str().unwrap();
+
+        let file = create_config(path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = file.metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        drop(file);
+
+        write_config(path, "server = \"x\"\n").unwrap();
+        let err = create_config(path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "server = \"x\"\n");
+    }
+}
@@ -0,0 +1,777 @@
+/*
+Realtime searches.
+
+Searches flagged realtime are followed between runs: a thread watches the
+directories of their locations with inotify and matches lines as they are
+appended. Matches are sent in batches, one every BATCH_INTERVAL at most,
+and at most `realtime_lines_per_minute` lines a minute are sent, the
+matches of a flood past that are dropped and their results flagged
+truncated.
+
+A file is followed from where it ended when following started, a file that
+shrinks or is replaced, e.g. by log rotation, is read from the start.
+A batch the server did not take is sent again with the next one, up to
+MAX_UNSENT_LINES lines. Realtime searches still run with the scheduled runs,
+they find the lines written while the client was down or dropped here.
+*/
+use crate::conf;
+use crate::models::{ClientSearchResult, Search};
+use crate::webclient;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// how long matches wait for more before they are sent
+const BATCH_INTERVAL: Duration = Duration::from_secs(2);
+// a batch this big is sent right away
+const MAX_BATCH_LINES: usize = 500;
+// lines sent a minute when the config does not say
+const DEFAULT_LINES_PER_MINUTE: u64 = 600;
+// how long a batch that could not be sent waits to be sent again, when
+// nothing new comes in
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+// matches kept while the server can't be reached, the oldest go first
+const MAX_UNSENT_LINES: usize = 5000;
+
+lazy_static! {
+    // the follower thread, started with the first realtime search
+    static ref FOLLOWER: Mutex<Option<Sender<Vec<Search>>>> = Mutex::new(None);
+}
+
+/**
+ * Whether this platform can follow files, realtime searches only run with
+ * the scheduled runs where it can't.
+ */
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/**
+ * Follow the enabled realtime searches among `searches`, called with every
+ * poll of the server so changed searches are picked up.
+ */
+pub fn refresh(searches: Vec<Search>) {
+    let searches: Vec<Search> = searches
+        .into_iter()
+        .filter(|search| search.realtime && search.enabled)
+        .collect();
+
+    let mut follower = FOLLOWER.lock().unwrap();
+    if follower.is_none() {
+        if searches.is_empty() {
+            return;
+        }
+        *follower = start();
+    }
+    if let Some(sender) = follower.as_ref() {
+        if sender.send(searches).is_err() {
+            warn!("realtime follower stopped, restarting it with the next poll");
+            *follower = None;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn start() -> Option<Sender<Vec<Search>>> {
+    let follower = match linux::Follower::new() {
+        Ok(follower) => follower,
+        Err(e) => {
+            warn!("cannot follow realtime searches: {}", e);
+            return None;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name(String::from("realtime"))
+        .spawn(move || follower.run(receiver))
+        .map_err(|e| warn!("cannot start the realtime follower: {}", e))
+        .ok()?;
+    info!("following realtime searches");
+
+    Some(sender)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start() -> Option<Sender<Vec<Search>>> {
+    warn!("realtime searches need inotify, they only run with the scheduled runs here");
+    // takes the sends so the warning is not repeated
+    let (sender, receiver) = mpsc::channel();
+    std::mem::forget(receiver);
+    Some(sender)
+}
+
+fn lines_per_minute() -> u64 {
+    conf::get_realtime_lines_per_minute().unwrap_or(DEFAULT_LINES_PER_MINUTE)
+}
+
+/**
+ * Lines that may be sent, refilled continuously up to a minute's worth.
+ */
+struct RateLimit {
+    per_minute: u64,
+    available: f64,
+    refilled: Instant,
+}
+impl RateLimit {
+    fn new(per_minute: u64) -> RateLimit {
+        RateLimit {
+            per_minute,
+            available: per_minute as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn take_one(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() / 60.0;
+        self.available =
+            (self.available + refill * self.per_minute as f64).min(self.per_minute as f64);
+        self.refilled = now;
+
+        if self.available >= 1.0 {
+            self.available -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/**
+ * Matches waiting to be sent, one result per search and location.
+ */
+struct Batch {
+    results: Vec<ClientSearchResult>,
+    // search id and location of each result
+    index: HashMap<(i32, String), usize>,
+    lines: usize,
+    // when the first match came in
+    opened: Option<Instant>,
+    // when the last batch was sent
+    sent: Option<Instant>,
+    limit: RateLimit,
+    dropped: u64,
+    // results of the batches that could not be sent, oldest first
+    unsent: Vec<ClientSearchResult>,
+}
+impl Batch {
+    fn new() -> Batch {
+        Batch {
+            results: Vec::new(),
+            index: HashMap::new(),
+            lines: 0,
+            opened: None,
+            sent: None,
+            limit: RateLimit::new(lines_per_minute()),
+            dropped: 0,
+            unsent: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, search: &Search, location: &str, line: String) {
+        let result = match self.index.get(&(search.id, location.to_string())) {
+            Some(result) => *result,
+            None => {
+                self.results
+                    .push(ClientSearchResult::new(search.id, &search.name, location));
+                self.index
+                    .insert((search.id, location.to_string()), self.results.len() - 1);
+                self.results.len() - 1
+            }
+        };
+        self.opened.get_or_insert_with(Instant::now);
+
+        if self.limit.take_one() {
+            self.results[result].found.push(line);
+            self.lines += 1;
+        } else {
+            self.results[result].truncated = true;
+            self.dropped += 1;
+        }
+    }
+
+    /**
+     * Whether the batch should be sent now, batches go out at most once a
+     * BATCH_INTERVAL. Unsent results are tried again every RETRY_INTERVAL.
+     */
+    fn is_due(&self) -> bool {
+        if self
+            .sent
+            .is_some_and(|sent| sent.elapsed() < BATCH_INTERVAL)
+        {
+            return false;
+        }
+        if let Some(opened) = self.opened {
+            if self.lines >= MAX_BATCH_LINES || opened.elapsed() >= BATCH_INTERVAL {
+                return true;
+            }
+        }
+        !self.unsent.is_empty()
+            && self
+                .sent
+                .is_none_or(|sent| sent.elapsed() >= RETRY_INTERVAL)
+    }
+
+    /**
+     * Send the batch to the server.
+     */
+    fn send(&mut self) {
+        self.flush(|results| match webclient::send_search_results(results) {
+            Ok(true) => true,
+            Ok(false) => {
+                warn!("the server did not take {} realtime results", results.len());
+                false
+            }
+            Err(e) => {
+                warn!("error sending realtime results: {}", e);
+                false
+            }
+        });
+    }
+
+    /**
+     * Send the unsent results and the batch with `send`, they are kept for
+     * the next flush if it fails.
+     */
+    fn flush<F: FnOnce(&[ClientSearchResult]) -> bool>(&mut self, send: F) {
+        let mut results = std::mem::take(&mut self.unsent);
+        results.extend(
+            self.results
+                .drain(..)
+                .filter(|result| !result.found.is_empty() || result.truncated),
+        );
+        self.index.clear();
+        self.lines = 0;
+        self.opened = None;
+        self.sent = Some(Instant::now());
+
+        if self.dropped > 0 {
+            warn!(
+                "dropped {} realtime matches over the limit of {} lines a minute",
+                self.dropped, self.limit.per_minute
+            );
+            self.dropped = 0;
+        }
+        if results.is_empty() {
+            return;
+        }
+
+        debug!("sending {} realtime results", results.len());
+        if !send(&results) {
+            self.keep_unsent(results);
+        }
+    }
+
+    fn keep_unsent(&mut self, mut results: Vec<ClientSearchResult>) {
+        let mut lines: usize = results.iter().map(|result| result.found.len()).sum();
+        let mut dropped = 0;
+        while lines > MAX_UNSENT_LINES {
+            let oldest = results.remove(0);
+            lines -= oldest.found.len();
+            dropped += oldest.found.len();
+        }
+        if dropped > 0 {
+            warn!(
+                "dropped {} unsent realtime matches, the scheduled runs find them again",
+                dropped
+            );
+        }
+
+        self.unsent = results;
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::Batch;
+    use crate::models::Search;
+    use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+    use securelog_proto::SearchType;
+    use securelog_search::MatcherSet;
+    use std::collections::{HashMap, HashSet};
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+    use std::os::unix::fs::MetadataExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+    use std::time::Duration;
+
+    // how long to wait for new searches between reading events
+    const TICK: Duration = Duration::from_millis(250);
+
+    /**
+     * A followed location and the searches that look at it.
+     */
+    struct Followed {
+        location: String,
+        // indexes into the followed searches
+        searches: Vec<usize>,
+        set: MatcherSet,
+        // where the next line starts, in the file with this inode
+        offset: u64,
+        inode: Option<u64>,
+    }
+    impl Followed {
+        /**
+         * Follow from the end of the file, or its start once it is created.
+         */
+        fn new(location: &str, searches: Vec<usize>, set: MatcherSet) -> Followed {
+            let (offset, inode) = match std::fs::metadata(location) {
+                Ok(metadata) => (metadata.len(), Some(metadata.ino())),
+                Err(_) => (0, None),
+            };
+
+            Followed {
+                location: location.to_string(),
+                searches,
+                set,
+                offset,
+                inode,
+            }
+        }
+
+        /**
+         * The complete lines appended since the last read, a line still
+         * being written is read once it ends.
+         */
+        fn read_new(&mut self) -> io::Result<Vec<String>> {
+            let mut file = File::open(&self.location)?;
+            let metadata = file.metadata()?;
+            if self.inode != Some(metadata.ino()) || metadata.len() < self.offset {
+                if self.inode.is_some() {
+                    info!(
+                        "{} was replaced or truncated, following it from the start",
+                        self.location
+                    );
+                }
+                self.inode = Some(metadata.ino());
+                self.offset = 0;
+            }
+            if metadata.len() == self.offset {
+                return Ok(Vec::new());
+            }
+
+            file.seek(SeekFrom::Start(self.offset))?;
+            let mut reader = BufReader::new(file);
+            let mut lines = Vec::new();
+            let mut buffer = Vec::new();
+            loop {
+                buffer.clear();
+                let read = reader.read_until(b'\n', &mut buffer)?;
+                if read == 0 || buffer.last() != Some(&b'\n') {
+                    break;
+                }
+                self.offset += read as u64;
+
+                let line = String::from_utf8_lossy(&buffer);
+                lines.push(line.trim_end_matches(['\n', '\r']).to_string());
+            }
+
+            Ok(lines)
+        }
+    }
+
+    pub struct Follower {
+        inotify: Inotify,
+        // the directory of each watch
+        dirs: HashMap<WatchDescriptor, PathBuf>,
+        files: HashMap<PathBuf, Followed>,
+        searches: Vec<Search>,
+        batch: Batch,
+    }
+    impl Follower {
+        pub fn new() -> io::Result<Follower> {
+            Ok(Follower {
+                inotify: Inotify::init()?,
+                dirs: HashMap::new(),
+                files: HashMap::new(),
+                searches: Vec::new(),
+                batch: Batch::new(),
+            })
+        }
+
+        pub fn run(mut self, refresh: Receiver<Vec<Search>>) {
+            let mut buffer = [0; 4096];
+            loop {
+                match refresh.recv_timeout(TICK) {
+                    Ok(searches) => self.update(searches),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+
+                for path in self.changed(&mut buffer) {
+                    self.read(&path);
+                }
+                if self.batch.is_due() {
+                    self.batch.send();
+                }
+            }
+        }
+
+        /**
+         * Follow `searches`, files followed before keep their place. Watches
+         * that could not be added before are tried again.
+         */
+        fn update(&mut self, searches: Vec<Search>) {
+            if searches != self.searches {
+                info!("following {} realtime searches", searches.len());
+                let mut files = HashMap::new();
+                for (location, indexes) in locations(&searches) {
+                    let patterns: Vec<(&SearchType, &str)> = indexes
+                        .iter()
+                        .map(|i| (&searches[*i].stype, searches[*i].search.as_str()))
+                        .collect();
+                    let set = match MatcherSet::new(&patterns) {
+                        Ok(set) => set,
+                        Err(e) => {
+                            warn!("cannot follow {}: {}", location, e);
+                            continue;
+                        }
+                    };
+
+                    let path = key(Path::new(location));
+                    let followed = match self.files.remove(&path) {
+                        Some(old) => Followed {
+                            searches: indexes,
+                            set,
+                            ..old
+                        },
+                        None => Followed::new(location, indexes, set),
+                    };
+                    files.insert(path, followed);
+                }
+                self.files = files;
+                self.searches = searches;
+            }
+
+            self.watch();
+        }
+
+        // watch the directories of the followed files, they see files
+        // being created and rotated as well as written
+        fn watch(&mut self) {
+            let wanted: HashSet<PathBuf> = self.files.keys().map(|path| parent(path)).collect();
+
+            let unwanted: Vec<WatchDescriptor> = self
+                .dirs
+                .iter()
+                .filter(|(_, dir)| !wanted.contains(*dir))
+                .map(|(wd, _)| wd.clone())
+                .collect();
+            for wd in unwanted {
+                self.dirs.remove(&wd);
+                // fails if the directory is gone, the watch is gone with it
+                let _ = self.inotify.watches().remove(wd);
+            }
+
+            let watched: HashSet<PathBuf> = self.dirs.values().cloned().collect();
+            for dir in wanted.difference(&watched) {
+                let mask = WatchMask::MODIFY | WatchMask::CREATE | WatchMask::MOVED_TO;
+                match self.inotify.watches().add(dir, mask) {
+                    Ok(wd) => {
+                        self.dirs.insert(wd, dir.clone());
+                        // written to while it was not watched
+                        for path in self.files_in(dir) {
+                            self.read(&path);
+                        }
+                    }
+                    Err(e) => warn!("cannot watch {}: {}", dir.display(), e),
+                }
+            }
+        }
+
+        fn files_in(&self, dir: &Path) -> Vec<PathBuf> {
+            self.files
+                .keys()
+                .filter(|path| parent(path) == dir)
+                .cloned()
+                .collect()
+        }
+
+        /**
+         * The followed files that changed since the last call.
+         */
+        fn changed(&mut self, buffer: &mut [u8]) -> HashSet<PathBuf> {
+            let mut changed = HashSet::new();
+            loop {
+                let events = match self.inotify.read_events(buffer) {
+                    Ok(events) => events,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return changed,
+                    Err(e) => {
+                        warn!("error reading inotify events: {}", e);
+                        return changed;
+                    }
+                };
+
+                for event in events {
+                    if event.mask.contains(EventMask::Q_OVERFLOW) {
+                        // events were lost, any file could have changed
+                        changed.extend(self.files.keys().cloned());
+                    } else if event.mask.contains(EventMask::IGNORED) {
+                        // the directory is gone, watched again with the next poll
+                        self.dirs.remove(&event.wd);
+                    } else if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) {
+                        let path = dir.join(name);
+                        if self.files.contains_key(&path) {
+                            changed.insert(path);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn read(&mut self, path: &Path) {
+            let Some(followed) = self.files.get_mut(path) else {
+                return;
+            };
+            let lines = match followed.read_new() {
+                Ok(lines) => lines,
+                Err(e) => {
+                    debug!("error reading {}: {}", followed.location, e);
+                    return;
+                }
+            };
+
+            let mut matched = Vec::new();
+            for line in lines {
+                followed.set.matches_into(&line, &mut matched);
+                for search in &matched {
+                    let search = &self.searches[followed.searches[*search]];
+                    self.batch.add(search, &followed.location, line.clone());
+                }
+            }
+        }
+    }
+
+    // a relative location without a directory is in the working directory
+    fn parent(path: &Path) -> PathBuf {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    // the path of a location as its directory's events name it
+    fn key(path: &Path) -> PathBuf {
+        match path.file_name() {
+            Some(name) => parent(path).join(name),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /**
+     * The searches of every location, in order.
+     */
+    fn locations(searches: &[Search]) -> Vec<(&str, Vec<usize>)> {
+        let mut locations: Vec<(&str, Vec<usize>)> = Vec::new();
+        for (i, search) in searches.iter().enumerate() {
+            // only files are followed, the scheduled runs search the
+            // journal and commands
+            let files = search
+                .locations
+                .iter()
+                .filter(|location| securelog_search::is_file(location));
+            for location in files {
+                match locations.iter_mut().find(|(l, _)| l == location) {
+                    Some((_, indexes)) if indexes.last() != Some(&i) => indexes.push(i),
+                    Some(_) => (),
+                    None => locations.push((location, vec![i])),
+                }
+            }
+        }
+        locations
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::io::Write;
+
+        fn followed(path: &Path) -> Followed {
+            let set = MatcherSet::new(&[(&SearchType::Contains, "error")]).unwrap();
+            Followed::new(&path.to_string_lossy(), vec![0], set)
+        }
+
+        fn append(path: &Path, text: &str) {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        }
+
+        #[test]
+        fn new_lines_are_read_once_they_end() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("syslog");
+            append(&path, "written before following\n");
+
+            let mut followed = followed(&path);
+            assert!(followed.read_new().unwrap().is_empty());
+
+            append(&path, "first\nsecond\r\nthird, still being writ");
+            assert_eq!(followed.read_new().unwrap(), vec!["first", "second"]);
+            assert!(followed.read_new().unwrap().is_empty());
+
+            append(&path, "ten\n");
+            assert_eq!(
+                followed.read_new().unwrap(),
+                vec!["third, still being written"]
+            );
+        }
+
+        #[test]
+        fn truncated_and_rotated_files_are_read_from_the_start() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("syslog");
+
+            // created after following started
+            let mut followed = followed(&path);
+            assert!(followed.read_new().is_err());
+            append(&path, "created\n");
+            assert_eq!(followed.read_new().unwrap(), vec!["created"]);
+
+            append(&path, "a longer line before the truncation\n");
+            assert_eq!(followed.read_new().unwrap().len(), 1);
+            std::fs::write(&path, "truncated\n").unwrap();
+            assert_eq!(followed.read_new().unwrap(), vec!["truncated"]);
+
+            // rotated, the new file is longer than where the old one ended
+            std::fs::rename(&path, dir.path().join("syslog.1")).unwrap();
+            append(
+                &path,
+                "rotated, a line longer than the old file\nand another\n",
+            );
+            assert_eq!(
+                followed.read_new().unwrap(),
+                vec!["rotated, a line longer than the old file", "and another"]
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use securelog_proto::SearchType;
+
+    fn search(id: i32) -> Search {
+        Search::new(
+            id,
+            format!("search{}", id),
+            SearchType::Contains,
+            "error".to_string(),
+            vec!["/var/log/syslog".to_string()],
+            true,
+            None,
+        )
+    }
+
+    fn ago(duration: Duration) -> Instant {
+        Instant::now().checked_sub(duration).unwrap()
+    }
+
+    #[test]
+    fn rate_limit_allows_a_minute_of_lines() {
+        let mut limit = RateLimit::new(3);
+        assert!(limit.take_one());
+        assert!(limit.take_one());
+        assert!(limit.take_one());
+        assert!(!limit.take_one());
+
+        // refilled at the rate, never past a minute's worth
+        limit.refilled = ago(Duration::from_secs(20));
+        assert!(limit.take_one());
+        assert!(!limit.take_one());
+        limit.refilled = ago(Duration::from_secs(600));
+        assert!(limit.take_one());
+        assert!(limit.take_one());
+        assert!(limit.take_one());
+        assert!(!limit.take_one());
+    }
+
+    #[test]
+    fn matches_are_batched_per_search_and_location() {
+        let mut batch = Batch::new();
+        batch.limit = RateLimit::new(3);
+        assert!(!batch.is_due());
+
+        batch.add(&search(1), "/var/log/syslog", "one".to_string());
+        batch.add(&search(1), "/var/log/syslog", "two".to_string());
+        batch.add(&search(2), "/var/log/syslog", "three".to_string());
+        batch.add(
+            &search(1),
+            "/var/log/auth.log",
+            "over the limit".to_string(),
+        );
+        assert!(!batch.is_due());
+        batch.opened = Some(ago(BATCH_INTERVAL));
+        assert!(batch.is_due());
+
+        let mut sent = Vec::new();
+        batch.flush(|results| {
+            sent = results
+                .iter()
+                .map(|result| (result.found.clone(), result.truncated))
+                .collect();
+            true
+        });
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], (vec!["one".to_string(), "two".to_string()], false));
+        assert_eq!(sent[1], (vec!["three".to_string()], false));
+        assert_eq!(sent[2], (vec![], true));
+        assert!(batch.unsent.is_empty());
+
+        // at most once a BATCH_INTERVAL, even when full
+        batch.limit = RateLimit::new(MAX_BATCH_LINES as u64);
+        for i in 0..MAX_BATCH_LINES {
+            batch.add(&search(1), "/var/log/syslog", i.to_string());
+        }
+        assert!(!batch.is_due());
+        batch.sent = Some(ago(BATCH_INTERVAL));
+        assert!(batch.is_due());
+    }
+
+    #[test]
+    fn unsent_batches_are_kept_for_the_next_flush() {
+        let mut batch = Batch::new();
+        batch.add(&search(1), "/var/log/syslog", "one".to_string());
+        batch.flush(|_| false);
+        assert_eq!(batch.unsent.len(), 1);
+
+        // tried again on their own, after RETRY_INTERVAL
+        assert!(!batch.is_due());
+        batch.sent = Some(ago(RETRY_INTERVAL));
+        assert!(batch.is_due());
+
+        batch.add(&search(1), "/var/log/syslog", "two".to_string());
+        let mut sent = Vec::new();
+        batch.flush(|results| {
+            sent = results
+                .iter()
+                .map(|result| (result.found.clone(), result.truncated))
+                .collect();
+            true
+        });
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].0, vec!["one"]);
+        assert_eq!(sent[1].0, vec!["two"]);
+        assert!(batch.unsent.is_empty());
+        assert!(!batch.is_due());
+    }
+
+    #[test]
+    fn unsent_results_are_capped() {
+        let mut batch = Batch::new();
+        for i in 0..3 {
+            let mut result = ClientSearchResult::new(i, "search", "/var/log/syslog");
+            result.found = vec![i.to_string(); MAX_UNSENT_LINES / 2];
+            batch.unsent.push(result);
+        }
+        batch.flush(|_| false);
+
+        // the oldest is dropped
+        assert_eq!(batch.unsent.len(), 2);
+        assert_eq!(batch.unsent[0].search_id, 1);
+    }
+}
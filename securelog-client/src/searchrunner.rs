@@ -1,7 +1,20 @@
-use crate::models::{Search, SearchResult, SearchType};
+use crate::archiver;
+use crate::command::{self, CommandError};
+use crate::conf;
+use crate::integrity;
+use crate::journal::{self, JournalReader, JournalStart};
+use crate::models::{ClientSearchResult, RunError, RunErrorKind, Search, SearchRunReport};
+use crate::settings;
+use crate::since::SinceLines;
+use crate::throttle::{Throttle, Throttled};
 use crate::webclient::{self};
+use chrono::{DateTime, Local};
+use securelog_proto::{ResourceLimits, SearchType, INTEGRITY_VERSION, RUN_REPORTS_VERSION};
+use securelog_search::{map_bounded, Allowance, Budget, Matcher, MatcherSet, Scan, SetError};
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
+use std::io::{BufReader, ErrorKind};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Error)]
 pub enum SearchError {
@@ -15,95 +28,443 @@ pub enum SearchError {
     Web(#[from] crate::webclient::WebError),
 
     #[error("SearchError(Regex({0}))")]
-    Regex(#[from] regex::Error),
-}
+    Regex(#[from] securelog_search::RegexError),
 
-type Result<T> = std::result::Result<T, SearchError>;
+    #[error("SearchError(Set({0}))")]
+    Set(String),
 
-pub fn run_once() -> Result<()> {
-    let searches = webclient::get_searches()?;
+    #[error("SearchError(Journal({0}))")]
+    Journal(#[from] journal::JournalError),
+
+    #[error("SearchError(Command({0}))")]
+    Command(#[from] CommandError),
+}
 
-    for search in &searches {
-        let mut results: Vec<SearchResult> = Vec::new();
+type Result<T> = std::result::Result<T, SearchError>;
 
-        for location in &search.locations {
-            match run_search(location, search) {
-                Ok(result) => {
-                    results.push(result);
-                }
-                Err(e) => {
-                    warn!("error running search {}: {}", search.id, e);
-                }
+impl SearchError {
+    /**
+     * The error as reported to the server.
+     */
+    fn run_error(&self) -> RunError {
+        let kind = match self {
+            SearchError::IO(e) if e.kind() == ErrorKind::NotFound => RunErrorKind::Missing,
+            SearchError::IO(e) if e.kind() == ErrorKind::PermissionDenied => {
+                RunErrorKind::PermissionDenied
             }
-        }
-        if !results.is_empty() {
-            webclient::send_search_results(&results)?;
+            SearchError::FileReadOnly(_) => RunErrorKind::PermissionDenied,
+            SearchError::Regex(_)
+            | SearchError::Set(_)
+            | SearchError::Journal(journal::JournalError::Location(_)) => {
+                RunErrorKind::InvalidSearch
+            }
+            // journalctl is not installed
+            SearchError::Journal(journal::JournalError::IO(e))
+                if e.kind() == ErrorKind::NotFound =>
+            {
+                RunErrorKind::Missing
+            }
+            SearchError::Command(CommandError::Location(_)) => RunErrorKind::InvalidSearch,
+            SearchError::Command(CommandError::NotAllowed(_)) => RunErrorKind::PermissionDenied,
+            SearchError::Command(CommandError::IO(e)) if e.kind() == ErrorKind::NotFound => {
+                RunErrorKind::Missing
+            }
+            SearchError::IO(_)
+            | SearchError::Web(_)
+            | SearchError::Journal(_)
+            | SearchError::Command(_) => RunErrorKind::Io,
+        };
+
+        RunError {
+            kind,
+            message: self.to_string(),
         }
     }
+}
 
-    Ok(())
+// files searched in parallel when the config does not say
+const MAX_DEFAULT_THREADS: usize = 4;
+
+fn search_threads() -> usize {
+    conf::get_search_threads().unwrap_or_else(|_| {
+        std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1)
+            .min(MAX_DEFAULT_THREADS)
+    })
 }
 
-pub fn run_search(path: &str, search: &Search) -> Result<SearchResult> {
-    check_file_can_read(path)?;
+/**
+ * A location and the searches that read it, matched in one pass.
+ */
+struct FileJob<'a> {
+    location: &'a str,
+    // indexes into the run's searches
+    searches: Vec<usize>,
+    set: std::result::Result<MatcherSet, SetError>,
+}
 
-    match search.stype {
-        SearchType::Contains => run_search_contains(path, search),
-        SearchType::Regex => run_search_regex(path, search),
-        SearchType::Wildcard => run_search_wildcard(path, search),
+/**
+ * What a run may still use, shared by the files it scans in parallel.
+ */
+struct RunBudget {
+    bytes: Allowance,
+    deadline: Option<Instant>,
+    // per search
+    search_bytes: Vec<Allowance>,
+    search_found: Vec<Allowance>,
+    search_deadline: Option<Instant>,
+    throttle: Throttle,
+}
+impl RunBudget {
+    fn new(limits: &ResourceLimits, searches: usize) -> RunBudget {
+        // all searches run together, they start with the run
+        let started = Instant::now();
+        let after = |secs: Option<u64>| secs.map(|secs| started + Duration::from_secs(secs));
+
+        RunBudget {
+            bytes: Allowance::new(limits.max_run_bytes),
+            deadline: after(limits.max_run_secs),
+            search_bytes: (0..searches)
+                .map(|_| Allowance::new(limits.max_search_bytes))
+                .collect(),
+            search_found: (0..searches)
+                .map(|_| Allowance::new(limits.max_search_lines))
+                .collect(),
+            search_deadline: after(limits.max_search_secs),
+            throttle: Throttle::new(limits.max_read_rate),
+        }
+    }
+
+    /**
+     * Why the run has to stop, None while it is within its limits.
+     */
+    fn spent(&self) -> Option<String> {
+        if self.bytes.is_spent() {
+            return Some(format!("read {} bytes", self.bytes.used()));
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(String::from("ran out of time"));
+        }
+        None
+    }
+
+    fn budget(&self, search: usize) -> Budget<'_> {
+        Budget {
+            bytes: vec![&self.bytes, &self.search_bytes[search]],
+            found: Some(&self.search_found[search]),
+            deadline: match (self.deadline, self.search_deadline) {
+                (Some(run), Some(search)) => Some(run.min(search)),
+                (run, search) => run.or(search),
+            },
+        }
     }
 }
 
-fn run_search_contains(path: &str, search: &Search) -> Result<SearchResult> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+/**
+ * What scanning a location found for each of its searches, with the
+ * journal cursor it got to.
+ */
+struct LocationScan {
+    scans: Vec<(usize, Option<ClientSearchResult>, SearchRunReport)>,
+    cursor: Option<String>,
+}
+
+/**
+ * Group the searches by location, every location is read once for all the
+ * searches that look at it.
+ */
+fn file_jobs<'a>(searches: &[&'a Search]) -> Vec<FileJob<'a>> {
+    let mut locations: Vec<(&str, Vec<usize>)> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
 
-    let mut results = SearchResult::new(search.id, &search.name, path);
-    for (_index, line) in reader.lines().enumerate() {
-        let line = line?;
-        if line.contains(&search.search) {
-            results.found.push(line);
+    for (search, s) in searches.iter().enumerate() {
+        for location in &s.locations {
+            let job = *index.entry(location).or_insert_with(|| {
+                locations.push((location, Vec::new()));
+                locations.len() - 1
+            });
+            // a search that lists a location twice reads it once
+            if locations[job].1.last() != Some(&search) {
+                locations[job].1.push(search);
+            }
         }
     }
 
-    Ok(results)
+    locations
+        .into_iter()
+        .map(|(location, job_searches)| {
+            let patterns: Vec<(&SearchType, &str)> = job_searches
+                .iter()
+                .map(|search| (&searches[*search].stype, searches[*search].search.as_str()))
+                .collect();
+
+            FileJob {
+                location,
+                set: MatcherSet::new(&patterns),
+                searches: job_searches,
+            }
+        })
+        .collect()
+}
+
+/**
+ * Where a run starts reading. Files are always read whole, the journal
+ * from where the last run stopped in scheduled runs.
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Start {
+    LastRun,
+    // from the start, or only what was written since the time
+    Since(Option<DateTime<Local>>),
+}
+
+/**
+ * What a run found and how every search went on every location.
+ */
+pub struct RunScan {
+    // results of one search each, searches that do not compile have none
+    pub results: Vec<Vec<ClientSearchResult>>,
+    pub reports: Vec<SearchRunReport>,
+    // journal locations and the cursor of the last entry scanned
+    cursors: Vec<(String, String)>,
 }
 
-fn run_search_regex(path: &str, search: &Search) -> Result<SearchResult> {
-    use regex::Regex;
-    let rgx = Regex::new(&search.search)?;
-    let mut results = SearchResult::new(search.id, &search.name, path);
+/**
+ * Scan the locations of `searches` within the resource limits.
+ */
+pub fn scan_searches(searches: &[Search], start: Start) -> RunScan {
+    let mut reports: Vec<SearchRunReport> = Vec::new();
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    // searches that do not compile are reported and left out
+    let mut runnable: Vec<&Search> = Vec::new();
+    for search in searches {
+        match Matcher::new(&search.stype, &search.search) {
+            Ok(_) => runnable.push(search),
+            Err(e) => {
+                let e = SearchError::Regex(e);
+                warn!("error running search {}: {}", search.id, e);
+                for location in &search.locations {
+                    let mut report = SearchRunReport::new(search.id, location);
+                    report.error = Some(e.run_error());
+                    reports.push(report);
+                }
+            }
+        }
+    }
 
-    for (_index, line) in reader.lines().enumerate() {
-        let line = line?;
-        if rgx.is_match(&line) {
-            results.found.push(line);
+    // a location that runs out of budget is searched up to there and its
+    // results flagged truncated, once the run is out of budget it stops
+    let budget = RunBudget::new(&settings::limits(), runnable.len());
+    let jobs = file_jobs(&runnable);
+    let scanned = map_bounded(&jobs, search_threads(), |job| {
+        if budget.spent().is_some() {
+            return None;
         }
+        Some(scan_location(job, &runnable, &budget, start))
+    });
+
+    let skipped = scanned.iter().filter(|scan| scan.is_none()).count();
+    if skipped > 0 {
+        warn!(
+            "stopped the run with {} of {} locations left: {}",
+            skipped,
+            jobs.len(),
+            budget.spent().unwrap_or_default()
+        );
     }
 
-    Ok(results)
+    let mut results: Vec<Vec<ClientSearchResult>> = runnable.iter().map(|_| Vec::new()).collect();
+    let mut cursors: Vec<(String, String)> = Vec::new();
+    for (job, scan) in jobs.iter().zip(scanned) {
+        let Some(scan) = scan else {
+            continue;
+        };
+        if let Some(cursor) = scan.cursor {
+            cursors.push((job.location.to_string(), cursor));
+        }
+        for (search, result, report) in scan.scans {
+            if let Some(result) = result {
+                if result.truncated {
+                    info!(
+                        "search {} on {} ran out of budget, sending partial results",
+                        result.search_id, result.location
+                    );
+                }
+                results[search].push(result);
+            }
+            reports.push(report);
+        }
+    }
+
+    RunScan {
+        results,
+        reports,
+        cursors,
+    }
 }
 
-fn run_search_wildcard(path: &str, search: &Search) -> Result<SearchResult> {
-    use wildmatch::WildMatch;
-    let wmatch = WildMatch::new(&search.search);
+pub fn run_once() -> Result<()> {
+    let searches = webclient::get_searches()?;
+    let RunScan {
+        results,
+        reports,
+        cursors,
+    } = scan_searches(&searches, Start::LastRun);
 
-    let mut results = SearchResult::new(search.id, &search.name, path);
+    let mut sent = true;
+    for results in results.iter().filter(|results| !results.is_empty()) {
+        sent &= webclient::send_search_results(results)?;
+    }
+    // journal entries are searched again if their results did not get through
+    if sent {
+        journal::save_cursors(&cursors)?;
+    }
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    // older servers do not take reports
+    if !reports.is_empty() && webclient::server_speaks(RUN_REPORTS_VERSION) {
+        webclient::send_run_reports(&reports)?;
+    }
 
-    for (_index, line) in reader.lines().enumerate() {
-        let line = line?;
-        if wmatch.matches(&line) {
-            results.found.push(line);
+    if archiver::is_enabled() {
+        if let Err(e) = archiver::archive_once(&searches) {
+            warn!("error archiving logs: {}", e);
         }
     }
 
-    Ok(results)
+    // older servers do not keep baselines
+    if integrity::is_enabled() && webclient::server_speaks(INTEGRITY_VERSION) {
+        if let Err(e) = integrity::check_once() {
+            warn!("error checking file integrity: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Read a location once for all its searches, each within its budget. Every
+ * search gets a report, and a result unless the location could not be
+ * read. The matching is the same as the server's dry run and archive
+ * searches use.
+ */
+fn scan_location(
+    job: &FileJob,
+    searches: &[&Search],
+    budget: &RunBudget,
+    start: Start,
+) -> LocationScan {
+    let mut reports: Vec<SearchRunReport> = job
+        .searches
+        .iter()
+        .map(|search| SearchRunReport::new(searches[*search].id, job.location))
+        .collect();
+    let budgets: Vec<Budget> = job
+        .searches
+        .iter()
+        .map(|search| budget.budget(*search))
+        .collect();
+    let timer = Instant::now();
+
+    let mut cursor = None;
+    let scans = match &job.set {
+        Ok(set) if securelog_search::is_journal(job.location) => {
+            scan_journal(job.location, set, &budgets, start).map(|(scans, journal_cursor)| {
+                cursor = journal_cursor;
+                scans
+            })
+        }
+        Ok(set) if securelog_search::is_command(job.location) => command::run(job.location)
+            .map_err(SearchError::from)
+            .and_then(|output| match start {
+                Start::Since(Some(since)) => {
+                    Ok(set.scan_within(SinceLines::new(output.as_slice(), since), &budgets)?)
+                }
+                _ => Ok(set.scan_within(output.as_slice(), &budgets)?),
+            }),
+        Ok(set) => check_file_can_read(job.location)
+            .and_then(|_| Ok(File::open(job.location)?))
+            .and_then(|file| {
+                let file = BufReader::new(Throttled::new(file, &budget.throttle));
+                match start {
+                    Start::Since(Some(since)) => {
+                        Ok(set.scan_within(SinceLines::new(file, since), &budgets)?)
+                    }
+                    _ => Ok(set.scan_within(file, &budgets)?),
+                }
+            }),
+        Err(e) => Err(SearchError::Set(e.to_string())),
+    };
+    let duration_ms = timer.elapsed().as_millis() as u64;
+    for report in &mut reports {
+        report.duration_ms = duration_ms;
+    }
+
+    let scans = match scans {
+        Ok(scans) => scans,
+        Err(e) => {
+            warn!("error searching {}: {}", job.location, e);
+            let scans = job
+                .searches
+                .iter()
+                .zip(reports)
+                .map(|(search, mut report)| {
+                    report.error = Some(e.run_error());
+                    (*search, None, report)
+                })
+                .collect();
+            return LocationScan {
+                scans,
+                cursor: None,
+            };
+        }
+    };
+
+    let scans = job
+        .searches
+        .iter()
+        .zip(reports)
+        .zip(scans)
+        .map(|((search, mut report), scan)| {
+            let s = searches[*search];
+            report.lines_scanned = scan.lines;
+            report.bytes_read = scan.bytes;
+
+            let mut result = ClientSearchResult::new(s.id, &s.name, job.location);
+            result.started = report.started;
+            result.found = scan.found;
+            result.truncated = scan.truncated;
+
+            (*search, Some(result), report)
+        })
+        .collect();
+
+    LocationScan { scans, cursor }
+}
+
+/**
+ * Scan the journal entries from `start`, with the cursor of the last entry
+ * scanned.
+ */
+fn scan_journal(
+    location: &str,
+    set: &MatcherSet,
+    budgets: &[Budget],
+    start: Start,
+) -> Result<(Vec<Scan>, Option<String>)> {
+    let from = match start {
+        Start::LastRun => journal::cursor(location)?.map(JournalStart::After),
+        Start::Since(since) => since.map(JournalStart::Since),
+    };
+    let mut reader = JournalReader::open(location, from)?;
+    let scans = set.scan_within(reader.lines(), budgets)?;
+    let last = reader.finish()?;
+
+    // nothing new keeps the cursor the run started from
+    Ok((scans, last))
 }
 
 fn check_file_can_read(path: &str) -> Result<()> {
@@ -0,0 +1,87 @@
+/*
+Settings managed by the server.
+
+The server pushes ClientSettings with every should_run answer, they are
+applied without a restart. A setting the server leaves unset falls back to
+the client's own config, resource limits set on both sides take the stricter.
+*/
+use crate::conf;
+use flexi_logger::LoggerHandle;
+use securelog_proto::{ClientSettings, ResourceLimits};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+// how often the server is polled when it sets no interval
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref SETTINGS: RwLock<ClientSettings> = RwLock::new(ClientSettings::default());
+    // kept to change the log level at runtime
+    static ref LOGGER: Mutex<Option<LoggerHandle>> = Mutex::new(None);
+}
+
+/**
+ * The log level from the config, info if there is none.
+ */
+pub fn config_log_level() -> String {
+    conf::get_log_level().unwrap_or_else(|_| String::from("info"))
+}
+
+pub fn set_logger(handle: LoggerHandle) {
+    *LOGGER.lock().unwrap() = Some(handle);
+}
+
+fn set_log_level(level: &str) {
+    if let Some(handle) = LOGGER.lock().unwrap().as_ref() {
+        if let Err(e) = handle.parse_new_spec(level) {
+            warn!("failed to set log level {}: {}", level, e);
+        }
+    }
+}
+
+/**
+ * Apply the settings the server sent, invalid settings are ignored.
+ */
+pub fn apply(settings: ClientSettings) {
+    if let Err(e) = settings.validate() {
+        warn!("ignoring settings from the server: {}", e);
+        return;
+    }
+
+    let mut current = SETTINGS.write().unwrap();
+    if *current == settings {
+        return;
+    }
+
+    if current.log_level != settings.log_level {
+        match &settings.log_level {
+            Some(level) => set_log_level(level),
+            None => set_log_level(&config_log_level()),
+        }
+    }
+    info!("applying settings from the server: {:?}", settings);
+
+    *current = settings;
+}
+
+pub fn poll_interval() -> Duration {
+    SETTINGS
+        .read()
+        .unwrap()
+        .poll_interval
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_POLL_INTERVAL)
+}
+
+/**
+ * The stricter of the limits from the config and the ones the server sets,
+ * the server can tighten the limits a host has but never loosen them.
+ */
+pub fn limits() -> ResourceLimits {
+    let local = conf::get_limits().unwrap_or_else(|e| {
+        warn!("ignoring the limits in the config: {}", e);
+        ResourceLimits::default()
+    });
+
+    local.strictest(&SETTINGS.read().unwrap().limits)
+}
@@ -0,0 +1,141 @@
+use crate::conf;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey, SECRET_KEY_LENGTH};
+use securelog_proto::signed_message;
+
+#[derive(Debug, Error)]
+pub enum SigningError {
+    #[error("SigningError(Config({0}))")]
+    Config(#[from] config::ConfigError),
+
+    #[error("SigningError(Base64({0}))")]
+    Base64(#[from] base64::DecodeError),
+
+    #[error("SigningError(invalid signing key length {0})")]
+    KeyLength(usize),
+}
+pub type Result<T> = std::result::Result<T, SigningError>;
+
+/**
+ * Generate a new Ed25519 keypair for this client.
+ * The public half is registered with the server on client creation.
+ */
+pub fn generate_signing_key() -> SigningKey {
+    let mut csprng = rand::rngs::OsRng;
+
+    SigningKey::generate(&mut csprng)
+}
+
+pub fn encode_signing_key(key: &SigningKey) -> String {
+    BASE64.encode(key.to_bytes())
+}
+
+pub fn encode_public_key(key: &SigningKey) -> String {
+    BASE64.encode(key.verifying_key().to_bytes())
+}
+
+/**
+ * Decode a base64 encoded signing key, as written to the config by
+ * encode_signing_key.
+ */
+pub fn decode_signing_key(encoded: &str) -> Result<SigningKey> {
+    let bytes = BASE64.decode(encoded.trim())?;
+    let bytes: [u8; SECRET_KEY_LENGTH] = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| SigningError::KeyLength(bytes.len()))?;
+
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/**
+ * Load the signing key from the config. Returns None if no key is configured,
+ * which is the case for clients created before result signing existed.
+ */
+pub fn load_signing_key() -> Result<Option<SigningKey>> {
+    match conf::get_signing_key() {
+        Ok(encoded) => Ok(Some(decode_signing_key(&encoded)?)),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/**
+ * A base64 signature and the unix time it covers.
+ */
+#[derive(Debug)]
+pub struct Signed {
+    pub signature: String,
+    pub time: i64,
+}
+
+/**
+ * Sign the time along with a payload, see securelog_proto::signed_message.
+ */
+pub fn sign_with(key: &SigningKey, time: i64, data: &[u8]) -> String {
+    BASE64.encode(key.sign(&signed_message(time, data)).to_bytes())
+}
+
+/**
+ * Sign a payload with the configured key at the current time.
+ */
+pub fn sign(data: &[u8]) -> Result<Option<Signed>> {
+    match load_signing_key()? {
+        Some(key) => {
+            let time = chrono::Utc::now().timestamp();
+            Ok(Some(Signed {
+                signature: sign_with(&key, time, data),
+                time,
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    #[test]
+    fn keys_are_encoded_and_decoded() {
+        let key = generate_signing_key();
+        let decoded = decode_signing_key(&format!("{}\n", encode_signing_key(&key))).unwrap();
+        assert_eq!(decoded.to_bytes(), key.to_bytes());
+
+        let pubkey = BASE64.decode(encode_public_key(&key)).unwrap();
+        assert_eq!(pubkey, key.verifying_key().to_bytes());
+
+        assert!(matches!(
+            decode_signing_key("not base64!"),
+            Err(SigningError::Base64(_))
+        ));
+        assert!(matches!(
+            decode_signing_key(&BASE64.encode([1u8; 16])),
+            Err(SigningError::KeyLength(16))
+        ));
+    }
+
+    #[test]
+    fn signatures_cover_the_time_and_payload() {
+        let key = generate_signing_key();
+        let pubkey: VerifyingKey = key.verifying_key();
+        let signature = sign_with(&key, 1_700_000_000, b"results");
+        let signature = Signature::from_slice(&BASE64.decode(signature).unwrap()).unwrap();
+
+        assert!(pubkey
+            .verify_strict(&signed_message(1_700_000_000, b"results"), &signature)
+            .is_ok());
+        assert!(pubkey
+            .verify_strict(&signed_message(1_700_000_001, b"results"), &signature)
+            .is_err());
+        assert!(pubkey
+            .verify_strict(&signed_message(1_700_000_000, b"resultz"), &signature)
+            .is_err());
+        assert!(generate_signing_key()
+            .verifying_key()
+            .verify_strict(&signed_message(1_700_000_000, b"results"), &signature)
+            .is_err());
+    }
+}
@@ -0,0 +1,249 @@
+/*
+Scanning only recent content, for --since in dry runs.
+
+Lines are kept or skipped by the timestamp they start with, ISO 8601 as in
+"2026-10-19T06:19:32Z" or "[2026-10-19 06:19:32.82 +00:00]", or syslog's
+"Oct 19 06:19:32". A line without one goes with the line before it, so
+the rest of a multi-line message stays with its first line, and a file
+without timestamps is scanned whole. Times without an offset are local.
+*/
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::io::{self, BufRead, Read};
+
+// "2026-10-19T06:19:32" and "Oct 19 06:19:32"
+const ISO_LENGTH: usize = 19;
+const SYSLOG_LENGTH: usize = 15;
+
+fn local(time: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&time).earliest()
+}
+
+/**
+ * Parse --since, a duration back from `now` such as 30m, 12h or 7d, or a
+ * time as in 2026-10-19, 2026-10-19 06:00:00 or 2026-10-19T06:00:00+02:00.
+ */
+pub fn parse_since(value: &str, now: DateTime<Local>) -> Result<DateTime<Local>, String> {
+    let value = value.trim();
+    let invalid = || {
+        format!(
+            "--since {} is not a duration like 30m, 12h or 7d or a time like 2026-10-19 06:00:00",
+            value
+        )
+    };
+
+    // a number and a unit, the Z of "2026-10-19T06:00:00Z" is no unit
+    let duration = value.char_indices().last().filter(|(index, unit)| {
+        unit.is_ascii_alphabetic()
+            && *index > 0
+            && value[..*index].chars().all(|c| c.is_ascii_digit())
+    });
+    if let Some((index, unit)) = duration {
+        let amount: i64 = value[..index].parse().map_err(|_| invalid())?;
+        let duration = match unit {
+            's' => Duration::try_seconds(amount),
+            'm' => Duration::try_minutes(amount),
+            'h' => Duration::try_hours(amount),
+            'd' => Duration::try_days(amount),
+            _ => None,
+        };
+        return duration
+            .and_then(|duration| now.checked_sub_signed(duration))
+            .ok_or_else(invalid);
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Local));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .and_then(local)
+        .ok_or_else(invalid)
+}
+
+// the offset after an ISO time, "Z", "+02:00", "+0200" or " +00:00"
+fn iso_offset(rest: &str) -> Option<chrono::FixedOffset> {
+    let rest = rest.trim_start_matches(|c: char| c == '.' || c == ',' || c.is_ascii_digit());
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+    if rest.starts_with('Z') {
+        return chrono::FixedOffset::east_opt(0);
+    }
+
+    let sign = match rest.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = rest[1..]
+        .chars()
+        .take(5)
+        .filter(char::is_ascii_digit)
+        .collect();
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    chrono::FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/**
+ * The time a log line starts with, None if it does not start with one.
+ */
+pub fn line_time(line: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    let line = line.strip_prefix('[').unwrap_or(line);
+
+    if let Some(start) = line.get(..ISO_LENGTH) {
+        let start = start.replacen('T', " ", 1);
+        if let Ok(time) = NaiveDateTime::parse_from_str(&start, "%Y-%m-%d %H:%M:%S") {
+            return match iso_offset(&line[ISO_LENGTH..]) {
+                Some(offset) => offset
+                    .from_local_datetime(&time)
+                    .single()
+                    .map(|time| time.with_timezone(&Local)),
+                None => local(time),
+            };
+        }
+    }
+
+    // syslog leaves out the year, the one that is not in the future
+    let start = line.get(..SYSLOG_LENGTH)?;
+    let time =
+        NaiveDateTime::parse_from_str(&format!("{} {}", now.year(), start), "%Y %b %e %H:%M:%S")
+            .ok()
+            .and_then(local)?;
+    if time > now + Duration::days(1) {
+        time.with_year(now.year() - 1)
+    } else {
+        Some(time)
+    }
+}
+
+/**
+ * The lines of a reader from the first one written at or after `since`.
+ */
+pub struct SinceLines<R> {
+    reader: R,
+    since: DateTime<Local>,
+    now: DateTime<Local>,
+    // whether the lines without a time are kept
+    keep: bool,
+    line: Vec<u8>,
+    pos: usize,
+}
+impl<R: BufRead> SinceLines<R> {
+    pub fn new(reader: R, since: DateTime<Local>) -> SinceLines<R> {
+        SinceLines {
+            reader,
+            since,
+            now: Local::now(),
+            keep: true,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+impl<R: BufRead> BufRead for SinceLines<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                break;
+            }
+
+            if let Some(time) = line_time(&String::from_utf8_lossy(&self.line), self.now) {
+                self.keep = time >= self.since;
+            }
+            if !self.keep {
+                self.line.clear();
+            }
+        }
+
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.line.len());
+    }
+}
+impl<R: BufRead> Read for SinceLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.consume(amount);
+
+        Ok(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Timelike, Utc};
+
+    #[test]
+    fn since_values() {
+        let now = Utc
+            .with_ymd_and_hms(2026, 10, 19, 8, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let utc = |y, mo, d, h, mi, s| Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap();
+
+        for (value, expected) in [
+            ("45s", utc(2026, 10, 19, 7, 59, 15)),
+            ("30m", utc(2026, 10, 19, 7, 30, 0)),
+            ("12h", utc(2026, 10, 18, 20, 0, 0)),
+            (" 7d ", utc(2026, 10, 12, 8, 0, 0)),
+            ("0m", utc(2026, 10, 19, 8, 0, 0)),
+            ("2026-10-19T06:00:00Z", utc(2026, 10, 19, 6, 0, 0)),
+            ("2026-10-19T06:00:00.5Z", utc(2026, 10, 19, 6, 0, 0)),
+            ("2026-10-19T06:00:00+02:00", utc(2026, 10, 19, 4, 0, 0)),
+            ("2026-10-19T06:00:00-05:30", utc(2026, 10, 19, 11, 30, 0)),
+        ] {
+            let since = parse_since(value, now).unwrap();
+            assert_eq!(
+                since.with_timezone(&Utc).with_nanosecond(0).unwrap(),
+                expected,
+                "{}",
+                value
+            );
+        }
+
+        // without an offset the time is local
+        let local = |value| {
+            parse_since(value, now)
+                .unwrap()
+                .naive_local()
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        };
+        assert_eq!(local("2026-10-19 06:00:00"), "2026-10-19 06:00:00");
+        assert_eq!(local("2026-10-19T06:00:00"), "2026-10-19 06:00:00");
+        assert_eq!(local("2026-10-19"), "2026-10-19 00:00:00");
+
+        for value in [
+            "",
+            "m",
+            "30",
+            "-30m",
+            "30w",
+            "1.5h",
+            "h30",
+            "30 m",
+            "yesterday",
+            "2026-10-19Z",
+            "2026-13-01",
+            "2026-10-19T06:00:00+25:00",
+            "99999999999999999d",
+        ] {
+            assert!(parse_since(value, now).is_err(), "{}", value);
+        }
+    }
+}
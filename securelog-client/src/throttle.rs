@@ -0,0 +1,57 @@
+/*
+IO throttling for searches.
+
+A Throttle is shared by all the files a run reads, in parallel or not.
+Throttled readers sleep after each read until reading everything read so
+far at the configured rate would have taken as long, so a search over a
+multi-GB log does not starve the host of IO.
+*/
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+pub struct Throttle {
+    // bytes per second, None reads as fast as possible
+    rate: Option<u64>,
+    started: Instant,
+    read: AtomicU64,
+}
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Throttle {
+        Throttle {
+            rate: rate.filter(|rate| *rate > 0),
+            started: Instant::now(),
+            read: AtomicU64::new(0),
+        }
+    }
+
+    fn wait(&self, read: usize) {
+        if let Some(rate) = self.rate {
+            let total = self.read.fetch_add(read as u64, Ordering::Relaxed) + read as u64;
+            let due = Duration::from_secs_f64(total as f64 / rate as f64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                sleep(due - elapsed);
+            }
+        }
+    }
+}
+
+pub struct Throttled<'a, R> {
+    inner: R,
+    throttle: &'a Throttle,
+}
+impl<'a, R: Read> Throttled<'a, R> {
+    pub fn new(inner: R, throttle: &'a Throttle) -> Throttled<'a, R> {
+        Throttled { inner, throttle }
+    }
+}
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.throttle.wait(read);
+
+        Ok(read)
+    }
+}
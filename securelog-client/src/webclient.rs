@@ -1,9 +1,17 @@
+use crate::realtime;
+use crate::settings;
 use crate::{
     conf,
-    models::{Search, SearchResult},
+    models::{ClientSearchResult, IntegritySnapshot, Search, SearchRunReport},
+    signing,
 };
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, RequestBuilder};
 use reqwest::StatusCode;
+use securelog_proto::{
+    capability, format_tags, negotiate, LoginResponse, ShouldRunResponse, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION, SIGNATURE_HEADER, SIGNATURE_TIME_HEADER,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 #[derive(Debug, Error)]
 pub enum WebError {
@@ -15,6 +23,19 @@ pub enum WebError {
 
     #[error("WebError(Json({0}))")]
     Json(#[from] serde_json::Error),
+
+    #[error("WebError(Signing({0}))")]
+    Signing(#[from] crate::signing::SigningError),
+
+    #[error("WebError(Io({0}))")]
+    Io(#[from] std::io::Error),
+
+    #[error("WebError(Protocol({0}))")]
+    Protocol(#[from] securelog_proto::ProtocolError),
+
+    // the server refused us, with its reason
+    #[error("WebError(Server({0}))")]
+    Server(String),
 }
 pub type Result<T> = std::result::Result<T, WebError>;
 
@@ -25,15 +46,88 @@ lazy_static! {
         .cookie_store(true).build().unwrap();
 }
 
+// integrity snapshots are compressed like archive segments
+const ZSTD_LEVEL: i32 = 3;
+
+// the protocol version agreed on at the last login
+static PROTOCOL: AtomicU32 = AtomicU32::new(1);
+
+/**
+ * The protocol version agreed on at the last login.
+ */
+pub fn protocol() -> u32 {
+    PROTOCOL.load(Ordering::Relaxed)
+}
+
+/**
+ * Whether the server we are logged in to speaks `version`.
+ */
+pub fn server_speaks(version: u32) -> bool {
+    PROTOCOL.load(Ordering::Relaxed) >= version
+}
+
+/**
+ * The OS the client runs on, e.g. "Debian GNU/Linux 12 (bookworm) linux x86_64".
+ */
+fn os_description() -> String {
+    let platform = format!("{} {}", std::env::consts::OS, std::env::consts::ARCH);
+    match whoami::distro() {
+        Ok(distro) => format!("{} {}", distro, platform),
+        Err(_) => platform,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClientLogin {
+    id: String,
+    token: String,
+    protocol: u32,
+    min_protocol: u32,
+    tags: String,
+    // what we run, unknown fields are left out
+    version: &'static str,
+    git_sha: Option<&'static str>,
+    os: String,
+    hostname: Option<String>,
+    // comma separated, see securelog_proto::capability
+    capabilities: String,
+}
+/**
+ * Log in and agree on a protocol version with the server. A server that
+ * speaks no version we do is an error, retrying will not help.
+ */
+fn capabilities() -> Vec<&'static str> {
+    let mut capabilities = vec![
+        capability::ARCHIVE,
+        capability::RUN_REPORTS,
+        capability::SETTINGS,
+        capability::INTEGRITY,
+    ];
+    if realtime::SUPPORTED {
+        capabilities.push(capability::REALTIME);
+    }
+    capabilities
+}
+
 pub fn login() -> Result<bool> {
     let server = conf::get_server()?;
     let id = conf::get_id()?;
     let token = conf::get_token()?;
-
-    let params = json!({
-        "id": id,
-        "token": token
-    });
+    // sent even when empty so tags removed from the config go away
+    let tags = format_tags(&conf::get_tags()?);
+
+    let params = ClientLogin {
+        id,
+        token,
+        protocol: PROTOCOL_VERSION,
+        min_protocol: MIN_PROTOCOL_VERSION,
+        tags,
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: option_env!("VERGEN_GIT_SHA"),
+        os: os_description(),
+        hostname: whoami::hostname().ok(),
+        capabilities: capabilities().join(","),
+    };
 
     let url = format!("{}/api/client/login", server);
 
@@ -42,8 +136,19 @@ pub fn login() -> Result<bool> {
     let text = result.text()?;
 
     match status {
-        StatusCode::OK => Ok(true),
+        StatusCode::OK => {
+            // servers from before version negotiation answer with text and speak version 1
+            let protocol = serde_json::from_str::<LoginResponse>(&text)
+                .map(|resp| resp.protocol)
+                .unwrap_or(1);
+            negotiate(protocol, protocol)?;
+            PROTOCOL.store(protocol, Ordering::Relaxed);
+            info!("logged in with protocol version {}", protocol);
+
+            Ok(true)
+        }
         StatusCode::UNAUTHORIZED => Ok(false),
+        StatusCode::UPGRADE_REQUIRED | StatusCode::BAD_REQUEST => Err(WebError::Server(text)),
         _ => {
             warn!("login: Unexpected status {}, text={}", status, text);
             Ok(false)
@@ -98,10 +203,29 @@ pub fn get_searches() -> Result<Vec<Search>> {
     }
 }
 
-pub fn send_search_results(result: &[SearchResult]) -> Result<bool> {
+#[derive(Debug, Serialize)]
+struct SendSearchResults {
+    results: String,
+    // base64 Ed25519 signature over `signature_time` and `results`
+    signature: Option<String>,
+    // unix time the signature was made at
+    signature_time: Option<i64>,
+}
+pub fn send_search_results(result: &[ClientSearchResult]) -> Result<bool> {
     let server = conf::get_server()?;
 
-    let params = json!({ "results": serde_json::to_string(&result)? });
+    let results = serde_json::to_string(&result)?;
+    let signed = signing::sign(results.as_bytes())?;
+    if signed.is_none() {
+        warn!("no signing_key configured, sending unsigned search results");
+    }
+    let (signature, signature_time) = signed.map(|signed| (signed.signature, signed.time)).unzip();
+
+    let params = SendSearchResults {
+        results,
+        signature,
+        signature_time,
+    };
 
     let url = format!("{}/api/client/send_search_results", server);
 
@@ -119,6 +243,122 @@ pub fn send_search_results(result: &[SearchResult]) -> Result<bool> {
     }
 }
 
+#[derive(Debug, Serialize)]
+struct SendRunReports {
+    reports: String,
+    // base64 Ed25519 signature over `signature_time` and `reports`
+    signature: Option<String>,
+    // unix time the signature was made at
+    signature_time: Option<i64>,
+}
+/**
+ * Send how every search went in a run, signed like search results.
+ */
+pub fn send_run_reports(reports: &[SearchRunReport]) -> Result<bool> {
+    let server = conf::get_server()?;
+
+    let reports = serde_json::to_string(&reports)?;
+    let signed = signing::sign(reports.as_bytes())?;
+    let (signature, signature_time) = signed.map(|signed| (signed.signature, signed.time)).unzip();
+
+    let params = SendRunReports {
+        reports,
+        signature,
+        signature_time,
+    };
+
+    let url = format!("{}/api/client/send_run_reports", server);
+
+    let result = CLIENT.post(&url).form(&params).send()?;
+
+    if result.status() != StatusCode::OK {
+        warn!(
+            "send_run_reports: unexpected server error: {}, text={}",
+            result.status(),
+            result.text()?
+        );
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+/**
+ * Set a raw body, with the signature and its time in the headers.
+ */
+fn signed_body(request: RequestBuilder, body: Vec<u8>) -> Result<RequestBuilder> {
+    let signed = signing::sign(&body)?;
+    let mut request = request.body(body);
+    if let Some(signed) = signed {
+        request = request
+            .header(SIGNATURE_HEADER, signed.signature)
+            .header(SIGNATURE_TIME_HEADER, signed.time);
+    }
+
+    Ok(request)
+}
+
+/**
+ * Send the state of the watched files as zstd compressed json, signed like
+ * archive segments. A snapshot has an entry for every watched file, far
+ * more than fits in a form.
+ */
+pub fn send_integrity_snapshot(snapshot: &IntegritySnapshot) -> Result<bool> {
+    let server = conf::get_server()?;
+
+    let compressed = zstd::encode_all(serde_json::to_vec(snapshot)?.as_slice(), ZSTD_LEVEL)?;
+
+    let url = format!("{}/api/client/send_integrity_snapshot", server);
+
+    let request = signed_body(CLIENT.post(&url), compressed)?;
+
+    let result = request.send()?;
+
+    if result.status() != StatusCode::OK {
+        warn!(
+            "send_integrity_snapshot: unexpected server error: {}, text={}",
+            result.status(),
+            result.text()?
+        );
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+/**
+ * Send a zstd compressed raw log segment to the archive.
+ * Returns false if the server doesn't keep an archive.
+ */
+pub fn send_archive_segment(location: &str, offset: u64, compressed: &[u8]) -> Result<bool> {
+    let server = conf::get_server()?;
+
+    let url = format!("{}/api/client/archive_segment", server);
+
+    let request = signed_body(
+        CLIENT
+            .post(&url)
+            .query(&[("location", location), ("offset", &offset.to_string())]),
+        compressed.to_vec(),
+    )?;
+
+    let result = request.send()?;
+    let status = result.status();
+    let text = result.text()?;
+
+    match status {
+        StatusCode::OK => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        _ => {
+            warn!(
+                "send_archive_segment: unexpected status {}, text={}",
+                status, text
+            );
+            Ok(false)
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ClientAuth {
     pub id: String,
@@ -129,11 +369,13 @@ pub fn create_client_token(
     name: &str,
     username: &str,
     password: &str,
+    pubkey: &str,
 ) -> Result<ClientAuth> {
     let params = json!({
         "name": name,
         "username": username,
         "password": password,
+        "pubkey": pubkey,
     });
 
     let url = format!("{}/api/client/create", server);
@@ -160,10 +402,54 @@ pub fn create_client_token(
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ClientShouldRun {
-    pub should_run: bool,
+/**
+ * Create a client with an enrollment token an admin minted, instead of
+ * admin credentials. The server's reason is returned if it refuses.
+ */
+pub fn enroll_client(server: &str, token: &str, name: &str, pubkey: &str) -> Result<ClientAuth> {
+    let params = json!({
+        "token": token,
+        "name": name,
+        "pubkey": pubkey,
+    });
+
+    let url = format!("{}/api/client/enroll", server);
+
+    let result = CLIENT.post(&url).form(&params).send()?;
+    let status = result.status();
+    let text = result.text()?;
+
+    match status {
+        StatusCode::OK => Ok(serde_json::from_str(&text)?),
+        _ => Err(WebError::Server(format!("{}: {}", status, text))),
+    }
 }
+
+/**
+ * Register the public key of a client created before result signing, with
+ * the client's id and token. The server's reason is returned if it refuses,
+ * it never replaces a key already registered.
+ */
+pub fn register_key(pubkey: &str) -> Result<()> {
+    let server = conf::get_server()?;
+    let params = json!({
+        "id": conf::get_id()?,
+        "token": conf::get_token()?,
+        "pubkey": pubkey,
+    });
+
+    let url = format!("{}/api/client/register_key", server);
+
+    let result = CLIENT.post(&url).form(&params).send()?;
+    let status = result.status();
+    let text = result.text()?;
+
+    match status {
+        StatusCode::OK => Ok(()),
+        _ => Err(WebError::Server(format!("{}: {}", status, text))),
+    }
+}
+
 pub fn get_should_run() -> Result<bool> {
     let server = conf::get_server()?;
 
@@ -183,7 +469,11 @@ pub fn get_should_run() -> Result<bool> {
             Ok(false)
         }
         StatusCode::OK => {
-            let resp: ClientShouldRun = serde_json::from_str(&text)?;
+            let resp: ShouldRunResponse = serde_json::from_str(&text)?;
+            // older servers send no settings, keep the ones we have
+            if let Some(pushed) = resp.settings {
+                settings::apply(pushed);
+            }
 
             Ok(resp.should_run)
         }
@@ -0,0 +1,860 @@
+/*!
+Types the client and server exchange.
+
+Everything that goes over the wire between a client and the server is
+defined here so both sides serialize it the same way. PROTOCOL_VERSION is
+bumped whenever a change breaks older peers, client and server agree on a
+version at login, see negotiate.
+*/
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate thiserror;
+
+use chrono::{DateTime, Utc};
+
+/**
+ * The newest protocol version this build speaks.
+ *
+ * 1: search results
+ * 2: clients send run reports, see SearchRunReport
+ * 3: clients send file integrity snapshots, see IntegritySnapshot
+ */
+pub const PROTOCOL_VERSION: u32 = 3;
+/**
+ * The oldest protocol version this build still speaks.
+ */
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/**
+ * The first protocol version with run reports.
+ */
+pub const RUN_REPORTS_VERSION: u32 = 2;
+/**
+ * The first protocol version with file integrity snapshots.
+ */
+pub const INTEGRITY_VERSION: u32 = 3;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ProtocolError {
+    #[error("incompatible protocol: we speak versions {min}-{max}, peer speaks {peer_min}-{peer}")]
+    Incompatible {
+        min: u32,
+        max: u32,
+        peer_min: u32,
+        peer: u32,
+    },
+
+    #[error("invalid tag {0:?}, tags are key=value of letters, digits and _-./:")]
+    InvalidTag(String),
+
+    #[error("tag {0} is given twice")]
+    DuplicateTag(String),
+
+    #[error("invalid client setting: {0}")]
+    InvalidSetting(String),
+}
+
+/**
+ * What a client build can do, sent at login.
+ */
+pub mod capability {
+    // sends raw log segments when archive is enabled
+    pub const ARCHIVE: &str = "archive";
+    // sends SearchRunReports
+    pub const RUN_REPORTS: &str = "run_reports";
+    // applies the ClientSettings it polls
+    pub const SETTINGS: &str = "settings";
+    // follows realtime searches between runs
+    pub const REALTIME: &str = "realtime";
+    // sends IntegritySnapshots of the paths it is set to watch
+    pub const INTEGRITY: &str = "integrity";
+}
+
+/**
+ * Clients with a signing key sign the unix time along with every payload.
+ * The server refuses signatures made further than this many seconds from
+ * its own clock, and signatures it has already seen, so a captured request
+ * can't be replayed.
+ */
+pub const MAX_SIGNATURE_AGE: i64 = 300;
+/**
+ * Headers carrying the signature and its time on requests with a raw body,
+ * form posts send them as the `signature` and `signature_time` fields.
+ */
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const SIGNATURE_TIME_HEADER: &str = "X-Signature-Time";
+
+/**
+ * The bytes a signature covers, the time it was made followed by the payload.
+ */
+pub fn signed_message(time: i64, payload: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n", time).into_bytes();
+    message.extend_from_slice(payload);
+    message
+}
+
+/**
+ * Pick the version to talk to a peer that speaks `peer_min` to `peer`, the
+ * newest version both sides speak.
+ */
+pub fn negotiate(peer: u32, peer_min: u32) -> Result<u32, ProtocolError> {
+    let version = peer.min(PROTOCOL_VERSION);
+    if version < peer_min || version < MIN_PROTOCOL_VERSION {
+        return Err(ProtocolError::Incompatible {
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+            peer_min,
+            peer,
+        });
+    }
+
+    Ok(version)
+}
+
+/**
+ * Whether `word` can be a tag key or value. Tags are sent comma separated
+ * and used in search selectors, so they are limited to characters that
+ * mean nothing in either.
+ */
+pub fn is_tag_word(word: &str) -> bool {
+    !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':'))
+}
+
+/**
+ * Parse one key=value tag.
+ */
+pub fn parse_tag(tag: &str) -> Result<(String, String), ProtocolError> {
+    match tag.trim().split_once('=') {
+        Some((key, value)) if is_tag_word(key.trim()) && is_tag_word(value.trim()) => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(ProtocolError::InvalidTag(tag.to_string())),
+    }
+}
+
+/**
+ * Parse tags the way a client sends them at login, comma separated
+ * key=value. An empty string is no tags.
+ */
+pub fn parse_tags(tags: &str) -> Result<Vec<(String, String)>, ProtocolError> {
+    let mut parsed: Vec<(String, String)> = Vec::new();
+    for tag in tags.split(',').filter(|tag| !tag.trim().is_empty()) {
+        let (key, value) = parse_tag(tag)?;
+        if parsed.iter().any(|(k, _)| *k == key) {
+            return Err(ProtocolError::DuplicateTag(key));
+        }
+        parsed.push((key, value));
+    }
+
+    Ok(parsed)
+}
+
+pub fn format_tags(tags: &[(String, String)]) -> String {
+    tags.iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/**
+ * What the server answers a client login with.
+ */
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct LoginResponse {
+    // the negotiated version
+    pub protocol: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub enum SearchType {
+    // regex crate syntax, matches anywhere in the line
+    Regex,
+    // case sensitive substring
+    Contains,
+    // * and ? against the whole line
+    Wildcard,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Search {
+    pub id: i32,
+    pub name: String,
+    pub stype: SearchType,
+    pub search: String,
+    pub locations: Vec<String>,
+    // disabled searches are not sent to clients
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // tags a client needs to get the search, None for every client
+    #[serde(default)]
+    pub selector: Option<String>,
+    // clients follow the locations and send matches as they are written
+    #[serde(default)]
+    pub realtime: bool,
+}
+impl Search {
+    /**
+     * A search that runs with the scheduled runs, not in realtime.
+     */
+    pub fn new(
+        id: i32,
+        name: String,
+        stype: SearchType,
+        search: String,
+        locations: Vec<String>,
+        enabled: bool,
+        selector: Option<String>,
+    ) -> Search {
+        Search {
+            id,
+            name,
+            stype,
+            search,
+            locations,
+            enabled,
+            selector,
+            realtime: false,
+        }
+    }
+}
+
+/**
+ * The lines one search found in one location, as a client sends them.
+ */
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ClientSearchResult {
+    pub search_id: i32,
+    pub search_name: String,
+    pub found: Vec<String>,
+    pub location: String,
+    pub started: DateTime<Utc>,
+    // a resource limit stopped the search before the end of the location,
+    // older clients do not send it
+    #[serde(default)]
+    pub truncated: bool,
+}
+impl ClientSearchResult {
+    pub fn new(id: i32, name: &str, location: &str) -> ClientSearchResult {
+        ClientSearchResult {
+            search_id: id,
+            search_name: name.to_owned(),
+            location: location.to_string(),
+            found: Vec::new(),
+            started: Utc::now(),
+            truncated: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub enum RunErrorKind {
+    // the location does not exist
+    Missing,
+    PermissionDenied,
+    // the search itself is broken, i.e. a bad regex
+    InvalidSearch,
+    // any other error reading the location
+    Io,
+}
+
+/**
+ * Why a search failed on a location.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct RunError {
+    pub kind: RunErrorKind,
+    pub message: String,
+}
+
+/**
+ * How one search went over one location in a client run, sent whether or
+ * not anything was found.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchRunReport {
+    pub search_id: i32,
+    pub location: String,
+    pub started: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub lines_scanned: u64,
+    pub bytes_read: u64,
+    // None if the search ran
+    pub error: Option<RunError>,
+}
+impl SearchRunReport {
+    pub fn new(search_id: i32, location: &str) -> SearchRunReport {
+        SearchRunReport {
+            search_id,
+            location: location.to_string(),
+            started: Utc::now(),
+            duration_ms: 0,
+            lines_scanned: 0,
+            bytes_read: 0,
+            error: None,
+        }
+    }
+}
+
+// log levels a client can be set to
+pub const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+// bounds for the poll interval, in seconds
+pub const MIN_POLL_INTERVAL: u64 = 10;
+pub const MAX_POLL_INTERVAL: u64 = 24 * 60 * 60;
+// lowest read rate a client can be throttled to, in bytes per second
+pub const MIN_READ_RATE: u64 = 4096;
+
+/**
+ * Limits on what one client run may use.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ResourceLimits {
+    // bytes read over all searches
+    #[serde(default)]
+    pub max_run_bytes: Option<u64>,
+    // wall time of the whole run, in seconds
+    #[serde(default)]
+    pub max_run_secs: Option<u64>,
+    // bytes one search reads over all its locations
+    #[serde(default)]
+    pub max_search_bytes: Option<u64>,
+    // lines one search returns over all its locations
+    #[serde(default)]
+    pub max_search_lines: Option<u64>,
+    // wall time of one search, in seconds
+    #[serde(default)]
+    pub max_search_secs: Option<u64>,
+    // bytes read per second, the client sleeps to stay under it
+    #[serde(default)]
+    pub max_read_rate: Option<u64>,
+}
+impl ResourceLimits {
+    /**
+     * These limits with the ones `over` sets replacing them.
+     */
+    pub fn merge(&self, over: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            max_run_bytes: over.max_run_bytes.or(self.max_run_bytes),
+            max_run_secs: over.max_run_secs.or(self.max_run_secs),
+            max_search_bytes: over.max_search_bytes.or(self.max_search_bytes),
+            max_search_lines: over.max_search_lines.or(self.max_search_lines),
+            max_search_secs: over.max_search_secs.or(self.max_search_secs),
+            max_read_rate: over.max_read_rate.or(self.max_read_rate),
+        }
+    }
+
+    /**
+     * The stricter of these limits and `other`, field by field. A limit
+     * only one side sets is kept.
+     */
+    pub fn strictest(&self, other: &ResourceLimits) -> ResourceLimits {
+        let min = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        ResourceLimits {
+            max_run_bytes: min(self.max_run_bytes, other.max_run_bytes),
+            max_run_secs: min(self.max_run_secs, other.max_run_secs),
+            max_search_bytes: min(self.max_search_bytes, other.max_search_bytes),
+            max_search_lines: min(self.max_search_lines, other.max_search_lines),
+            max_search_secs: min(self.max_search_secs, other.max_search_secs),
+            max_read_rate: min(self.max_read_rate, other.max_read_rate),
+        }
+    }
+}
+
+/**
+ * Settings the server manages for its clients, they apply them without a
+ * restart. None leaves the client's own config in charge.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ClientSettings {
+    // one of LOG_LEVELS
+    #[serde(default)]
+    pub log_level: Option<String>,
+    // seconds between asking the server whether to run
+    #[serde(default)]
+    pub poll_interval: Option<u64>,
+    #[serde(default)]
+    pub limits: ResourceLimits,
+}
+impl ClientSettings {
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        if let Some(level) = &self.log_level {
+            if !LOG_LEVELS.contains(&level.as_str()) {
+                return Err(ProtocolError::InvalidSetting(format!(
+                    "log level {:?} is not one of {}",
+                    level,
+                    LOG_LEVELS.join(", ")
+                )));
+            }
+        }
+        if let Some(interval) = self.poll_interval {
+            if !(MIN_POLL_INTERVAL..=MAX_POLL_INTERVAL).contains(&interval) {
+                return Err(ProtocolError::InvalidSetting(format!(
+                    "poll interval {} is not between {} and {} seconds",
+                    interval, MIN_POLL_INTERVAL, MAX_POLL_INTERVAL
+                )));
+            }
+        }
+        if let Some(rate) = self.limits.max_read_rate {
+            if rate < MIN_READ_RATE {
+                return Err(ProtocolError::InvalidSetting(format!(
+                    "read rate {} is below {} bytes per second",
+                    rate, MIN_READ_RATE
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * These settings with the ones `over` sets replacing them.
+     */
+    pub fn merge(&self, over: &ClientSettings) -> ClientSettings {
+        ClientSettings {
+            log_level: over.log_level.clone().or_else(|| self.log_level.clone()),
+            poll_interval: over.poll_interval.or(self.poll_interval),
+            limits: self.limits.merge(&over.limits),
+        }
+    }
+}
+
+/**
+ * What the server answers a client asking whether to run.
+ */
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ShouldRunResponse {
+    pub should_run: bool,
+    // older servers send no settings
+    #[serde(default)]
+    pub settings: Option<ClientSettings>,
+}
+
+// st_mode file type bits
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+
+/**
+ * Whether `path` is one of `dirs` or below one of them.
+ */
+pub fn is_under(path: &str, dirs: &[String]) -> bool {
+    dirs.iter().any(|dir| {
+        let dir = dir.trim_end_matches('/');
+        path == dir
+            || path
+                .strip_prefix(dir)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+/**
+ * A file in a file integrity snapshot.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FileState {
+    pub path: String,
+    // st_mode, the file type and permission bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub mtime: DateTime<Utc>,
+    // hex sha256 of the content, regular files only
+    pub sha256: Option<String>,
+    // where a symlink points
+    pub link: Option<String>,
+}
+impl FileState {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /**
+     * The names of the fields that differ from `before`. The size and mtime
+     * of a directory change with its entries, which are in the snapshot
+     * themselves, so they are left out.
+     */
+    pub fn changes(&self, before: &FileState) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.sha256 != before.sha256 {
+            changed.push("sha256");
+        }
+        if self.link != before.link {
+            changed.push("link");
+        }
+        if self.mode != before.mode {
+            changed.push("mode");
+        }
+        if self.uid != before.uid {
+            changed.push("uid");
+        }
+        if self.gid != before.gid {
+            changed.push("gid");
+        }
+        if !(self.is_dir() && before.is_dir()) {
+            if self.size != before.size {
+                changed.push("size");
+            }
+            if self.mtime != before.mtime {
+                changed.push("mtime");
+            }
+        }
+
+        changed
+    }
+}
+
+/**
+ * The files under the paths a client watches, sent after each run. The
+ * server compares it with the client's last one.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IntegritySnapshot {
+    pub started: DateTime<Utc>,
+    // the paths the client is set to watch, directories are walked
+    pub paths: Vec<String>,
+    pub files: Vec<FileState>,
+    // paths that could not be read, what is known of them is kept
+    pub unreadable: Vec<String>,
+}
+impl IntegritySnapshot {
+    pub fn watches(&self, path: &str) -> bool {
+        is_under(path, &self.paths)
+    }
+
+    pub fn is_unreadable(&self, path: &str) -> bool {
+        is_under(path, &self.unreadable)
+    }
+}
+
+/**
+ * A result as the server stored it, with the client that sent it.
+ */
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SearchResult {
+    pub client_id: String,
+    pub client_name: String,
+    pub search_id: i32,
+    pub search_name: String,
+    pub found: Vec<String>,
+    pub location: String,
+    pub started: DateTime<Utc>,
+    pub signature: Option<String>,
+    pub truncated: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::fmt::Debug;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T) {
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+    }
+
+    fn started() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 19, 8, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn search_round_trips() {
+        for stype in [
+            SearchType::Regex,
+            SearchType::Contains,
+            SearchType::Wildcard,
+        ] {
+            round_trip(&Search::new(
+                7,
+                "ssh failures".to_string(),
+                stype,
+                "Failed password".to_string(),
+                vec!["/var/log/auth.log".to_string()],
+                false,
+                Some("role=web".to_string()),
+            ));
+        }
+    }
+
+    #[test]
+    fn results_round_trip() {
+        round_trip(&ClientSearchResult {
+            search_id: 7,
+            search_name: "ssh failures".to_string(),
+            found: vec!["Oct 19 sshd[1]: Failed password for root".to_string()],
+            location: "/var/log/auth.log".to_string(),
+            started: started(),
+            truncated: true,
+        });
+        round_trip(&SearchResult {
+            client_id: "abc".to_string(),
+            client_name: "web1".to_string(),
+            search_id: 7,
+            search_name: "ssh failures".to_string(),
+            found: Vec::new(),
+            location: "/var/log/auth.log".to_string(),
+            started: started(),
+            signature: None,
+            truncated: false,
+        });
+        round_trip(&LoginResponse { protocol: 1 });
+    }
+
+    #[test]
+    fn run_reports_round_trip() {
+        let mut report = SearchRunReport::new(7, "/var/log/auth.log");
+        report.started = started();
+        report.lines_scanned = 120;
+        report.bytes_read = 8192;
+        round_trip(&report);
+
+        report.error = Some(RunError {
+            kind: RunErrorKind::PermissionDenied,
+            message: "Permission denied (os error 13)".to_string(),
+        });
+        round_trip(&report);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap()["error"]["kind"],
+            "PermissionDenied"
+        );
+    }
+
+    #[test]
+    fn wire_format_is_stable() {
+        assert_eq!(
+            serde_json::to_string(&SearchType::Wildcard).unwrap(),
+            "\"Wildcard\""
+        );
+
+        // servers from before searches could be disabled
+        let search: Search = serde_json::from_str(
+            r#"{"id":1,"name":"n","stype":"Regex","search":"s","locations":[]}"#,
+        )
+        .unwrap();
+        assert!(search.enabled);
+        assert_eq!(search.selector, None);
+        assert!(!search.realtime);
+
+        let result = serde_json::to_value(ClientSearchResult {
+            search_id: 1,
+            search_name: "n".to_string(),
+            found: vec!["line".to_string()],
+            location: "/l".to_string(),
+            started: started(),
+            truncated: false,
+        })
+        .unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "search_id": 1,
+                "search_name": "n",
+                "found": ["line"],
+                "location": "/l",
+                "started": "2026-10-19T08:30:00Z",
+                "truncated": false,
+            })
+        );
+
+        // clients from before resource limits
+        let result: ClientSearchResult = serde_json::from_str(
+            r#"{"search_id":1,"search_name":"n","found":[],"location":"/l","started":"2026-10-19T08:30:00Z"}"#,
+        )
+        .unwrap();
+        assert!(!result.truncated);
+    }
+
+    #[test]
+    fn the_strictest_limits_win() {
+        let local = ResourceLimits {
+            max_run_bytes: Some(1 << 20),
+            max_run_secs: Some(600),
+            max_search_lines: Some(100),
+            ..Default::default()
+        };
+        let server = ResourceLimits {
+            max_run_bytes: Some(1 << 30),
+            max_run_secs: Some(60),
+            max_read_rate: Some(1 << 10),
+            ..Default::default()
+        };
+
+        let limits = local.strictest(&server);
+        assert_eq!(limits, server.strictest(&local));
+        assert_eq!(limits.max_run_bytes, Some(1 << 20));
+        assert_eq!(limits.max_run_secs, Some(60));
+        assert_eq!(limits.max_search_lines, Some(100));
+        assert_eq!(limits.max_read_rate, Some(1 << 10));
+        assert_eq!(limits.max_search_bytes, None);
+    }
+
+    #[test]
+    fn settings_merge_and_validate() {
+        let defaults = ClientSettings {
+            log_level: Some("info".to_string()),
+            poll_interval: Some(60),
+            limits: ResourceLimits {
+                max_run_bytes: Some(1 << 30),
+                max_read_rate: Some(1 << 20),
+                ..Default::default()
+            },
+        };
+        let client = ClientSettings {
+            log_level: Some("debug".to_string()),
+            limits: ResourceLimits {
+                max_run_secs: Some(300),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let merged = defaults.merge(&client);
+        assert_eq!(merged.log_level.as_deref(), Some("debug"));
+        assert_eq!(merged.poll_interval, Some(60));
+        assert_eq!(merged.limits.max_run_bytes, Some(1 << 30));
+        assert_eq!(merged.limits.max_run_secs, Some(300));
+        assert_eq!(merged.limits.max_read_rate, Some(1 << 20));
+        assert!(merged.validate().is_ok());
+
+        let loud = ClientSettings {
+            log_level: Some("loud".to_string()),
+            ..Default::default()
+        };
+        assert!(loud.validate().is_err());
+        let hasty = ClientSettings {
+            poll_interval: Some(1),
+            ..Default::default()
+        };
+        assert!(hasty.validate().is_err());
+        let slow = ClientSettings {
+            limits: ResourceLimits {
+                max_read_rate: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(slow.validate().is_err());
+
+        // servers from before settings
+        let resp: ShouldRunResponse = serde_json::from_str(r#"{"should_run":true}"#).unwrap();
+        assert_eq!(resp.settings, None);
+        round_trip(&ShouldRunResponse {
+            should_run: false,
+            settings: Some(merged),
+        });
+    }
+
+    fn file(path: &str, mode: u32, sha256: Option<&str>) -> FileState {
+        FileState {
+            path: path.to_string(),
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 10,
+            mtime: started(),
+            sha256: sha256.map(str::to_string),
+            link: None,
+        }
+    }
+
+    #[test]
+    fn file_states_compare() {
+        let passwd = file("/etc/passwd", 0o100644, Some("aa"));
+        assert!(passwd.changes(&passwd).is_empty());
+
+        let mut edited = passwd.clone();
+        edited.sha256 = Some("bb".to_string());
+        edited.mode = 0o100666;
+        edited.mtime = started() + chrono::Duration::seconds(1);
+        assert_eq!(edited.changes(&passwd), vec!["sha256", "mode", "mtime"]);
+
+        // a directory's size and mtime follow its entries
+        let dir = file("/etc/sudoers.d", 0o040750, None);
+        let mut grown = dir.clone();
+        grown.size = 4096;
+        grown.mtime = started() + chrono::Duration::seconds(1);
+        assert!(dir.is_dir());
+        assert!(grown.changes(&dir).is_empty());
+        grown.uid = 1000;
+        assert_eq!(grown.changes(&dir), vec!["uid"]);
+
+        let snapshot = IntegritySnapshot {
+            started: started(),
+            paths: vec!["/etc/sudoers.d/".to_string(), "/usr/bin/sudo".to_string()],
+            files: vec![dir, passwd],
+            unreadable: Vec::new(),
+        };
+        assert!(snapshot.watches("/etc/sudoers.d"));
+        assert!(snapshot.watches("/etc/sudoers.d/admins"));
+        assert!(!snapshot.watches("/etc/sudoers.dpkg-old"));
+        assert!(!snapshot.watches("/usr/bin/sudoedit"));
+        assert!(is_under("/etc/passwd", &["/".to_string()]));
+        round_trip(&snapshot);
+    }
+
+    #[test]
+    fn negotiates_newest_common_version() {
+        assert_eq!(
+            negotiate(PROTOCOL_VERSION, MIN_PROTOCOL_VERSION),
+            Ok(PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate(PROTOCOL_VERSION + 5, 1), Ok(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn refuses_incompatible_peers() {
+        let err = negotiate(PROTOCOL_VERSION + 5, PROTOCOL_VERSION + 1).unwrap_err();
+        assert_eq!(
+            err,
+            ProtocolError::Incompatible {
+                min: MIN_PROTOCOL_VERSION,
+                max: PROTOCOL_VERSION,
+                peer_min: PROTOCOL_VERSION + 1,
+                peer: PROTOCOL_VERSION + 5,
+            }
+        );
+        assert!(negotiate(0, 0).is_err());
+    }
+
+    #[test]
+    fn tags_parse_and_format() {
+        let tags = parse_tags(" role=web, env = prod ,,").unwrap();
+        assert_eq!(
+            tags,
+            vec![
+                ("role".to_string(), "web".to_string()),
+                ("env".to_string(), "prod".to_string())
+            ]
+        );
+        assert_eq!(format_tags(&tags), "role=web,env=prod");
+        assert_eq!(parse_tags("").unwrap(), Vec::new());
+
+        assert!(parse_tags("role").is_err());
+        assert!(parse_tags("role=").is_err());
+        assert!(parse_tags("role=web|db").is_err());
+        assert_eq!(
+            parse_tags("role=web,role=db"),
+            Err(ProtocolError::DuplicateTag("role".to_string()))
+        );
+    }
+}
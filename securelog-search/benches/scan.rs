@@ -0,0 +1,158 @@
+/*
+Compares reading a file once per search, as clients did, to reading it once
+for a MatcherSet of all of them, and scanning files one after the other to
+scanning them on a bounded pool.
+
+    cargo bench -p securelog-search
+*/
+use securelog_search::{map_bounded, Budget, Matcher, MatcherSet, SearchType};
+use std::fs;
+use std::hint::black_box;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const LINES: usize = 200_000;
+const FILES: usize = 8;
+const ROUNDS: u32 = 3;
+
+fn searches() -> Vec<(SearchType, &'static str)> {
+    vec![
+        (SearchType::Contains, "Failed password"),
+        (SearchType::Contains, "session opened"),
+        (SearchType::Contains, "segfault"),
+        (SearchType::Contains, "Out of memory"),
+        (SearchType::Contains, "authentication failure"),
+        (SearchType::Contains, "link down"),
+        (SearchType::Contains, "COMMAND=/bin/su"),
+        (SearchType::Contains, "root"),
+        (SearchType::Regex, r"sshd\[\d+\]: Invalid user \w+"),
+        (SearchType::Regex, r"(?i)error|critical"),
+        (SearchType::Regex, r"from 10\.\d+\.\d+\.\d+ port"),
+        (SearchType::Regex, r"user (\w+) by \(uid=0\)"),
+        (SearchType::Regex, r"GET /\S+ 5\d\d"),
+        (SearchType::Regex, r"handled in \d{4,}ms"),
+        (
+            SearchType::Regex,
+            r"USER=root ; COMMAND=/usr/bin/(passwd|visudo)",
+        ),
+        (SearchType::Regex, r"kernel: \[\d+\.\d+\] \w+: link down"),
+        (SearchType::Regex, r"^Oct \d+ \S+ \S+ nginx\[\d+\]: 10\."),
+        (SearchType::Regex, r"port (22|2222) ssh2$"),
+        (SearchType::Regex, r"(?i)permission denied"),
+        (SearchType::Regex, r"Accepted (password|publickey) for \w+"),
+        (SearchType::Wildcard, "* sudo: * COMMAND=*"),
+        (SearchType::Wildcard, "*segfault at *"),
+    ]
+}
+
+fn log(lines: usize) -> String {
+    let templates = [
+        "Oct 19 08:30:01 web01 sshd[{}]: Failed password for root from 10.0.3.{} port 52144 ssh2",
+        "Oct 19 08:30:02 web01 CRON[{}]: pam_unix(cron:session): session opened for user root by (uid={})",
+        "Oct 19 08:30:03 web01 kernel: [{}.{}] eth0: link up, 1000Mbps, full-duplex",
+        "Oct 19 08:30:04 web01 sudo: admin : TTY=pts/{} ; PWD=/home/admin ; USER=root ; COMMAND=/usr/bin/ls {}",
+        "Oct 19 08:30:05 web01 nginx[{}]: 192.168.1.{} GET /index.html 200",
+        "Oct 19 08:30:06 web01 sshd[{}]: Invalid user test{} from 172.16.0.1",
+        "Oct 19 08:30:07 web01 app[{}]: request {} handled in 12ms",
+    ];
+
+    let mut text = String::new();
+    for line in 0..lines {
+        let template = templates[line % templates.len()];
+        text.push_str(&template.replacen("{}", &line.to_string(), 1).replacen(
+            "{}",
+            &(line % 250).to_string(),
+            1,
+        ));
+        text.push('\n');
+    }
+    text
+}
+
+/**
+ * Best of ROUNDS runs.
+ */
+fn time(mut run: impl FnMut()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, baseline: Duration, improved: Duration, bytes: usize) {
+    let rate = |duration: Duration| bytes as f64 / duration.as_secs_f64() / (1 << 20) as f64;
+    println!(
+        "{}: {:?} ({:.0} MiB/s) -> {:?} ({:.0} MiB/s), {:.1}x",
+        name,
+        baseline,
+        rate(baseline),
+        improved,
+        rate(improved),
+        baseline.as_secs_f64() / improved.as_secs_f64()
+    );
+}
+
+fn main() {
+    let searches = searches();
+    let refs: Vec<(&SearchType, &str)> = searches.iter().map(|(t, s)| (t, *s)).collect();
+    let matchers: Vec<Matcher> = searches
+        .iter()
+        .map(|(stype, search)| Matcher::new(stype, search).unwrap())
+        .collect();
+    let set = MatcherSet::new(&refs).unwrap();
+    let budgets = vec![Budget::default(); set.len()];
+
+    let text = log(LINES);
+    let dir = std::env::temp_dir().join(format!("securelog_bench_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let files: Vec<PathBuf> = (0..FILES)
+        .map(|file| {
+            let path = dir.join(format!("{}.log", file));
+            fs::write(&path, &text).unwrap();
+            path
+        })
+        .collect();
+    let open = |path: &PathBuf| BufReader::new(fs::File::open(path).unwrap());
+
+    let separate = time(|| {
+        for matcher in &matchers {
+            black_box(matcher.scan(open(&files[0])).unwrap());
+        }
+    });
+    let one_pass = time(|| {
+        black_box(set.scan_within(open(&files[0]), &budgets).unwrap());
+    });
+    report(
+        &format!("{} searches, one pass", searches.len()),
+        separate,
+        one_pass,
+        text.len(),
+    );
+
+    let scan_file = |path: &PathBuf| {
+        black_box(set.scan_within(open(path), &budgets).unwrap());
+    };
+    let threads = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1)
+        .min(FILES);
+    let sequential = time(|| {
+        map_bounded(&files, 1, scan_file);
+    });
+    let parallel = time(|| {
+        map_bounded(&files, threads, scan_file);
+    });
+    report(
+        &format!("{} files, {} threads", FILES, threads),
+        sequential,
+        parallel,
+        text.len() * FILES,
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,80 @@
+/*
+Command locations.
+
+A location command://<name> searches the output of a command the client
+runs. The server only names the command: what runs for a name is set in
+the client's own config and a name it does not have is not run, so a
+server cannot make clients run anything they were not set up to.
+*/
+use std::fmt;
+
+pub const COMMAND_SCHEME: &str = "command://";
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    // the location does not start with command://
+    NotCommand(String),
+    Name(String),
+}
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotCommand(location) => {
+                write!(f, "{} is not a command location", location)
+            }
+            CommandError::Name(name) => write!(
+                f,
+                "command name {:?} is not letters, digits, _, - and .",
+                name
+            ),
+        }
+    }
+}
+impl std::error::Error for CommandError {}
+
+pub fn is_command(location: &str) -> bool {
+    location.starts_with(COMMAND_SCHEME)
+}
+
+/**
+ * The name of the command a location runs.
+ */
+pub fn command_name(location: &str) -> Result<&str, CommandError> {
+    let name = location
+        .strip_prefix(COMMAND_SCHEME)
+        .ok_or_else(|| CommandError::NotCommand(location.to_string()))?;
+
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(name)
+    } else {
+        Err(CommandError::Name(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parse() {
+        assert!(is_command("command://last"));
+        assert!(!is_command("/var/log/command"));
+        assert_eq!(command_name("command://dpkg-audit"), Ok("dpkg-audit"));
+        assert_eq!(
+            command_name("/bin/ss"),
+            Err(CommandError::NotCommand(String::from("/bin/ss")))
+        );
+        assert_eq!(
+            command_name("command://"),
+            Err(CommandError::Name(String::new()))
+        );
+        assert_eq!(
+            command_name("command://ss -tlnp"),
+            Err(CommandError::Name(String::from("ss -tlnp")))
+        );
+    }
+}
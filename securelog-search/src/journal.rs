@@ -0,0 +1,434 @@
+/*
+systemd journal locations.
+
+A location starting with journal:// searches the journal instead of a file,
+what follows are filters separated by commas, every filter has to match:
+
+    journal://                      every entry
+    journal://unit=sshd.service     entries of a unit, _SYSTEMD_UNIT
+    journal://identifier=sudo       SYSLOG_IDENTIFIER
+    journal://priority<=3           err and more urgent, 0-7 or a name
+    journal://_UID=0                any journal field
+
+Filters on the same field match either value, as journalctl does. Clients
+read the journal with `journalctl --output=export`, JournalLines turns its
+entries into lines so searches match them like the lines of a file.
+*/
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+pub const JOURNAL_SCHEME: &str = "journal://";
+
+// fields shown after the message of a matching entry
+const REPORTED_FIELDS: &[&str] = &[
+    "_SYSTEMD_UNIT",
+    "SYSLOG_IDENTIFIER",
+    "_PID",
+    "_UID",
+    "_COMM",
+    "PRIORITY",
+    "__REALTIME_TIMESTAMP",
+];
+
+// journald does not store bigger fields, a bigger size is a corrupt stream
+const MAX_FIELD_SIZE: u64 = 64 * 1024 * 1024;
+
+pub(crate) const PRIORITIES: &[&str] = &[
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum JournalError {
+    // the location does not start with journal://
+    NotJournal(String),
+    Filter(String),
+    Priority(String),
+    Field(String),
+}
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::NotJournal(location) => {
+                write!(f, "{} is not a journal location", location)
+            }
+            JournalError::Filter(filter) => {
+                write!(f, "journal filter {} is not field=value", filter)
+            }
+            JournalError::Priority(priority) => write!(
+                f,
+                "journal priority {} is not 0-7 or one of {}",
+                priority,
+                PRIORITIES.join(", ")
+            ),
+            JournalError::Field(field) => write!(
+                f,
+                "journal field {} is not upper case letters, digits and _",
+                field
+            ),
+        }
+    }
+}
+impl std::error::Error for JournalError {}
+
+pub fn is_journal(location: &str) -> bool {
+    location.starts_with(JOURNAL_SCHEME)
+}
+
+// journald accepts these in field names, see sd_journal_sendv
+fn is_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn parse_priority(priority: &str) -> Result<u8, JournalError> {
+    match priority.parse::<u8>() {
+        Ok(priority) if (priority as usize) < PRIORITIES.len() => Ok(priority),
+        _ => PRIORITIES
+            .iter()
+            .position(|name| *name == priority)
+            .map(|priority| priority as u8)
+            .ok_or_else(|| JournalError::Priority(priority.to_string())),
+    }
+}
+
+/**
+ * The entries a journal location selects.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct JournalQuery {
+    // field and value, e.g. _SYSTEMD_UNIT and sshd.service
+    pub fields: Vec<(String, String)>,
+    // the least urgent priority selected
+    pub priority: Option<u8>,
+}
+impl JournalQuery {
+    pub fn parse(location: &str) -> Result<JournalQuery, JournalError> {
+        let filters = location
+            .strip_prefix(JOURNAL_SCHEME)
+            .ok_or_else(|| JournalError::NotJournal(location.to_string()))?;
+
+        let mut query = JournalQuery::default();
+        for filter in filters.split(',').map(str::trim) {
+            if filter.is_empty() {
+                continue;
+            }
+            if let Some(priority) = filter.strip_prefix("priority<=") {
+                query.priority = Some(parse_priority(priority.trim())?);
+                continue;
+            }
+
+            let (name, value) = filter
+                .split_once('=')
+                .ok_or_else(|| JournalError::Filter(filter.to_string()))?;
+            let field = match name.trim() {
+                "unit" => "_SYSTEMD_UNIT",
+                "identifier" => "SYSLOG_IDENTIFIER",
+                field if is_field_name(field) => field,
+                field => return Err(JournalError::Field(field.to_string())),
+            };
+            query
+                .fields
+                .push((field.to_string(), value.trim().to_string()));
+        }
+
+        Ok(query)
+    }
+
+    /**
+     * The journalctl arguments that print the selected entries in export
+     * format.
+     */
+    pub fn journalctl_args(&self) -> Vec<String> {
+        let mut args = vec![String::from("--output=export"), String::from("--no-pager")];
+        if let Some(priority) = self.priority {
+            args.push(format!("--priority={}", priority));
+        }
+        args.extend(
+            self.fields
+                .iter()
+                .map(|(field, value)| format!("{}={}", field, value)),
+        );
+        args
+    }
+}
+
+/**
+ * One journal entry, binary field values are read lossily as text.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct JournalEntry {
+    pub fields: Vec<(String, String)>,
+}
+impl JournalEntry {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn cursor(&self) -> Option<&str> {
+        self.get("__CURSOR")
+    }
+
+    /**
+     * The line searches match and results show, the message followed by
+     * the reported fields the entry has, e.g.
+     * "Failed password for root _SYSTEMD_UNIT=sshd.service _PID=1234".
+     * A message of several lines is joined with spaces.
+     */
+    pub fn line(&self) -> String {
+        let mut line = self.get("MESSAGE").unwrap_or_default().replace('\n', " ");
+        for field in REPORTED_FIELDS {
+            if let Some(value) = self.get(field) {
+                line.push_str(&format!(" {}={}", field, value.replace('\n', " ")));
+            }
+        }
+        line
+    }
+}
+
+/**
+ * Read the next entry of journal export format, None at the end.
+ */
+pub fn read_entry<R: BufRead>(reader: &mut R) -> io::Result<Option<JournalEntry>> {
+    let mut entry = JournalEntry::default();
+    let mut buf = Vec::new();
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            // the last entry does not need a blank line after it
+            return Ok((!entry.fields.is_empty()).then_some(entry));
+        }
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        if buf.is_empty() {
+            if entry.fields.is_empty() {
+                continue;
+            }
+            return Ok(Some(entry));
+        }
+
+        let field = match buf.iter().position(|b| *b == b'=') {
+            Some(equals) => (
+                String::from_utf8_lossy(&buf[..equals]).into_owned(),
+                String::from_utf8_lossy(&buf[equals + 1..]).into_owned(),
+            ),
+            // binary: the name alone, the size as 64 bit little endian,
+            // the value and a newline
+            None => {
+                let name = String::from_utf8_lossy(&buf).into_owned();
+                let mut size = [0; 8];
+                reader.read_exact(&mut size)?;
+                let size = u64::from_le_bytes(size);
+                if size > MAX_FIELD_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("journal field {} of {} bytes", name, size),
+                    ));
+                }
+                let mut value = vec![0; size as usize];
+                reader.read_exact(&mut value)?;
+                let mut newline = [0; 1];
+                reader.read_exact(&mut newline)?;
+                (name, String::from_utf8_lossy(&value).into_owned())
+            }
+        };
+        entry.fields.push(field);
+    }
+}
+
+/**
+ * Journal export format read as lines, one per entry, see
+ * JournalEntry::line. Entries are read one at a time, cursor() is the
+ * cursor of the last entry read to its end.
+ */
+pub struct JournalLines<R> {
+    reader: R,
+    line: Vec<u8>,
+    pos: usize,
+    // of the entry in `line`
+    line_cursor: Option<String>,
+    cursor: Option<String>,
+}
+impl<R: BufRead> JournalLines<R> {
+    pub fn new(reader: R) -> JournalLines<R> {
+        JournalLines {
+            reader,
+            line: Vec::new(),
+            pos: 0,
+            line_cursor: None,
+            cursor: None,
+        }
+    }
+
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+impl<R: BufRead> Read for JournalLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+
+        Ok(read)
+    }
+}
+impl<R: BufRead> BufRead for JournalLines<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if let Some(entry) = read_entry(&mut self.reader)? {
+                self.line = entry.line().into_bytes();
+                self.line.push(b'\n');
+                self.line_cursor = entry.cursor().map(str::to_string);
+            }
+        }
+
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+        if !self.line.is_empty() && self.pos >= self.line.len() {
+            if let Some(cursor) = self.line_cursor.take() {
+                self.cursor = Some(cursor);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Matcher, SearchType};
+
+    fn export() -> Vec<u8> {
+        let mut export = b"__CURSOR=s=1;i=1\n\
+            __REALTIME_TIMESTAMP=1760862601000000\n\
+            _SYSTEMD_UNIT=sshd.service\n\
+            _PID=1234\n\
+            _UID=0\n\
+            MESSAGE=Failed password for root from 10.0.0.1\n\
+            \n\
+            __CURSOR=s=1;i=2\n\
+            _SYSTEMD_UNIT=cron.service\n\
+            MESSAGE=(root) CMD (true)\n\
+            \n\
+            __CURSOR=s=1;i=3\n\
+            MESSAGE\n"
+            .to_vec();
+        let binary = b"two\nlines \xff";
+        export.extend_from_slice(&(binary.len() as u64).to_le_bytes());
+        export.extend_from_slice(binary);
+        export.extend_from_slice(b"\n_PID=1\n");
+        export
+    }
+
+    #[test]
+    fn locations_parse() {
+        assert!(is_journal("journal://"));
+        assert!(!is_journal("/var/log/journal"));
+        assert_eq!(
+            JournalQuery::parse("journal://").unwrap(),
+            JournalQuery::default()
+        );
+        assert_eq!(
+            JournalQuery::parse("journal://unit=sshd.service, priority<=err,_UID=0")
+                .unwrap()
+                .journalctl_args(),
+            vec![
+                "--output=export",
+                "--no-pager",
+                "--priority=3",
+                "_SYSTEMD_UNIT=sshd.service",
+                "_UID=0"
+            ]
+        );
+        assert_eq!(
+            JournalQuery::parse("journal://priority<=5")
+                .unwrap()
+                .priority,
+            Some(5)
+        );
+
+        assert_eq!(
+            JournalQuery::parse("/var/log/syslog"),
+            Err(JournalError::NotJournal(String::from("/var/log/syslog")))
+        );
+        assert_eq!(
+            JournalQuery::parse("journal://priority<=8"),
+            Err(JournalError::Priority(String::from("8")))
+        );
+        assert_eq!(
+            JournalQuery::parse("journal://sshd"),
+            Err(JournalError::Filter(String::from("sshd")))
+        );
+        assert_eq!(
+            JournalQuery::parse("journal://uid=0"),
+            Err(JournalError::Field(String::from("uid")))
+        );
+    }
+
+    #[test]
+    fn export_format_reads() {
+        let export = export();
+        let mut reader = export.as_slice();
+
+        let first = read_entry(&mut reader).unwrap().unwrap();
+        assert_eq!(first.cursor(), Some("s=1;i=1"));
+        assert_eq!(
+            first.line(),
+            "Failed password for root from 10.0.0.1 _SYSTEMD_UNIT=sshd.service _PID=1234 _UID=0 \
+            __REALTIME_TIMESTAMP=1760862601000000"
+        );
+        read_entry(&mut reader).unwrap().unwrap();
+        let binary = read_entry(&mut reader).unwrap().unwrap();
+        assert_eq!(binary.get("MESSAGE"), Some("two\nlines \u{fffd}"));
+        assert_eq!(binary.line(), "two lines \u{fffd} _PID=1");
+        assert_eq!(read_entry(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn entries_scan_as_lines() {
+        let export = export();
+        let mut lines = JournalLines::new(export.as_slice());
+        let scan = Matcher::new(&SearchType::Contains, "root")
+            .unwrap()
+            .scan(&mut lines)
+            .unwrap();
+
+        assert_eq!(scan.lines, 3);
+        assert_eq!(scan.found.len(), 2);
+        assert!(scan.found[0].ends_with(
+            "_SYSTEMD_UNIT=sshd.service _PID=1234 _UID=0 __REALTIME_TIMESTAMP=1760862601000000"
+        ));
+        assert_eq!(lines.cursor(), Some("s=1;i=3"));
+    }
+
+    #[test]
+    fn cursor_is_of_entries_read_whole() {
+        let export = export();
+        let mut lines = JournalLines::new(export.as_slice());
+        let mut line = String::new();
+        lines.read_line(&mut line).unwrap();
+        assert_eq!(lines.cursor(), Some("s=1;i=1"));
+
+        // peeking at the next entry does not move the cursor
+        assert!(!lines.fill_buf().unwrap().is_empty());
+        lines.consume(3);
+        assert_eq!(lines.cursor(), Some("s=1;i=1"));
+    }
+}
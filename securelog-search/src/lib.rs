@@ -0,0 +1,347 @@
+/*!
+Search matching shared by the client and the server.
+
+Clients run searches over their log files and the server runs the same
+searches over archived segments, sample text and received syslog messages,
+all go through Matcher so a search matches the same lines wherever it runs.
+*/
+pub use command::{command_name, is_command, CommandError};
+pub use journal::{is_journal, JournalError, JournalLines, JournalQuery};
+pub use pool::map_bounded;
+pub use regex::Error as RegexError;
+pub use securelog_proto::SearchType;
+pub use set::{MatcherSet, SetError};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+pub use syslog::{is_syslog, SyslogError, SyslogMessage, SyslogQuery};
+
+pub mod command;
+pub mod journal;
+mod pool;
+mod set;
+pub mod syslog;
+
+/**
+ * Whether a location is a file, not the journal, a command or syslog.
+ */
+pub fn is_file(location: &str) -> bool {
+    !is_journal(location) && !is_command(location) && !is_syslog(location)
+}
+
+// lines read between deadline checks
+const DEADLINE_CHECK_LINES: u64 = 1024;
+
+/**
+ * What a matcher found in a reader, and how much it read.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct Scan {
+    pub found: Vec<String>,
+    pub lines: u64,
+    pub bytes: u64,
+    // the budget ran out before the end of the reader
+    pub truncated: bool,
+}
+
+/**
+ * An amount scans draw from, shared by scans running on several threads,
+ * e.g. the bytes a whole run may read. Without a maximum it only counts.
+ */
+#[derive(Debug, Default)]
+pub struct Allowance {
+    max: Option<u64>,
+    used: AtomicU64,
+}
+impl Allowance {
+    pub fn new(max: Option<u64>) -> Allowance {
+        Allowance {
+            max,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.max.is_some_and(|max| self.used() >= max)
+    }
+
+    pub fn spend(&self, amount: u64) {
+        self.used.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /**
+     * Take one, false if there was none left.
+     */
+    pub fn take_one(&self) -> bool {
+        let before = self.used.fetch_add(1, Ordering::Relaxed);
+        self.max.is_none_or(|max| before < max)
+    }
+}
+
+/**
+ * How much a scan may read and return, a scan without allowances is not
+ * limited. Limits are checked between lines, a line is always read whole.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct Budget<'a> {
+    // every one is charged the bytes read, the scan stops when one is spent
+    pub bytes: Vec<&'a Allowance>,
+    // lines the scan may return
+    pub found: Option<&'a Allowance>,
+    pub deadline: Option<Instant>,
+}
+impl Budget<'_> {
+    fn spent(&self, lines: u64) -> bool {
+        if self.bytes.iter().any(|bytes| bytes.is_spent()) {
+            return true;
+        }
+        lines.is_multiple_of(DEADLINE_CHECK_LINES)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/**
+ * Read `reader` line by line for searches with their own budgets, `matching`
+ * adds the searches a line matches. Lines are split like BufRead::lines so
+ * a trailing \r\n or \n is not part of the line. A search whose budget is
+ * spent before the end of the reader, or that has to drop a match, is
+ * truncated, reading stops once every search is done.
+ */
+fn scan_lines<R: BufRead>(
+    mut reader: R,
+    budgets: &[Budget],
+    mut matching: impl FnMut(&str, &mut Vec<usize>),
+) -> std::io::Result<Vec<Scan>> {
+    let mut scans: Vec<Scan> = budgets.iter().map(|_| Scan::default()).collect();
+    let mut active = vec![true; budgets.len()];
+    let mut lines: u64 = 0;
+    let mut buf = String::new();
+    let mut matched: Vec<usize> = Vec::new();
+    let mut charged: Vec<&Allowance> = Vec::new();
+
+    loop {
+        let mut stopped = Vec::new();
+        for (search, budget) in budgets.iter().enumerate() {
+            if active[search] && budget.spent(lines) {
+                active[search] = false;
+                stopped.push(search);
+            }
+        }
+        if !stopped.is_empty() {
+            let more = !reader.fill_buf()?.is_empty();
+            for search in stopped {
+                scans[search].truncated = more;
+            }
+        }
+        if !active.contains(&true) {
+            break;
+        }
+
+        buf.clear();
+        let read = reader.read_line(&mut buf)? as u64;
+        if read == 0 {
+            break;
+        }
+        lines += 1;
+
+        // searches can share allowances, each is charged once
+        charged.clear();
+        for (search, budget) in budgets.iter().enumerate() {
+            if !active[search] {
+                continue;
+            }
+            scans[search].lines += 1;
+            scans[search].bytes += read;
+            for bytes in &budget.bytes {
+                if !charged.iter().any(|other| std::ptr::eq(*other, *bytes)) {
+                    charged.push(bytes);
+                }
+            }
+        }
+        for bytes in &charged {
+            bytes.spend(read);
+        }
+
+        let line = buf.strip_suffix('\n').unwrap_or(&buf);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        matched.clear();
+        matching(line, &mut matched);
+        for &search in &matched {
+            if !active[search] {
+                continue;
+            }
+            if budgets[search].found.is_some_and(|found| !found.take_one()) {
+                scans[search].truncated = true;
+                active[search] = false;
+                continue;
+            }
+            scans[search].found.push(line.to_string());
+        }
+    }
+
+    Ok(scans)
+}
+
+pub enum Matcher {
+    Regex(regex::Regex),
+    Contains(String),
+    Wildcard(wildmatch::WildMatch),
+}
+impl Matcher {
+    /**
+     * Compile a search, only regexes can fail.
+     */
+    pub fn new(stype: &SearchType, search: &str) -> Result<Matcher, regex::Error> {
+        Ok(match stype {
+            SearchType::Regex => Matcher::Regex(regex::Regex::new(search)?),
+            SearchType::Contains => Matcher::Contains(search.to_string()),
+            SearchType::Wildcard => Matcher::Wildcard(wildmatch::WildMatch::new(search)),
+        })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Matcher::Regex(rgx) => rgx.is_match(line),
+            Matcher::Contains(search) => line.contains(search.as_str()),
+            Matcher::Wildcard(wmatch) => wmatch.matches(line),
+        }
+    }
+
+    /**
+     * The matching lines of `reader`, split like BufRead::lines so a
+     * trailing \r\n or \n is not part of the line.
+     */
+    pub fn matching_lines<R: BufRead>(&self, reader: R) -> std::io::Result<Vec<String>> {
+        Ok(self.scan(reader)?.found)
+    }
+
+    /**
+     * The matching lines of `reader` with the number of lines and bytes
+     * read, lines are split like matching_lines.
+     */
+    pub fn scan<R: BufRead>(&self, reader: R) -> std::io::Result<Scan> {
+        self.scan_within(reader, &Budget::default())
+    }
+
+    /**
+     * Scan `reader` until it ends or the budget is spent, the scan is
+     * truncated if lines were left unread or a match was dropped.
+     */
+    pub fn scan_within<R: BufRead>(&self, reader: R, budget: &Budget) -> std::io::Result<Scan> {
+        let mut scans = scan_lines(reader, std::slice::from_ref(budget), |line, matched| {
+            if self.is_match(line) {
+                matched.push(0);
+            }
+        })?;
+
+        Ok(scans.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "Oct 19 sshd[1]: Failed password for root\r\n\
+        Oct 19 sshd[2]: Accepted password for bob\n\
+        Oct 19 CRON[3]: (root) CMD (true)\n\
+        failed password lowercase";
+
+    fn lines(stype: SearchType, search: &str) -> Vec<String> {
+        Matcher::new(&stype, search)
+            .unwrap()
+            .matching_lines(SAMPLE.as_bytes())
+            .unwrap()
+    }
+
+    #[test]
+    fn contains_is_case_sensitive() {
+        assert_eq!(
+            lines(SearchType::Contains, "Failed"),
+            vec!["Oct 19 sshd[1]: Failed password for root"]
+        );
+        assert_eq!(lines(SearchType::Contains, "password").len(), 3);
+    }
+
+    #[test]
+    fn regex_matches_anywhere() {
+        assert_eq!(lines(SearchType::Regex, r"sshd\[\d+\]").len(), 2);
+        assert_eq!(lines(SearchType::Regex, "(?i)^failed").len(), 1);
+        assert_eq!(lines(SearchType::Regex, "root$").len(), 1);
+        assert!(Matcher::new(&SearchType::Regex, "(").is_err());
+    }
+
+    #[test]
+    fn scan_counts_lines_and_bytes() {
+        let scan = Matcher::new(&SearchType::Contains, "root")
+            .unwrap()
+            .scan(SAMPLE.as_bytes())
+            .unwrap();
+        assert_eq!(scan.found.len(), 2);
+        assert_eq!(scan.lines, 4);
+        assert_eq!(scan.bytes, SAMPLE.len() as u64);
+    }
+
+    #[test]
+    fn budget_truncates_scan() {
+        let matcher = Matcher::new(&SearchType::Contains, "password").unwrap();
+        let within = |budget: Budget| matcher.scan_within(SAMPLE.as_bytes(), &budget).unwrap();
+
+        let one = Allowance::new(Some(1));
+        let scan = within(Budget {
+            found: Some(&one),
+            ..Default::default()
+        });
+        assert_eq!(scan.found.len(), 1);
+        assert!(scan.truncated);
+
+        // the first line is read whole
+        let byte = Allowance::new(Some(1));
+        let scan = within(Budget {
+            bytes: vec![&byte],
+            ..Default::default()
+        });
+        assert_eq!(scan.lines, 1);
+        assert!(scan.truncated);
+        assert!(byte.used() > 1);
+
+        let scan = within(Budget {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        });
+        assert_eq!(scan.lines, 0);
+        assert!(scan.truncated);
+
+        // a budget that fits is not truncated
+        let all = Allowance::new(Some(SAMPLE.len() as u64));
+        let three = Allowance::new(Some(3));
+        let scan = within(Budget {
+            bytes: vec![&all],
+            found: Some(&three),
+            deadline: None,
+        });
+        assert_eq!(scan.found.len(), 3);
+        assert!(!scan.truncated);
+
+        // the allowance carries over to the next scan
+        let scan = within(Budget {
+            bytes: vec![&all],
+            ..Default::default()
+        });
+        assert_eq!(scan.lines, 0);
+        assert!(scan.truncated);
+    }
+
+    #[test]
+    fn wildcard_matches_whole_line() {
+        assert!(lines(SearchType::Wildcard, "Failed").is_empty());
+        assert_eq!(lines(SearchType::Wildcard, "*Failed*").len(), 1);
+        assert_eq!(lines(SearchType::Wildcard, "Oct 19 ????[?]: *").len(), 3);
+    }
+}
@@ -0,0 +1,278 @@
+/*
+Searches matched together in one pass.
+
+A file several searches look at is read once, every line goes through all
+of them: the regexes as one RegexSet and the literals as one Aho-Corasick
+automaton, only wildcards are matched one by one. A few regexes are faster
+matched one by one too, each gets its own literal prefilter where a set has
+to run over every byte; see benches/scan.rs.
+*/
+use crate::{scan_lines, Budget, Scan, SearchType};
+use aho_corasick::AhoCorasick;
+use regex::{Regex, RegexSet};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+
+// fewer regexes than this are matched one by one
+const REGEX_SET_MIN: usize = 8;
+
+#[derive(Debug)]
+pub enum SetError {
+    Regex(regex::Error),
+    Literals(aho_corasick::BuildError),
+}
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetError::Regex(e) => write!(f, "SetError(Regex({}))", e),
+            SetError::Literals(e) => write!(f, "SetError(Literals({}))", e),
+        }
+    }
+}
+impl std::error::Error for SetError {}
+impl From<regex::Error> for SetError {
+    fn from(e: regex::Error) -> SetError {
+        SetError::Regex(e)
+    }
+}
+impl From<aho_corasick::BuildError> for SetError {
+    fn from(e: aho_corasick::BuildError) -> SetError {
+        SetError::Literals(e)
+    }
+}
+
+enum Regexes {
+    // with the search of each regex in the set
+    Set(RegexSet, Vec<usize>),
+    Each(Vec<(usize, Regex)>),
+}
+
+/**
+ * Searches compiled to be matched together, they are numbered in the
+ * order they were given.
+ */
+pub struct MatcherSet {
+    len: usize,
+    regexes: Regexes,
+    literals: AhoCorasick,
+    // searches of each distinct literal
+    literal_searches: Vec<Vec<usize>>,
+    // the empty literal is in every line
+    everything: Vec<usize>,
+    wildcards: Vec<(usize, wildmatch::WildMatch)>,
+}
+impl MatcherSet {
+    /**
+     * Compile searches into a set, the same searches compile with
+     * Matcher::new.
+     */
+    pub fn new(searches: &[(&SearchType, &str)]) -> Result<MatcherSet, SetError> {
+        let mut regexes = Vec::new();
+        let mut regex_searches = Vec::new();
+        let mut literals: Vec<&str> = Vec::new();
+        let mut literal_searches: Vec<Vec<usize>> = Vec::new();
+        let mut literal_index: HashMap<&str, usize> = HashMap::new();
+        let mut everything = Vec::new();
+        let mut wildcards = Vec::new();
+
+        for (search, (stype, text)) in searches.iter().enumerate() {
+            match stype {
+                SearchType::Regex => {
+                    regexes.push(*text);
+                    regex_searches.push(search);
+                }
+                SearchType::Contains if text.is_empty() => everything.push(search),
+                SearchType::Contains => {
+                    let index = *literal_index.entry(text).or_insert_with(|| {
+                        literals.push(text);
+                        literal_searches.push(Vec::new());
+                        literals.len() - 1
+                    });
+                    literal_searches[index].push(search);
+                }
+                SearchType::Wildcard => {
+                    wildcards.push((search, wildmatch::WildMatch::new(text)));
+                }
+            }
+        }
+
+        let regexes = if regexes.len() >= REGEX_SET_MIN {
+            Regexes::Set(RegexSet::new(regexes)?, regex_searches)
+        } else {
+            Regexes::Each(
+                regex_searches
+                    .into_iter()
+                    .zip(regexes)
+                    .map(|(search, regex)| Ok((search, Regex::new(regex)?)))
+                    .collect::<Result<Vec<(usize, Regex)>, regex::Error>>()?,
+            )
+        };
+
+        Ok(MatcherSet {
+            len: searches.len(),
+            regexes,
+            literals: AhoCorasick::new(literals)?,
+            literal_searches,
+            everything,
+            wildcards,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+     * Fill `matched` with the searches matching `line`, in order and once
+     * each.
+     */
+    pub fn matches_into(&self, line: &str, matched: &mut Vec<usize>) {
+        matched.clear();
+        matched.extend_from_slice(&self.everything);
+        match &self.regexes {
+            Regexes::Set(regexes, searches) => {
+                matched.extend(regexes.matches(line).iter().map(|regex| searches[regex]));
+            }
+            Regexes::Each(regexes) => {
+                for (search, regex) in regexes {
+                    if regex.is_match(line) {
+                        matched.push(*search);
+                    }
+                }
+            }
+        }
+        if !self.literal_searches.is_empty() {
+            for found in self.literals.find_overlapping_iter(line) {
+                matched.extend_from_slice(&self.literal_searches[found.pattern().as_usize()]);
+            }
+        }
+        for (search, wildcard) in &self.wildcards {
+            if wildcard.matches(line) {
+                matched.push(*search);
+            }
+        }
+
+        // a literal can be in a line more than once
+        matched.sort_unstable();
+        matched.dedup();
+    }
+
+    /**
+     * Scan `reader` once for every search, each within its own budget.
+     * There is one budget and one scan per search, in order.
+     */
+    pub fn scan_within<R: BufRead>(
+        &self,
+        reader: R,
+        budgets: &[Budget],
+    ) -> std::io::Result<Vec<Scan>> {
+        assert_eq!(budgets.len(), self.len, "one budget per search");
+
+        scan_lines(reader, budgets, |line, matched| {
+            self.matches_into(line, matched)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Allowance, Matcher};
+
+    const SAMPLE: &str = "Oct 19 sshd[1]: Failed password for root from 10.0.0.1\n\
+        Oct 19 sshd[2]: Accepted password for bob\n\
+        Oct 19 CRON[3]: (root) CMD (true)\n\
+        root root root\n\
+        \n\
+        failed password lowercase";
+
+    fn searches() -> Vec<(SearchType, &'static str)> {
+        vec![
+            (SearchType::Contains, "root"),
+            (SearchType::Regex, r"sshd\[\d+\]"),
+            (SearchType::Contains, "password"),
+            (SearchType::Wildcard, "Oct 19 ????[?]: *"),
+            (SearchType::Contains, "root"),
+            (SearchType::Regex, "(?i)^failed"),
+            (SearchType::Contains, ""),
+            (SearchType::Contains, "ass"),
+            (SearchType::Regex, r"\d+\.\d+\.\d+\.\d+$"),
+        ]
+    }
+
+    #[test]
+    fn set_matches_like_single_matchers() {
+        let searches = searches();
+        let refs: Vec<(&SearchType, &str)> = searches.iter().map(|(t, s)| (t, *s)).collect();
+        let set = MatcherSet::new(&refs).unwrap();
+        assert_eq!(set.len(), searches.len());
+
+        let budgets = vec![Budget::default(); searches.len()];
+        let scans = set.scan_within(SAMPLE.as_bytes(), &budgets).unwrap();
+
+        for ((stype, search), scan) in searches.iter().zip(&scans) {
+            let single = Matcher::new(stype, search)
+                .unwrap()
+                .scan(SAMPLE.as_bytes())
+                .unwrap();
+            assert_eq!(*scan, single, "{:?} {:?}", stype, search);
+        }
+    }
+
+    #[test]
+    fn regex_set_matches_like_single_matchers() {
+        let patterns: Vec<String> = (0..REGEX_SET_MIN * 2)
+            .map(|i| format!(r"(?:sshd|CRON)\[{}\]|root.*{}|^\w+ {}", i % 4, i, i + 10))
+            .collect();
+        let refs: Vec<(&SearchType, &str)> = patterns
+            .iter()
+            .map(|pattern| (&SearchType::Regex, pattern.as_str()))
+            .collect();
+        let set = MatcherSet::new(&refs).unwrap();
+        assert!(matches!(set.regexes, Regexes::Set(..)));
+
+        let budgets = vec![Budget::default(); refs.len()];
+        let scans = set.scan_within(SAMPLE.as_bytes(), &budgets).unwrap();
+        for ((stype, search), scan) in refs.iter().zip(&scans) {
+            let single = Matcher::new(stype, search)
+                .unwrap()
+                .scan(SAMPLE.as_bytes())
+                .unwrap();
+            assert_eq!(*scan, single, "{:?}", search);
+        }
+    }
+
+    #[test]
+    fn searches_stop_on_their_own_budget() {
+        let searches = searches();
+        let refs: Vec<(&SearchType, &str)> = searches.iter().map(|(t, s)| (t, *s)).collect();
+        let set = MatcherSet::new(&refs).unwrap();
+
+        let one = Allowance::new(Some(1));
+        let shared = Allowance::new(None);
+        let mut budgets = vec![
+            Budget {
+                bytes: vec![&shared],
+                ..Default::default()
+            };
+            searches.len()
+        ];
+        budgets[0].found = Some(&one);
+        let scans = set.scan_within(SAMPLE.as_bytes(), &budgets).unwrap();
+
+        assert_eq!(
+            scans[0].found,
+            vec!["Oct 19 sshd[1]: Failed password for root from 10.0.0.1"]
+        );
+        assert!(scans[0].truncated);
+        assert_eq!(scans[4].found.len(), 3);
+        assert!(!scans[4].truncated);
+        // the shared allowance is charged once per line
+        assert_eq!(shared.used(), SAMPLE.len() as u64);
+    }
+}
@@ -0,0 +1,437 @@
+/*
+Syslog locations.
+
+A location starting with syslog:// searches the syslog messages the server
+receives from devices that can not run a client, see the server's
+syslog.rs. What follows are filters separated by commas, every filter has
+to match:
+
+    syslog://                       every message
+    syslog://facility=auth          a facility, 0-23 or a name
+    syslog://severity<=warning      warning and more urgent, 0-7 or a name
+    syslog://app=sshd               the APP-NAME or TAG of the message
+
+Filters on the same field match either value. Messages are parsed as RFC
+5424 when they say version 1 and as RFC 3164 otherwise, searches match the
+message as it was received without its <PRI>.
+*/
+use crate::journal::PRIORITIES;
+use std::fmt;
+
+pub const SYSLOG_SCHEME: &str = "syslog://";
+
+const FACILITIES: &[&str] = &[
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+// what RFC 3164 says a message without a <PRI> is, user.notice
+const DEFAULT_PRI: u8 = 13;
+
+const MONTHS: &[&str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SyslogError {
+    // the location does not start with syslog://
+    NotSyslog(String),
+    Filter(String),
+    Facility(String),
+    Severity(String),
+}
+impl fmt::Display for SyslogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyslogError::NotSyslog(location) => {
+                write!(f, "{} is not a syslog location", location)
+            }
+            SyslogError::Filter(filter) => write!(
+                f,
+                "syslog filter {} is not facility=, severity<= or app=",
+                filter
+            ),
+            SyslogError::Facility(facility) => write!(
+                f,
+                "syslog facility {} is not 0-23 or one of {}",
+                facility,
+                FACILITIES.join(", ")
+            ),
+            SyslogError::Severity(severity) => write!(
+                f,
+                "syslog severity {} is not 0-7 or one of {}",
+                severity,
+                PRIORITIES.join(", ")
+            ),
+        }
+    }
+}
+impl std::error::Error for SyslogError {}
+
+pub fn is_syslog(location: &str) -> bool {
+    location.starts_with(SYSLOG_SCHEME)
+}
+
+// a number below names.len() or one of names
+fn parse_code(value: &str, names: &[&str]) -> Option<u8> {
+    match value.parse::<u8>() {
+        Ok(code) if (code as usize) < names.len() => Some(code),
+        _ => names
+            .iter()
+            .position(|name| *name == value)
+            .map(|code| code as u8),
+    }
+}
+
+/**
+ * The messages a syslog location selects.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct SyslogQuery {
+    pub facilities: Vec<u8>,
+    // the least urgent severity selected
+    pub severity: Option<u8>,
+    pub apps: Vec<String>,
+}
+impl SyslogQuery {
+    pub fn parse(location: &str) -> Result<SyslogQuery, SyslogError> {
+        let filters = location
+            .strip_prefix(SYSLOG_SCHEME)
+            .ok_or_else(|| SyslogError::NotSyslog(location.to_string()))?;
+
+        let mut query = SyslogQuery::default();
+        for filter in filters.split(',').map(str::trim) {
+            if filter.is_empty() {
+                continue;
+            }
+            if let Some(severity) = filter.strip_prefix("severity<=") {
+                let severity = severity.trim();
+                query.severity = Some(
+                    parse_code(severity, PRIORITIES)
+                        .ok_or_else(|| SyslogError::Severity(severity.to_string()))?,
+                );
+                continue;
+            }
+
+            match filter.split_once('=') {
+                Some((name, facility)) if name.trim() == "facility" => {
+                    let facility = facility.trim();
+                    query.facilities.push(
+                        parse_code(facility, FACILITIES)
+                            .ok_or_else(|| SyslogError::Facility(facility.to_string()))?,
+                    );
+                }
+                Some((name, app)) if name.trim() == "app" => {
+                    query.apps.push(app.trim().to_string());
+                }
+                _ => return Err(SyslogError::Filter(filter.to_string())),
+            }
+        }
+
+        Ok(query)
+    }
+
+    pub fn matches(&self, message: &SyslogMessage) -> bool {
+        (self.facilities.is_empty() || self.facilities.contains(&message.facility))
+            && self
+                .severity
+                .is_none_or(|severity| message.severity <= severity)
+            && (self.apps.is_empty()
+                || message
+                    .app
+                    .as_ref()
+                    .is_some_and(|app| self.apps.contains(app)))
+    }
+}
+
+/**
+ * A received syslog message.
+ */
+#[derive(Debug, PartialEq)]
+pub struct SyslogMessage {
+    pub facility: u8,
+    pub severity: u8,
+    // as the sender wrote it, None if it sent none
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    pub app: Option<String>,
+    pub message: String,
+    // the message as received without its <PRI>, what searches match
+    pub line: String,
+}
+
+// "<PRI>" at the start of a message and what follows it
+fn split_pri(text: &str) -> Option<(u8, &str)> {
+    let rest = text.strip_prefix('<')?;
+    let (pri, rest) = rest.split_once('>')?;
+    if pri.is_empty() || pri.len() > 3 || !pri.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    match pri.parse::<u8>() {
+        Ok(pri) if pri < 192 => Some((pri, rest)),
+        _ => None,
+    }
+}
+
+// the RFC 5424 NILVALUE is none
+fn field(value: &str) -> Option<String> {
+    match value {
+        "" | "-" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+// the length of the STRUCTURED-DATA at the start of `text`, brackets in
+// quoted and escaped values do not end an element
+fn structured_data_len(text: &str) -> usize {
+    if text.starts_with('-') {
+        return 1;
+    }
+
+    let mut end = 0;
+    let mut in_element = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if !in_element {
+            if c != '[' {
+                break;
+            }
+            in_element = true;
+        } else if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == ']' && !in_quotes {
+            in_element = false;
+            end = i + 1;
+        }
+    }
+    end
+}
+
+// <PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]
+fn parse_rfc5424(pri: u8, text: &str) -> Option<SyslogMessage> {
+    let mut fields = text.splitn(7, ' ');
+    if fields.next()? != "1" {
+        return None;
+    }
+    let timestamp = field(fields.next()?);
+    let hostname = field(fields.next()?);
+    let app = field(fields.next()?);
+    let _procid = fields.next()?;
+    let _msgid = fields.next()?;
+    let rest = fields.next().unwrap_or_default();
+
+    // without STRUCTURED-DATA it is all message
+    let message = match structured_data_len(rest) {
+        0 => rest,
+        end => rest[end..].strip_prefix(' ').unwrap_or_default(),
+    };
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+
+    Some(SyslogMessage {
+        facility: pri >> 3,
+        severity: pri & 7,
+        timestamp,
+        hostname,
+        app,
+        message: message.to_string(),
+        line: text.to_string(),
+    })
+}
+
+// "Mmm dd hh:mm:ss", the day is padded with a space
+fn is_bsd_timestamp(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    text.len() >= 15
+        && text.is_char_boundary(15)
+        && MONTHS.contains(&&text[..3])
+        && bytes[3] == b' '
+        && (bytes[4] == b' ' || bytes[4].is_ascii_digit())
+        && bytes[5].is_ascii_digit()
+        && bytes[6] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+}
+
+// TAG[PID]: or TAG: at the start of `text`, the tag and the message after it
+fn split_tag(text: &str) -> Option<(&str, &str)> {
+    let end = text.find([':', '[', ' '])?;
+    let (tag, rest) = text.split_at(end);
+    if tag.is_empty() {
+        return None;
+    }
+    let rest = match rest.strip_prefix('[') {
+        Some(rest) => &rest[rest.find(']')? + 1..],
+        None => rest,
+    };
+    let message = rest.strip_prefix(':')?;
+
+    Some((tag, message.strip_prefix(' ').unwrap_or(message)))
+}
+
+// <PRI>Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG, senders leave parts out
+fn parse_rfc3164(pri: u8, rest: &str) -> SyslogMessage {
+    let (timestamp, after) = if is_bsd_timestamp(rest) {
+        (Some(rest[..15].to_string()), rest[15..].trim_start())
+    } else {
+        (None, rest)
+    };
+
+    // a host is only sent after a timestamp, and is not the tag
+    let (hostname, after) = match after.split_once(' ') {
+        Some((host, message))
+            if timestamp.is_some() && !host.is_empty() && split_tag(after).is_none() =>
+        {
+            (Some(host.to_string()), message)
+        }
+        _ => (None, after),
+    };
+    let (app, message) = match split_tag(after) {
+        Some((tag, message)) => (Some(tag.to_string()), message),
+        None => (None, after),
+    };
+
+    SyslogMessage {
+        facility: pri >> 3,
+        severity: pri & 7,
+        timestamp,
+        hostname,
+        app,
+        message: message.to_string(),
+        line: rest.to_string(),
+    }
+}
+
+impl SyslogMessage {
+    /**
+     * Parse a message, trailing line ends and NULs are dropped. A message
+     * without a <PRI> is user.notice, as RFC 3164 says.
+     */
+    pub fn parse(text: &str) -> SyslogMessage {
+        let text = text.trim_end_matches(['\r', '\n', '\0']);
+        let (pri, rest) = split_pri(text).unwrap_or((DEFAULT_PRI, text));
+
+        if rest.starts_with("1 ") {
+            if let Some(message) = parse_rfc5424(pri, rest) {
+                return message;
+            }
+        }
+        parse_rfc3164(pri, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Matcher, SearchType};
+
+    #[test]
+    fn locations_parse() {
+        assert!(is_syslog("syslog://"));
+        assert!(!is_syslog("/var/log/syslog"));
+        assert_eq!(
+            SyslogQuery::parse("syslog://").unwrap(),
+            SyslogQuery::default()
+        );
+        assert_eq!(
+            SyslogQuery::parse("syslog://facility=auth, facility=10,severity<=warning,app=sshd")
+                .unwrap(),
+            SyslogQuery {
+                facilities: vec![4, 10],
+                severity: Some(4),
+                apps: vec![String::from("sshd")],
+            }
+        );
+        assert_eq!(
+            SyslogQuery::parse("syslog://facility=local8"),
+            Err(SyslogError::Facility(String::from("local8")))
+        );
+        assert_eq!(
+            SyslogQuery::parse("syslog://severity<=8"),
+            Err(SyslogError::Severity(String::from("8")))
+        );
+        assert_eq!(
+            SyslogQuery::parse("syslog://host=sw01"),
+            Err(SyslogError::Filter(String::from("host=sw01")))
+        );
+        assert_eq!(
+            SyslogQuery::parse("/var/log/syslog"),
+            Err(SyslogError::NotSyslog(String::from("/var/log/syslog")))
+        );
+    }
+
+    #[test]
+    fn rfc3164_messages_parse() {
+        let message = SyslogMessage::parse(
+            "<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick\n",
+        );
+        assert_eq!(message.facility, 4);
+        assert_eq!(message.severity, 2);
+        assert_eq!(message.timestamp.as_deref(), Some("Oct 11 22:14:15"));
+        assert_eq!(message.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(message.app.as_deref(), Some("su"));
+        assert_eq!(message.message, "'su root' failed for lonvick");
+        assert_eq!(
+            message.line,
+            "Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick"
+        );
+
+        // no host, a pid
+        let message = SyslogMessage::parse("<38>Feb  5 17:32:18 sshd[4721]: Accepted publickey");
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app.as_deref(), Some("sshd"));
+        assert_eq!(message.message, "Accepted publickey");
+
+        // no <PRI>, no timestamp
+        let message =
+            SyslogMessage::parse("%LINK-3-UPDOWN: Interface Gi0/1, changed state to down");
+        assert_eq!((message.facility, message.severity), (1, 5));
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.app.as_deref(), Some("%LINK-3-UPDOWN"));
+        assert_eq!(message.message, "Interface Gi0/1, changed state to down");
+    }
+
+    #[test]
+    fn rfc5424_messages_parse() {
+        let message = SyslogMessage::parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+            [exampleSDID@32473 iut=\"3\" eventSource=\"App]lication\"] \u{feff}An application event",
+        );
+        assert_eq!((message.facility, message.severity), (20, 5));
+        assert_eq!(
+            message.timestamp.as_deref(),
+            Some("2003-10-11T22:14:15.003Z")
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app.as_deref(), Some("evntslog"));
+        assert_eq!(message.message, "An application event");
+        assert!(message.line.starts_with("1 2003-10-11T22:14:15.003Z"));
+
+        let message = SyslogMessage::parse("<13>1 - - - - - -");
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app, None);
+        assert_eq!(message.message, "");
+    }
+
+    #[test]
+    fn queries_select_messages() {
+        let sshd = SyslogMessage::parse("<38>Feb  5 17:32:18 web01 sshd[4721]: Failed password");
+        let kernel = SyslogMessage::parse("<6>Feb  5 17:32:18 web01 kernel: eth0 up");
+
+        let query = SyslogQuery::parse("syslog://facility=auth,severity<=info").unwrap();
+        assert!(query.matches(&sshd));
+        assert!(!query.matches(&kernel));
+        let query = SyslogQuery::parse("syslog://severity<=warning").unwrap();
+        assert!(!query.matches(&sshd));
+        let query = SyslogQuery::parse("syslog://app=kernel,app=sshd").unwrap();
+        assert!(query.matches(&sshd) && query.matches(&kernel));
+
+        let matcher = Matcher::new(&SearchType::Contains, "web01 sshd").unwrap();
+        assert!(matcher.is_match(&sshd.line));
+    }
+}
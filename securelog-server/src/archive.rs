@@ -0,0 +1,512 @@
+/*
+Raw log archive store.
+
+Clients running with archive enabled ship the raw contents of the files they
+search, zstd compressed. Segments are stored outside the database under
+<archive_dir>/<client id>/<first 2 chars>/<full id>, where the id is the
+sha256 of the compressed data, so every read can be checked for integrity.
+*/
+use crate::models::{SearchResult, SearchType};
+use crate::sql::archive::{ArchiveSegment, SegmentCursor};
+use crate::{conf, sql};
+use actix_web::web::{self, Bytes};
+use chrono::{DateTime, Utc};
+use securelog_search::Matcher;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+// default number of days archived segments are kept
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+// how often expired segments are pruned
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// largest segment accepted from a client, compressed or not
+pub const MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("ArchiveError(archive_dir not configured)")]
+    Disabled,
+
+    #[error("ArchiveError(IO({0}))")]
+    IO(#[from] std::io::Error),
+
+    #[error("ArchiveError(Sql({0}))")]
+    Sql(#[from] sql::SqlError),
+
+    #[error("ArchiveError(Regex({0}))")]
+    Regex(#[from] regex::Error),
+
+    #[error("ArchiveError(segment {0} failed integrity check)")]
+    HashMismatch(String),
+
+    #[error("ArchiveError(invalid segment id {0})")]
+    InvalidId(String),
+
+    #[error("ArchiveError(segment larger than {0} bytes)")]
+    TooLarge(u64),
+
+    #[error("ArchiveError(Blocking({0}))")]
+    Blocking(#[from] actix_web::error::BlockingError),
+}
+
+impl actix_web::ResponseError for ArchiveError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            ArchiveError::Disabled => StatusCode::NOT_FOUND,
+            ArchiveError::Regex(_) | ArchiveError::InvalidId(_) => StatusCode::BAD_REQUEST,
+            ArchiveError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ArchiveError>;
+
+pub fn is_enabled() -> bool {
+    conf::get_archive_dir().is_ok()
+}
+
+fn archive_dir() -> Result<PathBuf> {
+    conf::get_archive_dir()
+        .map(PathBuf::from)
+        .map_err(|_| ArchiveError::Disabled)
+}
+
+fn segment_path(dir: &Path, clientid: &str, id: &str) -> Result<PathBuf> {
+    // ids and client ids end up in paths, never allow anything but hex/alnum
+    if id.len() != 64 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ArchiveError::InvalidId(id.to_string()));
+    }
+    if clientid.is_empty() || !clientid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ArchiveError::InvalidId(clientid.to_string()));
+    }
+
+    Ok(dir.join(clientid).join(&id[..2]).join(id))
+}
+
+/**
+ * Size of the data once decompressed, without holding it in memory.
+ * Also makes sure the data is zstd before it is kept around.
+ */
+fn decompressed_size(compressed: &[u8]) -> Result<u64> {
+    use std::io::Read;
+    let decoder = zstd::stream::read::Decoder::new(compressed)?;
+    let size = std::io::copy(
+        &mut decoder.take(MAX_SEGMENT_SIZE + 1),
+        &mut std::io::sink(),
+    )?;
+
+    if size > MAX_SEGMENT_SIZE {
+        Err(ArchiveError::TooLarge(MAX_SEGMENT_SIZE))
+    } else {
+        Ok(size)
+    }
+}
+
+fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/**
+ * Check a compressed segment and write it to the store, unless the same
+ * data is already there. Returns its id and decompressed size.
+ */
+fn write_segment(dir: &Path, clientid: &str, compressed: &[u8]) -> Result<(String, u64)> {
+    let size = decompressed_size(compressed)?;
+
+    let id = hash(compressed);
+    let path = segment_path(dir, clientid, &id)?;
+
+    if !path.exists() {
+        use std::io::Write;
+
+        let parent = path.parent().unwrap_or(dir);
+        fs::create_dir_all(parent)?;
+        // write a temp file of its own then move it in place, so a crash never
+        // leaves a partial segment behind and uploads of the same data at the
+        // same time don't write into each other's file
+        let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+        tmp.write_all(compressed)?;
+        match tmp.persist_noclobber(&path) {
+            Ok(_) => (),
+            // the other upload got there first, it is the same data
+            Err(e) if e.error.kind() == std::io::ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(e.error.into()),
+        }
+    }
+
+    Ok((id, size))
+}
+
+/**
+ * Store a zstd compressed segment for a client.
+ * Returns the segment index entry, the id is the sha256 of `compressed`.
+ */
+pub async fn store_segment(
+    clientid: &str,
+    location: &str,
+    start_offset: i64,
+    compressed: Bytes,
+) -> Result<ArchiveSegment> {
+    let dir = archive_dir()?;
+    let stored_size = compressed.len() as i64;
+    // decompressing up to MAX_SEGMENT_SIZE and the file io block
+    let writer_clientid = clientid.to_string();
+    let (id, size) =
+        web::block(move || write_segment(&dir, &writer_clientid, &compressed)).await??;
+
+    let segment = ArchiveSegment {
+        id,
+        client: clientid.to_string(),
+        location: location.to_string(),
+        start_offset,
+        size: size as i64,
+        stored_size,
+        received: Utc::now(),
+    };
+    if !sql::archive::insert_segment(&segment).await? {
+        debug!(
+            "segment {} of {} at {} for {} already archived",
+            segment.id, location, start_offset, clientid
+        );
+    }
+
+    Ok(segment)
+}
+
+/**
+ * Read a segment back, decompressed. Fails if the stored data no longer
+ * matches its id.
+ */
+fn read_segment_file(dir: &Path, clientid: &str, id: &str) -> Result<Vec<u8>> {
+    let path = segment_path(dir, clientid, id)?;
+    let compressed = fs::read(path)?;
+
+    if hash(&compressed) != id {
+        error!("archive segment {}/{} is corrupted", clientid, id);
+        return Err(ArchiveError::HashMismatch(id.to_string()));
+    }
+
+    Ok(zstd::decode_all(compressed.as_slice())?)
+}
+
+/**
+ * Read a segment back on a blocking thread, see read_segment_file.
+ */
+pub async fn read_segment(clientid: &str, id: &str) -> Result<Vec<u8>> {
+    let dir = archive_dir()?;
+    let (clientid, id) = (clientid.to_string(), id.to_string());
+
+    web::block(move || read_segment_file(&dir, &clientid, &id)).await?
+}
+
+fn delete_segment(dir: &Path, clientid: &str, id: &str) -> Result<()> {
+    let path = segment_path(dir, clientid, id)?;
+
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// page size of an archive search when none is given, and the largest allowed
+pub const DEFAULT_SEARCH_RESULTS: i64 = 100;
+pub const MAX_SEARCH_RESULTS: i64 = 1000;
+// how much one archive search call reads before it stops with a cursor
+const MAX_SEARCH_SEGMENTS: i64 = 1000;
+const MAX_SEARCH_BYTES: u64 = 1024 * 1024 * 1024;
+
+pub struct ArchiveSearch<'a> {
+    pub search_id: i32,
+    pub search_name: &'a str,
+    pub stype: &'a SearchType,
+    pub search: &'a str,
+    pub client: Option<&'a str>,
+    pub location: Option<&'a str>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    // next_cursor of the previous page
+    pub cursor: Option<SegmentCursor>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ArchiveSearchPage {
+    pub results: Vec<SearchResult>,
+    // the search stopped before the end of the archive, because of the
+    // limit or how much one call may read, next_cursor continues it
+    pub truncated: bool,
+    pub next_cursor: Option<String>,
+}
+
+/**
+ * Run a search over archived segments, one result per segment with matches.
+ * This is what lets a new search be run over history the clients already sent.
+ * One call stops after `limit` results, MAX_SEARCH_SEGMENTS segments or
+ * MAX_SEARCH_BYTES of decompressed data, whichever comes first.
+ */
+pub async fn search_segments(search: &ArchiveSearch<'_>) -> Result<ArchiveSearchPage> {
+    let dir = archive_dir()?;
+    let matcher = Matcher::new(search.stype, search.search)?;
+    let limit = search
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_RESULTS)
+        .clamp(1, MAX_SEARCH_RESULTS) as usize;
+
+    // fetch one extra segment to know if there is more to search
+    let mut segments = sql::archive::get_segments(&sql::archive::SegmentFilter {
+        client: search.client.map(str::to_string),
+        location: search.location.map(str::to_string),
+        after: search.after,
+        before: search.before,
+        cursor: search.cursor.clone(),
+        limit: Some(MAX_SEARCH_SEGMENTS + 1),
+    })
+    .await?;
+    let more = segments.len() as i64 > MAX_SEARCH_SEGMENTS;
+    segments.truncate(MAX_SEARCH_SEGMENTS as usize);
+    let last = segments.last().map(SegmentCursor::of);
+
+    let mut client_names = std::collections::HashMap::new();
+    for client in sql::client::get_clients().await? {
+        client_names.insert(client.id, client.name);
+    }
+
+    // every segment is read and decompressed, keep it off the workers
+    let (found, stopped) =
+        web::block(move || scan_segments(&dir, segments, &matcher, limit, MAX_SEARCH_BYTES))
+            .await??;
+    // reading every segment fetched still leaves the ones after them
+    let stopped = stopped.or(last.filter(|_| more));
+
+    let results = found
+        .into_iter()
+        .map(|(segment, found)| SearchResult {
+            client_name: client_names
+                .get(&segment.client)
+                .cloned()
+                .unwrap_or_default(),
+            client_id: segment.client,
+            search_id: search.search_id,
+            search_name: search.search_name.to_string(),
+            found,
+            location: segment.location,
+            started: segment.received,
+            signature: None,
+            truncated: false,
+        })
+        .collect();
+
+    Ok(ArchiveSearchPage {
+        results,
+        truncated: stopped.is_some(),
+        next_cursor: stopped.map(|cursor| cursor.encode()),
+    })
+}
+
+// a segment and the lines of it that matched
+type SegmentMatches = (ArchiveSegment, Vec<String>);
+
+/**
+ * Match segments in order until `limit` of them had matches or reading the
+ * next one would go over `max_bytes` decompressed. At least one segment is
+ * always read so a search makes progress. Returns the segments with matches
+ * and, if it stopped early, the last segment read.
+ */
+fn scan_segments(
+    dir: &Path,
+    segments: Vec<ArchiveSegment>,
+    matcher: &Matcher,
+    limit: usize,
+    max_bytes: u64,
+) -> Result<(Vec<SegmentMatches>, Option<SegmentCursor>)> {
+    let mut found_in: Vec<SegmentMatches> = Vec::new();
+    let mut scanned: u64 = 0;
+    let mut last: Option<SegmentCursor> = None;
+    for segment in segments {
+        let size = segment.size.max(0) as u64;
+        if found_in.len() >= limit || (last.is_some() && scanned + size > max_bytes) {
+            return Ok((found_in, last));
+        }
+        scanned += size;
+        last = Some(SegmentCursor::of(&segment));
+
+        let data = match read_segment_file(dir, &segment.client, &segment.id) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("skipping archive segment {}: {}", segment.id, e);
+                continue;
+            }
+        };
+
+        let found = matcher.matching_lines(String::from_utf8_lossy(&data).as_bytes())?;
+        if !found.is_empty() {
+            found_in.push((segment, found));
+        }
+    }
+
+    Ok((found_in, None))
+}
+
+/**
+ * Delete every segment older than the retention period.
+ */
+pub async fn prune_expired() -> Result<usize> {
+    let dir = archive_dir()?;
+    let days = conf::get_archive_retention_days().unwrap_or(DEFAULT_RETENTION_DAYS);
+    let before = Utc::now() - chrono::Duration::days(days);
+
+    let expired = sql::archive::delete_segments_before(before).await?;
+    let mut files: Vec<(&str, &str)> = expired
+        .iter()
+        .map(|segment| (segment.client.as_str(), segment.id.as_str()))
+        .collect();
+    files.sort_unstable();
+    files.dedup();
+    for (clientid, id) in files {
+        // the same data sent for another location or offset is still indexed
+        if sql::archive::get_segment(clientid, id).await?.is_some() {
+            continue;
+        }
+        if let Err(e) = delete_segment(&dir, clientid, id) {
+            warn!("failed to delete archive segment {}: {}", id, e);
+        }
+    }
+
+    Ok(expired.len())
+}
+
+/**
+ * Background task applying the retention policy, runs for the lifetime
+ * of the server.
+ */
+pub async fn retention_task() {
+    let mut interval = actix_web::rt::time::interval(RETENTION_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        match prune_expired().await {
+            Ok(0) => (),
+            Ok(count) => info!("pruned {} expired archive segments", count),
+            Err(e) => warn!("error pruning archive: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_are_stored_and_checked() {
+        let dir = std::env::temp_dir().join(format!("securelog_archive_{}", std::process::id()));
+        let data = b"Jan  1 00:00:00 web01 sshd[1]: Failed password for root\n".repeat(100);
+        let compressed = zstd::encode_all(data.as_slice(), 3).unwrap();
+
+        let (id, size) = write_segment(&dir, "client1", &compressed).unwrap();
+        assert_eq!(id, hash(&compressed));
+        assert_eq!(size, data.len() as u64);
+        // the same data again is the same file
+        assert_eq!(
+            write_segment(&dir, "client1", &compressed).unwrap(),
+            (id.clone(), size)
+        );
+        // also when it is sent for several locations at once
+        let path = segment_path(&dir, "client1", &id).unwrap();
+        fs::remove_file(&path).unwrap();
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let (dir, compressed) = (dir.clone(), compressed.clone());
+                std::thread::spawn(move || write_segment(&dir, "client1", &compressed).unwrap())
+            })
+            .collect();
+        for writer in writers {
+            assert_eq!(writer.join().unwrap(), (id.clone(), size));
+        }
+        // no temp files are left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        assert_eq!(read_segment_file(&dir, "client1", &id).unwrap(), data);
+
+        fs::write(&path, zstd::encode_all(&b"edited\n"[..], 3).unwrap()).unwrap();
+        assert!(matches!(
+            read_segment_file(&dir, "client1", &id),
+            Err(ArchiveError::HashMismatch(_))
+        ));
+
+        // ids and clients end up in paths
+        assert!(matches!(
+            read_segment_file(&dir, "client1", "../../etc/passwd"),
+            Err(ArchiveError::InvalidId(_))
+        ));
+        assert!(matches!(
+            write_segment(&dir, "../client1", &compressed),
+            Err(ArchiveError::InvalidId(_))
+        ));
+        assert!(matches!(
+            write_segment(&dir, "client1", b"not zstd"),
+            Err(ArchiveError::IO(_))
+        ));
+
+        delete_segment(&dir, "client1", &id).unwrap();
+        assert!(!path.exists());
+        // already gone is fine
+        delete_segment(&dir, "client1", &id).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn searches_stop_at_the_limit_and_byte_cap() {
+        let dir = std::env::temp_dir().join(format!("securelog_scan_{}", std::process::id()));
+        let segment = |text: &str, start_offset: i64| {
+            let data = text.repeat(10);
+            let compressed = zstd::encode_all(data.as_bytes(), 3).unwrap();
+            let (id, size) = write_segment(&dir, "client1", &compressed).unwrap();
+            ArchiveSegment {
+                id,
+                client: "client1".to_string(),
+                location: "/var/log/auth.log".to_string(),
+                start_offset,
+                size: size as i64,
+                stored_size: compressed.len() as i64,
+                received: Utc::now(),
+            }
+        };
+        let segments = vec![
+            segment("Failed password for root\n", 0),
+            segment("Accepted publickey for admin\n", 250),
+            segment("Failed password for admin\n", 540),
+        ];
+        let two_segments = (segments[0].size + segments[1].size) as u64;
+        let matcher = Matcher::new(&SearchType::Contains, "Failed").unwrap();
+
+        // everything fits
+        let (found, stopped) =
+            scan_segments(&dir, segments.clone(), &matcher, 10, u64::MAX).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].1.len(), 10);
+        assert!(stopped.is_none());
+
+        // stops once it has as many segments with matches as asked for
+        let (found, stopped) =
+            scan_segments(&dir, segments.clone(), &matcher, 1, u64::MAX).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(stopped, Some(SegmentCursor::of(&segments[0])));
+
+        // or before it reads more than allowed, but always reads one segment
+        let (found, stopped) = scan_segments(&dir, segments.clone(), &matcher, 10, 1).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(stopped, Some(SegmentCursor::of(&segments[0])));
+        let (found, stopped) =
+            scan_segments(&dir, segments.clone(), &matcher, 10, two_segments).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(stopped, Some(SegmentCursor::of(&segments[1])));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
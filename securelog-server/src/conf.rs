@@ -36,7 +36,10 @@ fn check_config(config: &Config) {
     check_config_exists(config, constants::CONFIG_SERVER_LISTEN);
 
     // database config options
-    check_config_exists(config, constants::CONFIG_PG_PARAMS);
+    match config.get_string(constants::CONFIG_DB_BACKEND).as_deref() {
+        Ok("sqlite") => (),
+        _ => check_config_exists(config, constants::CONFIG_PG_PARAMS),
+    }
 }
 
 fn check_config_exists(config: &Config, key: &str) {
@@ -63,11 +66,66 @@ pub fn get_server_listen() -> Result<String, ConfigError> {
 
     config.get_string(constants::CONFIG_SERVER_LISTEN)
 }
+pub fn get_db_backend() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_DB_BACKEND)
+}
+pub fn get_sqlite_path() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_SQLITE_PATH)
+}
 pub fn get_pg_params() -> Result<String, ConfigError> {
     let config = CONFIG.read().unwrap();
 
     config.get_string(constants::CONFIG_PG_PARAMS)
 }
+pub fn get_pg_sslmode() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_PG_SSLMODE)
+}
+pub fn get_pg_ssl_ca() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_PG_SSL_CA)
+}
+pub fn get_pg_ssl_cert() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_PG_SSL_CERT)
+}
+pub fn get_pg_ssl_key() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_PG_SSL_KEY)
+}
+pub fn get_pg_pool_size() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_PG_POOL_SIZE)
+}
+pub fn get_pg_pool_timeout() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_PG_POOL_TIMEOUT)
+}
+pub fn get_pg_connect_timeout() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_PG_CONNECT_TIMEOUT)
+}
+pub fn get_pg_statement_timeout() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_PG_STATEMENT_TIMEOUT)
+}
+pub fn get_pg_recycling_method() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_PG_RECYCLING_METHOD)
+}
 pub fn get_log_dir() -> Result<String, ConfigError> {
     let config = CONFIG.read().unwrap();
 
@@ -88,3 +146,58 @@ pub fn get_use_https() -> Result<bool, ConfigError> {
 
     config.get_bool(constants::CONFIG_SERVER_HTTPS)
 }
+pub fn get_archive_dir() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_ARCHIVE_DIR)
+}
+pub fn get_archive_retention_days() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_ARCHIVE_RETENTION_DAYS)
+}
+pub fn get_retention_days() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_RETENTION_DAYS)
+}
+pub fn get_retention_max_rows() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_RETENTION_MAX_ROWS)
+}
+pub fn get_retention_export_dir() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_RETENTION_EXPORT_DIR)
+}
+pub fn get_missed_checkin_factor() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_MISSED_CHECKIN_FACTOR)
+}
+pub fn get_syslog_udp() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_SYSLOG_UDP)
+}
+pub fn get_syslog_tcp() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_SYSLOG_TCP)
+}
+pub fn get_syslog_tls() -> Result<String, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_string(constants::CONFIG_SYSLOG_TLS)
+}
+pub fn get_syslog_max_hosts() -> Result<i64, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_int(constants::CONFIG_SYSLOG_MAX_HOSTS)
+}
+pub fn get_require_signatures() -> Result<bool, ConfigError> {
+    let config = CONFIG.read().unwrap();
+
+    config.get_bool(constants::CONFIG_REQUIRE_SIGNATURES)
+}
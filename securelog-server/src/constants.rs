@@ -1,10 +1,32 @@
 pub const CONFIG_SERVER_CERT: &str = "cert";
 pub const CONFIG_SERVER_KEY: &str = "key";
 pub const CONFIG_SERVER_LISTEN: &str = "listen";
+pub const CONFIG_DB_BACKEND: &str = "db_backend";
+pub const CONFIG_SQLITE_PATH: &str = "sqlite_path";
 pub const CONFIG_PG_PARAMS: &str = "pg_params";
+pub const CONFIG_PG_SSLMODE: &str = "pg_sslmode";
+pub const CONFIG_PG_SSL_CA: &str = "pg_ssl_ca";
+pub const CONFIG_PG_SSL_CERT: &str = "pg_ssl_cert";
+pub const CONFIG_PG_SSL_KEY: &str = "pg_ssl_key";
+pub const CONFIG_PG_POOL_SIZE: &str = "pg_pool_size";
+pub const CONFIG_PG_POOL_TIMEOUT: &str = "pg_pool_timeout";
+pub const CONFIG_PG_CONNECT_TIMEOUT: &str = "pg_connect_timeout";
+pub const CONFIG_PG_STATEMENT_TIMEOUT: &str = "pg_statement_timeout";
+pub const CONFIG_PG_RECYCLING_METHOD: &str = "pg_recycling_method";
 pub const CONFIG_LOG_DIR: &str = "log_dir";
 pub const CONFIG_LOG_LEVEL: &str = "log_level";
 pub const CONFIG_LOG_STDOUT: &str = "log_stdout";
+pub const CONFIG_ARCHIVE_DIR: &str = "archive_dir";
+pub const CONFIG_ARCHIVE_RETENTION_DAYS: &str = "archive_retention_days";
+pub const CONFIG_RETENTION_DAYS: &str = "retention_days";
+pub const CONFIG_RETENTION_MAX_ROWS: &str = "retention_max_rows";
+pub const CONFIG_RETENTION_EXPORT_DIR: &str = "retention_export_dir";
+pub const CONFIG_MISSED_CHECKIN_FACTOR: &str = "missed_checkin_factor";
+pub const CONFIG_SYSLOG_UDP: &str = "syslog_udp";
+pub const CONFIG_SYSLOG_TCP: &str = "syslog_tcp";
+pub const CONFIG_SYSLOG_TLS: &str = "syslog_tls";
+pub const CONFIG_SYSLOG_MAX_HOSTS: &str = "syslog_max_hosts";
+pub const CONFIG_REQUIRE_SIGNATURES: &str = "require_signatures";
 
 // only available with 'debug' feature enabled
 pub const CONFIG_SERVER_HTTPS: &str = "https";
@@ -12,3 +34,15 @@ pub const CONFIG_SERVER_HTTPS: &str = "https";
 pub const SEARCH_REGEX: i32 = 1;
 pub const SEARCH_CONTAINS: i32 = 2;
 pub const SEARCH_WILDCARD: i32 = 3;
+
+pub const TAG_SOURCE_ADMIN: i32 = 1;
+pub const TAG_SOURCE_CLIENT: i32 = 2;
+
+pub const RUN_ERROR_MISSING: i32 = 1;
+pub const RUN_ERROR_PERMISSION_DENIED: i32 = 2;
+pub const RUN_ERROR_INVALID_SEARCH: i32 = 3;
+pub const RUN_ERROR_IO: i32 = 4;
+
+pub const FILE_ADDED: i32 = 1;
+pub const FILE_REMOVED: i32 = 2;
+pub const FILE_MODIFIED: i32 = 3;
@@ -0,0 +1,327 @@
+/*
+File integrity monitoring.
+
+Clients with integrity_paths send a snapshot of the files under them after
+each run. The last snapshot of every client is kept as its baseline, what
+differs in the next one is stored as integrity changes and alerted about
+through the webhooks. The first snapshot of a client, and files under paths
+it just started watching, only become the baseline.
+*/
+use crate::models::{FileChange, FileChangeKind, FileState, IntegritySnapshot};
+use crate::sql::integrity::IntegrityBaseline;
+use crate::{sql, webhooks};
+use securelog_proto::is_under;
+use std::collections::HashMap;
+use std::io::Read;
+
+// files named in one alert, the rest are counted
+const MAX_ALERT_FILES: usize = 10;
+// largest snapshot accepted from a client once decompressed, clients send at
+// most 100000 files
+pub const MAX_SNAPSHOT_SIZE: u64 = 128 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("SnapshotError(IO({0}))")]
+    IO(#[from] std::io::Error),
+
+    #[error("SnapshotError(Json({0}))")]
+    Json(#[from] serde_json::Error),
+
+    #[error("SnapshotError(snapshot larger than {0} bytes)")]
+    TooLarge(u64),
+}
+
+/**
+ * Read a snapshot as clients send it, zstd compressed json.
+ */
+pub fn decode_snapshot(compressed: &[u8]) -> Result<IntegritySnapshot, SnapshotError> {
+    let decoder = zstd::stream::read::Decoder::new(compressed)?;
+    let mut json = Vec::new();
+    decoder.take(MAX_SNAPSHOT_SIZE + 1).read_to_end(&mut json)?;
+
+    if json.len() as u64 > MAX_SNAPSHOT_SIZE {
+        Err(SnapshotError::TooLarge(MAX_SNAPSHOT_SIZE))
+    } else {
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/**
+ * What changed from `baseline` in `snapshot`, by path.
+ */
+pub fn diff(baseline: Option<&IntegrityBaseline>, snapshot: &IntegritySnapshot) -> Vec<FileChange> {
+    let (watched, known): (&[String], &[FileState]) = match baseline {
+        Some(baseline) => (&baseline.paths, &baseline.files),
+        None => (&[], &[]),
+    };
+    let before: HashMap<&str, &FileState> = known
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let after: HashMap<&str, &FileState> = snapshot
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+
+    let mut changes = Vec::new();
+    for file in &snapshot.files {
+        match before.get(file.path.as_str()) {
+            Some(old) => {
+                let changed = file.changes(old);
+                if !changed.is_empty() {
+                    changes.push(FileChange {
+                        path: file.path.clone(),
+                        kind: FileChangeKind::Modified,
+                        changed: changed.into_iter().map(str::to_string).collect(),
+                        before: Some((*old).clone()),
+                        after: Some(file.clone()),
+                    });
+                }
+            }
+            None if is_under(&file.path, watched) => changes.push(FileChange {
+                path: file.path.clone(),
+                kind: FileChangeKind::Added,
+                changed: Vec::new(),
+                before: None,
+                after: Some(file.clone()),
+            }),
+            // newly watched
+            None => {}
+        }
+    }
+    for file in known {
+        let path = file.path.as_str();
+        if !after.contains_key(path) && snapshot.watches(path) && !snapshot.is_unreadable(path) {
+            changes.push(FileChange {
+                path: file.path.clone(),
+                kind: FileChangeKind::Removed,
+                changed: Vec::new(),
+                before: Some(file.clone()),
+                after: None,
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/**
+ * The baseline after `snapshot`. Files that could not be read keep what
+ * the old baseline had, files no longer watched are forgotten.
+ */
+pub fn next_baseline(
+    baseline: Option<&IntegrityBaseline>,
+    snapshot: &IntegritySnapshot,
+) -> IntegrityBaseline {
+    let mut files = snapshot.files.clone();
+    if let Some(baseline) = baseline {
+        files.extend(
+            baseline
+                .files
+                .iter()
+                .filter(|file| {
+                    snapshot.watches(&file.path)
+                        && snapshot.is_unreadable(&file.path)
+                        && !snapshot.files.iter().any(|new| new.path == file.path)
+                })
+                .cloned(),
+        );
+    }
+
+    IntegrityBaseline {
+        paths: snapshot.paths.clone(),
+        files,
+        updated: snapshot.started,
+    }
+}
+
+fn alert_message(client_id: &str, changes: &[FileChange]) -> String {
+    let mut files: Vec<String> = changes
+        .iter()
+        .take(MAX_ALERT_FILES)
+        .map(|change| match change.kind {
+            FileChangeKind::Added => format!("{} added", change.path),
+            FileChangeKind::Removed => format!("{} removed", change.path),
+            FileChangeKind::Modified => {
+                format!("{} modified ({})", change.path, change.changed.join(", "))
+            }
+        })
+        .collect();
+    if changes.len() > MAX_ALERT_FILES {
+        files.push(format!("and {} more", changes.len() - MAX_ALERT_FILES));
+    }
+
+    format!(
+        "file integrity changes on client {}: {}",
+        client_id,
+        files.join(", ")
+    )
+}
+
+/**
+ * Compare a client's snapshot with its baseline, store what changed and
+ * alert about it. The snapshot becomes the new baseline.
+ */
+pub async fn record_snapshot(
+    client_id: &str,
+    snapshot: &IntegritySnapshot,
+) -> sql::Result<Vec<FileChange>> {
+    let baseline = sql::integrity::get_integrity_baseline(client_id).await?;
+
+    let changes = diff(baseline.as_ref(), snapshot);
+    let next = next_baseline(baseline.as_ref(), snapshot);
+    sql::integrity::set_integrity_baseline(client_id, &next, &changes).await?;
+
+    if baseline.is_none() {
+        info!(
+            "client {} sent its first integrity snapshot, {} files",
+            client_id,
+            next.files.len()
+        );
+    }
+    if !changes.is_empty() {
+        webhooks::send_message(&alert_message(client_id, &changes)).await?;
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 19, 8, minute, 0).unwrap()
+    }
+
+    fn file(path: &str, sha256: &str) -> FileState {
+        FileState {
+            path: path.to_string(),
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            size: 10,
+            mtime: at(0),
+            sha256: Some(sha256.to_string()),
+            link: None,
+        }
+    }
+
+    fn snapshot(paths: &[&str], files: Vec<FileState>, unreadable: &[&str]) -> IntegritySnapshot {
+        IntegritySnapshot {
+            started: at(30),
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            files,
+            unreadable: unreadable.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+
+    fn kinds(changes: &[FileChange]) -> Vec<(&str, FileChangeKind)> {
+        changes
+            .iter()
+            .map(|change| (change.path.as_str(), change.kind))
+            .collect()
+    }
+
+    #[test]
+    fn first_snapshot_is_the_baseline() {
+        let first = snapshot(&["/etc/passwd"], vec![file("/etc/passwd", "aa")], &[]);
+
+        assert!(diff(None, &first).is_empty());
+        assert_eq!(next_baseline(None, &first).files, first.files);
+    }
+
+    #[test]
+    fn changes_are_found() {
+        let baseline = next_baseline(
+            None,
+            &snapshot(
+                &["/etc/passwd", "/etc/sudoers.d"],
+                vec![
+                    file("/etc/passwd", "aa"),
+                    file("/etc/sudoers.d/admins", "bb"),
+                    file("/etc/sudoers.d/old", "cc"),
+                ],
+                &[],
+            ),
+        );
+
+        let mut passwd = file("/etc/passwd", "dd");
+        passwd.uid = 1000;
+        let next = snapshot(
+            &["/etc/passwd", "/etc/sudoers.d", "/usr/bin"],
+            vec![
+                passwd,
+                file("/etc/sudoers.d/admins", "bb"),
+                file("/etc/sudoers.d/backdoor", "ee"),
+                // newly watched, not a change
+                file("/usr/bin/sudo", "ff"),
+            ],
+            &[],
+        );
+
+        let changes = diff(Some(&baseline), &next);
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                ("/etc/passwd", FileChangeKind::Modified),
+                ("/etc/sudoers.d/backdoor", FileChangeKind::Added),
+                ("/etc/sudoers.d/old", FileChangeKind::Removed),
+            ]
+        );
+        assert_eq!(changes[0].changed, vec!["sha256", "uid"]);
+        assert_eq!(
+            alert_message("c1", &changes),
+            "file integrity changes on client c1: /etc/passwd modified (sha256, uid), \
+            /etc/sudoers.d/backdoor added, /etc/sudoers.d/old removed"
+        );
+    }
+
+    #[test]
+    fn unreadable_and_unwatched_files() {
+        let baseline = next_baseline(
+            None,
+            &snapshot(
+                &["/etc/shadow", "/etc/hosts"],
+                vec![file("/etc/shadow", "aa"), file("/etc/hosts", "bb")],
+                &[],
+            ),
+        );
+
+        // shadow could not be read, hosts is no longer watched
+        let next = snapshot(&["/etc/shadow"], Vec::new(), &["/etc/shadow"]);
+        assert!(diff(Some(&baseline), &next).is_empty());
+
+        let kept = next_baseline(Some(&baseline), &next);
+        assert_eq!(kept.files, vec![file("/etc/shadow", "aa")]);
+        assert_eq!(kept.updated, at(30));
+    }
+
+    #[test]
+    fn snapshots_are_decoded_with_a_size_limit() {
+        let sent = snapshot(&["/etc"], vec![file("/etc/passwd", "a")], &["/etc/shadow"]);
+        let compressed =
+            zstd::encode_all(serde_json::to_vec(&sent).unwrap().as_slice(), 3).unwrap();
+        assert_eq!(decode_snapshot(&compressed).unwrap(), sent);
+
+        assert!(matches!(decode_snapshot(b"{}"), Err(SnapshotError::IO(_))));
+        let compressed = zstd::encode_all(b"{}".as_slice(), 3).unwrap();
+        assert!(matches!(
+            decode_snapshot(&compressed),
+            Err(SnapshotError::Json(_))
+        ));
+
+        // a few KB that decompress past the limit
+        let bomb = vec![b' '; MAX_SNAPSHOT_SIZE as usize + 1];
+        let compressed = zstd::encode_all(bomb.as_slice(), 3).unwrap();
+        assert!(compressed.len() < 64 * 1024);
+        assert!(matches!(
+            decode_snapshot(&compressed),
+            Err(SnapshotError::TooLarge(_))
+        ));
+    }
+}
@@ -1,3 +1,44 @@
+var clients_by_id = {};
+
+function tag_text(tag) {
+    return tag.key + "=" + tag.value + (tag.source == "Admin" ? "" : " (client)");
+}
+
+function fill_tags_form(id) {
+    var client = clients_by_id[id];
+    if (client === undefined) {
+        return;
+    }
+
+    document.getElementById("tags-tags").value = client.tags
+        .filter(function(tag) { return tag.source == "Admin"; })
+        .map(function(tag) { return tag.key + "=" + tag.value; })
+        .join("\n");
+}
+
+function fill_settings_form(id) {
+    var settings_xhr = new XMLHttpRequest();
+    settings_xhr.open("GET", "/api/user/client/settings?id=" + encodeURIComponent(id));
+    settings_xhr.setRequestHeader("Accept", "application/json");
+
+    settings_xhr.onreadystatechange = function() {
+        if (settings_xhr.readyState == 4 && settings_xhr.status == 200) {
+            var settings = JSON.parse(settings_xhr.responseText);
+            var value = function(v) { return v === null ? "" : v; };
+
+            document.getElementById("settings-log-level").value = value(settings.log_level);
+            document.getElementById("settings-poll-interval").value = value(settings.poll_interval);
+            document.getElementById("settings-max-run-bytes").value = value(settings.limits.max_run_bytes);
+            document.getElementById("settings-max-run-secs").value = value(settings.limits.max_run_secs);
+            document.getElementById("settings-max-search-bytes").value = value(settings.limits.max_search_bytes);
+            document.getElementById("settings-max-search-lines").value = value(settings.limits.max_search_lines);
+            document.getElementById("settings-max-search-secs").value = value(settings.limits.max_search_secs);
+            document.getElementById("settings-max-read-rate").value = value(settings.limits.max_read_rate);
+        }
+    }
+    settings_xhr.send();
+}
+
 var xhr = new XMLHttpRequest();
 xhr.open("GET", "/api/user/client/fetch_all");
 xhr.setRequestHeader("Accept", "application/json");
@@ -9,8 +50,11 @@ xhr.onreadystatechange = function() {
 
         var select = document.getElementById("delete-clientid");
         var select2 = document.getElementById("enabled-clientid");
+        var select3 = document.getElementById("tags-clientid");
+        var select4 = document.getElementById("settings-clientid");
 
         for (var i = 0; i < clients.length; i++) {
+            clients_by_id[clients[i].id] = clients[i];
 
             var id = document.createElement("td");
             id.textContent = clients[i].id;
@@ -24,11 +68,27 @@ xhr.onreadystatechange = function() {
             var lastconnect = document.createElement("td");
             lastconnect.textContent = clients[i].lastconnect;
 
+            var tags = document.createElement("td");
+            tags.textContent = clients[i].tags.map(tag_text).join(", ");
+
+            var inventory = [
+                clients[i].agent_version,
+                clients[i].os,
+                clients[i].hostname,
+                clients[i].capabilities.join(", "),
+            ];
+
             var tr = document.createElement("tr");
             tr.appendChild(id);
             tr.appendChild(enabled);
             tr.appendChild(created);
             tr.appendChild(lastconnect);
+            tr.appendChild(tags);
+            for (var j = 0; j < inventory.length; j++) {
+                var td = document.createElement("td");
+                td.textContent = inventory[j] === null ? "" : inventory[j];
+                tr.appendChild(td);
+            }
 
             table.appendChild(tr);
 
@@ -38,8 +98,172 @@ xhr.onreadystatechange = function() {
             option.textContent = clients[i].id + ": " + clients[i].name;
             
             select.appendChild(option.cloneNode(true));
+            select3.appendChild(option.cloneNode(true));
+            select4.appendChild(option.cloneNode(true));
             select2.appendChild(option);
         }
+
+        select3.onchange = function() {
+            fill_tags_form(select3.value);
+        };
+        fill_tags_form(select3.value);
+
+        select4.onchange = function() {
+            fill_settings_form(select4.value);
+        };
+        fill_settings_form(select4.value);
+    }
+}
+xhr.send();
+
+var status_xhr = new XMLHttpRequest();
+status_xhr.open("GET", "/api/user/client/status");
+status_xhr.setRequestHeader("Accept", "application/json");
+
+status_xhr.onreadystatechange = function() {
+    if (status_xhr.readyState == 4) {
+        var statuses = JSON.parse(status_xhr.responseText);
+        var table = document.getElementById("status-table-body");
+
+        for (var i = 0; i < statuses.length; i++) {
+            var status = statuses[i];
+            var error = status.last_error === null ? "" : status.last_error_at + ": " + status.last_error;
+            var cells = [
+                status.name,
+                status.agent_version,
+                status.last_seen,
+                status.last_run,
+                status.last_result,
+                error,
+                status.overdue,
+            ];
+
+            var tr = document.createElement("tr");
+            if (status.overdue) {
+                tr.className = "table-danger";
+            }
+            for (var j = 0; j < cells.length; j++) {
+                var td = document.createElement("td");
+                td.textContent = cells[j] === null ? "" : cells[j];
+                tr.appendChild(td);
+            }
+
+            table.appendChild(tr);
+        }
     }
 }
-xhr.send();
\ No newline at end of file
+status_xhr.send();
+
+// the fields of a file state that changed, all of them for added and removed files
+function file_state_text(state, fields) {
+    if (state === null) {
+        return "";
+    }
+    if (fields.length == 0) {
+        fields = ["sha256", "link", "mode", "uid", "gid", "size", "mtime"];
+    }
+
+    return fields
+        .filter(function(field) { return state[field] !== null; })
+        .map(function(field) {
+            var value = field == "mode" ? state.mode.toString(8) : state[field];
+            return field + ": " + value;
+        })
+        .join("\n");
+}
+
+var integrity_xhr = new XMLHttpRequest();
+integrity_xhr.open("GET", "/api/user/client/integrity");
+integrity_xhr.setRequestHeader("Accept", "application/json");
+
+integrity_xhr.onreadystatechange = function() {
+    if (integrity_xhr.readyState == 4) {
+        var changes = JSON.parse(integrity_xhr.responseText);
+        var table = document.getElementById("integrity-table-body");
+
+        for (var i = 0; i < changes.length; i++) {
+            var change = changes[i];
+            var cells = [
+                change.detected,
+                change.client_name,
+                change.path,
+                change.kind,
+                file_state_text(change.before, change.changed),
+                file_state_text(change.after, change.changed),
+            ];
+
+            var tr = document.createElement("tr");
+            for (var j = 0; j < cells.length; j++) {
+                var td = document.createElement("td");
+                td.textContent = cells[j];
+                td.style.whiteSpace = "pre-wrap";
+                tr.appendChild(td);
+            }
+
+            table.appendChild(tr);
+        }
+    }
+}
+integrity_xhr.send();
+
+var enrollment_xhr = new XMLHttpRequest();
+enrollment_xhr.open("GET", "/api/user/enrollment/fetch");
+enrollment_xhr.setRequestHeader("Accept", "application/json");
+
+enrollment_xhr.onreadystatechange = function() {
+    if (enrollment_xhr.readyState == 4) {
+        var tokens = JSON.parse(enrollment_xhr.responseText);
+        var table = document.getElementById("enrollment-table-body");
+        var select = document.getElementById("enrollment-delete-id");
+
+        for (var i = 0; i < tokens.length; i++) {
+            var token = tokens[i];
+            var cells = [
+                token.id,
+                token.name,
+                Object.keys(token.tags).map(function(key) { return key + "=" + token.tags[key]; }).join(", "),
+                token.uses + " of " + token.max_uses,
+                token.expires,
+                token.created_by,
+            ];
+
+            var tr = document.createElement("tr");
+            for (var j = 0; j < cells.length; j++) {
+                var td = document.createElement("td");
+                td.textContent = cells[j];
+                tr.appendChild(td);
+            }
+            table.appendChild(tr);
+
+            var option = document.createElement("option");
+            option.value = token.id;
+            option.textContent = token.id + " " + token.name;
+            select.appendChild(option);
+        }
+    }
+}
+enrollment_xhr.send();
+
+// the token is in the answer, so the form is sent from here instead of
+// following a redirect
+window.addEventListener("DOMContentLoaded", function() {
+    document.getElementById("enrollment-form").addEventListener("submit", function(event) {
+        event.preventDefault();
+
+        var create_xhr = new XMLHttpRequest();
+        create_xhr.open("POST", "/api/user/enrollment/create");
+        create_xhr.setRequestHeader("Content-Type", "application/x-www-form-urlencoded");
+
+        create_xhr.onreadystatechange = function() {
+            if (create_xhr.readyState == 4) {
+                var output = document.getElementById("enrollment-token");
+                if (create_xhr.status == 200) {
+                    output.textContent = JSON.parse(create_xhr.responseText).token;
+                } else {
+                    output.textContent = "failed: " + create_xhr.responseText;
+                }
+            }
+        }
+        create_xhr.send(new URLSearchParams(new FormData(event.target)).toString());
+    });
+});
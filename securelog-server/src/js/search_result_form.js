@@ -17,4 +17,25 @@ xhr.onreadystatechange = function () {
         }
     }
 }
-xhr.send();
\ No newline at end of file
+xhr.send();
+
+var xhr2 = new XMLHttpRequest();
+xhr2.open("GET", "/api/user/get_searches");
+xhr2.setRequestHeader("Accept", "application/json");
+
+xhr2.onreadystatechange = function () {
+    if (xhr2.readyState == 4) {
+        var searches = JSON.parse(xhr2.responseText);
+
+        var select = document.getElementById("search-select");
+
+        for (var i = 0; i < searches.length; i++) {
+            var option = document.createElement("option");
+            option.setAttribute("value", searches[i].id);
+            option.textContent = searches[i].id + ": " + searches[i].name;
+
+            select.appendChild(option);
+        }
+    }
+}
+xhr2.send();
\ No newline at end of file
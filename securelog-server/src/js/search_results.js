@@ -1,48 +1,87 @@
 var params = new URLSearchParams(window.location.search);
 
-var url = "/api/user/get_search_results?" + params.toString();
+// drop empty form fields, and send datetime-local values as UTC
+var query = new URLSearchParams();
+params.forEach(function(value, key) {
+    if (value == "") {
+        return;
+    }
+    if (key == "before" || key == "after") {
+        value = new Date(value).toISOString();
+    }
+    query.set(key, value);
+});
 
-var xhr = new XMLHttpRequest();
-xhr.open("GET", url);
-xhr.setRequestHeader("Accept", "application/json");
+function addResult(container, result) {
+    var card = document.createElement("div");
+    card.setAttribute("class", "card");
 
-xhr.onreadystatechange = function() {
-    if (xhr.readyState == 4) {
-        var results = JSON.parse(xhr.responseText);
+    var header = document.createElement("div");
+    header.setAttribute("class", "card-header");
+    header.textContent = result.search_id + ": " + result.search_name + " on " + result.client_name;
+    card.appendChild(header);
 
-        var container = document.getElementById("mycontainer");
-        container.appendChild(document.createElement("br"));
+    var body = document.createElement("div");
+    body.setAttribute("class", "card-body");
+    var h5 = document.createElement("h5");
+    h5.textContent = "Time: " + result.started;
 
-        for (var i = 0; i < results.length; i++) {
-            var result = results[i];
+    body.appendChild(h5);
 
-            var card = document.createElement("div");
-            card.setAttribute("class", "card");
+    if (result.truncated) {
+        var partial = document.createElement("p");
+        partial.setAttribute("class", "text-warning");
+        partial.textContent = "Partial: the client stopped this search at a resource limit";
+        body.appendChild(partial);
+    }
 
-            var header = document.createElement("div");
-            header.setAttribute("class", "card-header");
-            header.textContent = result.search_id + ": " + result.search_name;
-            card.appendChild(header);
+    for (var j = 0; j < result.found.length; j++) {
+        body.appendChild(document.createElement("hr"));
+        var para = document.createElement("p");
+        para.textContent = result.found[j];
+        body.appendChild(para);
+    }
 
-            var body = document.createElement("div");
-            body.setAttribute("class", "card-body");
-            var h5 = document.createElement("h5");
-            h5.textContent = "Time: " + result.started;
+    card.appendChild(body);
 
-            body.appendChild(h5);
+    container.appendChild(card);
+    container.appendChild(document.createElement("br"));
+}
 
-            for (var j = 0; j < result.found.length; j++) {
-                body.appendChild(document.createElement("hr"));
-                var para = document.createElement("p");
-                para.textContent = result.found[j];
-                body.appendChild(para);
-            }
+function loadResults(cursor) {
+    if (cursor) {
+        query.set("cursor", cursor);
+    }
+    var url = "/api/user/get_search_results?" + query.toString();
 
-            card.appendChild(body);
+    var xhr = new XMLHttpRequest();
+    xhr.open("GET", url);
+    xhr.setRequestHeader("Accept", "application/json");
 
-            container.appendChild(card);
-            container.appendChild(document.createElement("br"));
+    xhr.onreadystatechange = function() {
+        if (xhr.readyState == 4) {
+            var page = JSON.parse(xhr.responseText);
+
+            var container = document.getElementById("results");
+
+            for (var i = 0; i < page.results.length; i++) {
+                addResult(container, page.results[i]);
+            }
+
+            var more = document.getElementById("load-more");
+            if (page.next_cursor) {
+                more.onclick = function() {
+                    loadResults(page.next_cursor);
+                };
+                more.hidden = false;
+            } else {
+                more.hidden = true;
+            }
         }
     }
+    xhr.send();
 }
-xhr.send();
\ No newline at end of file
+
+window.addEventListener("DOMContentLoaded", function() {
+    loadResults(null);
+});
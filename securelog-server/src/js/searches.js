@@ -2,16 +2,171 @@ var xhr = new XMLHttpRequest();
 xhr.open("GET", "/api/user/get_searches");
 xhr.setRequestHeader("Accept", "application/json");
 
+var searches_by_id = {};
+
+function fill_edit_form(id) {
+    var search = searches_by_id[id];
+    if (search === undefined) {
+        return;
+    }
+
+    document.getElementById("edit-name").value = search.name;
+    document.getElementById("edit-stype").value = search.stype;
+    document.getElementById("edit-search").value = search.search;
+    document.getElementById("edit-locations").value = search.locations.join("\n");
+    document.getElementById("edit-selector").value = search.selector === null ? "" : search.selector;
+    document.getElementById("edit-realtime").checked = search.realtime;
+}
+
+function fill_enable_form(id) {
+    var search = searches_by_id[id];
+    if (search === undefined) {
+        return;
+    }
+
+    document.getElementById("enable-enabled").checked = search.enabled;
+}
+
+function load_history(id) {
+    document.getElementById("revert-search-id").value = id;
+
+    var body = document.getElementById("history-tbody");
+    body.textContent = "";
+
+    var history_xhr = new XMLHttpRequest();
+    history_xhr.open("GET", "/api/user/search_history?id=" + encodeURIComponent(id));
+    history_xhr.setRequestHeader("Accept", "application/json");
+
+    history_xhr.onreadystatechange = function() {
+        if (history_xhr.readyState == 4 && history_xhr.status == 200) {
+            var versions = JSON.parse(history_xhr.responseText);
+
+            for (var i = 0; i < versions.length; i++) {
+                var version = versions[i];
+                var tr = document.createElement("tr");
+
+                var cells = [
+                    version.version,
+                    version.changed,
+                    version.changed_by === null ? "" : version.changed_by,
+                    version.name,
+                    version.stype,
+                    version.search,
+                    version.locations.join(','),
+                    version.selector === null ? "" : version.selector,
+                    version.realtime ? "Yes" : "No",
+                ];
+                for (var j = 0; j < cells.length; j++) {
+                    var td = document.createElement("td");
+                    td.textContent = cells[j];
+                    tr.appendChild(td);
+                }
+
+                body.appendChild(tr);
+            }
+        }
+    }
+    history_xhr.send();
+}
+
+function health_text(health) {
+    if (health === undefined) {
+        return "not run";
+    }
+
+    var text = health.failing_clients + "/" + health.clients + " clients failing";
+    if (health.missing_files > 0) {
+        text += ", " + health.missing_files + " files missing";
+    }
+    return text;
+}
+
+function load_health() {
+    var health_xhr = new XMLHttpRequest();
+    health_xhr.open("GET", "/api/user/search_health");
+    health_xhr.setRequestHeader("Accept", "application/json");
+
+    health_xhr.onreadystatechange = function() {
+        if (health_xhr.readyState == 4 && health_xhr.status == 200) {
+            var healths = JSON.parse(health_xhr.responseText);
+            var by_search = {};
+            for (var i = 0; i < healths.length; i++) {
+                by_search[healths[i].search_id] = healths[i];
+            }
+
+            for (var id in searches_by_id) {
+                var cell = document.getElementById("health-" + id);
+                var health = by_search[id];
+                cell.textContent = health_text(health);
+                if (health !== undefined && health.failing_clients > 0) {
+                    cell.className = "table-danger";
+                }
+            }
+        }
+    }
+    health_xhr.send();
+}
+
+function load_runs(id) {
+    var body = document.getElementById("runs-tbody");
+    body.textContent = "";
+
+    var runs_xhr = new XMLHttpRequest();
+    runs_xhr.open("GET", "/api/user/search_runs?id=" + encodeURIComponent(id));
+    runs_xhr.setRequestHeader("Accept", "application/json");
+
+    runs_xhr.onreadystatechange = function() {
+        if (runs_xhr.readyState == 4 && runs_xhr.status == 200) {
+            var runs = JSON.parse(runs_xhr.responseText);
+
+            for (var i = 0; i < runs.length; i++) {
+                var run = runs[i];
+                var tr = document.createElement("tr");
+                if (run.error !== null) {
+                    tr.className = "table-danger";
+                }
+
+                var cells = [
+                    run.client_name,
+                    run.location,
+                    run.started,
+                    run.duration_ms,
+                    run.lines_scanned,
+                    run.bytes_read,
+                    run.error === null ? "" : run.error.kind + ": " + run.error.message,
+                ];
+                for (var j = 0; j < cells.length; j++) {
+                    var td = document.createElement("td");
+                    td.textContent = cells[j];
+                    tr.appendChild(td);
+                }
+
+                body.appendChild(tr);
+            }
+        }
+    }
+    runs_xhr.send();
+}
+
 xhr.onreadystatechange = function() {
     if (xhr.readyState == 4) {
         var searches = JSON.parse(xhr.responseText);
 
         var body = document.getElementById("searches-tbody");
 
-        var delete_select = document.getElementById("delete-search-id");
+        var selects = [
+            document.getElementById("delete-search-id"),
+            document.getElementById("retention-search-id"),
+            document.getElementById("edit-search-id"),
+            document.getElementById("enable-search-id"),
+            document.getElementById("clone-search-id"),
+            document.getElementById("history-search-id"),
+            document.getElementById("runs-search-id"),
+        ];
 
         for (var i = 0; i < searches.length; i++) {
             var search = searches[i];
+            searches_by_id[search.id] = search;
 
             var id = document.createElement("td");
             id.textContent = search.id;
@@ -28,6 +183,17 @@ xhr.onreadystatechange = function() {
             var locations = document.createElement("td");
             locations.textContent = search.locations.join(',');
 
+            var selector = document.createElement("td");
+            selector.textContent = search.selector === null ? "" : search.selector;
+
+            var realtime = document.createElement("td");
+            realtime.textContent = search.realtime ? "Yes" : "No";
+
+            var enabled = document.createElement("td");
+            enabled.textContent = search.enabled ? "Yes" : "No";
+
+            var health = document.createElement("td");
+            health.id = "health-" + search.id;
 
             var tr = document.createElement("tr");
 
@@ -36,15 +202,38 @@ xhr.onreadystatechange = function() {
             tr.appendChild(stype);
             tr.appendChild(text);
             tr.appendChild(locations);
+            tr.appendChild(selector);
+            tr.appendChild(realtime);
+            tr.appendChild(enabled);
+            tr.appendChild(health);
 
             body.appendChild(tr);
 
             var option = document.createElement("option");
             option.setAttribute("value", search.id);
             option.textContent = search.id + ": " + search.name;
-            delete_select.appendChild(option);
+            for (var j = 0; j < selects.length; j++) {
+                selects[j].appendChild(option.cloneNode(true));
+            }
+        }
+
+        var edit_select = document.getElementById("edit-search-id");
+        edit_select.addEventListener("change", function() { fill_edit_form(edit_select.value); });
+        var enable_select = document.getElementById("enable-search-id");
+        enable_select.addEventListener("change", function() { fill_enable_form(enable_select.value); });
+        var history_select = document.getElementById("history-search-id");
+        history_select.addEventListener("change", function() { load_history(history_select.value); });
+        var runs_select = document.getElementById("runs-search-id");
+        runs_select.addEventListener("change", function() { load_runs(runs_select.value); });
+
+        load_health();
+
+        if (searches.length > 0) {
+            fill_edit_form(edit_select.value);
+            fill_enable_form(enable_select.value);
+            load_history(history_select.value);
+            load_runs(runs_select.value);
         }
-        
     }
 }
-xhr.send();
\ No newline at end of file
+xhr.send();
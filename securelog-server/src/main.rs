@@ -10,11 +10,20 @@ extern crate log;
 extern crate clap;
 #[macro_use]
 extern crate thiserror;
+#[macro_use]
+extern crate async_trait;
 
+mod archive;
 mod conf;
 mod constants;
+mod integrity;
 mod models;
+mod monitor;
+mod retention;
+mod selector;
+mod signing;
 mod sql;
+mod syslog;
 mod web;
 mod webhooks;
 
@@ -33,7 +42,24 @@ async fn main() {
                     .required(false),
             ),
         )
-        .subcommand(Command::new("initialize-db").about("Initialize database or update database"))
+        .subcommand(
+            Command::new("initialize-db")
+                .about("Initialize database or update database")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Print the SQL of pending migrations without applying them")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .help("Migrate to this version instead of the latest, can go down")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i32))
+                        .required(false),
+                ),
+        )
         .arg(
             Arg::new("config")
                 .short('c')
@@ -63,14 +89,56 @@ async fn main() {
 
     setup_log().unwrap();
 
-    // will check database, initialize/update if needed
-    sql::initialize_db().await.unwrap();
+    // fail clearly here rather than on the first query
+    if let Err(e) = sql::connect().await {
+        exit_with_error("failed to connect to database", &e);
+    }
+
+    if let Some(smatches) = matches.subcommand_matches("initialize-db") {
+        let dry_run = smatches.get_flag("dry-run");
+        let target = smatches.get_one::<i32>("target").copied();
+
+        let steps = match sql::migrate(target, dry_run).await {
+            Ok(steps) => steps,
+            Err(e) => exit_with_error("failed to migrate the database", &e),
+        };
+        if steps.is_empty() {
+            println!("Database is up to date, nothing to apply");
+        }
+        for step in &steps {
+            if dry_run {
+                println!(
+                    "-- v{}: {} ({:?})",
+                    step.migration.version, step.migration.name, step.direction
+                );
+                print!("{}", step.sql());
+            } else {
+                println!(
+                    "Applied v{}: {} ({:?}), database now at v{}",
+                    step.migration.version,
+                    step.migration.name,
+                    step.direction,
+                    step.version_after()
+                );
+            }
+        }
 
-    if let Some(_matches) = matches.subcommand_matches("initialize-db") {
         info!("Database initialized, exiting!");
         std::process::exit(0);
     }
 
+    // will check database, initialize/update if needed
+    let steps = match sql::initialize_db().await {
+        Ok(steps) => steps,
+        Err(e) => exit_with_error("failed to initialize the database", &e),
+    };
+    for step in steps {
+        info!(
+            "Applied database migration v{}: {}",
+            step.migration.version, step.migration.name
+        );
+    }
+
     if let Some(smatches) = matches.subcommand_matches("create-user") {
         let username = match smatches.get_one::<String>("username") {
             Some(username) => username.to_string(),
@@ -93,10 +161,42 @@ async fn main() {
         create_first_user().await.unwrap();
     }
 
+    actix_web::rt::spawn(retention::retention_task());
+    if archive::is_enabled() {
+        actix_web::rt::spawn(archive::retention_task());
+    }
+    actix_web::rt::spawn(monitor::checkin_task());
+    if syslog::is_enabled() {
+        if let Err(e) = syslog::start().await {
+            error!("failed to start the syslog receiver: {}", e);
+            eprintln!("failed to start the syslog receiver: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     webhooks::send_message("starting up!").await.unwrap();
     web::start().await.unwrap();
 }
 
+/**
+ * Log and print why startup failed, then exit.
+ */
+fn exit_with_error(context: &str, e: &dyn std::error::Error) -> ! {
+    // the cause, i.e. why a TLS handshake failed, is in the sources
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            message.push_str(&format!(": {}", cause_message));
+        }
+        source = cause.source();
+    }
+    error!("{}: {}", context, message);
+    eprintln!("{}: {}", context, message);
+    std::process::exit(1);
+}
+
 fn prompt_user_input(prompt: &str) -> std::io::Result<String> {
     use std::io;
     use std::io::Write;
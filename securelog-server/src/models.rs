@@ -1,21 +1,28 @@
 use crate::constants;
 use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-pub enum SearchType {
-    Regex,
-    Contains,
-    Wildcard,
+pub use securelog_proto::{
+    ClientSearchResult, ClientSettings, FileState, IntegritySnapshot, ResourceLimits, RunError,
+    RunErrorKind, Search, SearchResult, SearchRunReport, SearchType,
+};
+
+/**
+ * How a value is stored in an INT column.
+ */
+pub trait SqlCode: Sized {
+    fn sql_code(&self) -> i32;
+    fn from_sql_code(code: i32) -> Option<Self>;
 }
-impl SearchType {
-    pub fn sql_code(&self) -> i32 {
+impl SqlCode for SearchType {
+    fn sql_code(&self) -> i32 {
         match self {
             SearchType::Regex => constants::SEARCH_REGEX,
             SearchType::Contains => constants::SEARCH_CONTAINS,
             SearchType::Wildcard => constants::SEARCH_WILDCARD,
         }
     }
-    pub fn from_sql_code(code: i32) -> Option<SearchType> {
+    fn from_sql_code(code: i32) -> Option<SearchType> {
         match code {
             constants::SEARCH_REGEX => Some(SearchType::Regex),
             constants::SEARCH_CONTAINS => Some(SearchType::Contains),
@@ -25,47 +32,111 @@ impl SearchType {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Search {
-    pub id: i32,
+impl SqlCode for RunErrorKind {
+    fn sql_code(&self) -> i32 {
+        match self {
+            RunErrorKind::Missing => constants::RUN_ERROR_MISSING,
+            RunErrorKind::PermissionDenied => constants::RUN_ERROR_PERMISSION_DENIED,
+            RunErrorKind::InvalidSearch => constants::RUN_ERROR_INVALID_SEARCH,
+            RunErrorKind::Io => constants::RUN_ERROR_IO,
+        }
+    }
+    fn from_sql_code(code: i32) -> Option<RunErrorKind> {
+        match code {
+            constants::RUN_ERROR_MISSING => Some(RunErrorKind::Missing),
+            constants::RUN_ERROR_PERMISSION_DENIED => Some(RunErrorKind::PermissionDenied),
+            constants::RUN_ERROR_INVALID_SEARCH => Some(RunErrorKind::InvalidSearch),
+            constants::RUN_ERROR_IO => Some(RunErrorKind::Io),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+pub enum FileChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+impl SqlCode for FileChangeKind {
+    fn sql_code(&self) -> i32 {
+        match self {
+            FileChangeKind::Added => constants::FILE_ADDED,
+            FileChangeKind::Removed => constants::FILE_REMOVED,
+            FileChangeKind::Modified => constants::FILE_MODIFIED,
+        }
+    }
+    fn from_sql_code(code: i32) -> Option<FileChangeKind> {
+        match code {
+            constants::FILE_ADDED => Some(FileChangeKind::Added),
+            constants::FILE_REMOVED => Some(FileChangeKind::Removed),
+            constants::FILE_MODIFIED => Some(FileChangeKind::Modified),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * How a file differs from the client's integrity baseline, see integrity.rs.
+ */
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: FileChangeKind,
+    // the fields that changed, see FileState::changes
+    pub changed: Vec<String>,
+    // None for added files
+    pub before: Option<FileState>,
+    // None for removed files
+    pub after: Option<FileState>,
+}
+
+/**
+ * A saved version of a search, every create, edit and revert adds one.
+ */
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchVersion {
+    pub search_id: i32,
+    pub version: i32,
     pub name: String,
     pub stype: SearchType,
     pub search: String,
     pub locations: Vec<String>,
+    pub changed: DateTime<Utc>,
+    // None for versions from before history was kept
+    pub changed_by: Option<String>,
+    pub selector: Option<String>,
+    pub realtime: bool,
+}
+
+/**
+ * Where a client tag came from. Tags set by an admin win over tags with the
+ * same key the client reports itself.
+ */
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+pub enum TagSource {
+    Admin,
+    Client,
 }
-impl Search {
-    pub fn new(
-        id: i32,
-        name: String,
-        stype: SearchType,
-        search: String,
-        locations: Vec<String>,
-    ) -> Search {
-        Search {
-            id,
-            name,
-            stype,
-            search,
-            locations,
+impl SqlCode for TagSource {
+    fn sql_code(&self) -> i32 {
+        match self {
+            TagSource::Admin => constants::TAG_SOURCE_ADMIN,
+            TagSource::Client => constants::TAG_SOURCE_CLIENT,
+        }
+    }
+    fn from_sql_code(code: i32) -> Option<TagSource> {
+        match code {
+            constants::TAG_SOURCE_ADMIN => Some(TagSource::Admin),
+            constants::TAG_SOURCE_CLIENT => Some(TagSource::Client),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SearchResult {
-    pub client_id: String,
-    pub client_name: String,
-    pub search_id: i32,
-    pub search_name: String,
-    pub found: Vec<String>,
-    pub location: String,
-    pub started: DateTime<Utc>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClientSearchResult {
-    pub search_id: i32,
-    pub found: Vec<String>,
-    pub location: String,
-    pub started: DateTime<Utc>,
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ClientTag {
+    pub key: String,
+    pub value: String,
+    pub source: TagSource,
 }
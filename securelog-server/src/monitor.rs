@@ -0,0 +1,143 @@
+/*
+Client check-in monitoring.
+
+Clients poll should_run every minute, every request updates their last_seen.
+A background task alerts through the webhooks when an enabled client has not
+been seen for missed_checkin_factor times the scan interval, once per outage,
+and again when the client is back.
+*/
+use crate::sql::client::ClientStatus;
+use crate::{conf, sql, webhooks};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+// how often the clients are checked
+const CHECKIN_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_MISSED_CHECKIN_FACTOR: i64 = 3;
+
+/**
+ * How long a client can go without checking in, None if the alerts are
+ * disabled.
+ */
+async fn missed_checkin_after() -> sql::Result<Option<chrono::Duration>> {
+    let factor = conf::get_missed_checkin_factor().unwrap_or(DEFAULT_MISSED_CHECKIN_FACTOR);
+    if factor <= 0 {
+        return Ok(None);
+    }
+
+    let interval = sql::get_scan_schedule().await?.get_interval();
+    Ok(chrono::Duration::from_std(interval)
+        .ok()
+        .and_then(|interval| interval.checked_mul(factor as i32)))
+}
+
+fn is_overdue(status: &ClientStatus, after: chrono::Duration, now: DateTime<Utc>) -> bool {
+    // clients from before last_seen count from their creation
+    status.enabled && status.last_seen.unwrap_or(status.created) + after < now
+}
+
+/**
+ * Status of every client, with overdue set.
+ */
+pub async fn client_statuses() -> sql::Result<Vec<ClientStatus>> {
+    let mut statuses = sql::client::get_client_statuses().await?;
+
+    if let Some(after) = missed_checkin_after().await? {
+        let now = Utc::now();
+        for status in &mut statuses {
+            status.overdue = is_overdue(status, after, now);
+        }
+    }
+
+    Ok(statuses)
+}
+
+/**
+ * Alert about clients that stopped checking in, and about the ones that
+ * are back.
+ */
+pub async fn check_clients() -> sql::Result<()> {
+    for status in client_statuses().await? {
+        let last_seen = status
+            .last_seen
+            .map(|seen| seen.to_rfc3339())
+            .unwrap_or_else(|| "never".to_string());
+
+        if status.overdue && status.missed_alert.is_none() {
+            webhooks::send_message(&format!(
+                "client {} ({}) missed its check-in, last seen {}",
+                status.name, status.id, last_seen
+            ))
+            .await?;
+            sql::client::set_client_missed_alert(&status.id, Some(Utc::now())).await?;
+        } else if !status.overdue && status.missed_alert.is_some() {
+            // disabled clients are not expected to check in, forget the alert quietly
+            if status.enabled {
+                webhooks::send_message(&format!(
+                    "client {} ({}) checked in again, last seen {}",
+                    status.name, status.id, last_seen
+                ))
+                .await?;
+            }
+            sql::client::set_client_missed_alert(&status.id, None).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Background task checking the clients, runs for the lifetime of the server.
+ */
+pub async fn checkin_task() {
+    let mut interval = actix_web::rt::time::interval(CHECKIN_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = check_clients().await {
+            warn!("error checking client check-ins: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(enabled: bool, last_seen: Option<DateTime<Utc>>) -> ClientStatus {
+        ClientStatus {
+            id: "c1".to_string(),
+            name: "web01".to_string(),
+            enabled,
+            created: Utc::now() - chrono::Duration::days(7),
+            agent_version: None,
+            last_seen,
+            last_run: None,
+            last_result: None,
+            last_error: None,
+            last_error_at: None,
+            missed_alert: None,
+            overdue: false,
+        }
+    }
+
+    #[test]
+    fn clients_are_overdue_after_missed_checkins() {
+        let now = Utc::now();
+        let after = chrono::Duration::minutes(90);
+
+        assert!(!is_overdue(
+            &status(true, Some(now - chrono::Duration::minutes(89))),
+            after,
+            now
+        ));
+        assert!(is_overdue(
+            &status(true, Some(now - chrono::Duration::minutes(91))),
+            after,
+            now
+        ));
+        // never seen, counts from creation
+        assert!(is_overdue(&status(true, None), after, now));
+        assert!(!is_overdue(&status(false, None), after, now));
+    }
+}
@@ -0,0 +1,122 @@
+/*
+Retention for search_results.
+
+Every search can keep results for a number of days and/or a number of rows,
+falling back to the global retention_days/retention_max_rows settings. A
+background task deletes expired rows in batches, writing them to
+retention_export_dir first if it is set.
+*/
+use crate::sql::retention::{ExpiredResult, PruneTarget};
+use crate::{conf, sql};
+use chrono::Utc;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// how often expired results are pruned
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// rows deleted per transaction, keeps locks short on big tables
+const PRUNE_BATCH_SIZE: i64 = 1000;
+
+fn prune_target(search: Option<i32>, days: Option<i64>, rows: Option<i64>) -> Option<PruneTarget> {
+    if days.is_none() && rows.is_none() {
+        return None;
+    }
+
+    Some(PruneTarget {
+        search,
+        older_than: days.map(|days| Utc::now() - chrono::Duration::days(days)),
+        keep_rows: rows,
+    })
+}
+
+/**
+ * Append expired rows to today's export file as json lines.
+ */
+fn export_expired(dir: &str, expired: &[ExpiredResult]) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = PathBuf::from(dir).join(format!(
+        "search_results-{}.jsonl",
+        Utc::now().format("%Y-%m-%d")
+    ));
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for result in expired {
+        writeln!(file, "{}", serde_json::to_string(result)?)?;
+    }
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/**
+ * Apply the retention policies once. Returns the number of rows deleted.
+ */
+pub async fn prune_expired() -> sql::Result<usize> {
+    let global_days = conf::get_retention_days().ok();
+    let global_rows = conf::get_retention_max_rows().ok();
+    let export_dir = conf::get_retention_export_dir().ok();
+
+    let mut targets: Vec<PruneTarget> = Vec::new();
+    for retention in sql::retention::get_search_retentions().await? {
+        let days = retention.days.map(i64::from).or(global_days);
+        let rows = retention.rows.map(i64::from).or(global_rows);
+
+        if let Some(target) = prune_target(Some(retention.search_id), days, rows) {
+            targets.push(target);
+        }
+    }
+    // results left behind by deleted searches
+    if let Some(target) = prune_target(None, global_days, global_rows) {
+        targets.push(target);
+    }
+
+    let mut total = 0;
+    for target in &targets {
+        loop {
+            let export_dir = export_dir.clone();
+            let deleted = match sql::retention::prune_search_results(
+                target,
+                PRUNE_BATCH_SIZE,
+                move |expired| match &export_dir {
+                    Some(dir) => export_expired(dir, expired),
+                    None => Ok(()),
+                },
+            )
+            .await
+            {
+                Ok(deleted) => deleted,
+                // the other targets are still pruned
+                Err(e) => {
+                    warn!("error pruning search results of {:?}: {}", target, e);
+                    break;
+                }
+            };
+
+            total += deleted;
+            if deleted < PRUNE_BATCH_SIZE as usize {
+                break;
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+/**
+ * Background task applying the retention policies, runs for the lifetime
+ * of the server.
+ */
+pub async fn retention_task() {
+    let mut interval = actix_web::rt::time::interval(RETENTION_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        match prune_expired().await {
+            Ok(0) => (),
+            Ok(count) => info!("pruned {} expired search results", count),
+            Err(e) => warn!("error pruning search results: {}", e),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+/*
+Search selectors.
+
+A selector picks the clients a search is sent to by their tags. It is a
+comma separated list of terms that all have to match:
+
+    role=web        the tag role is web
+    role=web|db     the tag role is web or db
+    role!=db        the tag role is not db, or there is no role tag
+    role            there is a role tag
+    !role           there is no role tag
+
+A search without a selector goes to every client.
+*/
+use securelog_proto::is_tag_word;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SelectorError {
+    #[error("invalid selector term {0:?}")]
+    Term(String),
+
+    #[error("empty selector")]
+    Empty,
+}
+
+#[derive(Debug, PartialEq)]
+enum Term {
+    Has(String),
+    Missing(String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+}
+impl Term {
+    fn parse(term: &str) -> Result<Term, SelectorError> {
+        let invalid = || SelectorError::Term(term.to_string());
+
+        let values = |values: &str| -> Result<Vec<String>, SelectorError> {
+            values
+                .split('|')
+                .map(|value| {
+                    let value = value.trim();
+                    if is_tag_word(value) {
+                        Ok(value.to_string())
+                    } else {
+                        Err(invalid())
+                    }
+                })
+                .collect()
+        };
+        let key = |key: &str| -> Result<String, SelectorError> {
+            let key = key.trim();
+            if is_tag_word(key) {
+                Ok(key.to_string())
+            } else {
+                Err(invalid())
+            }
+        };
+
+        let term = term.trim();
+        if let Some((k, v)) = term.split_once("!=") {
+            Ok(Term::NotIn(key(k)?, values(v)?))
+        } else if let Some((k, v)) = term.split_once('=') {
+            Ok(Term::In(key(k)?, values(v)?))
+        } else if let Some(k) = term.strip_prefix('!') {
+            Ok(Term::Missing(key(k)?))
+        } else {
+            Ok(Term::Has(key(term)?))
+        }
+    }
+
+    fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        match self {
+            Term::Has(key) => tags.contains_key(key),
+            Term::Missing(key) => !tags.contains_key(key),
+            Term::In(key, values) => tags.get(key).is_some_and(|tag| values.contains(tag)),
+            Term::NotIn(key, values) => !tags.get(key).is_some_and(|tag| values.contains(tag)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Selector {
+    terms: Vec<Term>,
+}
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Selector, SelectorError> {
+        if selector.trim().is_empty() {
+            return Err(SelectorError::Empty);
+        }
+
+        Ok(Selector {
+            terms: selector
+                .split(',')
+                .map(Term::parse)
+                .collect::<Result<Vec<Term>, SelectorError>>()?,
+        })
+    }
+
+    pub fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.terms.iter().all(|term| term.matches(tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> BTreeMap<String, String> {
+        tags.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn matches(selector: &str, client: &[(&str, &str)]) -> bool {
+        Selector::parse(selector).unwrap().matches(&tags(client))
+    }
+
+    #[test]
+    fn terms_match_tags() {
+        let web = [("role", "web"), ("env", "prod")];
+        let db = [("role", "db")];
+
+        assert!(matches("role=web", &web));
+        assert!(!matches("role=web", &db));
+        assert!(matches("role=web|db", &db));
+        assert!(matches("role=web, env=prod", &web));
+        assert!(!matches("role=web,env=test", &web));
+
+        assert!(matches("role!=db", &web));
+        assert!(!matches("role!=db", &db));
+        assert!(matches("env!=prod", &db));
+
+        assert!(matches("env", &web));
+        assert!(!matches("env", &db));
+        assert!(matches("!env", &db));
+        assert!(!matches("!env", &web));
+    }
+
+    #[test]
+    fn invalid_selectors_are_rejected() {
+        assert_eq!(Selector::parse(" "), Err(SelectorError::Empty));
+        for selector in [
+            "role=",
+            "=web",
+            "role=web,",
+            "role==web",
+            "role=web db",
+            "!",
+        ] {
+            assert!(Selector::parse(selector).is_err(), "{}", selector);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH};
+use securelog_proto::{signed_message, MAX_SIGNATURE_AGE};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("SignatureError(Base64({0}))")]
+    Base64(#[from] base64::DecodeError),
+
+    #[error("SignatureError(Ed25519({0}))")]
+    Ed25519(#[from] ed25519_dalek::SignatureError),
+
+    #[error("SignatureError(invalid public key length {0})")]
+    KeyLength(usize),
+
+    #[error("SignatureError(missing signature time)")]
+    MissingTime,
+
+    #[error("SignatureError(signature time {0} is too far from now)")]
+    Stale(i64),
+
+    #[error("SignatureError(signature was already used)")]
+    Replayed,
+}
+pub type Result<T> = std::result::Result<T, SignatureError>;
+
+lazy_static! {
+    // signatures accepted by check_signature, see SeenSignatures
+    static ref SEEN_SIGNATURES: SeenSignatures = SeenSignatures::default();
+}
+
+/**
+ * Parse a base64 encoded Ed25519 public key, as sent by the client on creation.
+ */
+pub fn parse_public_key(pubkey: &str) -> Result<VerifyingKey> {
+    let bytes = BASE64.decode(pubkey.trim())?;
+    let bytes: [u8; PUBLIC_KEY_LENGTH] = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| SignatureError::KeyLength(bytes.len()))?;
+
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/**
+ * Verify a base64 encoded signature over data with the client's public key.
+ */
+pub fn verify(pubkey: &str, data: &[u8], signature: &str) -> Result<()> {
+    let key = parse_public_key(pubkey)?;
+    let signature = Signature::from_slice(&BASE64.decode(signature.trim())?)?;
+
+    key.verify_strict(data, &signature)?;
+
+    Ok(())
+}
+
+/**
+ * Verify a signature over the unix time it was made at and the data, see
+ * securelog_proto::signed_message. Times more than MAX_SIGNATURE_AGE
+ * seconds from `now` are refused.
+ */
+pub fn verify_signed(
+    pubkey: &str,
+    data: &[u8],
+    signature: &str,
+    time: i64,
+    now: i64,
+) -> Result<()> {
+    if (now - time).abs() > MAX_SIGNATURE_AGE {
+        return Err(SignatureError::Stale(time));
+    }
+
+    verify(pubkey, &signed_message(time, data), signature)
+}
+
+/**
+ * Signatures accepted within the last MAX_SIGNATURE_AGE seconds. Older ones
+ * are forgotten, verify_signed refuses them anyway.
+ */
+#[derive(Default)]
+pub struct SeenSignatures {
+    seen: Mutex<HashMap<String, i64>>,
+}
+impl SeenSignatures {
+    /**
+     * Remember a signature, fails if it was seen before.
+     */
+    pub fn insert(&self, signature: &str, time: i64, now: i64) -> Result<()> {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, seen_time| now - *seen_time <= MAX_SIGNATURE_AGE);
+
+        if seen.insert(signature.trim().to_string(), time).is_some() {
+            return Err(SignatureError::Replayed);
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Check a signature a client sent with a payload: it is valid for the data
+ * and time, the time is recent and the signature wasn't used before.
+ */
+pub fn check_signature(
+    pubkey: &str,
+    data: &[u8],
+    signature: &str,
+    time: Option<i64>,
+) -> Result<()> {
+    let time = time.ok_or(SignatureError::MissingTime)?;
+    let now = chrono::Utc::now().timestamp();
+
+    verify_signed(pubkey, data, signature, time, now)?;
+    SEEN_SIGNATURES.insert(signature, time, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn pubkey(key: &SigningKey) -> String {
+        BASE64.encode(key.verifying_key().to_bytes())
+    }
+
+    fn sign(key: &SigningKey, time: i64, data: &[u8]) -> String {
+        BASE64.encode(key.sign(&signed_message(time, data)).to_bytes())
+    }
+
+    #[test]
+    fn public_keys_are_parsed() {
+        assert!(parse_public_key(&pubkey(&key(1))).is_ok());
+        assert!(parse_public_key(&format!(" {}\n", pubkey(&key(1)))).is_ok());
+
+        assert!(matches!(
+            parse_public_key("not base64!"),
+            Err(SignatureError::Base64(_))
+        ));
+        assert!(matches!(
+            parse_public_key(&BASE64.encode([1u8; 16])),
+            Err(SignatureError::KeyLength(16))
+        ));
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let key = key(1);
+        let signature = sign(&key, NOW, b"results");
+
+        assert!(verify_signed(&pubkey(&key), b"results", &signature, NOW, NOW).is_ok());
+
+        // tampered payload
+        assert!(matches!(
+            verify_signed(&pubkey(&key), b"resultz", &signature, NOW, NOW),
+            Err(SignatureError::Ed25519(_))
+        ));
+        // the time is covered by the signature too
+        assert!(matches!(
+            verify_signed(&pubkey(&key), b"results", &signature, NOW + 1, NOW),
+            Err(SignatureError::Ed25519(_))
+        ));
+        // wrong key
+        assert!(matches!(
+            verify_signed(
+                &pubkey(&super::tests::key(2)),
+                b"results",
+                &signature,
+                NOW,
+                NOW
+            ),
+            Err(SignatureError::Ed25519(_))
+        ));
+        // malformed signatures
+        assert!(matches!(
+            verify_signed(&pubkey(&key), b"results", "not base64!", NOW, NOW),
+            Err(SignatureError::Base64(_))
+        ));
+        assert!(matches!(
+            verify_signed(
+                &pubkey(&key),
+                b"results",
+                &BASE64.encode([1u8; 10]),
+                NOW,
+                NOW
+            ),
+            Err(SignatureError::Ed25519(_))
+        ));
+    }
+
+    #[test]
+    fn stale_signatures_are_refused() {
+        let key = key(1);
+        let old = NOW - MAX_SIGNATURE_AGE - 1;
+        let future = NOW + MAX_SIGNATURE_AGE + 1;
+
+        assert!(matches!(
+            verify_signed(&pubkey(&key), b"results", &sign(&key, old, b"results"), old, NOW),
+            Err(SignatureError::Stale(time)) if time == old
+        ));
+        assert!(matches!(
+            verify_signed(
+                &pubkey(&key),
+                b"results",
+                &sign(&key, future, b"results"),
+                future,
+                NOW
+            ),
+            Err(SignatureError::Stale(_))
+        ));
+        // a little clock skew is fine
+        let skewed = NOW + 30;
+        assert!(verify_signed(
+            &pubkey(&key),
+            b"results",
+            &sign(&key, skewed, b"results"),
+            skewed,
+            NOW
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn signatures_are_used_once() {
+        let seen = SeenSignatures::default();
+
+        assert!(seen.insert("first", NOW, NOW).is_ok());
+        assert!(seen.insert("second", NOW, NOW).is_ok());
+        assert!(matches!(
+            seen.insert("first", NOW, NOW + 10),
+            Err(SignatureError::Replayed)
+        ));
+        assert!(matches!(
+            seen.insert(" second\n", NOW, NOW + 10),
+            Err(SignatureError::Replayed)
+        ));
+
+        // forgotten once verify_signed refuses them as stale
+        assert!(seen
+            .insert("first", NOW, NOW + MAX_SIGNATURE_AGE + 1)
+            .is_ok());
+        assert_eq!(seen.seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn signatures_need_a_time() {
+        let key = key(3);
+        assert!(matches!(
+            check_signature(
+                &pubkey(&key),
+                b"results",
+                &sign(&key, NOW, b"results"),
+                None
+            ),
+            Err(SignatureError::MissingTime)
+        ));
+
+        let now = chrono::Utc::now().timestamp();
+        let signature = sign(&key, now, b"results");
+        assert!(check_signature(&pubkey(&key), b"results", &signature, Some(now)).is_ok());
+        assert!(matches!(
+            check_signature(&pubkey(&key), b"results", &signature, Some(now)),
+            Err(SignatureError::Replayed)
+        ));
+    }
+}
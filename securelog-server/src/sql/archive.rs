@@ -0,0 +1,112 @@
+use super::{storage, Result};
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ArchiveSegment {
+    pub id: String,
+    pub client: String,
+    pub location: String,
+    pub start_offset: i64,
+    pub size: i64,
+    pub stored_size: i64,
+    pub received: DateTime<Utc>,
+}
+
+/**
+ * Record a stored segment. Segments are content addressed, so sending the
+ * same data twice for the same location and offset only keeps the first
+ * entry. The same data at another location or offset gets its own entry
+ * and shares the stored file.
+ */
+pub async fn insert_segment(segment: &ArchiveSegment) -> Result<bool> {
+    storage().insert_segment(segment).await
+}
+
+pub async fn get_segment(clientid: &str, id: &str) -> Result<Option<ArchiveSegment>> {
+    storage().get_segment(clientid, id).await
+}
+
+/**
+ * Position in the segments, segments are ordered oldest first by
+ * (received, start_offset, client, location, id), which is unique.
+ * Sent to users as an opaque token to continue an archive search with.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentCursor {
+    pub received: DateTime<Utc>,
+    pub start_offset: i64,
+    pub client: String,
+    pub location: String,
+    pub id: String,
+}
+impl SegmentCursor {
+    pub fn of(segment: &ArchiveSegment) -> SegmentCursor {
+        SegmentCursor {
+            received: segment.received,
+            start_offset: segment.start_offset,
+            client: segment.client.clone(),
+            location: segment.location.clone(),
+            id: segment.id.clone(),
+        }
+    }
+    pub fn encode(&self) -> String {
+        use base64::Engine;
+        // the location goes last, it is the only part that can hold a ':'
+        let token = format!(
+            "{}:{}:{}:{}:{}",
+            self.received.timestamp_micros(),
+            self.start_offset,
+            self.client,
+            self.id,
+            self.location
+        );
+
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token)
+    }
+    pub fn decode(token: &str) -> Option<SegmentCursor> {
+        use base64::Engine;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()?;
+        let token = String::from_utf8(bytes).ok()?;
+        let mut parts = token.splitn(5, ':');
+
+        Some(SegmentCursor {
+            received: DateTime::from_timestamp_micros(parts.next()?.parse().ok()?)?,
+            start_offset: parts.next()?.parse().ok()?,
+            client: parts.next()?.to_string(),
+            id: parts.next()?.to_string(),
+            location: parts.next()?.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SegmentFilter {
+    pub client: Option<String>,
+    pub location: Option<String>,
+    // received after/before
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    // only segments after this one
+    pub cursor: Option<SegmentCursor>,
+    // None lists every segment
+    pub limit: Option<i64>,
+}
+
+/**
+ * List segments matching the filter. Ordered oldest first so segments of
+ * a file read in order.
+ */
+pub async fn get_segments(filter: &SegmentFilter) -> Result<Vec<ArchiveSegment>> {
+    storage().get_segments(filter).await
+}
+
+/**
+ * Remove segments received before `before` from the index, returning
+ * them so the caller can delete the stored files.
+ */
+pub async fn delete_segments_before(before: DateTime<Utc>) -> Result<Vec<ArchiveSegment>> {
+    storage().delete_segments_before(before).await
+}
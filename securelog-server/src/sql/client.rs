@@ -1,184 +1,241 @@
-use super::{random_string, Result, SqlError, POOL};
+use super::storage::NewClient;
+use super::{enrollment, random_string, storage, Result, SqlError};
+use crate::models::{ClientSettings, ClientTag, TagSource};
 use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 
 pub async fn client_authenticate(id: &str, token: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT * FROM clients WHERE id=$1;", &[&id])
-        .await?;
-
-    if !rows.is_empty() {
-        let row = &rows[0];
-        let enabled: bool = row.get("enabled");
-
-        if !enabled {
-            return Ok(false);
-        }
-
-        let sqltoken: String = row.get("token");
+    match storage().get_client_token(id).await? {
+        Some(row) => {
+            if !row.enabled {
+                return Ok(false);
+            }
 
-        let valid = bcrypt::verify(token, &sqltoken)?;
+            let valid = bcrypt::verify(token, &row.token)?;
 
-        if valid {
-            let result = client
-                .execute(
-                    "UPDATE clients SET lastconnect=$1 WHERE id=$2;",
-                    &[&Utc::now(), &id],
-                )
-                .await?;
-            if result < 1 {
-                warn!("lastconnect not updated! id={}", id);
+            if valid {
+                let now = Utc::now();
+                let result = storage().set_client_last_connect(id, now).await?;
+                if result < 1 {
+                    warn!("lastconnect not updated! id={}", id);
+                }
+                storage().set_client_last_seen(id, now).await?;
             }
+            Ok(valid)
+        }
+        None => {
+            info!("Client does not exist {}", id);
+            Err(SqlError::ClientNotExist(id.to_string()))
         }
-        Ok(valid)
-    } else {
-        info!("Client does not exist {}", id);
-        Err(SqlError::ClientNotExist(id.to_string()))
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ClientAuth {
-    id: String,
+    pub id: String,
     token: String,
 }
-pub async fn client_auth_create(name: &str) -> Result<ClientAuth> {
+/**
+ * Create a new client. `pubkey` is the base64 Ed25519 key the client
+ * signs its search results with.
+ */
+pub async fn client_auth_create(name: &str, pubkey: Option<&str>) -> Result<ClientAuth> {
     if client_name_exists(name).await? {
         return Err(SqlError::ClientNameExists(name.to_string()));
     }
+    let (auth, sqltoken) = new_client_auth().await?;
+
+    storage()
+        .insert_client(&NewClient {
+            id: &auth.id,
+            token: &sqltoken,
+            name,
+            pubkey,
+            created: Utc::now(),
+        })
+        .await?;
+
+    Ok(auth)
+}
+
+/**
+ * Create a new client with an enrollment token, None if the token can not
+ * be used. The client gets the tags of the token. Whether the name is taken
+ * is only checked for usable tokens.
+ */
+pub async fn client_enroll(
+    enrollment_token: &str,
+    name: &str,
+    pubkey: Option<&str>,
+) -> Result<Option<ClientAuth>> {
+    let (auth, sqltoken) = new_client_auth().await?;
+    let now = Utc::now();
+
+    let used = storage()
+        .enroll_client(
+            &enrollment::token_hash(enrollment_token),
+            &NewClient {
+                id: &auth.id,
+                token: &sqltoken,
+                name,
+                pubkey,
+                created: now,
+            },
+            now,
+        )
+        .await?;
+
+    Ok(used.map(|used| {
+        info!(
+            "client {} ({}) enrolled with token {} ({})",
+            name, auth.id, used.id, used.name
+        );
+        auth
+    }))
+}
+
+// id and token for a new client, and the hash of the token to store
+async fn new_client_auth() -> Result<(ClientAuth, String)> {
     let token = random_string(32);
     let sqltoken = bcrypt::hash(&token, bcrypt::DEFAULT_COST)?;
 
-    let client = POOL.get().await?;
-
     let mut id = random_string(32);
     while client_exists(&id).await? {
         id = random_string(32);
     }
 
-    let ts = Utc::now();
-
-    let _result = client
-        .execute(
-            "INSERT INTO clients 
-            (id, token, name, enabled, created, lastconnect) 
-            VALUES($1, $2, $3, $4, $5, $6);",
-            &[&id, &sqltoken, &name, &true, &ts, &ts],
-        )
-        .await?;
-
-    let _result2 = client
-        .execute(
-            "INSERT INTO client_schedule 
-                (id, lastrun, manualrun)
-                VALUES($1, $2, $3);",
-            &[&id, &Utc::now(), &false],
-        )
-        .await?;
-
-    Ok(ClientAuth { id, token: token })
+    Ok((ClientAuth { id, token }, sqltoken))
 }
 
 pub async fn delete_client(id: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let result = client
-        .execute("DELETE FROM clients WHERE id=$1;", &[&id])
-        .await?;
-
-    Ok(result > 0)
+    storage().delete_client(id).await
 }
 
 pub async fn client_exists(id: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT id FROM clients WHERE id=$1;", &[&id])
-        .await?;
-
-    Ok(!rows.is_empty())
+    storage().client_exists(id).await
 }
 
 pub async fn client_name_exists(name: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT id FROM clients WHERE name=$1 LIMIT 1;", &[&name])
-        .await?;
-
-    Ok(!rows.is_empty())
+    storage().client_name_exists(name).await
 }
 
 pub async fn client_set_enabled(id: &str, enabled: bool) -> Result<()> {
-    let client = POOL.get().await?;
-
-    let _result = client
-        .execute(
-            "UPDATE clients SET enabled=$1 WHERE id=$2;",
-            &[&enabled, &id],
-        )
-        .await?;
-
-    Ok(())
+    storage().set_client_enabled(id, enabled).await
 }
 
-pub async fn client_enabled(id: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT enabled FROM clients WHERE id=$id LIMIT 1;", &[&id])
-        .await?;
+/**
+ * Get the registered public key of a client, None if the client was
+ * created before result signing.
+ */
+pub async fn get_client_pubkey(id: &str) -> Result<Option<String>> {
+    storage()
+        .get_client_pubkey(id)
+        .await?
+        .ok_or_else(|| SqlError::ClientNotExist(id.to_string()))
+}
 
-    if !rows.is_empty() {
-        Ok(rows[0].get("enabled"))
-    } else {
-        Ok(false)
-    }
+/**
+ * Register the public key of a client created before result signing.
+ * A registered key is never replaced, returns false if there already is one.
+ */
+pub async fn set_client_pubkey(id: &str, pubkey: &str) -> Result<bool> {
+    storage().set_client_pubkey(id, pubkey).await
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SLClient {
     pub id: String,
     pub name: String,
     pub enabled: bool,
     pub created: DateTime<Utc>,
     pub lastconnect: DateTime<Utc>,
+    pub tags: Vec<ClientTag>,
+    #[serde(flatten)]
+    pub inventory: ClientInventory,
 }
 
-pub async fn get_clients() -> Result<Vec<SLClient>> {
-    let client = POOL.get().await?;
-
-    let mut clients: Vec<SLClient> = Vec::new();
+/**
+ * What a client reports about itself at login, None for clients that are
+ * too old to send it.
+ */
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ClientInventory {
+    pub agent_version: Option<String>,
+    pub git_sha: Option<String>,
+    pub os: Option<String>,
+    pub hostname: Option<String>,
+    // see securelog_proto::capability
+    pub capabilities: Vec<String>,
+}
 
-    let rows = client.query("SELECT * FROM clients;", &[]).await?;
+// capabilities are stored comma separated
+pub fn capabilities_to_sql(capabilities: &[String]) -> String {
+    capabilities.join(",")
+}
+pub fn capabilities_from_sql(capabilities: Option<String>) -> Vec<String> {
+    capabilities
+        .map(|capabilities| {
+            capabilities
+                .split(',')
+                .filter(|capability| !capability.is_empty())
+                .map(|capability| capability.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
 
-    for row in rows {
-        let slclient = SLClient {
-            id: row.get("id"),
-            enabled: row.get("enabled"),
-            name: row.get("name"),
-            created: row.get("created"),
-            lastconnect: row.get("lastconnect"),
-        };
+pub async fn get_clients() -> Result<Vec<SLClient>> {
+    storage().get_clients().await
+}
 
-        clients.push(slclient);
-    }
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClientStatus {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub created: DateTime<Utc>,
+    // version the agent reported at its last login
+    pub agent_version: Option<String>,
+    // last request from the client
+    pub last_seen: Option<DateTime<Utc>>,
+    // last time the client started a scan
+    pub last_run: Option<DateTime<Utc>>,
+    // start of the newest search result the client sent
+    pub last_result: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    // when the missed check-in alert went out, None while the client checks in
+    pub missed_alert: Option<DateTime<Utc>>,
+    // the client has not checked in for missed_checkin_factor intervals
+    pub overdue: bool,
+}
 
-    Ok(clients)
+pub async fn get_client_statuses() -> Result<Vec<ClientStatus>> {
+    storage().get_client_statuses().await
 }
 
-pub async fn get_client_names() -> Result<Vec<String>> {
-    let client = POOL.get().await?;
+/**
+ * Note a request from the client, the check-in monitor alerts when these
+ * stop.
+ */
+pub async fn client_seen(id: &str) -> Result<()> {
+    storage().set_client_last_seen(id, Utc::now()).await
+}
 
-    let rows = client.query("SELECT name FROM clients;", &[]).await?;
+pub async fn set_client_inventory(id: &str, inventory: &ClientInventory) -> Result<()> {
+    storage().set_client_inventory(id, inventory).await
+}
 
-    let mut names: Vec<String> = Vec::new();
-    for row in rows {
-        names.push(row.get("name"));
-    }
+/**
+ * Keep the last error seen for a client, shown in its status.
+ */
+pub async fn set_client_error(id: &str, error: &str) -> Result<()> {
+    storage().set_client_error(id, error, Utc::now()).await
+}
 
-    Ok(names)
+pub async fn set_client_missed_alert(id: &str, ts: Option<DateTime<Utc>>) -> Result<()> {
+    storage().set_client_missed_alert(id, ts).await
 }
 
 #[derive(Debug)]
@@ -187,47 +244,97 @@ pub struct ClientLastRun {
     pub manualrun: bool,
 }
 pub async fn get_client_last_run(id: &str) -> Result<ClientLastRun> {
-    let client = POOL.get().await?;
+    storage()
+        .get_client_last_run(id)
+        .await?
+        .ok_or_else(|| SqlError::ClientNotExist(id.to_string()))
+}
 
-    let rows = client
-        .query("SELECT * FROM client_schedule WHERE id=$1 LIMIT 1;", &[&id])
-        .await?;
+pub async fn set_client_last_run(id: &str, dt: DateTime<Utc>) -> Result<()> {
+    storage().set_client_last_run(id, dt).await
+}
 
-    if !rows.is_empty() {
-        let row = &rows[0];
-        let manualrun: Option<bool> = row.get("manualrun");
+pub async fn get_client_tags(id: &str) -> Result<Vec<ClientTag>> {
+    storage().get_client_tags(id).await
+}
 
-        Ok(ClientLastRun {
-            lastrun: row.get("lastrun"),
-            manualrun: manualrun.unwrap_or(false),
-        })
-    } else {
-        Err(SqlError::ClientNotExist(id.to_string()))
+/**
+ * Replace the tags a client has from `source`, the client reports its own
+ * at login and admins set theirs in the UI.
+ */
+pub async fn set_client_tags(id: &str, source: TagSource, tags: &[(String, String)]) -> Result<()> {
+    if !client_exists(id).await? {
+        return Err(SqlError::ClientNotExist(id.to_string()));
     }
+
+    storage().set_client_tags(id, source, tags).await
 }
 
-pub async fn set_client_last_run(id: &str, dt: DateTime<Utc>) -> Result<()> {
-    let client = POOL.get().await?;
+/**
+ * The tags selectors are matched against, an admin tag replaces a client
+ * tag with the same key.
+ */
+pub fn effective_tags(tags: &[ClientTag]) -> BTreeMap<String, String> {
+    let mut effective = BTreeMap::new();
+    for source in [TagSource::Client, TagSource::Admin] {
+        for tag in tags.iter().filter(|tag| tag.source == source) {
+            effective.insert(tag.key.clone(), tag.value.clone());
+        }
+    }
 
-    let _result = client
-        .execute(
-            "UPDATE client_schedule SET lastrun=$1, manualrun=$3 WHERE id=$2;",
-            &[&dt, &id, &false],
-        )
-        .await?;
+    effective
+}
+
+// the settings every client gets unless its own say otherwise
+const DEFAULT_SETTINGS: &str = "";
+
+/**
+ * The settings every client gets, empty if none are set.
+ */
+pub async fn get_default_settings() -> Result<ClientSettings> {
+    Ok(storage()
+        .get_client_settings(DEFAULT_SETTINGS)
+        .await?
+        .unwrap_or_default())
+}
 
-    Ok(())
+/**
+ * The settings a client has of its own, empty if it uses the defaults.
+ */
+pub async fn get_client_settings(id: &str) -> Result<ClientSettings> {
+    Ok(storage().get_client_settings(id).await?.unwrap_or_default())
 }
 
-pub async fn set_client_manual_run(id: &str) -> Result<()> {
-    let client = POOL.get().await?;
+pub async fn set_default_settings(settings: &ClientSettings) -> Result<()> {
+    storage()
+        .set_client_settings(DEFAULT_SETTINGS, non_empty_settings(settings))
+        .await
+}
 
-    let _result = client
-        .execute(
-            "UPDATE client_schedule SET manualrun=$1 WHERE id=$2;",
-            &[&true, &id],
-        )
-        .await?;
+pub async fn set_client_settings(id: &str, settings: &ClientSettings) -> Result<()> {
+    if !client_exists(id).await? {
+        return Err(SqlError::ClientNotExist(id.to_string()));
+    }
+
+    storage()
+        .set_client_settings(id, non_empty_settings(settings))
+        .await
+}
+
+// settings that set nothing are not kept
+fn non_empty_settings(settings: &ClientSettings) -> Option<&ClientSettings> {
+    if *settings == ClientSettings::default() {
+        None
+    } else {
+        Some(settings)
+    }
+}
 
-    Ok(())
+/**
+ * The settings pushed to a client, its own over the defaults.
+ */
+pub async fn effective_settings(id: &str) -> Result<ClientSettings> {
+    Ok(get_default_settings()
+        .await?
+        .merge(&get_client_settings(id).await?))
 }
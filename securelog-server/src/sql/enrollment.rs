@@ -0,0 +1,113 @@
+/*
+Enrollment tokens, for creating clients without admin credentials.
+
+An admin mints a token that can create a limited number of clients until it
+expires, `securelog-client enroll` sends it with the new client's name and
+public key. Clients created with a token get its tags as admin tags. Only the
+sha256 of a token is stored, it is shown once when it is created.
+*/
+use super::storage::NewEnrollmentToken;
+use super::{random_string, storage, Result, SqlError};
+use chrono::{DateTime, Duration, Utc};
+use securelog_proto::is_tag_word;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+const TOKEN_LENGTH: usize = 40;
+// tokens are valid for a year at most
+const MAX_HOURS: i64 = 24 * 365;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EnrollmentToken {
+    pub id: i32,
+    // what the token is for, i.e. "web servers"
+    pub name: String,
+    // admin tags of the clients it creates
+    pub tags: BTreeMap<String, String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires: DateTime<Utc>,
+    pub created: DateTime<Utc>,
+    pub created_by: String,
+}
+impl EnrollmentToken {
+    /**
+     * Whether the token can create another client at `now`.
+     */
+    pub fn usable(&self, now: DateTime<Utc>) -> bool {
+        self.uses < self.max_uses && now < self.expires
+    }
+}
+
+// tokens are looked up by their hash, they are random enough for sha256
+pub fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/**
+ * Mint a token that creates up to `max_uses` clients in the next `hours`.
+ * Returns the token, it can not be read back later.
+ */
+pub async fn create_enrollment_token(
+    name: &str,
+    tags: &BTreeMap<String, String>,
+    max_uses: i32,
+    hours: i64,
+    username: &str,
+) -> Result<(String, EnrollmentToken)> {
+    let invalid = |reason: &str| Err(SqlError::InvalidEnrollmentToken(reason.to_string()));
+    if name.trim().is_empty() {
+        return invalid("name must not be empty");
+    }
+    if max_uses < 1 {
+        return invalid("max_uses must be greater than 0");
+    }
+    if !(1..=MAX_HOURS).contains(&hours) {
+        return invalid("hours must be between 1 and 8760");
+    }
+    if let Some((key, value)) = tags
+        .iter()
+        .find(|(key, value)| !is_tag_word(key) || !is_tag_word(value))
+    {
+        return invalid(&format!(
+            "invalid tag {}={}, tags are letters, digits and _-./:",
+            key, value
+        ));
+    }
+
+    let token = random_string(TOKEN_LENGTH);
+    let created = Utc::now();
+    let new = NewEnrollmentToken {
+        name,
+        token: &token_hash(&token),
+        tags,
+        max_uses,
+        expires: created + Duration::hours(hours),
+        created,
+        created_by: username,
+    };
+    let id = storage().insert_enrollment_token(&new).await?;
+
+    Ok((
+        token,
+        EnrollmentToken {
+            id,
+            name: name.to_string(),
+            tags: tags.clone(),
+            max_uses,
+            uses: 0,
+            expires: new.expires,
+            created,
+            created_by: username.to_string(),
+        },
+    ))
+}
+
+pub async fn get_enrollment_tokens() -> Result<Vec<EnrollmentToken>> {
+    storage().get_enrollment_tokens().await
+}
+
+pub async fn delete_enrollment_token(id: i32) -> Result<bool> {
+    storage().delete_enrollment_token(id).await
+}
@@ -0,0 +1,344 @@
+/*
+Full text search within collected results.
+
+On Postgres search_results.found_tsv indexes every found line with the
+'simple' text search config, so words, ip addresses and hostnames are matched
+as written. On SQLite every found line is copied to search_result_lines and
+indexed by the FTS5 table search_result_lines_fts.
+Queries use websearch_to_tsquery syntax: "quoted phrases", -excluded and or,
+SQLite gets them translated by fts5_query.
+*/
+use super::{storage, Result};
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+// markers ts_headline puts around matched words, control characters that
+// are not expected in log lines
+pub(super) const HIGHLIGHT_START: char = '\u{2}';
+pub(super) const HIGHLIGHT_STOP: char = '\u{3}';
+
+#[derive(Debug, Default)]
+pub struct MatchQuery {
+    pub query: String,
+    pub client: Option<String>,
+    pub search: Option<i32>,
+    pub before: Option<DateTime<Utc>>,
+    pub after: Option<DateTime<Utc>>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LineMatch {
+    pub result_id: i32,
+    pub client_id: String,
+    pub client_name: String,
+    pub search_id: i32,
+    pub search_name: String,
+    pub location: Option<String>,
+    pub started: DateTime<Utc>,
+    pub line: String,
+    // [start, end) character offsets of matched words in line
+    pub highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Facet {
+    pub id: String,
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MatchPage {
+    pub matches: Vec<LineMatch>,
+    // matching lines in total, not just this page
+    pub total: i64,
+    pub clients: Vec<Facet>,
+    pub searches: Vec<Facet>,
+}
+
+/**
+ * Split a ts_headline output into the plain line and highlighted ranges.
+ */
+pub(super) fn parse_headline(headline: &str) -> (String, Vec<(usize, usize)>) {
+    let mut line = String::with_capacity(headline.len());
+    let mut highlights: Vec<(usize, usize)> = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut len = 0;
+
+    for c in headline.chars() {
+        match c {
+            HIGHLIGHT_START => start = Some(len),
+            HIGHLIGHT_STOP => {
+                if let Some(start) = start.take() {
+                    highlights.push((start, len));
+                }
+            }
+            c => {
+                line.push(c);
+                len += 1;
+            }
+        }
+    }
+
+    (line, highlights)
+}
+
+// lines of the rows matching the filters, shared by the page and facet queries.
+// querytree() drops negated terms, a row can hold an excluded word on another
+// line and still have matching lines
+pub(super) const MATCHED_LINES: &str = "
+    WITH q AS (SELECT websearch_to_tsquery('simple', $1) AS query),
+    lines AS (
+        SELECT r.id, r.client, r.search, r.location, r.started, line, ord
+        FROM search_results r, q, unnest(r.found) WITH ORDINALITY AS t(line, ord)
+        WHERE (querytree(q.query)='T' OR r.found_tsv @@ querytree(q.query)::TSQUERY)
+        AND to_tsvector('simple', line) @@ q.query
+        AND ($2::TEXT IS NULL OR r.client=$2)
+        AND ($3::INT IS NULL OR r.search=$3)
+        AND ($4::TIMESTAMPTZ IS NULL OR r.started<$4)
+        AND ($5::TIMESTAMPTZ IS NULL OR r.started>$5)
+    )";
+
+/**
+ * Translate a websearch_to_tsquery style query to an FTS5 one. Every word
+ * and phrase is quoted so punctuation in log lines is never FTS5 syntax.
+ * None if nothing is left to look for, i.e. only exclusions.
+ */
+pub(super) fn fts5_query(query: &str) -> Option<String> {
+    // terms and exclusions of the groups between ors
+    let mut groups: Vec<(Vec<String>, Vec<String>)> = vec![(Vec::new(), Vec::new())];
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let excluded = c == '-';
+        if excluded {
+            chars.next();
+        }
+        let quoted = chars.peek() == Some(&'"');
+        let term: String = if quoted {
+            chars.next();
+            chars.by_ref().take_while(|&c| c != '"').collect()
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            word
+        };
+
+        if !quoted && !excluded && term.eq_ignore_ascii_case("or") {
+            groups.push((Vec::new(), Vec::new()));
+            continue;
+        }
+        // nothing FTS5 would index
+        if !term.chars().any(char::is_alphanumeric) {
+            continue;
+        }
+
+        let phrase = format!("\"{}\"", term);
+        if let Some((terms, exclusions)) = groups.last_mut() {
+            if excluded {
+                exclusions.push(phrase);
+            } else {
+                terms.push(phrase);
+            }
+        }
+    }
+
+    // NOT binds tighter than AND and OR in FTS5, "a b NOT c OR d" is
+    // (a AND (b NOT c)) OR d like websearch_to_tsquery has it
+    let groups: Vec<String> = groups
+        .into_iter()
+        .filter(|(terms, _)| !terms.is_empty())
+        .map(|(terms, exclusions)| {
+            let mut group = terms.join(" ");
+            for exclusion in exclusions {
+                group.push_str(" NOT ");
+                group.push_str(&exclusion);
+            }
+            group
+        })
+        .collect();
+
+    if groups.is_empty() {
+        None
+    } else {
+        Some(groups.join(" OR "))
+    }
+}
+
+/**
+ * Find the stored lines matching a text query, with highlighting and
+ * per client/search counts.
+ */
+pub async fn search_matches(query: &MatchQuery) -> Result<MatchPage> {
+    storage().search_matches(query).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::storage::SearchEdit;
+    use super::super::testdb::{add_client, result, TestDb};
+    use super::super::Backend;
+    use super::*;
+    use crate::models::SearchType;
+    use chrono::Duration;
+
+    #[test]
+    fn headline_highlights_are_character_offsets() {
+        let (line, highlights) =
+            parse_headline("sshd: \u{2}Failed\u{3} password for \u{2}röot\u{3}");
+        assert_eq!(line, "sshd: Failed password for röot");
+        assert_eq!(highlights, vec![(6, 12), (26, 30)]);
+    }
+
+    #[test]
+    fn queries_are_translated_for_fts5() {
+        for (query, fts5) in [
+            ("failed password", Some("\"failed\" \"password\"")),
+            (
+                "\"publickey for\" -root or 10.0.0.1",
+                Some("\"publickey for\" NOT \"root\" OR \"10.0.0.1\""),
+            ),
+            ("-\"only excluded\"", None),
+            ("a OR -b or", Some("\"a\"")),
+            ("NOT* ^col:x", Some("\"NOT*\" \"^col:x\"")),
+            ("\"or\" : --", Some("\"or\"")),
+        ] {
+            assert_eq!(fts5_query(query).as_deref(), fts5, "{}", query);
+        }
+    }
+
+    async fn inserted_results_are_matched(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web1").await;
+        add_client(storage, "c2", "db1").await;
+        let search = storage
+            .insert_search(
+                &SearchEdit {
+                    name: "auth",
+                    stype: &SearchType::Contains,
+                    search: "sshd",
+                    locations: &["/var/log/auth.log".to_string()],
+                    selector: None,
+                    realtime: false,
+                    changed_by: "admin",
+                    changed: Utc::now(),
+                },
+                true,
+            )
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        for (client, found, started) in [
+            (
+                "c1",
+                vec![
+                    "sshd: Failed password for root from 10.0.0.1",
+                    "sshd: Accepted publickey for deploy",
+                ],
+                now - Duration::hours(2),
+            ),
+            (
+                "c2",
+                vec!["sshd: Failed password for admin from 10.0.0.2"],
+                now - Duration::hours(1),
+            ),
+        ] {
+            storage
+                .insert_search_result(client, &result(search, &found, started), Some("sig"))
+                .await
+                .unwrap();
+        }
+
+        let page = storage
+            .search_matches(&MatchQuery {
+                query: "failed password".to_string(),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.matches.len(), 2);
+        // newest first, with the matched words highlighted
+        assert_eq!(page.matches[0].client_name, "db1");
+        assert_eq!(page.matches[0].search_name, "auth");
+        assert_eq!(
+            page.matches[0].line,
+            "sshd: Failed password for admin from 10.0.0.2"
+        );
+        assert_eq!(page.matches[0].highlights, vec![(6, 12), (13, 21)]);
+        assert_eq!(page.clients.len(), 2);
+        assert_eq!(page.searches[0].count, 2);
+
+        // phrases, exclusions and filters
+        let count = |query: &str, client: Option<&str>| MatchQuery {
+            query: query.to_string(),
+            client: client.map(str::to_string),
+            limit: 10,
+            ..Default::default()
+        };
+        for (query, client, total) in [
+            ("\"publickey for deploy\"", None, 1),
+            ("sshd -failed", None, 1),
+            ("10.0.0.1 or 10.0.0.2", None, 2),
+            ("failed", Some("c1"), 1),
+            ("missing", None, 0),
+        ] {
+            let page = storage.search_matches(&count(query, client)).await.unwrap();
+            assert_eq!(page.total, total, "{}", query);
+        }
+
+        let page = storage
+            .search_matches(&MatchQuery {
+                query: "sshd".to_string(),
+                after: Some(now - Duration::minutes(90)),
+                limit: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 1);
+
+        // deleted results are no longer found
+        let page = storage
+            .search_matches(&count("failed", Some("c1")))
+            .await
+            .unwrap();
+        storage
+            .delete_search_results(&[page.matches[0].result_id])
+            .await
+            .unwrap();
+        let page = storage.search_matches(&count("sshd", None)).await.unwrap();
+        assert_eq!(page.total, 1);
+
+        db.drop().await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_inserted_results_are_matched() {
+        inserted_results_are_matched(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_inserted_results_are_matched() {
+        inserted_results_are_matched(Backend::Sqlite).await;
+    }
+}
@@ -0,0 +1,68 @@
+use super::{storage, Result};
+use crate::models::{FileChange, FileChangeKind, FileState};
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+// changes returned when no limit is given, and the most returned at once
+pub const DEFAULT_CHANGES_PAGE: i64 = 100;
+pub const MAX_CHANGES_PAGE: i64 = 1000;
+
+/**
+ * The files of a client as of its last integrity snapshot.
+ */
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IntegrityBaseline {
+    // the paths the client watched
+    pub paths: Vec<String>,
+    pub files: Vec<FileState>,
+    // when the client took the snapshot
+    pub updated: DateTime<Utc>,
+}
+
+/**
+ * A file change as stored, with the client it was found on.
+ */
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IntegrityChange {
+    pub id: i32,
+    pub client_id: String,
+    pub client_name: String,
+    pub path: String,
+    pub kind: FileChangeKind,
+    pub changed: Vec<String>,
+    pub before: Option<FileState>,
+    pub after: Option<FileState>,
+    // when the client took the snapshot the change was found in
+    pub detected: DateTime<Utc>,
+}
+
+pub async fn get_integrity_baseline(clientid: &str) -> Result<Option<IntegrityBaseline>> {
+    storage().get_integrity_baseline(clientid).await
+}
+
+/**
+ * Replace the baseline of a client and store the changes from the old one.
+ */
+pub async fn set_integrity_baseline(
+    clientid: &str,
+    baseline: &IntegrityBaseline,
+    changes: &[FileChange],
+) -> Result<()> {
+    storage()
+        .set_integrity_baseline(clientid, baseline, changes)
+        .await
+}
+
+/**
+ * Changes on one client or all of them, newest first.
+ */
+pub async fn get_integrity_changes(
+    clientid: Option<&str>,
+    limit: Option<i64>,
+) -> Result<Vec<IntegrityChange>> {
+    let limit = limit
+        .unwrap_or(DEFAULT_CHANGES_PAGE)
+        .clamp(1, MAX_CHANGES_PAGE);
+
+    storage().get_integrity_changes(clientid, limit).await
+}
@@ -0,0 +1,1142 @@
+/*
+Database migrations.
+
+Every schema change is a Migration with the version it brings the database
+to. Migrations run in order, each in its own transaction together with the
+dbinfo.dbver bump, so a failed migration leaves the database at the last
+version that applied cleanly. To change the schema add a new entry at the
+end of MIGRATIONS, never edit one that has shipped.
+
+Each migration has the SQL for both backends. SQLite has no arrays or
+timestamp types, arrays are stored as json text and timestamps as text in
+the format rusqlite writes, which sorts in time order.
+*/
+use super::storage::Storage;
+use super::{Backend, Result, SqlError};
+
+pub struct Scripts {
+    pub up: &'static [&'static str],
+    // None if the migration can't be undone
+    pub down: Option<&'static [&'static str]>,
+}
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub postgres: Scripts,
+    pub sqlite: Scripts,
+}
+impl Migration {
+    pub fn scripts(&self, backend: Backend) -> &Scripts {
+        match backend {
+            Backend::Postgres => &self.postgres,
+            Backend::Sqlite => &self.sqlite,
+        }
+    }
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create tables",
+        postgres: Scripts {
+            up: &[
+                "CREATE TABLE dbinfo (
+                    id SERIAL PRIMARY KEY,
+                    dbver INT
+                );",
+                "INSERT INTO dbinfo (dbver) VALUES(0);",
+                "CREATE TABLE auth (
+                    username TEXT PRIMARY KEY,
+                    passwd TEXT NOT NULL,
+                    lastlogin TIMESTAMP WITH TIME ZONE,
+                    enabled BOOL NOT NULL
+                );",
+                // meant for preventing authentication brute forcing
+                // not implemented yet
+                "CREATE TABLE authbrute (
+                    id TEXT PRIMARY KEY,
+                    ip TEXT NOT NULL,
+                    ts TIMESTAMP WITH TIME ZONE,
+                    count INT NOT NULL
+                );",
+                "CREATE TABLE clients (
+                    id TEXT PRIMARY KEY,
+                    token TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    enabled BOOL NOT NULL,
+                    created TIMESTAMP WITH TIME ZONE,
+                    lastconnect TIMESTAMP WITH TIME ZONE
+                );",
+                "CREATE TABLE client_schedule (
+                    id TEXT PRIMARY KEY,
+                    lastrun TIMESTAMP WITH TIME ZONE,
+                    manualrun BOOL
+                );",
+                "CREATE TABLE scan_schedule (
+                    searchid INT NOT NULL,
+                    schedule INT NOT NULL,
+                    manual BOOL NOT NULL
+                );",
+                // default scan every 30 minutes
+                "INSERT INTO scan_schedule (searchid, schedule, manual) VALUES(0, 30, 'f');",
+                // 'searches' table is for storing log searches.
+                "CREATE TABLE searches (
+                    id SERIAL PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    type INT NOT NULL,
+                    search TEXT NOT NULL,
+                    locations TEXT [],
+                    enabled BOOL NOT NULL
+                );",
+                "CREATE TABLE search_results (
+                    id SERIAL PRIMARY KEY,
+                    client TEXT NOT NULL,
+                    search INT NOT NULL,
+                    location TEXT,
+                    found TEXT [] NOT NULL,
+                    started TIMESTAMP WITH TIME ZONE NOT NULL
+                );",
+                "CREATE TABLE webhooks (
+                    name TEXT PRIMARY KEY,
+                    url TEXT NOT NULL,
+                    username TEXT NOT NULL
+                );",
+            ],
+            down: Some(&[
+                "DROP TABLE webhooks;",
+                "DROP TABLE search_results;",
+                "DROP TABLE searches;",
+                "DROP TABLE scan_schedule;",
+                "DROP TABLE client_schedule;",
+                "DROP TABLE clients;",
+                "DROP TABLE authbrute;",
+                "DROP TABLE auth;",
+                "DROP TABLE dbinfo;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE dbinfo (
+                    id INTEGER PRIMARY KEY,
+                    dbver INT
+                );",
+                "INSERT INTO dbinfo (dbver) VALUES(0);",
+                "CREATE TABLE auth (
+                    username TEXT PRIMARY KEY,
+                    passwd TEXT NOT NULL,
+                    lastlogin TEXT,
+                    enabled BOOL NOT NULL
+                );",
+                "CREATE TABLE authbrute (
+                    id TEXT PRIMARY KEY,
+                    ip TEXT NOT NULL,
+                    ts TEXT,
+                    count INT NOT NULL
+                );",
+                "CREATE TABLE clients (
+                    id TEXT PRIMARY KEY,
+                    token TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    enabled BOOL NOT NULL,
+                    created TEXT,
+                    lastconnect TEXT
+                );",
+                "CREATE TABLE client_schedule (
+                    id TEXT PRIMARY KEY,
+                    lastrun TEXT,
+                    manualrun BOOL
+                );",
+                "CREATE TABLE scan_schedule (
+                    searchid INT NOT NULL,
+                    schedule INT NOT NULL,
+                    manual BOOL NOT NULL
+                );",
+                "INSERT INTO scan_schedule (searchid, schedule, manual) VALUES(0, 30, 0);",
+                // AUTOINCREMENT so ids of deleted rows are not reused, like SERIAL
+                // locations is a json array
+                "CREATE TABLE searches (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    type INT NOT NULL,
+                    search TEXT NOT NULL,
+                    locations TEXT,
+                    enabled BOOL NOT NULL
+                );",
+                // found is a json array
+                "CREATE TABLE search_results (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    client TEXT NOT NULL,
+                    search INT NOT NULL,
+                    location TEXT,
+                    found TEXT NOT NULL,
+                    started TEXT NOT NULL
+                );",
+                "CREATE TABLE webhooks (
+                    name TEXT PRIMARY KEY,
+                    url TEXT NOT NULL,
+                    username TEXT NOT NULL
+                );",
+            ],
+            down: Some(&[
+                "DROP TABLE webhooks;",
+                "DROP TABLE search_results;",
+                "DROP TABLE searches;",
+                "DROP TABLE scan_schedule;",
+                "DROP TABLE client_schedule;",
+                "DROP TABLE clients;",
+                "DROP TABLE authbrute;",
+                "DROP TABLE auth;",
+                "DROP TABLE dbinfo;",
+            ]),
+        },
+    },
+    // clients register an Ed25519 public key, and every result row keeps
+    // the signature of the batch it arrived in
+    Migration {
+        version: 2,
+        name: "result signing",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE clients ADD COLUMN pubkey TEXT;",
+                "ALTER TABLE search_results ADD COLUMN signature TEXT;",
+            ],
+            down: Some(&[
+                "ALTER TABLE search_results DROP COLUMN signature;",
+                "ALTER TABLE clients DROP COLUMN pubkey;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "ALTER TABLE clients ADD COLUMN pubkey TEXT;",
+                "ALTER TABLE search_results ADD COLUMN signature TEXT;",
+            ],
+            down: Some(&[
+                "ALTER TABLE search_results DROP COLUMN signature;",
+                "ALTER TABLE clients DROP COLUMN pubkey;",
+            ]),
+        },
+    },
+    // index of raw log segments kept in the archive store, see archive.rs
+    Migration {
+        version: 3,
+        name: "archive segments",
+        postgres: Scripts {
+            up: &[
+                "CREATE TABLE archive_segments (
+                    id TEXT NOT NULL,
+                    client TEXT NOT NULL,
+                    location TEXT NOT NULL,
+                    start_offset BIGINT NOT NULL,
+                    size BIGINT NOT NULL,
+                    stored_size BIGINT NOT NULL,
+                    received TIMESTAMP WITH TIME ZONE NOT NULL,
+                    PRIMARY KEY (client, id)
+                );",
+                "CREATE INDEX archive_segments_received ON archive_segments (received);",
+            ],
+            down: Some(&["DROP TABLE archive_segments;"]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE archive_segments (
+                    id TEXT NOT NULL,
+                    client TEXT NOT NULL,
+                    location TEXT NOT NULL,
+                    start_offset BIGINT NOT NULL,
+                    size BIGINT NOT NULL,
+                    stored_size BIGINT NOT NULL,
+                    received TEXT NOT NULL,
+                    PRIMARY KEY (client, id)
+                );",
+                "CREATE INDEX archive_segments_received ON archive_segments (received);",
+            ],
+            down: Some(&["DROP TABLE archive_segments;"]),
+        },
+    },
+    // per search retention, NULL falls back to the global setting
+    Migration {
+        version: 4,
+        name: "search retention",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE searches ADD COLUMN retention_days INT;",
+                "ALTER TABLE searches ADD COLUMN retention_rows INT;",
+                "CREATE INDEX search_results_search_started ON search_results (search, started);",
+            ],
+            down: Some(&[
+                "DROP INDEX search_results_search_started;",
+                "ALTER TABLE searches DROP COLUMN retention_rows;",
+                "ALTER TABLE searches DROP COLUMN retention_days;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "ALTER TABLE searches ADD COLUMN retention_days INT;",
+                "ALTER TABLE searches ADD COLUMN retention_rows INT;",
+                "CREATE INDEX search_results_search_started ON search_results (search, started);",
+            ],
+            down: Some(&[
+                "DROP INDEX search_results_search_started;",
+                "ALTER TABLE searches DROP COLUMN retention_rows;",
+                "ALTER TABLE searches DROP COLUMN retention_days;",
+            ]),
+        },
+    },
+    // indexes for paging through search_results newest first
+    Migration {
+        version: 5,
+        name: "search result paging indexes",
+        postgres: Scripts {
+            up: &[
+                "CREATE INDEX search_results_started_id ON search_results (started DESC, id DESC);",
+                "CREATE INDEX search_results_client_started_id
+                    ON search_results (client, started DESC, id DESC);",
+            ],
+            down: Some(&[
+                "DROP INDEX search_results_client_started_id;",
+                "DROP INDEX search_results_started_id;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE INDEX search_results_started_id ON search_results (started DESC, id DESC);",
+                "CREATE INDEX search_results_client_started_id
+                    ON search_results (client, started DESC, id DESC);",
+            ],
+            down: Some(&[
+                "DROP INDEX search_results_client_started_id;",
+                "DROP INDEX search_results_started_id;",
+            ]),
+        },
+    },
+    // full text index over found lines, see fulltext.rs
+    // SQLite gets its full text index in version 17
+    Migration {
+        version: 6,
+        name: "full text index",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE search_results ADD COLUMN found_tsv TSVECTOR;",
+                "UPDATE search_results SET found_tsv=to_tsvector('simple', array_to_string(found, ' '));",
+                "CREATE INDEX search_results_found_tsv ON search_results USING GIN (found_tsv);",
+            ],
+            down: Some(&[
+                "DROP INDEX search_results_found_tsv;",
+                "ALTER TABLE search_results DROP COLUMN found_tsv;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[],
+            down: Some(&[]),
+        },
+    },
+    // every saved version of a search, existing searches start at version 1
+    Migration {
+        version: 7,
+        name: "search history",
+        postgres: Scripts {
+            up: &[
+                "CREATE TABLE search_history (
+                    id SERIAL PRIMARY KEY,
+                    search INT NOT NULL,
+                    version INT NOT NULL,
+                    name TEXT NOT NULL,
+                    type INT NOT NULL,
+                    search_text TEXT NOT NULL,
+                    locations TEXT [],
+                    changed TIMESTAMPTZ NOT NULL,
+                    changed_by TEXT,
+                    UNIQUE (search, version)
+                );",
+                "INSERT INTO search_history
+                    (search, version, name, type, search_text, locations, changed)
+                    SELECT id, 1, name, type, search, locations, now() FROM searches;",
+            ],
+            down: Some(&["DROP TABLE search_history;"]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE search_history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    search INTEGER NOT NULL,
+                    version INTEGER NOT NULL,
+                    name TEXT NOT NULL,
+                    type INT NOT NULL,
+                    search_text TEXT NOT NULL,
+                    locations TEXT,
+                    changed TEXT NOT NULL,
+                    changed_by TEXT,
+                    UNIQUE (search, version)
+                );",
+                "INSERT INTO search_history
+                    (search, version, name, type, search_text, locations, changed)
+                    SELECT id, 1, name, type, search, locations,
+                        strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')
+                    FROM searches;",
+            ],
+            down: Some(&["DROP TABLE search_history;"]),
+        },
+    },
+    // client tags and the selectors searches pick clients by, see selector.rs
+    Migration {
+        version: 8,
+        name: "client tags",
+        postgres: Scripts {
+            up: &[
+                "CREATE TABLE client_tags (
+                    client TEXT NOT NULL,
+                    source INT NOT NULL,
+                    key TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    PRIMARY KEY (client, source, key)
+                );",
+                "ALTER TABLE searches ADD COLUMN selector TEXT;",
+                "ALTER TABLE search_history ADD COLUMN selector TEXT;",
+            ],
+            down: Some(&[
+                "ALTER TABLE search_history DROP COLUMN selector;",
+                "ALTER TABLE searches DROP COLUMN selector;",
+                "DROP TABLE client_tags;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE client_tags (
+                    client TEXT NOT NULL,
+                    source INT NOT NULL,
+                    key TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    PRIMARY KEY (client, source, key)
+                );",
+                "ALTER TABLE searches ADD COLUMN selector TEXT;",
+                "ALTER TABLE search_history ADD COLUMN selector TEXT;",
+            ],
+            down: Some(&[
+                "ALTER TABLE search_history DROP COLUMN selector;",
+                "ALTER TABLE searches DROP COLUMN selector;",
+                "DROP TABLE client_tags;",
+            ]),
+        },
+    },
+    // when clients were last seen and what they run, see monitor.rs
+    Migration {
+        version: 9,
+        name: "client status",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE clients ADD COLUMN last_seen TIMESTAMPTZ;",
+                "ALTER TABLE clients ADD COLUMN agent_version TEXT;",
+                "ALTER TABLE clients ADD COLUMN last_error TEXT;",
+                "ALTER TABLE clients ADD COLUMN last_error_at TIMESTAMPTZ;",
+                "ALTER TABLE clients ADD COLUMN missed_alert TIMESTAMPTZ;",
+                "UPDATE clients SET last_seen=lastconnect;",
+            ],
+            down: Some(&[
+                "ALTER TABLE clients DROP COLUMN missed_alert;",
+                "ALTER TABLE clients DROP COLUMN last_error_at;",
+                "ALTER TABLE clients DROP COLUMN last_error;",
+                "ALTER TABLE clients DROP COLUMN agent_version;",
+                "ALTER TABLE clients DROP COLUMN last_seen;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "ALTER TABLE clients ADD COLUMN last_seen TEXT;",
+                "ALTER TABLE clients ADD COLUMN agent_version TEXT;",
+                "ALTER TABLE clients ADD COLUMN last_error TEXT;",
+                "ALTER TABLE clients ADD COLUMN last_error_at TEXT;",
+                "ALTER TABLE clients ADD COLUMN missed_alert TEXT;",
+                "UPDATE clients SET last_seen=lastconnect;",
+            ],
+            down: Some(&[
+                "ALTER TABLE clients DROP COLUMN missed_alert;",
+                "ALTER TABLE clients DROP COLUMN last_error_at;",
+                "ALTER TABLE clients DROP COLUMN last_error;",
+                "ALTER TABLE clients DROP COLUMN agent_version;",
+                "ALTER TABLE clients DROP COLUMN last_seen;",
+            ]),
+        },
+    },
+    // the last run of every search on every client, see sql/runs.rs
+    Migration {
+        version: 10,
+        name: "search runs",
+        postgres: Scripts {
+            up: &[
+                "CREATE TABLE search_runs (
+                    client TEXT NOT NULL,
+                    search INT NOT NULL,
+                    location TEXT NOT NULL,
+                    started TIMESTAMPTZ NOT NULL,
+                    duration_ms BIGINT NOT NULL,
+                    lines_scanned BIGINT NOT NULL,
+                    bytes_read BIGINT NOT NULL,
+                    error_kind INT,
+                    error TEXT,
+                    PRIMARY KEY (client, search, location)
+                );",
+            ],
+            down: Some(&["DROP TABLE search_runs;"]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE search_runs (
+                    client TEXT NOT NULL,
+                    search INT NOT NULL,
+                    location TEXT NOT NULL,
+                    started TEXT NOT NULL,
+                    duration_ms INTEGER NOT NULL,
+                    lines_scanned INTEGER NOT NULL,
+                    bytes_read INTEGER NOT NULL,
+                    error_kind INT,
+                    error TEXT,
+                    PRIMARY KEY (client, search, location)
+                );",
+            ],
+            down: Some(&["DROP TABLE search_runs;"]),
+        },
+    },
+    // what clients run on, and the settings pushed to them as json, the
+    // defaults for every client are kept under client ''
+    Migration {
+        version: 11,
+        name: "client inventory and settings",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE clients ADD COLUMN git_sha TEXT;",
+                "ALTER TABLE clients ADD COLUMN os TEXT;",
+                "ALTER TABLE clients ADD COLUMN hostname TEXT;",
+                "ALTER TABLE clients ADD COLUMN capabilities TEXT;",
+                "CREATE TABLE client_settings (
+                    client TEXT PRIMARY KEY,
+                    settings TEXT NOT NULL
+                );",
+            ],
+            down: Some(&[
+                "DROP TABLE client_settings;",
+                "ALTER TABLE clients DROP COLUMN capabilities;",
+                "ALTER TABLE clients DROP COLUMN hostname;",
+                "ALTER TABLE clients DROP COLUMN os;",
+                "ALTER TABLE clients DROP COLUMN git_sha;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "ALTER TABLE clients ADD COLUMN git_sha TEXT;",
+                "ALTER TABLE clients ADD COLUMN os TEXT;",
+                "ALTER TABLE clients ADD COLUMN hostname TEXT;",
+                "ALTER TABLE clients ADD COLUMN capabilities TEXT;",
+                "CREATE TABLE client_settings (
+                    client TEXT PRIMARY KEY,
+                    settings TEXT NOT NULL
+                );",
+            ],
+            down: Some(&[
+                "DROP TABLE client_settings;",
+                "ALTER TABLE clients DROP COLUMN capabilities;",
+                "ALTER TABLE clients DROP COLUMN hostname;",
+                "ALTER TABLE clients DROP COLUMN os;",
+                "ALTER TABLE clients DROP COLUMN git_sha;",
+            ]),
+        },
+    },
+    // results a client resource limit cut short
+    Migration {
+        version: 12,
+        name: "truncated results",
+        postgres: Scripts {
+            up: &["ALTER TABLE search_results ADD COLUMN truncated BOOLEAN NOT NULL DEFAULT false;"],
+            down: Some(&["ALTER TABLE search_results DROP COLUMN truncated;"]),
+        },
+        sqlite: Scripts {
+            up: &["ALTER TABLE search_results ADD COLUMN truncated INTEGER NOT NULL DEFAULT 0;"],
+            down: Some(&["ALTER TABLE search_results DROP COLUMN truncated;"]),
+        },
+    },
+    // searches clients follow as lines are written, see the client's realtime.rs
+    Migration {
+        version: 13,
+        name: "realtime searches",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE searches ADD COLUMN realtime BOOLEAN NOT NULL DEFAULT false;",
+                "ALTER TABLE search_history ADD COLUMN realtime BOOLEAN NOT NULL DEFAULT false;",
+            ],
+            down: Some(&[
+                "ALTER TABLE search_history DROP COLUMN realtime;",
+                "ALTER TABLE searches DROP COLUMN realtime;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "ALTER TABLE searches ADD COLUMN realtime INTEGER NOT NULL DEFAULT 0;",
+                "ALTER TABLE search_history ADD COLUMN realtime INTEGER NOT NULL DEFAULT 0;",
+            ],
+            down: Some(&[
+                "ALTER TABLE search_history DROP COLUMN realtime;",
+                "ALTER TABLE searches DROP COLUMN realtime;",
+            ]),
+        },
+    },
+    // see integrity.rs, files and paths are json
+    Migration {
+        version: 14,
+        name: "file integrity",
+        postgres: Scripts {
+            up: &[
+                "CREATE TABLE integrity_baselines (
+                    client TEXT PRIMARY KEY,
+                    paths TEXT NOT NULL,
+                    files TEXT NOT NULL,
+                    updated TIMESTAMPTZ NOT NULL
+                );",
+                "CREATE TABLE integrity_changes (
+                    id SERIAL PRIMARY KEY,
+                    client TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    kind INT NOT NULL,
+                    changed TEXT NOT NULL,
+                    old_state TEXT,
+                    new_state TEXT,
+                    detected TIMESTAMPTZ NOT NULL
+                );",
+                "CREATE INDEX integrity_changes_client_detected ON integrity_changes (client, detected);",
+            ],
+            down: Some(&[
+                "DROP TABLE integrity_changes;",
+                "DROP TABLE integrity_baselines;",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE integrity_baselines (
+                    client TEXT PRIMARY KEY,
+                    paths TEXT NOT NULL,
+                    files TEXT NOT NULL,
+                    updated TEXT NOT NULL
+                );",
+                "CREATE TABLE integrity_changes (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    client TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    kind INT NOT NULL,
+                    changed TEXT NOT NULL,
+                    old_state TEXT,
+                    new_state TEXT,
+                    detected TEXT NOT NULL
+                );",
+                "CREATE INDEX integrity_changes_client_detected ON integrity_changes (client, detected);",
+            ],
+            down: Some(&[
+                "DROP TABLE integrity_changes;",
+                "DROP TABLE integrity_baselines;",
+            ]),
+        },
+    },
+    // see sql/enrollment.rs, token is the sha256 of what clients enroll with
+    Migration {
+        version: 15,
+        name: "enrollment tokens",
+        postgres: Scripts {
+            up: &["CREATE TABLE enrollment_tokens (
+                id SERIAL PRIMARY KEY,
+                name TEXT NOT NULL,
+                token TEXT NOT NULL UNIQUE,
+                tags TEXT NOT NULL,
+                max_uses INT NOT NULL,
+                uses INT NOT NULL DEFAULT 0,
+                expires TIMESTAMPTZ NOT NULL,
+                created TIMESTAMPTZ NOT NULL,
+                created_by TEXT NOT NULL
+            );"],
+            down: Some(&["DROP TABLE enrollment_tokens;"]),
+        },
+        sqlite: Scripts {
+            up: &["CREATE TABLE enrollment_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                token TEXT NOT NULL UNIQUE,
+                tags TEXT NOT NULL,
+                max_uses INT NOT NULL,
+                uses INT NOT NULL DEFAULT 0,
+                expires TEXT NOT NULL,
+                created TEXT NOT NULL,
+                created_by TEXT NOT NULL
+            );"],
+            down: Some(&["DROP TABLE enrollment_tokens;"]),
+        },
+    },
+    // the same data can be sent for another file or offset, i.e. a copied
+    // log, and needs an index entry of its own. SQLite can't change a
+    // primary key, the table is copied.
+    Migration {
+        version: 16,
+        name: "archive segment locations",
+        postgres: Scripts {
+            up: &[
+                "ALTER TABLE archive_segments DROP CONSTRAINT archive_segments_pkey;",
+                "ALTER TABLE archive_segments ADD PRIMARY KEY (client, id, location, start_offset);",
+            ],
+            down: Some(&[
+                "DELETE FROM archive_segments a USING archive_segments b
+                    WHERE a.client=b.client AND a.id=b.id AND a.ctid>b.ctid;",
+                "ALTER TABLE archive_segments DROP CONSTRAINT archive_segments_pkey;",
+                "ALTER TABLE archive_segments ADD PRIMARY KEY (client, id);",
+            ]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE archive_segments_new (
+                    id TEXT NOT NULL,
+                    client TEXT NOT NULL,
+                    location TEXT NOT NULL,
+                    start_offset BIGINT NOT NULL,
+                    size BIGINT NOT NULL,
+                    stored_size BIGINT NOT NULL,
+                    received TEXT NOT NULL,
+                    PRIMARY KEY (client, id, location, start_offset)
+                );",
+                "INSERT INTO archive_segments_new SELECT * FROM archive_segments;",
+                "DROP TABLE archive_segments;",
+                "ALTER TABLE archive_segments_new RENAME TO archive_segments;",
+                "CREATE INDEX archive_segments_received ON archive_segments (received);",
+            ],
+            down: Some(&[
+                "CREATE TABLE archive_segments_old (
+                    id TEXT NOT NULL,
+                    client TEXT NOT NULL,
+                    location TEXT NOT NULL,
+                    start_offset BIGINT NOT NULL,
+                    size BIGINT NOT NULL,
+                    stored_size BIGINT NOT NULL,
+                    received TEXT NOT NULL,
+                    PRIMARY KEY (client, id)
+                );",
+                "INSERT OR IGNORE INTO archive_segments_old
+                    SELECT * FROM archive_segments ORDER BY received;",
+                "DROP TABLE archive_segments;",
+                "ALTER TABLE archive_segments_old RENAME TO archive_segments;",
+                "CREATE INDEX archive_segments_received ON archive_segments (received);",
+            ]),
+        },
+    },
+    // full text index over found lines for SQLite, Postgres has had one since
+    // version 6. FTS5 indexes a copy of every line so a match is a line, the
+    // triggers keep it in step with search_results.
+    Migration {
+        version: 17,
+        name: "sqlite full text index",
+        postgres: Scripts {
+            up: &[],
+            down: Some(&[]),
+        },
+        sqlite: Scripts {
+            up: &[
+                "CREATE TABLE search_result_lines (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    result INT NOT NULL,
+                    ord INT NOT NULL,
+                    line TEXT NOT NULL
+                );",
+                "CREATE INDEX search_result_lines_result ON search_result_lines (result);",
+                "CREATE VIRTUAL TABLE search_result_lines_fts USING fts5(
+                    line, content='search_result_lines', content_rowid='id'
+                );",
+                "CREATE TRIGGER search_results_lines_insert AFTER INSERT ON search_results BEGIN
+                    INSERT INTO search_result_lines (result, ord, line)
+                        SELECT new.id, key, value FROM json_each(new.found);
+                END;",
+                "CREATE TRIGGER search_results_lines_delete AFTER DELETE ON search_results BEGIN
+                    DELETE FROM search_result_lines WHERE result=old.id;
+                END;",
+                "CREATE TRIGGER search_result_lines_fts_insert AFTER INSERT ON search_result_lines BEGIN
+                    INSERT INTO search_result_lines_fts (rowid, line) VALUES (new.id, new.line);
+                END;",
+                "CREATE TRIGGER search_result_lines_fts_delete AFTER DELETE ON search_result_lines BEGIN
+                    INSERT INTO search_result_lines_fts (search_result_lines_fts, rowid, line)
+                        VALUES ('delete', old.id, old.line);
+                END;",
+                // a row that is not json has no lines rather than failing the migration
+                "INSERT INTO search_result_lines (result, ord, line)
+                    SELECT r.id, j.key, j.value FROM search_results r,
+                        json_each(CASE WHEN json_valid(r.found) THEN r.found ELSE '[]' END) j
+                    ORDER BY r.id, j.key;",
+            ],
+            down: Some(&[
+                "DROP TRIGGER search_result_lines_fts_delete;",
+                "DROP TRIGGER search_result_lines_fts_insert;",
+                "DROP TRIGGER search_results_lines_delete;",
+                "DROP TRIGGER search_results_lines_insert;",
+                "DROP TABLE search_result_lines_fts;",
+                "DROP TABLE search_result_lines;",
+            ]),
+        },
+    },
+];
+
+/**
+ * Version the database is at once every migration is applied.
+ */
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// A migration that was, or in a dry run would be, applied.
+pub struct Step {
+    pub migration: &'static Migration,
+    pub direction: Direction,
+    pub backend: Backend,
+}
+impl Step {
+    pub fn statements(&self) -> &'static [&'static str] {
+        let scripts = self.migration.scripts(self.backend);
+        match self.direction {
+            Direction::Up => scripts.up,
+            Direction::Down => scripts.down.unwrap_or(&[]),
+        }
+    }
+    // version the database is at after this step
+    pub fn version_after(&self) -> i32 {
+        match self.direction {
+            Direction::Up => self.migration.version,
+            Direction::Down => self.migration.version - 1,
+        }
+    }
+    /**
+     * The SQL this step runs, including the version bump.
+     */
+    pub fn sql(&self) -> String {
+        let mut sql = String::new();
+        for statement in self.statements() {
+            sql.push_str(statement);
+            sql.push('\n');
+        }
+        // the v1 down migration drops dbinfo itself
+        if self.version_after() > 0 {
+            sql.push_str(&format!(
+                "UPDATE dbinfo SET dbver={};\n",
+                self.version_after()
+            ));
+        }
+        sql
+    }
+}
+
+/**
+ * Work out the migrations needed to get from `current` to `target`.
+ */
+pub fn plan(backend: Backend, current: i32, target: i32) -> Result<Vec<Step>> {
+    if current > latest_version() || current < 0 {
+        return Err(SqlError::UnknownDbVersion(current));
+    }
+    if target > latest_version() || target < 0 {
+        return Err(SqlError::UnknownDbVersion(target));
+    }
+
+    let mut steps: Vec<Step> = Vec::new();
+    if target >= current {
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|m| m.version > current && m.version <= target)
+        {
+            steps.push(Step {
+                migration,
+                direction: Direction::Up,
+                backend,
+            });
+        }
+    } else {
+        for migration in MIGRATIONS
+            .iter()
+            .rev()
+            .filter(|m| m.version <= current && m.version > target)
+        {
+            if migration.scripts(backend).down.is_none() {
+                return Err(SqlError::IrreversibleMigration(migration.version));
+            }
+            steps.push(Step {
+                migration,
+                direction: Direction::Down,
+                backend,
+            });
+        }
+    }
+
+    Ok(steps)
+}
+
+/**
+ * Migrate the database to `target`, or the latest version if None.
+ * With dry_run nothing is changed. Returns the steps applied, or that would
+ * have been applied.
+ */
+pub async fn migrate(
+    storage: &dyn Storage,
+    target: Option<i32>,
+    dry_run: bool,
+) -> Result<Vec<Step>> {
+    let current = storage.db_version().await?;
+    let steps = plan(
+        storage.backend(),
+        current,
+        target.unwrap_or_else(latest_version),
+    )?;
+
+    if dry_run {
+        return Ok(steps);
+    }
+
+    for step in &steps {
+        warn!(
+            "Migrating database {} v{}: {}",
+            match step.direction {
+                Direction::Up => "up to",
+                Direction::Down => "down from",
+            },
+            step.migration.version,
+            step.migration.name
+        );
+
+        storage.apply_migration(&step.sql()).await?;
+    }
+
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testdb::TestDb;
+    use super::*;
+
+    /**
+     * Columns, indexes and constraints of the schema, comparable
+     * between two databases of the same backend.
+     */
+    async fn schema(db: &TestDb) -> Vec<String> {
+        match db {
+            TestDb::Postgres { storage, .. } => {
+                let client = storage.pool.get().await.unwrap();
+                let mut schema: Vec<String> = Vec::new();
+
+                let rows = client
+                    .query(
+                        "SELECT table_name, column_name, data_type, is_nullable,
+                            COALESCE(column_default, '')
+                        FROM information_schema.columns WHERE table_schema='public'
+                        ORDER BY table_name, column_name;",
+                        &[],
+                    )
+                    .await
+                    .unwrap();
+                for row in rows {
+                    schema.push(format!(
+                        "column {}.{} {} {} {}",
+                        row.get::<usize, String>(0),
+                        row.get::<usize, String>(1),
+                        row.get::<usize, String>(2),
+                        row.get::<usize, String>(3),
+                        row.get::<usize, String>(4),
+                    ));
+                }
+
+                let rows = client
+                    .query(
+                        "SELECT indexdef FROM pg_indexes WHERE schemaname='public'
+                        ORDER BY indexname;",
+                        &[],
+                    )
+                    .await
+                    .unwrap();
+                for row in rows {
+                    schema.push(format!("index {}", row.get::<usize, String>(0)));
+                }
+
+                let rows = client
+                    .query(
+                        "SELECT conrelid::regclass::TEXT, conname, pg_get_constraintdef(oid)
+                        FROM pg_constraint WHERE connamespace='public'::regnamespace
+                        ORDER BY 1, 2;",
+                        &[],
+                    )
+                    .await
+                    .unwrap();
+                for row in rows {
+                    schema.push(format!(
+                        "constraint {} {} {}",
+                        row.get::<usize, String>(0),
+                        row.get::<usize, String>(1),
+                        row.get::<usize, String>(2),
+                    ));
+                }
+
+                schema
+            }
+            TestDb::Sqlite { storage, .. } => storage
+                .interact(|conn| {
+                    let mut schema: Vec<String> = Vec::new();
+
+                    // table_info rather than the CREATE statements, DROP COLUMN
+                    // rewrites those
+                    let mut stmt = conn.prepare(
+                        "SELECT m.name, p.name, p.type, p.\"notnull\",
+                            COALESCE(p.dflt_value, ''), p.pk
+                        FROM sqlite_master m, pragma_table_info(m.name) p
+                        WHERE m.type='table' AND m.name NOT LIKE 'sqlite_%'
+                        ORDER BY m.name, p.name;",
+                    )?;
+                    let mut rows = stmt.query([])?;
+                    while let Some(row) = rows.next()? {
+                        schema.push(format!(
+                            "column {}.{} {} {} {} {}",
+                            row.get::<usize, String>(0)?,
+                            row.get::<usize, String>(1)?,
+                            row.get::<usize, String>(2)?,
+                            row.get::<usize, bool>(3)?,
+                            row.get::<usize, String>(4)?,
+                            row.get::<usize, i64>(5)?,
+                        ));
+                    }
+
+                    let mut stmt = conn.prepare(
+                        "SELECT name, COALESCE(sql, '') FROM sqlite_master
+                        WHERE type='index' ORDER BY name;",
+                    )?;
+                    let mut rows = stmt.query([])?;
+                    while let Some(row) = rows.next()? {
+                        schema.push(format!(
+                            "index {} {}",
+                            row.get::<usize, String>(0)?,
+                            row.get::<usize, String>(1)?,
+                        ));
+                    }
+
+                    Ok(schema)
+                })
+                .await
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn migrations_are_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1);
+        }
+    }
+
+    #[test]
+    fn plan_rejects_unknown_versions() {
+        for backend in [Backend::Postgres, Backend::Sqlite] {
+            assert!(plan(backend, latest_version() + 1, latest_version()).is_err());
+            assert!(plan(backend, 0, latest_version() + 1).is_err());
+            assert_eq!(
+                plan(backend, latest_version(), latest_version())
+                    .unwrap()
+                    .len(),
+                0
+            );
+        }
+    }
+
+    async fn fresh_and_v1_databases_match(backend: Backend) {
+        let (fresh, old) = match (TestDb::create(backend).await, TestDb::create(backend).await) {
+            (Some(fresh), Some(old)) => (fresh, old),
+            _ => return,
+        };
+
+        let applied = migrate(fresh.storage(), None, false).await.unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+
+        // a database as created by the original create_db_tables, with data
+        migrate(old.storage(), Some(1), false).await.unwrap();
+        old.execute(
+            "INSERT INTO search_results (client, search, location, found, started)
+                VALUES('client', 1, '/var/log/auth.log', '{\"a line\"}', '2024-01-01 00:00:00+00:00');",
+        )
+        .await;
+        let applied = migrate(old.storage(), None, false).await.unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len() - 1);
+
+        assert_eq!(
+            fresh.storage().db_version().await.unwrap(),
+            latest_version()
+        );
+        assert_eq!(old.storage().db_version().await.unwrap(), latest_version());
+        assert_eq!(schema(&fresh).await, schema(&old).await);
+
+        fresh.drop().await;
+        old.drop().await;
+    }
+
+    async fn down_migrations_restore_schema(backend: Backend) {
+        let (db, v1) = match (TestDb::create(backend).await, TestDb::create(backend).await) {
+            (Some(db), Some(v1)) => (db, v1),
+            _ => return,
+        };
+
+        migrate(v1.storage(), Some(1), false).await.unwrap();
+        migrate(db.storage(), None, false).await.unwrap();
+        migrate(db.storage(), Some(1), false).await.unwrap();
+
+        assert_eq!(db.storage().db_version().await.unwrap(), 1);
+        assert_eq!(schema(&db).await, schema(&v1).await);
+
+        migrate(db.storage(), Some(0), false).await.unwrap();
+        assert_eq!(db.storage().db_version().await.unwrap(), 0);
+        assert!(schema(&db).await.is_empty());
+
+        db.drop().await;
+        v1.drop().await;
+    }
+
+    async fn dry_run_changes_nothing(backend: Backend) {
+        let db = match TestDb::create(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+
+        let steps = migrate(db.storage(), None, true).await.unwrap();
+        assert_eq!(steps.len(), MIGRATIONS.len());
+        assert_eq!(db.storage().db_version().await.unwrap(), 0);
+        assert!(schema(&db).await.is_empty());
+
+        db.drop().await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_fresh_and_v1_databases_match() {
+        fresh_and_v1_databases_match(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_down_migrations_restore_schema() {
+        down_migrations_restore_schema(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_dry_run_changes_nothing() {
+        dry_run_changes_nothing(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_fresh_and_v1_databases_match() {
+        fresh_and_v1_databases_match(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_down_migrations_restore_schema() {
+        down_migrations_restore_schema(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_dry_run_changes_nothing() {
+        dry_run_changes_nothing(Backend::Sqlite).await;
+    }
+}
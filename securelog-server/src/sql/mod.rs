@@ -1,11 +1,26 @@
-use crate::models::{self, ClientSearchResult, Search, SearchResult, SearchType};
-use crate::{conf, constants};
-use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use crate::conf;
+use crate::models::{self, ClientSearchResult, SearchResult, SearchType};
+use crate::selector::Selector;
+use chrono::{DateTime, Utc};
+use std::sync::OnceLock;
 use std::time::Duration;
-use tokio_postgres::NoTls;
+use storage::{SearchEdit, Storage};
+use utoipa::ToSchema;
 pub type Result<T> = std::result::Result<T, SqlError>;
 
+pub mod archive;
 pub mod client;
+pub mod enrollment;
+pub mod fulltext;
+pub mod integrity;
+pub mod migrations;
+pub mod postgres;
+pub mod retention;
+pub mod runs;
+pub mod sqlite;
+pub mod storage;
+#[cfg(test)]
+mod testdb;
 pub mod user;
 pub mod webhooks;
 
@@ -15,7 +30,32 @@ pub enum SqlError {
     TokioPostgres(#[from] tokio_postgres::Error),
 
     #[error("DeadPoolPostgres({0})")]
-    DeadPoolPostgres(#[from] deadpool::managed::PoolError<tokio_postgres::Error>),
+    DeadPoolPostgres(#[from] deadpool_postgres::PoolError),
+
+    #[error("DeadPoolPostgres(Build({0}))")]
+    PoolBuild(#[from] deadpool_postgres::BuildError),
+
+    #[error("OpenSsl({0})")]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+
+    #[error("Sqlite({0})")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("DeadPoolSqlite({0})")]
+    DeadPoolSqlite(#[from] deadpool_sqlite::PoolError),
+
+    #[error("DeadPoolSqlite(Build({0}))")]
+    SqlitePoolBuild(#[from] deadpool_sqlite::BuildError),
+
+    // a query on the sqlite connection thread panicked or was aborted
+    #[error("DeadPoolSqlite(Interact({0}))")]
+    SqliteInteract(String),
+
+    #[error("Json({0})")]
+    Json(#[from] serde_json::Error),
+
+    #[error("SqlError(Config({0}))")]
+    Config(String),
 
     #[error("Bcrypt({0})")]
     Bcrypt(#[from] bcrypt::BcryptError),
@@ -37,210 +77,135 @@ pub enum SqlError {
 
     #[error("SqlError(No schedule for search {0}")]
     NoSuchSchedule(i32),
-}
 
-impl actix_web::ResponseError for SqlError {}
+    #[error("SqlError(no search {0})")]
+    NoSuchSearch(i32),
 
-lazy_static! {
-    // Postres Pool all functions get their client from
-    static ref POOL: Pool = create_pool().unwrap();
-}
+    #[error("SqlError(search {0} has no version {1})")]
+    NoSuchSearchVersion(i32, i32),
 
-fn create_pool() -> Result<deadpool_postgres::Pool> {
-    let pg_params = conf::get_pg_params().expect("failed to get pg_params");
+    #[error("SqlError(invalid search: {0})")]
+    InvalidSearch(String),
 
-    let config: tokio_postgres::Config = pg_params.parse::<tokio_postgres::Config>()?;
+    #[error("SqlError(invalid enrollment token: {0})")]
+    InvalidEnrollmentToken(String),
 
-    let mgr_config = ManagerConfig {
-        recycling_method: RecyclingMethod::Fast,
-    };
+    #[error("SqlError(Export({0}))")]
+    Export(std::io::Error),
 
-    let mgr = Manager::from_config(config, NoTls, mgr_config);
-    let pool = Pool::builder(mgr).max_size(16).build().unwrap();
+    #[error("SqlError(unknown database version {0})")]
+    UnknownDbVersion(i32),
 
-    Ok(pool)
+    #[error("SqlError(migration to v{0} can not be reverted)")]
+    IrreversibleMigration(i32),
 }
 
-/**
- * Initialize the database. Will automatically update the database
- */
-pub async fn initialize_db() -> Result<()> {
-    /*
-    As db changes, we will check dbinfo to make sure
-    dbver matches current. If not we run the incremental
-    upgrade functions to update, i.e. update_v1_to_v2()
-    then update_v2_to_v3()
-    */
-    let dbver = get_db_version().await?;
-    warn!("Database version {}", dbver);
-
-    match dbver {
-        0 => create_db_tables().await?,
-        1 => {
-            // current version
-        }
-        _ => {
-            error!("unknown database version: {}", dbver);
-            panic!("unknown database version: {}", dbver);
+impl actix_web::ResponseError for SqlError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            SqlError::NoSuchSearch(_) | SqlError::NoSuchSearchVersion(_, _) => {
+                StatusCode::NOT_FOUND
+            }
+            SqlError::InvalidSearch(_) | SqlError::InvalidEnrollmentToken(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
-
-    Ok(())
 }
 
-pub async fn get_db_version() -> Result<i32> {
-    if !table_exists("dbinfo").await? {
-        return Ok(0);
+/**
+ * Database the server stores everything in, set with db_backend in the config.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+}
+impl std::str::FromStr for Backend {
+    type Err = SqlError;
+
+    fn from_str(s: &str) -> Result<Backend> {
+        match s {
+            "postgres" => Ok(Backend::Postgres),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(SqlError::Config(format!(
+                "db_backend must be postgres or sqlite, not {}",
+                s
+            ))),
+        }
     }
-    let client = POOL.get().await?;
+}
 
-    let rows = client
-        .query("SELECT dbver FROM dbinfo LIMIT 1;", &[])
-        .await?;
+// storage all functions go through, set by connect()
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
 
-    let dbver = if rows.is_empty() {
-        0
-    } else {
-        rows[0].get("dbver")
-    };
-
-    Ok(dbver)
+fn storage() -> &'static dyn Storage {
+    STORAGE
+        .get()
+        .expect("storage used before sql::connect()")
+        .as_ref()
 }
 
-async fn create_db_tables() -> Result<()> {
-    warn!("Creating db tables!");
-    let mut client = POOL.get().await?;
-    let tran = client.transaction().await?;
-
-    tran.execute(
-        "CREATE TABLE dbinfo (
-            id SERIAL PRIMARY KEY,
-            dbver INT
-         );",
-        &[],
-    )
-    .await?;
-    tran.execute("INSERT INTO dbinfo (dbver) VALUES(1);", &[])
-        .await?;
-
-    tran.execute(
-        "CREATE TABLE auth (
-            username TEXT PRIMARY KEY,
-            passwd TEXT NOT NULL,
-            lastlogin TIMESTAMP WITH TIME ZONE,
-            enabled BOOL NOT NULL
-        );",
-        &[],
-    )
-    .await?;
-
-    // meant for preventing authentication brute forcing
-    // not implemented yet
-    tran.execute(
-        "CREATE TABLE authbrute (
-            id TEXT PRIMARY KEY,
-            ip TEXT NOT NULL,
-            ts TIMESTAMP WITH TIME ZONE,
-            count INT NOT NULL
-        );",
-        &[],
-    )
-    .await?;
-
-    tran.execute(
-        "CREATE TABLE clients (
-            id TEXT PRIMARY KEY,
-            token TEXT NOT NULL,
-            name TEXT NOT NULL,
-            enabled BOOL NOT NULL,
-            created TIMESTAMP WITH TIME ZONE,
-            lastconnect TIMESTAMP WITH TIME ZONE
-        );",
-        &[],
-    )
-    .await?;
-
-    tran.execute(
-        "CREATE TABLE client_schedule (
-            id TEXT PRIMARY KEY,
-            lastrun TIMESTAMP WITH TIME ZONE,
-            manualrun BOOL
-        );",
-        &[],
-    )
-    .await?;
-
-    tran.execute(
-        "CREATE TABLE scan_schedule (
-            searchid INT NOT NULL,
-            schedule INT NOT NULL,
-            manual BOOL NOT NULL
-        );",
-        &[],
-    )
-    .await?;
-    // default scan every 30 minutes
-    tran.execute(
-        "INSERT INTO scan_schedule (searchid, schedule, manual) VALUES(0, 30, 'f');",
-        &[],
-    )
-    .await?;
+/**
+ * Connect to the configured database and check it can be reached.
+ * Has to be called once at startup before any other function here.
+ */
+pub async fn connect() -> Result<()> {
+    let backend: Backend = match conf::get_db_backend() {
+        Ok(backend) => backend.parse()?,
+        Err(_) => Backend::Postgres,
+    };
 
-    /*
-     * 'searches' table is for storing log searches.
-     */
-    tran.execute(
-        "CREATE TABLE searches (
-            id SERIAL PRIMARY KEY,
-            name TEXT NOT NULL,
-            type INT NOT NULL,
-            search TEXT NOT NULL,
-            locations TEXT [],
-            enabled BOOL NOT NULL
-        );",
-        &[],
-    )
-    .await?;
-
-    tran.execute(
-        "CREATE TABLE search_results (
-            id SERIAL PRIMARY KEY,
-            client TEXT NOT NULL,
-            search INT NOT NULL,
-            location TEXT,
-            found TEXT [] NOT NULL,
-            started TIMESTAMP WITH TIME ZONE NOT NULL
-        );",
-        &[],
-    )
-    .await?;
-
-    tran.execute(
-        "CREATE TABLE webhooks (
-            name TEXT PRIMARY KEY,
-            url TEXT NOT NULL,
-            username TEXT NOT NULL
-        );",
-        &[],
-    )
-    .await?;
+    let storage: Box<dyn Storage> = match backend {
+        Backend::Postgres => Box::new(postgres::Postgres::connect().await?),
+        Backend::Sqlite => Box::new(sqlite::Sqlite::connect().await?),
+    };
 
-    tran.commit().await?;
+    STORAGE
+        .set(storage)
+        .map_err(|_| SqlError::Config("sql::connect() called twice".to_string()))
+}
 
-    Ok(())
+/**
+ * Use a migrated SQLite database for the sql::* functions, for tests that go
+ * through them. Every test shares the same one, it is left in the temp dir.
+ */
+#[cfg(test)]
+pub async fn connect_test() {
+    static CONNECTED: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
+
+    CONNECTED
+        .get_or_init(|| async {
+            let path =
+                std::env::temp_dir().join(format!("securelog_test_{}.db", random_string(12)));
+            let storage = sqlite::Sqlite::open(&path.to_string_lossy()).await.unwrap();
+            migrations::migrate(&storage, None, false).await.unwrap();
+            if STORAGE.set(Box::new(storage)).is_err() {
+                panic!("sql::connect_test() after sql::connect()");
+            }
+        })
+        .await;
 }
 
-async fn table_exists(table_name: &str) -> Result<bool> {
-    let client = POOL.get().await?;
+/**
+ * Initialize the database. Will automatically update the database
+ * to the latest version, see migrations.rs
+ */
+pub async fn initialize_db() -> Result<Vec<migrations::Step>> {
+    migrate(None, false).await
+}
 
-    let row = client
-        .query_one(
-            "SELECT EXISTS (SELECT FROM pg_tables WHERE schemaname=$1 AND tablename=$2);",
-            &[&"public", &table_name],
-        )
-        .await?;
+/**
+ * Migrate the database to `target`, or the latest version if None.
+ * With dry_run nothing is changed, the steps that would run are returned.
+ */
+pub async fn migrate(target: Option<i32>, dry_run: bool) -> Result<Vec<migrations::Step>> {
+    let dbver = storage().db_version().await?;
+    warn!("Database version {}", dbver);
 
-    Ok(row.get(0))
+    migrations::migrate(storage(), target, dry_run).await
 }
 
 fn random_string(len: usize) -> String {
@@ -257,193 +222,398 @@ fn random_string(len: usize) -> String {
 }
 
 pub async fn get_search(id: i32) -> Result<Option<models::Search>> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT * FROM searches WHERE id=$1 LIMIT 1;", &[&id])
-        .await?;
-
-    if !rows.is_empty() {
-        let row = &rows[0];
-        let locations: Vec<String> = row.get("locations");
-
-        if let Some(stype) = models::SearchType::from_sql_code(row.get("type")) {
-            if let Some(search) = row.get("search") {
-                return Ok(Some(models::Search {
-                    id,
-                    name: row.get("name"),
-                    stype,
-                    search,
-                    locations,
-                }));
-            }
-        }
-    }
-
-    Ok(None)
+    storage().get_search(id).await
 }
 
+/**
+ * Enabled searches, the ones clients run.
+ */
 pub async fn get_searches() -> Result<Vec<models::Search>> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT * FROM searches WHERE enabled='t';", &[])
-        .await?;
-
-    let mut searches: Vec<models::Search> = Vec::new();
-    for row in rows {
-        let id: i32 = row.get("id");
-        let name: String = row.get("name");
-        let stype = models::SearchType::from_sql_code(row.get::<&str, i32>("type")).unwrap();
-        let search_str: String = row.get("search");
-        let locations: Vec<String> = row.get("locations");
-
-        let search = models::Search::new(id, name, stype, search_str, locations);
-        searches.push(search);
-    }
+    storage().get_searches().await
+}
+
+/**
+ * Enabled searches whose selector matches the client's tags.
+ */
+async fn selected_searches(clientid: &str) -> Result<Vec<models::Search>> {
+    let tags = client::effective_tags(&client::get_client_tags(clientid).await?);
+
+    Ok(get_searches()
+        .await?
+        .into_iter()
+        .filter(|search| match &search.selector {
+            Some(selector) => match Selector::parse(selector) {
+                Ok(selector) => selector.matches(&tags),
+                Err(e) => {
+                    warn!("search {} has a bad selector: {}", search.id, e);
+                    false
+                }
+            },
+            None => true,
+        })
+        .collect())
+}
+
+// the searches with only the locations `keep` takes, searches left with
+// none are dropped
+fn with_locations(
+    searches: Vec<models::Search>,
+    keep: impl Fn(&str) -> bool,
+) -> Vec<models::Search> {
+    searches
+        .into_iter()
+        .filter_map(|mut search| {
+            search.locations.retain(|location| keep(location));
+            (!search.locations.is_empty()).then_some(search)
+        })
+        .collect()
+}
 
-    Ok(searches)
+/**
+ * The searches a client runs. syslog:// locations are left out, the server
+ * searches those itself, see syslog.rs.
+ */
+pub async fn get_client_searches(clientid: &str) -> Result<Vec<models::Search>> {
+    Ok(with_locations(
+        selected_searches(clientid).await?,
+        |location| !securelog_search::is_syslog(location),
+    ))
+}
+
+/**
+ * The searches the server runs over the syslog messages of a client, with
+ * only their syslog:// locations.
+ */
+pub async fn get_syslog_searches(clientid: &str) -> Result<Vec<models::Search>> {
+    Ok(with_locations(
+        selected_searches(clientid).await?,
+        securelog_search::is_syslog,
+    ))
+}
+
+/**
+ * Every search, enabled or not.
+ */
+pub async fn get_all_searches() -> Result<Vec<models::Search>> {
+    storage().get_all_searches().await
 }
 
 pub async fn delete_search(id: i32) -> Result<()> {
-    let client = POOL.get().await?;
+    if storage().delete_search(id).await? {
+        Ok(())
+    } else {
+        Err(SqlError::NoSuchSearch(id))
+    }
+}
 
-    let _result = client
-        .execute("DELETE FROM searches WHERE id=$1;", &[&id])
-        .await?;
+/**
+ * A search as a user enters it.
+ */
+pub struct SearchFields<'a> {
+    pub name: &'a str,
+    pub stype: &'a SearchType,
+    pub search: &'a str,
+    pub locations: &'a [String],
+    // tags of the clients that get the search, None for every client
+    pub selector: Option<&'a str>,
+    pub realtime: bool,
+}
+
+/**
+ * Check a search can be saved.
+ */
+pub fn validate_search(fields: &SearchFields<'_>) -> Result<()> {
+    let SearchFields {
+        name,
+        stype,
+        search,
+        locations,
+        selector,
+        ..
+    } = *fields;
+    if name.trim().is_empty() {
+        return Err(SqlError::InvalidSearch(
+            "name must not be empty".to_string(),
+        ));
+    }
+    if search.is_empty() {
+        return Err(SqlError::InvalidSearch(
+            "search must not be empty".to_string(),
+        ));
+    }
+    // clients compile searches the same way, one that fails here would
+    // fail on every client
+    securelog_search::Matcher::new(stype, search)
+        .map_err(|e| SqlError::InvalidSearch(format!("invalid regex: {}", e)))?;
+
+    if locations.is_empty() {
+        return Err(SqlError::InvalidSearch(
+            "at least one location is needed".to_string(),
+        ));
+    }
+    for location in locations {
+        if location.trim().is_empty() {
+            return Err(SqlError::InvalidSearch(
+                "locations must not be empty".to_string(),
+            ));
+        }
+        if securelog_search::is_journal(location) {
+            securelog_search::JournalQuery::parse(location)
+                .map_err(|e| SqlError::InvalidSearch(e.to_string()))?;
+            continue;
+        }
+        if securelog_search::is_command(location) {
+            securelog_search::command_name(location)
+                .map_err(|e| SqlError::InvalidSearch(e.to_string()))?;
+            continue;
+        }
+        if securelog_search::is_syslog(location) {
+            securelog_search::SyslogQuery::parse(location)
+                .map_err(|e| SqlError::InvalidSearch(e.to_string()))?;
+            continue;
+        }
+        // clients open locations as they are
+        if location.contains(['*', '?']) {
+            return Err(SqlError::InvalidSearch(format!(
+                "location {} has a wildcard",
+                location
+            )));
+        }
+    }
+    if let Some(selector) = selector {
+        Selector::parse(selector).map_err(|e| SqlError::InvalidSearch(e.to_string()))?;
+    }
 
     Ok(())
 }
 
+// a blank selector is no selector
+fn non_empty_selector(selector: Option<&str>) -> Option<&str> {
+    selector
+        .map(str::trim)
+        .filter(|selector| !selector.is_empty())
+}
+
 /**
- * Insert the search object into the database.
+ * Insert the search object into the database, `user` is who created it.
  * Returns the new id associated with the search.
  */
-pub async fn insert_search(
-    name: &str,
-    stype: &SearchType,
-    search: &str,
-    locations: &[String],
-) -> Result<i32> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query(
-            "INSERT INTO searches
-        (name, type, search, locations, enabled)
-        VALUES($1, $2, $3, $4, $5)
-        RETURNING id;",
-            &[&name, &stype.sql_code(), &search, &locations, &true],
-        )
-        .await?;
-    let id: i32 = rows[0].get("id");
-
-    Ok(id)
+pub async fn insert_search(user: &str, fields: &SearchFields<'_>) -> Result<i32> {
+    let edit = search_edit(user, fields)?;
+    storage().insert_search(&edit, true).await
+}
+
+// validate what the user entered for saving
+fn search_edit<'a>(user: &'a str, fields: &SearchFields<'a>) -> Result<SearchEdit<'a>> {
+    let fields = SearchFields {
+        selector: non_empty_selector(fields.selector),
+        ..*fields
+    };
+    validate_search(&fields)?;
+
+    Ok(SearchEdit {
+        name: fields.name,
+        stype: fields.stype,
+        search: fields.search,
+        locations: fields.locations,
+        selector: fields.selector,
+        realtime: fields.realtime,
+        changed_by: user,
+        changed: Utc::now(),
+    })
+}
+
+/**
+ * Replace the search, the previous version is kept in its history.
+ * Returns the new version.
+ */
+pub async fn update_search(id: i32, user: &str, fields: &SearchFields<'_>) -> Result<i32> {
+    let edit = search_edit(user, fields)?;
+    storage()
+        .update_search(id, &edit)
+        .await?
+        .ok_or(SqlError::NoSuchSearch(id))
+}
+
+pub async fn set_search_enabled(id: i32, enabled: bool) -> Result<()> {
+    if storage().set_search_enabled(id, enabled).await? {
+        Ok(())
+    } else {
+        Err(SqlError::NoSuchSearch(id))
+    }
+}
+
+/**
+ * Copy a search under a new name, "<name> (copy)" if None. The copy starts
+ * disabled so clients don't run the same search twice before it is edited.
+ * Returns the id of the copy.
+ */
+pub async fn clone_search(id: i32, user: &str, name: Option<&str>) -> Result<i32> {
+    let original = get_search(id).await?.ok_or(SqlError::NoSuchSearch(id))?;
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => format!("{} (copy)", original.name),
+    };
+    let edit = search_edit(
+        user,
+        &SearchFields {
+            name: &name,
+            stype: &original.stype,
+            search: &original.search,
+            locations: &original.locations,
+            selector: original.selector.as_deref(),
+            realtime: original.realtime,
+        },
+    )?;
+    storage().insert_search(&edit, false).await
+}
+
+/**
+ * Saved versions of a search, newest first.
+ */
+pub async fn get_search_history(id: i32) -> Result<Vec<models::SearchVersion>> {
+    if get_search(id).await?.is_none() {
+        return Err(SqlError::NoSuchSearch(id));
+    }
+
+    storage().get_search_history(id).await
 }
 
+/**
+ * Make `version` the current search again, saved as a new version.
+ * Returns the new version.
+ */
+pub async fn revert_search(id: i32, version: i32, user: &str) -> Result<i32> {
+    let old = storage()
+        .get_search_version(id, version)
+        .await?
+        .ok_or(SqlError::NoSuchSearchVersion(id, version))?;
+
+    update_search(
+        id,
+        user,
+        &SearchFields {
+            name: &old.name,
+            stype: &old.stype,
+            search: &old.search,
+            locations: &old.locations,
+            selector: old.selector.as_deref(),
+            realtime: old.realtime,
+        },
+    )
+    .await
+}
+
+/**
+ * Insert a result sent by a client. `signature` is the verified signature of
+ * the batch the result was sent in, None for clients without a public key.
+ */
 pub async fn insert_client_search_result(
     clientid: &str,
     result: &ClientSearchResult,
+    signature: Option<&str>,
 ) -> Result<()> {
-    let client = POOL.get().await?;
-
-    let _result = client
-        .execute(
-            "INSERT INTO search_results
-        (client, search, location, found, started)
-        VALUES($1, $2, $3, $4, $5);",
-            &[
-                &clientid,
-                &result.search_id,
-                &result.location,
-                &result.found,
-                &result.started,
-            ],
-        )
-        .await?;
+    storage()
+        .insert_search_result(clientid, result, signature)
+        .await
+}
 
-    Ok(())
+// page size of get_search_results when none is given, and the largest allowed
+pub const DEFAULT_RESULTS_PAGE: i64 = 100;
+pub const MAX_RESULTS_PAGE: i64 = 1000;
+
+/**
+ * Position in the results, results are ordered newest first by (started, id).
+ * Sent to users as an opaque token to fetch the next page with.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResultsCursor {
+    pub started: DateTime<Utc>,
+    pub id: i32,
 }
+impl ResultsCursor {
+    pub fn encode(&self) -> String {
+        use base64::Engine;
+        let token = format!("{}:{}", self.started.timestamp_micros(), self.id);
 
-use chrono::{DateTime, Utc};
-pub async fn get_search_results(
-    clientid: Option<String>,
-    before: Option<DateTime<Utc>>,
-    after: Option<DateTime<Utc>>,
-) -> Result<Vec<SearchResult>> {
-    let results = get_all_search_results().await?;
-    let mut new_results: Vec<SearchResult> = Vec::new();
-
-    for result in results {
-        let mut include = true;
-        if let Some(clientid) = &clientid {
-            if &result.client_id != clientid {
-                include = false;
-            }
-        }
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token)
+    }
+    pub fn decode(token: &str) -> Option<ResultsCursor> {
+        use base64::Engine;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()?;
+        let token = String::from_utf8(bytes).ok()?;
+        let (micros, id) = token.split_once(':')?;
+
+        Some(ResultsCursor {
+            started: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: id.parse().ok()?,
+        })
+    }
+}
 
-        if let Some(before) = &before {
-            if &result.started <= before {
-                include = false;
-            }
-        }
-        if let Some(after) = &after {
-            if &result.started >= after {
-                include = false;
-            }
-        }
-        if include {
-            new_results.push(result);
-        }
+#[derive(Debug, Default)]
+pub struct SearchResultFilter {
+    pub client: Option<String>,
+    pub search: Option<i32>,
+    pub before: Option<DateTime<Utc>>,
+    pub after: Option<DateTime<Utc>>,
+    // case insensitive substring of any found line
+    pub text: Option<String>,
+    pub cursor: Option<ResultsCursor>,
+    pub limit: Option<i64>,
+}
+impl SearchResultFilter {
+    /**
+     * text with LIKE wildcards escaped, it is matched literally.
+     */
+    fn like_text(&self) -> Option<String> {
+        self.text.as_ref().map(|text| {
+            text.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        })
     }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchResultPage {
+    pub results: Vec<SearchResult>,
+    // None on the last page
+    pub next_cursor: Option<String>,
+}
+impl SearchResultPage {
+    /**
+     * Build a page from up to limit + 1 rows, the extra row only tells
+     * there is a next page.
+     */
+    fn from_rows(rows: Vec<(SearchResult, ResultsCursor)>, limit: i64) -> SearchResultPage {
+        let more = rows.len() as i64 > limit;
+
+        let mut results: Vec<SearchResult> = Vec::new();
+        let mut last: Option<ResultsCursor> = None;
+        for (result, cursor) in rows.into_iter().take(limit as usize) {
+            last = Some(cursor);
+            results.push(result);
+        }
 
-    Ok(new_results)
-}
-
-pub async fn get_all_search_results() -> Result<Vec<SearchResult>> {
-    let client = POOL.get().await?;
-
-    let rows = client.query("SELECT * FROM search_results;", &[]).await?;
-
-    let mut results: Vec<SearchResult> = Vec::new();
-
-    for row in rows {
-        let search_id: i32 = row.get("search");
-        let search_name: String = {
-            let rows = client
-                .query(
-                    "SELECT name FROM searches WHERE id=$1 LIMIT 1;",
-                    &[&search_id],
-                )
-                .await?;
-
-            rows[0].get("name")
-        };
-        let client_id: String = row.get("client");
-        let client_name: String = {
-            let rows = client
-                .query(
-                    "SELECT name FROM clients WHERE id=$1 LIMIT 1;",
-                    &[&client_id],
-                )
-                .await?;
-
-            rows[0].get("name")
-        };
-
-        let result = SearchResult {
-            client_id,
-            client_name,
-            search_id,
-            search_name,
-            location: row.get("location"),
-            found: row.get("found"),
-            started: row.get("started"),
-        };
-        results.push(result);
+        SearchResultPage {
+            results,
+            next_cursor: if more {
+                last.map(|cursor| cursor.encode())
+            } else {
+                None
+            },
+        }
     }
+}
 
-    Ok(results)
+/**
+ * Get one page of search results matching the filter, newest first.
+ */
+pub async fn get_search_results(filter: &SearchResultFilter) -> Result<SearchResultPage> {
+    storage().get_search_results(filter).await
 }
 
 #[derive(Debug)]
@@ -460,38 +630,12 @@ impl ScanSchedule {
     }
 }
 pub async fn get_scan_schedule() -> Result<ScanSchedule> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query(
-            "SELECT * FROM scan_schedule WHERE searchid=$1 LIMIT 1;",
-            &[&0i32],
-        )
-        .await?;
-
-    if !rows.is_empty() {
-        let row = &rows[0];
-        let schedule: i32 = row.get("schedule");
-        let duration = Duration::from_secs((schedule as u64) * 60);
-
-        Ok(ScanSchedule {
-            dur: duration,
-            manual: row.get("manual"),
-        })
-    } else {
-        Err(SqlError::NoSuchSchedule(0i32))
-    }
+    storage()
+        .get_scan_schedule()
+        .await?
+        .ok_or(SqlError::NoSuchSchedule(0i32))
 }
 
 pub async fn set_search_schedule_minutes(schedule: i32, manual: bool) -> Result<()> {
-    let client = POOL.get().await?;
-
-    let _result = client
-        .execute(
-            "UPDATE scan_schedule SET schedule=$1, manual=$3 WHERE searchid=$2;",
-            &[&schedule, &0i32, &manual],
-        )
-        .await?;
-
-    Ok(())
+    storage().set_scan_schedule(schedule, manual).await
 }
@@ -0,0 +1,1742 @@
+/*
+Postgres storage backend.
+*/
+use super::archive::{ArchiveSegment, SegmentFilter};
+use super::client::{
+    capabilities_from_sql, capabilities_to_sql, ClientInventory, ClientLastRun, ClientStatus,
+    SLClient,
+};
+use super::enrollment::EnrollmentToken;
+use super::fulltext::{self, Facet, LineMatch, MatchPage, MatchQuery};
+use super::integrity::{IntegrityBaseline, IntegrityChange};
+use super::retention::{ExpiredResult, PruneTarget, SearchRetention, StorageUsage};
+use super::runs::SearchRun;
+use super::storage::{ClientTokenRow, NewClient, NewEnrollmentToken, SearchEdit, Storage, UserRow};
+use super::webhooks::Webhook;
+use super::{
+    Backend, Result, ResultsCursor, ScanSchedule, SearchResultFilter, SearchResultPage, SqlError,
+    DEFAULT_RESULTS_PAGE, MAX_RESULTS_PAGE,
+};
+use crate::conf;
+use crate::models::{
+    self, ClientSearchResult, ClientSettings, ClientTag, FileChange, FileChangeKind, FileState,
+    RunError, RunErrorKind, SearchResult, SearchRunReport, SearchType, SqlCode, TagSource,
+};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio_postgres::NoTls;
+
+// default maximum number of connections in the pool
+const DEFAULT_POOL_SIZE: usize = 16;
+// default seconds to wait for a free connection or to connect
+const DEFAULT_POOL_TIMEOUT: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+/**
+ * TLS verification for the database connection, named like libpq's sslmode.
+ */
+#[derive(Debug, PartialEq)]
+enum SslMode {
+    // no TLS, the default
+    Disable,
+    // TLS without verifying the server certificate
+    Require,
+    // TLS, server certificate must chain to the CA bundle
+    VerifyCa,
+    // as VerifyCa and the certificate must match the host name
+    VerifyFull,
+}
+impl std::str::FromStr for SslMode {
+    type Err = SqlError;
+
+    fn from_str(s: &str) -> Result<SslMode> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(SqlError::Config(format!(
+                "pg_sslmode must be disable, require, verify-ca or verify-full, not {}",
+                s
+            ))),
+        }
+    }
+}
+
+fn recycling_method(method: &str) -> Result<RecyclingMethod> {
+    match method {
+        "fast" => Ok(RecyclingMethod::Fast),
+        "verified" => Ok(RecyclingMethod::Verified),
+        "clean" => Ok(RecyclingMethod::Clean),
+        _ => Err(SqlError::Config(format!(
+            "pg_recycling_method must be fast, verified or clean, not {}",
+            method
+        ))),
+    }
+}
+
+// optional positive number of seconds/connections from the config
+fn positive_option(
+    name: &str,
+    value: std::result::Result<i64, config::ConfigError>,
+) -> Result<Option<u64>> {
+    match value {
+        Ok(value) if value > 0 => Ok(Some(value as u64)),
+        Ok(value) => Err(SqlError::Config(format!(
+            "{} must be greater than 0, not {}",
+            name, value
+        ))),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(SqlError::Config(format!("{}: {}", name, e))),
+    }
+}
+
+fn make_tls_connector(mode: &SslMode) -> Result<MakeTlsConnector> {
+    let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+
+    // without a CA bundle the system roots are used
+    if let Ok(ca) = conf::get_pg_ssl_ca() {
+        builder
+            .set_ca_file(&ca)
+            .map_err(|e| SqlError::Config(format!("failed to load pg_ssl_ca {}: {}", ca, e)))?;
+    }
+
+    match (conf::get_pg_ssl_cert(), conf::get_pg_ssl_key()) {
+        (Ok(cert), Ok(key)) => {
+            builder.set_certificate_chain_file(&cert).map_err(|e| {
+                SqlError::Config(format!("failed to load pg_ssl_cert {}: {}", cert, e))
+            })?;
+            builder
+                .set_private_key_file(&key, SslFiletype::PEM)
+                .map_err(|e| {
+                    SqlError::Config(format!("failed to load pg_ssl_key {}: {}", key, e))
+                })?;
+            builder.check_private_key()?;
+        }
+        (Err(_), Err(_)) => (),
+        _ => {
+            return Err(SqlError::Config(
+                "pg_ssl_cert and pg_ssl_key must be set together".to_string(),
+            ))
+        }
+    }
+
+    if *mode == SslMode::Require {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+
+    let mut connector = MakeTlsConnector::new(builder.build());
+    if *mode != SslMode::VerifyFull {
+        connector.set_callback(|connect, _domain| {
+            connect.set_verify_hostname(false);
+            Ok(())
+        });
+    }
+
+    Ok(connector)
+}
+
+fn create_pool() -> Result<Pool> {
+    let pg_params =
+        conf::get_pg_params().map_err(|e| SqlError::Config(format!("pg_params: {}", e)))?;
+
+    let mut config: tokio_postgres::Config = pg_params.parse::<tokio_postgres::Config>()?;
+
+    let connect_timeout = positive_option("pg_connect_timeout", conf::get_pg_connect_timeout())?
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+    config.connect_timeout(Duration::from_secs(connect_timeout));
+
+    // in milliseconds, sent as a startup option so it applies to every query
+    if let Some(timeout) =
+        positive_option("pg_statement_timeout", conf::get_pg_statement_timeout())?
+    {
+        let options = match config.get_options() {
+            Some(options) => format!("{} -c statement_timeout={}", options, timeout),
+            None => format!("-c statement_timeout={}", timeout),
+        };
+        config.options(&options);
+    }
+
+    let mgr_config = ManagerConfig {
+        recycling_method: match conf::get_pg_recycling_method() {
+            Ok(method) => recycling_method(&method)?,
+            Err(_) => RecyclingMethod::Fast,
+        },
+    };
+
+    let sslmode: SslMode = match conf::get_pg_sslmode() {
+        Ok(mode) => mode.parse()?,
+        Err(_) => SslMode::Disable,
+    };
+
+    let mgr = if sslmode == SslMode::Disable {
+        Manager::from_config(config, NoTls, mgr_config)
+    } else {
+        // never fall back to plain text once TLS is configured
+        config.ssl_mode(tokio_postgres::config::SslMode::Require);
+        Manager::from_config(config, make_tls_connector(&sslmode)?, mgr_config)
+    };
+
+    let size = positive_option("pg_pool_size", conf::get_pg_pool_size())?
+        .map(|size| size as usize)
+        .unwrap_or(DEFAULT_POOL_SIZE);
+    let timeout = Duration::from_secs(
+        positive_option("pg_pool_timeout", conf::get_pg_pool_timeout())?
+            .unwrap_or(DEFAULT_POOL_TIMEOUT),
+    );
+
+    let pool = Pool::builder(mgr)
+        .max_size(size)
+        .runtime(Runtime::Tokio1)
+        .wait_timeout(Some(timeout))
+        .create_timeout(Some(Duration::from_secs(connect_timeout)))
+        .recycle_timeout(Some(timeout))
+        .build()?;
+
+    Ok(pool)
+}
+
+pub struct Postgres {
+    pub(super) pool: Pool,
+}
+impl Postgres {
+    /**
+     * Create the pool from the config and check the database can be reached.
+     */
+    pub async fn connect() -> Result<Postgres> {
+        let postgres = Postgres {
+            pool: create_pool()?,
+        };
+
+        let client = postgres.pool.get().await?;
+        let row = client.query_one("SELECT version();", &[]).await?;
+        info!("Connected to {}", row.get::<usize, String>(0));
+        drop(client);
+
+        Ok(postgres)
+    }
+}
+
+fn segment_from_row(row: &tokio_postgres::Row) -> ArchiveSegment {
+    ArchiveSegment {
+        id: row.get("id"),
+        client: row.get("client"),
+        location: row.get("location"),
+        start_offset: row.get("start_offset"),
+        size: row.get("size"),
+        stored_size: row.get("stored_size"),
+        received: row.get("received"),
+    }
+}
+
+fn storage_usage_from_row(row: &tokio_postgres::Row) -> StorageUsage {
+    StorageUsage {
+        id: row.get("id"),
+        name: row.get("name"),
+        rows: row.get("rows"),
+        bytes: row.get("bytes"),
+    }
+}
+
+fn search_from_row(row: &tokio_postgres::Row) -> Option<models::Search> {
+    let stype = SearchType::from_sql_code(row.get("type"))?;
+
+    Some(models::Search {
+        id: row.get("id"),
+        name: row.get("name"),
+        stype,
+        search: row.get("search"),
+        locations: row
+            .get::<&str, Option<Vec<String>>>("locations")
+            .unwrap_or_default(),
+        enabled: row.get("enabled"),
+        selector: row.get("selector"),
+        realtime: row.get("realtime"),
+    })
+}
+
+fn search_version_from_row(row: &tokio_postgres::Row) -> Option<models::SearchVersion> {
+    Some(models::SearchVersion {
+        search_id: row.get("search"),
+        version: row.get("version"),
+        name: row.get("name"),
+        stype: SearchType::from_sql_code(row.get("type"))?,
+        search: row.get("search_text"),
+        locations: row
+            .get::<&str, Option<Vec<String>>>("locations")
+            .unwrap_or_default(),
+        changed: row.get("changed"),
+        changed_by: row.get("changed_by"),
+        selector: row.get("selector"),
+        realtime: row.get("realtime"),
+    })
+}
+
+fn search_run_from_row(row: &tokio_postgres::Row) -> SearchRun {
+    let error = row
+        .get::<&str, Option<i32>>("error_kind")
+        .and_then(RunErrorKind::from_sql_code)
+        .map(|kind| RunError {
+            kind,
+            message: row.get::<&str, Option<String>>("error").unwrap_or_default(),
+        });
+
+    SearchRun {
+        client_id: row.get("client"),
+        client_name: row.get("client_name"),
+        search_id: row.get("search"),
+        location: row.get("location"),
+        started: row.get("started"),
+        duration_ms: row.get("duration_ms"),
+        lines_scanned: row.get("lines_scanned"),
+        bytes_read: row.get("bytes_read"),
+        error,
+    }
+}
+
+fn integrity_change_from_row(row: &tokio_postgres::Row) -> Result<Option<IntegrityChange>> {
+    let Some(kind) = FileChangeKind::from_sql_code(row.get("kind")) else {
+        return Ok(None);
+    };
+    let state = |column: &str| -> Result<Option<FileState>> {
+        Ok(row
+            .get::<&str, Option<&str>>(column)
+            .map(serde_json::from_str)
+            .transpose()?)
+    };
+
+    Ok(Some(IntegrityChange {
+        id: row.get("id"),
+        client_id: row.get("client"),
+        client_name: row.get("client_name"),
+        path: row.get("path"),
+        kind,
+        changed: serde_json::from_str(row.get("changed"))?,
+        before: state("old_state")?,
+        after: state("new_state")?,
+        detected: row.get("detected"),
+    }))
+}
+
+fn enrollment_token_from_row(row: &tokio_postgres::Row) -> Result<EnrollmentToken> {
+    Ok(EnrollmentToken {
+        id: row.get("id"),
+        name: row.get("name"),
+        tags: serde_json::from_str(row.get("tags"))?,
+        max_uses: row.get("max_uses"),
+        uses: row.get("uses"),
+        expires: row.get("expires"),
+        created: row.get("created"),
+        created_by: row.get("created_by"),
+    })
+}
+
+fn client_status_from_row(row: &tokio_postgres::Row) -> ClientStatus {
+    ClientStatus {
+        id: row.get("id"),
+        name: row.get("name"),
+        enabled: row.get("enabled"),
+        created: row.get("created"),
+        agent_version: row.get("agent_version"),
+        last_seen: row.get("last_seen"),
+        last_run: row.get("lastrun"),
+        last_result: row.get("last_result"),
+        last_error: row.get("last_error"),
+        last_error_at: row.get("last_error_at"),
+        missed_alert: row.get("missed_alert"),
+        overdue: false,
+    }
+}
+
+fn client_tag_from_row(row: &tokio_postgres::Row) -> Option<ClientTag> {
+    Some(ClientTag {
+        key: row.get("key"),
+        value: row.get("value"),
+        source: TagSource::from_sql_code(row.get("source"))?,
+    })
+}
+
+// add the next version of a search to its history, returns the version
+async fn insert_search_version(
+    tran: &deadpool_postgres::Transaction<'_>,
+    id: i32,
+    search: &SearchEdit<'_>,
+) -> Result<i32> {
+    let row = tran
+        .query_one(
+            "INSERT INTO search_history
+            (search, version, name, type, search_text, locations, changed, changed_by, selector,
+                realtime)
+            SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4, $5, $6, $7, $8, $9
+            FROM search_history WHERE search=$1
+            RETURNING version;",
+            &[
+                &id,
+                &search.name,
+                &search.stype.sql_code(),
+                &search.search,
+                &search.locations,
+                &search.changed,
+                &search.changed_by,
+                &search.selector,
+                &search.realtime,
+            ],
+        )
+        .await?;
+
+    Ok(row.get("version"))
+}
+
+#[async_trait]
+impl Storage for Postgres {
+    fn backend(&self) -> Backend {
+        Backend::Postgres
+    }
+
+    async fn db_version(&self) -> Result<i32> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_one(
+                "SELECT EXISTS (SELECT FROM pg_tables WHERE schemaname=$1 AND tablename=$2);",
+                &[&"public", &"dbinfo"],
+            )
+            .await?;
+        if !row.get::<usize, bool>(0) {
+            return Ok(0);
+        }
+
+        let rows = client
+            .query("SELECT dbver FROM dbinfo LIMIT 1;", &[])
+            .await?;
+
+        let dbver = if rows.is_empty() {
+            0
+        } else {
+            rows[0].get::<&str, Option<i32>>("dbver").unwrap_or(0)
+        };
+
+        Ok(dbver)
+    }
+
+    async fn apply_migration(&self, sql: &str) -> Result<()> {
+        let mut client = self.pool.get().await?;
+
+        let tran = client.transaction().await?;
+        tran.batch_execute(sql).await?;
+        tran.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_user(&self, username: &str) -> Result<Option<UserRow>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM auth WHERE username=$1 LIMIT 1;",
+                &[&username],
+            )
+            .await?;
+
+        Ok(rows.first().map(|row| UserRow {
+            passwd: row.get("passwd"),
+            enabled: row.get("enabled"),
+        }))
+    }
+
+    async fn insert_user(&self, username: &str, passwd: &str) -> Result<u64> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute(
+                "INSERT INTO auth (username, passwd, enabled)
+            VALUES($1, $2, $3);",
+                &[&username, &passwd, &true],
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_user_last_login(&self, username: &str, ts: DateTime<Utc>) -> Result<u64> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute(
+                "UPDATE auth SET lastlogin=$1 WHERE username=$2;",
+                &[&ts, &username],
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn has_users(&self) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT username FROM auth LIMIT 1;", &[])
+            .await?;
+
+        Ok(!rows.is_empty())
+    }
+
+    async fn get_client_token(&self, id: &str) -> Result<Option<ClientTokenRow>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT * FROM clients WHERE id=$1;", &[&id])
+            .await?;
+
+        Ok(rows.first().map(|row| ClientTokenRow {
+            token: row.get("token"),
+            enabled: row.get("enabled"),
+        }))
+    }
+
+    async fn set_client_last_connect(&self, id: &str, ts: DateTime<Utc>) -> Result<u64> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute(
+                "UPDATE clients SET lastconnect=$1 WHERE id=$2;",
+                &[&ts, &id],
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn insert_client(&self, new: &NewClient<'_>) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        tran.execute(
+            "INSERT INTO clients
+            (id, token, name, enabled, created, lastconnect, pubkey)
+            VALUES($1, $2, $3, $4, $5, $6, $7);",
+            &[
+                &new.id,
+                &new.token,
+                &new.name,
+                &true,
+                &new.created,
+                &new.created,
+                &new.pubkey,
+            ],
+        )
+        .await?;
+
+        tran.execute(
+            "INSERT INTO client_schedule
+                (id, lastrun, manualrun)
+                VALUES($1, $2, $3);",
+            &[&new.id, &new.created, &false],
+        )
+        .await?;
+
+        tran.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete_client(&self, id: &str) -> Result<bool> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        let result = tran
+            .execute("DELETE FROM clients WHERE id=$1;", &[&id])
+            .await?;
+        tran.execute("DELETE FROM client_tags WHERE client=$1;", &[&id])
+            .await?;
+        tran.execute("DELETE FROM search_runs WHERE client=$1;", &[&id])
+            .await?;
+        tran.execute("DELETE FROM client_settings WHERE client=$1;", &[&id])
+            .await?;
+        tran.execute("DELETE FROM integrity_baselines WHERE client=$1;", &[&id])
+            .await?;
+        tran.execute("DELETE FROM integrity_changes WHERE client=$1;", &[&id])
+            .await?;
+
+        tran.commit().await?;
+
+        Ok(result > 0)
+    }
+
+    async fn client_exists(&self, id: &str) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT id FROM clients WHERE id=$1;", &[&id])
+            .await?;
+
+        Ok(!rows.is_empty())
+    }
+
+    async fn client_name_exists(&self, name: &str) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT id FROM clients WHERE name=$1 LIMIT 1;", &[&name])
+            .await?;
+
+        Ok(!rows.is_empty())
+    }
+
+    async fn set_client_enabled(&self, id: &str, enabled: bool) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let _result = client
+            .execute(
+                "UPDATE clients SET enabled=$1 WHERE id=$2;",
+                &[&enabled, &id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_client_pubkey(&self, id: &str) -> Result<Option<Option<String>>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT pubkey FROM clients WHERE id=$1 LIMIT 1;", &[&id])
+            .await?;
+
+        Ok(rows.first().map(|row| row.get("pubkey")))
+    }
+
+    async fn set_client_pubkey(&self, id: &str, pubkey: &str) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let updated = client
+            .execute(
+                "UPDATE clients SET pubkey=$1 WHERE id=$2 AND pubkey IS NULL;",
+                &[&pubkey, &id],
+            )
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn get_clients(&self) -> Result<Vec<SLClient>> {
+        let client = self.pool.get().await?;
+
+        let rows = client.query("SELECT * FROM clients;", &[]).await?;
+        let tag_rows = client
+            .query("SELECT * FROM client_tags ORDER BY key, source;", &[])
+            .await?;
+
+        let mut tags: HashMap<String, Vec<ClientTag>> = HashMap::new();
+        for row in &tag_rows {
+            if let Some(tag) = client_tag_from_row(row) {
+                tags.entry(row.get("client")).or_default().push(tag);
+            }
+        }
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let id: String = row.get("id");
+                SLClient {
+                    tags: tags.remove(&id).unwrap_or_default(),
+                    id,
+                    enabled: row.get("enabled"),
+                    name: row.get("name"),
+                    created: row.get("created"),
+                    lastconnect: row.get("lastconnect"),
+                    inventory: ClientInventory {
+                        agent_version: row.get("agent_version"),
+                        git_sha: row.get("git_sha"),
+                        os: row.get("os"),
+                        hostname: row.get("hostname"),
+                        capabilities: capabilities_from_sql(row.get("capabilities")),
+                    },
+                }
+            })
+            .collect())
+    }
+
+    async fn get_client_last_run(&self, id: &str) -> Result<Option<ClientLastRun>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT * FROM client_schedule WHERE id=$1 LIMIT 1;", &[&id])
+            .await?;
+
+        Ok(rows.first().map(|row| ClientLastRun {
+            lastrun: row.get("lastrun"),
+            manualrun: row.get::<&str, Option<bool>>("manualrun").unwrap_or(false),
+        }))
+    }
+
+    async fn set_client_last_run(&self, id: &str, dt: DateTime<Utc>) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let _result = client
+            .execute(
+                "UPDATE client_schedule SET lastrun=$1, manualrun=$3 WHERE id=$2;",
+                &[&dt, &id, &false],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_client_tags(&self, id: &str) -> Result<Vec<ClientTag>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM client_tags WHERE client=$1 ORDER BY key, source;",
+                &[&id],
+            )
+            .await?;
+
+        Ok(rows.iter().filter_map(client_tag_from_row).collect())
+    }
+
+    async fn set_client_tags(
+        &self,
+        id: &str,
+        source: TagSource,
+        tags: &[(String, String)],
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        tran.execute(
+            "DELETE FROM client_tags WHERE client=$1 AND source=$2;",
+            &[&id, &source.sql_code()],
+        )
+        .await?;
+        for (key, value) in tags {
+            tran.execute(
+                "INSERT INTO client_tags (client, source, key, value) VALUES($1, $2, $3, $4);",
+                &[&id, &source.sql_code(), key, value],
+            )
+            .await?;
+        }
+
+        tran.commit().await?;
+
+        Ok(())
+    }
+
+    async fn set_client_last_seen(&self, id: &str, ts: DateTime<Utc>) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute("UPDATE clients SET last_seen=$1 WHERE id=$2;", &[&ts, &id])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_client_inventory(&self, id: &str, inventory: &ClientInventory) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "UPDATE clients SET agent_version=$1, git_sha=$2, os=$3, hostname=$4, capabilities=$5
+                WHERE id=$6;",
+                &[
+                    &inventory.agent_version,
+                    &inventory.git_sha,
+                    &inventory.os,
+                    &inventory.hostname,
+                    &capabilities_to_sql(&inventory.capabilities),
+                    &id,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_client_error(&self, id: &str, error: &str, ts: DateTime<Utc>) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "UPDATE clients SET last_error=$1, last_error_at=$2 WHERE id=$3;",
+                &[&error, &ts, &id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_client_missed_alert(&self, id: &str, ts: Option<DateTime<Utc>>) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        client
+            .execute(
+                "UPDATE clients SET missed_alert=$1 WHERE id=$2;",
+                &[&ts, &id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_client_settings(&self, client: &str) -> Result<Option<ClientSettings>> {
+        let conn = self.pool.get().await?;
+
+        let rows = conn
+            .query(
+                "SELECT settings FROM client_settings WHERE client=$1;",
+                &[&client],
+            )
+            .await?;
+
+        match rows.first() {
+            Some(row) => Ok(Some(serde_json::from_str(row.get("settings"))?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_client_settings(
+        &self,
+        client: &str,
+        settings: Option<&ClientSettings>,
+    ) -> Result<()> {
+        let conn = self.pool.get().await?;
+
+        match settings {
+            Some(settings) => {
+                conn.execute(
+                    "INSERT INTO client_settings (client, settings) VALUES($1, $2)
+                    ON CONFLICT (client) DO UPDATE SET settings=excluded.settings;",
+                    &[&client, &serde_json::to_string(settings)?],
+                )
+                .await?;
+            }
+            None => {
+                conn.execute("DELETE FROM client_settings WHERE client=$1;", &[&client])
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_client_statuses(&self) -> Result<Vec<ClientStatus>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT c.*, s.lastrun,
+                    (SELECT MAX(r.started) FROM search_results r WHERE r.client=c.id) AS last_result
+                FROM clients c LEFT JOIN client_schedule s ON s.id=c.id
+                ORDER BY c.name;",
+                &[],
+            )
+            .await?;
+
+        Ok(rows.iter().map(client_status_from_row).collect())
+    }
+
+    async fn get_search(&self, id: i32) -> Result<Option<models::Search>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT * FROM searches WHERE id=$1 LIMIT 1;", &[&id])
+            .await?;
+
+        Ok(rows.first().and_then(search_from_row))
+    }
+
+    async fn get_searches(&self) -> Result<Vec<models::Search>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT * FROM searches WHERE enabled='t';", &[])
+            .await?;
+
+        Ok(rows.iter().filter_map(search_from_row).collect())
+    }
+
+    async fn get_all_searches(&self) -> Result<Vec<models::Search>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT * FROM searches ORDER BY id;", &[])
+            .await?;
+
+        Ok(rows.iter().filter_map(search_from_row).collect())
+    }
+
+    async fn delete_search(&self, id: i32) -> Result<bool> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        let result = tran
+            .execute("DELETE FROM searches WHERE id=$1;", &[&id])
+            .await?;
+        tran.execute("DELETE FROM search_history WHERE search=$1;", &[&id])
+            .await?;
+
+        tran.commit().await?;
+
+        Ok(result > 0)
+    }
+
+    async fn insert_search(&self, search: &SearchEdit<'_>, enabled: bool) -> Result<i32> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        let row = tran
+            .query_one(
+                "INSERT INTO searches
+            (name, type, search, locations, enabled, selector, realtime)
+            VALUES($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id;",
+                &[
+                    &search.name,
+                    &search.stype.sql_code(),
+                    &search.search,
+                    &search.locations,
+                    &enabled,
+                    &search.selector,
+                    &search.realtime,
+                ],
+            )
+            .await?;
+        let id: i32 = row.get("id");
+
+        insert_search_version(&tran, id, search).await?;
+
+        tran.commit().await?;
+
+        Ok(id)
+    }
+
+    async fn update_search(&self, id: i32, search: &SearchEdit<'_>) -> Result<Option<i32>> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        // the row lock serializes versions of the same search
+        let result = tran
+            .execute(
+                "UPDATE searches SET name=$2, type=$3, search=$4, locations=$5, selector=$6,
+                realtime=$7
+                WHERE id=$1;",
+                &[
+                    &id,
+                    &search.name,
+                    &search.stype.sql_code(),
+                    &search.search,
+                    &search.locations,
+                    &search.selector,
+                    &search.realtime,
+                ],
+            )
+            .await?;
+        if result < 1 {
+            return Ok(None);
+        }
+
+        let version = insert_search_version(&tran, id, search).await?;
+
+        tran.commit().await?;
+
+        Ok(Some(version))
+    }
+
+    async fn set_search_enabled(&self, id: i32, enabled: bool) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute(
+                "UPDATE searches SET enabled=$2 WHERE id=$1;",
+                &[&id, &enabled],
+            )
+            .await?;
+
+        Ok(result > 0)
+    }
+
+    async fn get_search_history(&self, id: i32) -> Result<Vec<models::SearchVersion>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM search_history WHERE search=$1 ORDER BY version DESC;",
+                &[&id],
+            )
+            .await?;
+
+        Ok(rows.iter().filter_map(search_version_from_row).collect())
+    }
+
+    async fn get_search_version(
+        &self,
+        id: i32,
+        version: i32,
+    ) -> Result<Option<models::SearchVersion>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM search_history WHERE search=$1 AND version=$2;",
+                &[&id, &version],
+            )
+            .await?;
+
+        Ok(rows.first().and_then(search_version_from_row))
+    }
+
+    async fn insert_search_result(
+        &self,
+        clientid: &str,
+        result: &ClientSearchResult,
+        signature: Option<&str>,
+    ) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let _result = client
+            .execute(
+                "INSERT INTO search_results
+            (client, search, location, found, started, signature, truncated, found_tsv)
+            VALUES($1, $2, $3, $4, $5, $6, $7, to_tsvector('simple', array_to_string($4::TEXT[], ' ')));",
+                &[
+                    &clientid,
+                    &result.search_id,
+                    &result.location,
+                    &result.found,
+                    &result.started,
+                    &signature,
+                    &result.truncated,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_search_runs(&self, clientid: &str, reports: &[SearchRunReport]) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        tran.execute("DELETE FROM search_runs WHERE client=$1;", &[&clientid])
+            .await?;
+        for report in reports {
+            // a location listed twice in a search is reported twice, the last one wins
+            tran.execute(
+                "INSERT INTO search_runs
+                (client, search, location, started, duration_ms, lines_scanned, bytes_read, error_kind, error)
+                VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ON CONFLICT (client, search, location) DO UPDATE SET
+                started=excluded.started, duration_ms=excluded.duration_ms,
+                lines_scanned=excluded.lines_scanned, bytes_read=excluded.bytes_read,
+                error_kind=excluded.error_kind, error=excluded.error;",
+                &[
+                    &clientid,
+                    &report.search_id,
+                    &report.location,
+                    &report.started,
+                    &(report.duration_ms as i64),
+                    &(report.lines_scanned as i64),
+                    &(report.bytes_read as i64),
+                    &report.error.as_ref().map(|error| error.kind.sql_code()),
+                    &report.error.as_ref().map(|error| error.message.as_str()),
+                ],
+            )
+            .await?;
+        }
+
+        tran.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_search_runs(&self, search: Option<i32>) -> Result<Vec<SearchRun>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT r.*, c.name AS client_name FROM search_runs r
+                    JOIN clients c ON c.id=r.client
+                    WHERE $1::INT IS NULL OR r.search=$1
+                    ORDER BY r.search, c.name, r.location;",
+                &[&search],
+            )
+            .await?;
+
+        Ok(rows.iter().map(search_run_from_row).collect())
+    }
+
+    async fn get_search_results(&self, filter: &SearchResultFilter) -> Result<SearchResultPage> {
+        let client = self.pool.get().await?;
+
+        let limit = filter
+            .limit
+            .unwrap_or(DEFAULT_RESULTS_PAGE)
+            .clamp(1, MAX_RESULTS_PAGE);
+        let text = filter.like_text();
+        let cursor_started = filter.cursor.map(|cursor| cursor.started);
+        let cursor_id = filter.cursor.map(|cursor| cursor.id);
+
+        // fetch one extra row to know if there is a next page
+        let rows = client
+            .query(
+                "SELECT r.*, COALESCE(s.name, '') AS search_name, COALESCE(c.name, '') AS client_name
+                FROM search_results r
+                LEFT JOIN searches s ON s.id=r.search
+                LEFT JOIN clients c ON c.id=r.client
+                WHERE ($1::TEXT IS NULL OR r.client=$1)
+                AND ($2::INT IS NULL OR r.search=$2)
+                AND ($3::TIMESTAMPTZ IS NULL OR r.started<$3)
+                AND ($4::TIMESTAMPTZ IS NULL OR r.started>$4)
+                AND ($5::TEXT IS NULL OR EXISTS (
+                    SELECT 1 FROM unnest(r.found) AS line WHERE line ILIKE '%' || $5 || '%'
+                ))
+                AND ($6::TIMESTAMPTZ IS NULL OR (r.started, r.id) < ($6, $7::INT))
+                ORDER BY r.started DESC, r.id DESC
+                LIMIT $8;",
+                &[
+                    &filter.client,
+                    &filter.search,
+                    &filter.before,
+                    &filter.after,
+                    &text,
+                    &cursor_started,
+                    &cursor_id,
+                    &(limit + 1),
+                ],
+            )
+            .await?;
+
+        let results: Vec<(SearchResult, ResultsCursor)> = rows
+            .iter()
+            .map(|row| {
+                let result = SearchResult {
+                    client_id: row.get("client"),
+                    client_name: row.get("client_name"),
+                    search_id: row.get("search"),
+                    search_name: row.get("search_name"),
+                    location: row.get("location"),
+                    found: row.get("found"),
+                    started: row.get("started"),
+                    signature: row.get("signature"),
+                    truncated: row.get("truncated"),
+                };
+                let cursor = ResultsCursor {
+                    started: result.started,
+                    id: row.get("id"),
+                };
+                (result, cursor)
+            })
+            .collect();
+
+        Ok(SearchResultPage::from_rows(results, limit))
+    }
+
+    async fn get_integrity_baseline(&self, clientid: &str) -> Result<Option<IntegrityBaseline>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM integrity_baselines WHERE client=$1;",
+                &[&clientid],
+            )
+            .await?;
+
+        match rows.first() {
+            Some(row) => Ok(Some(IntegrityBaseline {
+                paths: serde_json::from_str(row.get("paths"))?,
+                files: serde_json::from_str(row.get("files"))?,
+                updated: row.get("updated"),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_integrity_baseline(
+        &self,
+        clientid: &str,
+        baseline: &IntegrityBaseline,
+        changes: &[FileChange],
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        tran.execute(
+            "INSERT INTO integrity_baselines (client, paths, files, updated)
+            VALUES($1, $2, $3, $4)
+            ON CONFLICT (client) DO UPDATE SET
+            paths=excluded.paths, files=excluded.files, updated=excluded.updated;",
+            &[
+                &clientid,
+                &serde_json::to_string(&baseline.paths)?,
+                &serde_json::to_string(&baseline.files)?,
+                &baseline.updated,
+            ],
+        )
+        .await?;
+        for change in changes {
+            tran.execute(
+                "INSERT INTO integrity_changes
+                (client, path, kind, changed, old_state, new_state, detected)
+                VALUES($1, $2, $3, $4, $5, $6, $7);",
+                &[
+                    &clientid,
+                    &change.path,
+                    &change.kind.sql_code(),
+                    &serde_json::to_string(&change.changed)?,
+                    &change
+                        .before
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?,
+                    &change
+                        .after
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?,
+                    &baseline.updated,
+                ],
+            )
+            .await?;
+        }
+
+        tran.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_integrity_changes(
+        &self,
+        clientid: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<IntegrityChange>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT i.*, COALESCE(c.name, '') AS client_name FROM integrity_changes i
+                LEFT JOIN clients c ON c.id=i.client
+                WHERE $1::TEXT IS NULL OR i.client=$1
+                ORDER BY i.detected DESC, i.id DESC
+                LIMIT $2;",
+                &[&clientid, &limit],
+            )
+            .await?;
+
+        let mut changes = Vec::new();
+        for row in &rows {
+            if let Some(change) = integrity_change_from_row(row)? {
+                changes.push(change);
+            }
+        }
+
+        Ok(changes)
+    }
+
+    async fn search_matches(&self, query: &MatchQuery) -> Result<MatchPage> {
+        let client = self.pool.get().await?;
+
+        let headline_options = format!(
+            "StartSel={}, StopSel={}, HighlightAll=true",
+            fulltext::HIGHLIGHT_START,
+            fulltext::HIGHLIGHT_STOP
+        );
+
+        let rows = client
+            .query(
+                &format!(
+                    "{}
+                    SELECT l.id, l.client, l.search, l.location, l.started,
+                        ts_headline('simple', l.line, q.query, $8) AS headline,
+                        COALESCE(s.name, '') AS search_name, COALESCE(c.name, '') AS client_name
+                    FROM lines l CROSS JOIN q
+                    LEFT JOIN searches s ON s.id=l.search
+                    LEFT JOIN clients c ON c.id=l.client
+                    ORDER BY l.started DESC, l.id DESC, l.ord
+                    LIMIT $6 OFFSET $7;",
+                    fulltext::MATCHED_LINES
+                ),
+                &[
+                    &query.query,
+                    &query.client,
+                    &query.search,
+                    &query.before,
+                    &query.after,
+                    &query.limit,
+                    &query.offset,
+                    &headline_options,
+                ],
+            )
+            .await?;
+
+        let matches: Vec<LineMatch> = rows
+            .iter()
+            .map(|row| {
+                let (line, highlights) = fulltext::parse_headline(row.get("headline"));
+                LineMatch {
+                    result_id: row.get("id"),
+                    client_id: row.get("client"),
+                    client_name: row.get("client_name"),
+                    search_id: row.get("search"),
+                    search_name: row.get("search_name"),
+                    location: row.get("location"),
+                    started: row.get("started"),
+                    line,
+                    highlights,
+                }
+            })
+            .collect();
+
+        let facet_params: [&(dyn tokio_postgres::types::ToSql + Sync); 5] = [
+            &query.query,
+            &query.client,
+            &query.search,
+            &query.before,
+            &query.after,
+        ];
+
+        let client_rows = client
+            .query(
+                &format!(
+                    "{}
+                    SELECT l.client AS id, COALESCE(c.name, '') AS name, COUNT(*) AS count
+                    FROM lines l LEFT JOIN clients c ON c.id=l.client
+                    GROUP BY l.client, c.name ORDER BY count DESC;",
+                    fulltext::MATCHED_LINES
+                ),
+                &facet_params,
+            )
+            .await?;
+        let search_rows = client
+            .query(
+                &format!(
+                    "{}
+                    SELECT l.search::TEXT AS id, COALESCE(s.name, '') AS name, COUNT(*) AS count
+                    FROM lines l LEFT JOIN searches s ON s.id=l.search
+                    GROUP BY l.search, s.name ORDER BY count DESC;",
+                    fulltext::MATCHED_LINES
+                ),
+                &facet_params,
+            )
+            .await?;
+
+        let facet = |row: &tokio_postgres::Row| Facet {
+            id: row.get("id"),
+            name: row.get("name"),
+            count: row.get("count"),
+        };
+        let clients: Vec<Facet> = client_rows.iter().map(facet).collect();
+        let searches: Vec<Facet> = search_rows.iter().map(facet).collect();
+
+        Ok(MatchPage {
+            matches,
+            total: clients.iter().map(|facet| facet.count).sum(),
+            clients,
+            searches,
+        })
+    }
+
+    async fn insert_enrollment_token(&self, token: &NewEnrollmentToken<'_>) -> Result<i32> {
+        let client = self.pool.get().await?;
+
+        let row = client
+            .query_one(
+                "INSERT INTO enrollment_tokens
+                (name, token, tags, max_uses, expires, created, created_by)
+                VALUES($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id;",
+                &[
+                    &token.name,
+                    &token.token,
+                    &serde_json::to_string(token.tags)?,
+                    &token.max_uses,
+                    &token.expires,
+                    &token.created,
+                    &token.created_by,
+                ],
+            )
+            .await?;
+
+        Ok(row.get("id"))
+    }
+
+    async fn get_enrollment_tokens(&self) -> Result<Vec<EnrollmentToken>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query("SELECT * FROM enrollment_tokens ORDER BY id;", &[])
+            .await?;
+
+        rows.iter().map(enrollment_token_from_row).collect()
+    }
+
+    async fn delete_enrollment_token(&self, id: i32) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute("DELETE FROM enrollment_tokens WHERE id=$1;", &[&id])
+            .await?;
+
+        Ok(result > 0)
+    }
+
+    async fn enroll_client(
+        &self,
+        token: &str,
+        new: &NewClient<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<EnrollmentToken>> {
+        let mut client = self.pool.get().await?;
+        let tran = client.transaction().await?;
+
+        // locks the token, two clients can't take the last use
+        let row = tran
+            .query_opt(
+                "SELECT * FROM enrollment_tokens WHERE token=$1 FOR UPDATE;",
+                &[&token],
+            )
+            .await?;
+        let Some(mut used) = row
+            .as_ref()
+            .map(enrollment_token_from_row)
+            .transpose()?
+            .filter(|used| used.usable(now))
+        else {
+            return Ok(None);
+        };
+        let taken = tran
+            .query_opt(
+                "SELECT id FROM clients WHERE name=$1 LIMIT 1;",
+                &[&new.name],
+            )
+            .await?;
+        if taken.is_some() {
+            return Err(SqlError::ClientNameExists(new.name.to_string()));
+        }
+
+        tran.execute(
+            "UPDATE enrollment_tokens SET uses=uses + 1 WHERE id=$1;",
+            &[&used.id],
+        )
+        .await?;
+        tran.execute(
+            "INSERT INTO clients
+            (id, token, name, enabled, created, lastconnect, pubkey)
+            VALUES($1, $2, $3, $4, $5, $6, $7);",
+            &[
+                &new.id,
+                &new.token,
+                &new.name,
+                &true,
+                &new.created,
+                &new.created,
+                &new.pubkey,
+            ],
+        )
+        .await?;
+        tran.execute(
+            "INSERT INTO client_schedule
+                (id, lastrun, manualrun)
+                VALUES($1, $2, $3);",
+            &[&new.id, &new.created, &false],
+        )
+        .await?;
+        for (key, value) in &used.tags {
+            tran.execute(
+                "INSERT INTO client_tags (client, source, key, value) VALUES($1, $2, $3, $4);",
+                &[&new.id, &TagSource::Admin.sql_code(), key, value],
+            )
+            .await?;
+        }
+
+        tran.commit().await?;
+        used.uses += 1;
+
+        Ok(Some(used))
+    }
+
+    async fn get_scan_schedule(&self) -> Result<Option<ScanSchedule>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM scan_schedule WHERE searchid=$1 LIMIT 1;",
+                &[&0i32],
+            )
+            .await?;
+
+        Ok(rows.first().map(|row| {
+            let schedule: i32 = row.get("schedule");
+            ScanSchedule {
+                dur: Duration::from_secs((schedule as u64) * 60),
+                manual: row.get("manual"),
+            }
+        }))
+    }
+
+    async fn set_scan_schedule(&self, minutes: i32, manual: bool) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let _result = client
+            .execute(
+                "UPDATE scan_schedule SET schedule=$1, manual=$3 WHERE searchid=$2;",
+                &[&minutes, &0i32, &manual],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_webhook(&self, name: &str, url: &str, username: &str) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let _result = client
+            .execute(
+                "INSERT INTO webhooks (name, url, username) VALUES($1, $2, $3);",
+                &[&name, &url, &username],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let client = self.pool.get().await?;
+
+        let rows = client.query("SELECT * FROM webhooks;", &[]).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| Webhook::new(row.get("name"), row.get("url"), row.get("username")))
+            .collect())
+    }
+
+    async fn delete_webhook(&self, name: &str) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute("DELETE FROM webhooks WHERE name=$1;", &[&name])
+            .await?;
+
+        Ok(result > 0)
+    }
+
+    async fn insert_segment(&self, segment: &ArchiveSegment) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let result = client
+            .execute(
+                "INSERT INTO archive_segments
+                (id, client, location, start_offset, size, stored_size, received)
+                VALUES($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT DO NOTHING;",
+                &[
+                    &segment.id,
+                    &segment.client,
+                    &segment.location,
+                    &segment.start_offset,
+                    &segment.size,
+                    &segment.stored_size,
+                    &segment.received,
+                ],
+            )
+            .await?;
+
+        Ok(result > 0)
+    }
+
+    async fn get_segment(&self, clientid: &str, id: &str) -> Result<Option<ArchiveSegment>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT * FROM archive_segments WHERE client=$1 AND id=$2 LIMIT 1;",
+                &[&clientid, &id],
+            )
+            .await?;
+
+        Ok(rows.first().map(segment_from_row))
+    }
+
+    async fn get_segments(&self, filter: &SegmentFilter) -> Result<Vec<ArchiveSegment>> {
+        let client = self.pool.get().await?;
+
+        let cursor = filter.cursor.as_ref();
+        let rows = client
+            .query(
+                "SELECT * FROM archive_segments
+                WHERE ($1::TEXT IS NULL OR client=$1)
+                AND ($2::TEXT IS NULL OR location=$2)
+                AND ($3::TIMESTAMPTZ IS NULL OR received>$3)
+                AND ($4::TIMESTAMPTZ IS NULL OR received<$4)
+                AND ($5::TIMESTAMPTZ IS NULL
+                    OR (received, start_offset, client, location, id)
+                    > ($5, $6::BIGINT, $7::TEXT, $8::TEXT, $9::TEXT))
+                ORDER BY received, start_offset, client, location, id
+                LIMIT $10::BIGINT;",
+                &[
+                    &filter.client,
+                    &filter.location,
+                    &filter.after,
+                    &filter.before,
+                    &cursor.map(|cursor| cursor.received),
+                    &cursor.map(|cursor| cursor.start_offset),
+                    &cursor.map(|cursor| cursor.client.as_str()),
+                    &cursor.map(|cursor| cursor.location.as_str()),
+                    &cursor.map(|cursor| cursor.id.as_str()),
+                    &filter.limit,
+                ],
+            )
+            .await?;
+
+        Ok(rows.iter().map(segment_from_row).collect())
+    }
+
+    async fn delete_segments_before(&self, before: DateTime<Utc>) -> Result<Vec<ArchiveSegment>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "DELETE FROM archive_segments WHERE received<$1 RETURNING *;",
+                &[&before],
+            )
+            .await?;
+
+        Ok(rows.iter().map(segment_from_row).collect())
+    }
+
+    async fn get_search_retentions(&self) -> Result<Vec<SearchRetention>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT id, retention_days, retention_rows FROM searches;",
+                &[],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| SearchRetention {
+                search_id: row.get("id"),
+                days: row.get("retention_days"),
+                rows: row.get("retention_rows"),
+            })
+            .collect())
+    }
+
+    async fn set_search_retention(
+        &self,
+        id: i32,
+        days: Option<i32>,
+        rows: Option<i32>,
+    ) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let _result = client
+            .execute(
+                "UPDATE searches SET retention_days=$1, retention_rows=$2 WHERE id=$3;",
+                &[&days, &rows, &id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_expired_results(
+        &self,
+        target: &PruneTarget,
+        batch: i64,
+    ) -> Result<Vec<ExpiredResult>> {
+        let client = self.pool.get().await?;
+
+        // rows past keep_rows, newest first, and rows older than older_than
+        let rows = client
+            .query(
+                "SELECT * FROM search_results
+                WHERE (($1::INT IS NULL AND search NOT IN (SELECT id FROM searches))
+                    OR search=$1)
+                AND (
+                    ($2::TIMESTAMPTZ IS NOT NULL AND started<$2)
+                    OR ($3::BIGINT IS NOT NULL AND id IN (
+                        SELECT id FROM search_results
+                        WHERE (($1::INT IS NULL AND search NOT IN (SELECT id FROM searches))
+                            OR search=$1)
+                        ORDER BY started DESC, id DESC
+                        OFFSET $3
+                    ))
+                )
+                ORDER BY id
+                LIMIT $4;",
+                &[
+                    &target.search,
+                    &target.older_than,
+                    &target.keep_rows,
+                    &batch,
+                ],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ExpiredResult {
+                id: row.get("id"),
+                client: row.get("client"),
+                search: row.get("search"),
+                location: row.get("location"),
+                found: row.get("found"),
+                started: row.get("started"),
+                signature: row.get("signature"),
+                truncated: row.get("truncated"),
+            })
+            .collect())
+    }
+
+    async fn delete_search_results(&self, ids: &[i32]) -> Result<usize> {
+        let client = self.pool.get().await?;
+
+        let deleted = client
+            .execute("DELETE FROM search_results WHERE id=ANY($1);", &[&ids])
+            .await?;
+
+        Ok(deleted as usize)
+    }
+
+    async fn get_client_storage_usage(&self) -> Result<Vec<StorageUsage>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT r.client AS id, c.name, COUNT(*) AS rows,
+                    SUM(pg_column_size(r.*))::BIGINT AS bytes
+                FROM search_results r LEFT JOIN clients c ON c.id=r.client
+                GROUP BY r.client, c.name ORDER BY bytes DESC;",
+                &[],
+            )
+            .await?;
+
+        Ok(rows.iter().map(storage_usage_from_row).collect())
+    }
+
+    async fn get_search_storage_usage(&self) -> Result<Vec<StorageUsage>> {
+        let client = self.pool.get().await?;
+
+        let rows = client
+            .query(
+                "SELECT r.search::TEXT AS id, s.name, COUNT(*) AS rows,
+                    SUM(pg_column_size(r.*))::BIGINT AS bytes
+                FROM search_results r LEFT JOIN searches s ON s.id=r.search
+                GROUP BY r.search, s.name ORDER BY bytes DESC;",
+                &[],
+            )
+            .await?;
+
+        Ok(rows.iter().map(storage_usage_from_row).collect())
+    }
+}
@@ -0,0 +1,174 @@
+use super::{storage, Result, SqlError};
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchRetention {
+    pub search_id: i32,
+    pub days: Option<i32>,
+    pub rows: Option<i32>,
+}
+
+pub async fn get_search_retentions() -> Result<Vec<SearchRetention>> {
+    storage().get_search_retentions().await
+}
+
+/**
+ * Set the retention of a search, None uses the global setting.
+ */
+pub async fn set_search_retention(id: i32, days: Option<i32>, rows: Option<i32>) -> Result<()> {
+    storage().set_search_retention(id, days, rows).await
+}
+
+// a search_results row removed by retention, as written to the export file
+#[derive(Debug, Serialize)]
+pub struct ExpiredResult {
+    pub id: i32,
+    pub client: String,
+    pub search: i32,
+    pub location: Option<String>,
+    pub found: Vec<String>,
+    pub started: DateTime<Utc>,
+    pub signature: Option<String>,
+    pub truncated: bool,
+}
+
+// which rows a prune applies to and what to keep
+#[derive(Debug)]
+pub struct PruneTarget {
+    // None targets results of searches that no longer exist
+    pub search: Option<i32>,
+    pub older_than: Option<DateTime<Utc>>,
+    pub keep_rows: Option<i64>,
+}
+
+/**
+ * Delete at most `batch` expired rows for a target. `export` gets the rows
+ * before they are deleted, on a blocking thread outside of any transaction,
+ * if it fails nothing is deleted. Returns the number of rows deleted, 0 once
+ * nothing is left to prune.
+ */
+pub async fn prune_search_results<F>(target: &PruneTarget, batch: i64, export: F) -> Result<usize>
+where
+    F: FnOnce(&[ExpiredResult]) -> std::io::Result<()> + Send + 'static,
+{
+    let expired = storage().get_expired_results(target, batch).await?;
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<i32> = expired.iter().map(|result| result.id).collect();
+    actix_web::rt::task::spawn_blocking(move || export(&expired))
+        .await
+        .map_err(|e| SqlError::Export(std::io::Error::other(e)))?
+        .map_err(SqlError::Export)?;
+
+    storage().delete_search_results(&ids).await
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageUsage {
+    pub id: String,
+    pub name: Option<String>,
+    pub rows: i64,
+    pub bytes: i64,
+}
+
+/**
+ * Rows and bytes of search_results used by each client.
+ */
+pub async fn get_client_storage_usage() -> Result<Vec<StorageUsage>> {
+    storage().get_client_storage_usage().await
+}
+
+/**
+ * Rows and bytes of search_results used by each search.
+ */
+pub async fn get_search_storage_usage() -> Result<Vec<StorageUsage>> {
+    storage().get_search_storage_usage().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::storage::SearchEdit;
+    use super::super::testdb::{add_client, result};
+    use super::super::{connect_test, random_string};
+    use super::*;
+    use crate::models::SearchType;
+    use chrono::Duration;
+    use std::sync::{Arc, Mutex};
+
+    #[actix_web::test]
+    async fn rows_are_exported_before_they_are_deleted() {
+        connect_test().await;
+        let client = random_string(12);
+        add_client(storage(), &client, &client).await;
+        let search = storage()
+            .insert_search(
+                &SearchEdit {
+                    name: &client,
+                    stype: &SearchType::Contains,
+                    search: "error",
+                    locations: &[],
+                    selector: None,
+                    realtime: false,
+                    changed_by: "admin",
+                    changed: Utc::now(),
+                },
+                true,
+            )
+            .await
+            .unwrap();
+        for days in [10, 11, 12] {
+            storage()
+                .insert_search_result(
+                    &client,
+                    &result(search, &["error"], Utc::now() - Duration::days(days)),
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+
+        let target = PruneTarget {
+            search: Some(search),
+            older_than: Some(Utc::now() - Duration::days(7)),
+            keep_rows: None,
+        };
+
+        // nothing is deleted when the export fails
+        let failed =
+            prune_search_results(&target, 100, |_| Err(std::io::Error::other("disk full"))).await;
+        assert!(matches!(failed, Err(SqlError::Export(_))));
+        assert_eq!(
+            storage()
+                .get_expired_results(&target, 100)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+
+        let exported = Arc::new(Mutex::new(Vec::new()));
+        let rows = exported.clone();
+        let deleted = prune_search_results(&target, 2, move |expired| {
+            rows.lock()
+                .unwrap()
+                .extend(expired.iter().map(|result| result.id));
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(exported.lock().unwrap().len(), 2);
+
+        assert_eq!(
+            prune_search_results(&target, 2, |_| Ok(())).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            prune_search_results(&target, 2, |_| Ok(())).await.unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use super::{storage, Result};
+use crate::models::{RunError, RunErrorKind, SearchRunReport};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashSet};
+use utoipa::ToSchema;
+
+/**
+ * The last run of a search over one location on one client.
+ */
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchRun {
+    pub client_id: String,
+    pub client_name: String,
+    pub search_id: i32,
+    pub location: String,
+    pub started: DateTime<Utc>,
+    pub duration_ms: i64,
+    pub lines_scanned: i64,
+    pub bytes_read: i64,
+    // None if the search ran
+    pub error: Option<RunError>,
+}
+
+/**
+ * How a search is doing across the clients that run it, from their last
+ * runs.
+ */
+#[derive(Debug, Serialize, ToSchema, PartialEq)]
+pub struct SearchHealth {
+    pub search_id: i32,
+    // clients that reported running the search
+    pub clients: usize,
+    // clients where it failed on at least one location
+    pub failing_clients: usize,
+    // locations it failed on, over all clients
+    pub errors: usize,
+    // locations that do not exist, counted in errors too
+    pub missing_files: usize,
+    pub lines_scanned: i64,
+    pub bytes_read: i64,
+    pub last_run: Option<DateTime<Utc>>,
+}
+
+/**
+ * Store the reports of a client run, replacing the reports of its previous
+ * run.
+ */
+pub async fn set_search_runs(clientid: &str, reports: &[SearchRunReport]) -> Result<()> {
+    storage().set_search_runs(clientid, reports).await
+}
+
+/**
+ * Last runs on every client, of one search or all of them.
+ */
+pub async fn get_search_runs(search: Option<i32>) -> Result<Vec<SearchRun>> {
+    storage().get_search_runs(search).await
+}
+
+pub async fn get_search_health() -> Result<Vec<SearchHealth>> {
+    Ok(summarize(&get_search_runs(None).await?))
+}
+
+pub fn summarize(runs: &[SearchRun]) -> Vec<SearchHealth> {
+    let mut health: BTreeMap<i32, (SearchHealth, HashSet<&str>, HashSet<&str>)> = BTreeMap::new();
+
+    for run in runs {
+        let (search, clients, failing) = health.entry(run.search_id).or_insert_with(|| {
+            (
+                SearchHealth {
+                    search_id: run.search_id,
+                    clients: 0,
+                    failing_clients: 0,
+                    errors: 0,
+                    missing_files: 0,
+                    lines_scanned: 0,
+                    bytes_read: 0,
+                    last_run: None,
+                },
+                HashSet::new(),
+                HashSet::new(),
+            )
+        });
+
+        clients.insert(&run.client_id);
+        if let Some(error) = &run.error {
+            failing.insert(&run.client_id);
+            search.errors += 1;
+            if error.kind == RunErrorKind::Missing {
+                search.missing_files += 1;
+            }
+        }
+        search.lines_scanned += run.lines_scanned;
+        search.bytes_read += run.bytes_read;
+        search.last_run = search.last_run.max(Some(run.started));
+    }
+
+    health
+        .into_values()
+        .map(|(mut search, clients, failing)| {
+            search.clients = clients.len();
+            search.failing_clients = failing.len();
+            search
+        })
+        .collect()
+}
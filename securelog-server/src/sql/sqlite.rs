@@ -0,0 +1,1750 @@
+/*
+SQLite storage backend, for small deployments without a Postgres server.
+
+rusqlite is blocking, every query runs on the connection's own thread through
+deadpool's interact(). Arrays are stored as json text, see migrations.rs.
+*/
+use super::archive::{ArchiveSegment, SegmentFilter};
+use super::client::{
+    capabilities_from_sql, capabilities_to_sql, ClientInventory, ClientLastRun, ClientStatus,
+    SLClient,
+};
+use super::enrollment::EnrollmentToken;
+use super::fulltext::{self, Facet, LineMatch, MatchPage, MatchQuery};
+use super::integrity::{IntegrityBaseline, IntegrityChange};
+use super::retention::{ExpiredResult, PruneTarget, SearchRetention, StorageUsage};
+use super::runs::SearchRun;
+use super::storage::{ClientTokenRow, NewClient, NewEnrollmentToken, SearchEdit, Storage, UserRow};
+use super::webhooks::Webhook;
+use super::{
+    Backend, Result, ResultsCursor, ScanSchedule, SearchResultFilter, SearchResultPage, SqlError,
+    DEFAULT_RESULTS_PAGE, MAX_RESULTS_PAGE,
+};
+use crate::conf;
+use crate::models::{
+    self, ClientSearchResult, ClientSettings, ClientTag, FileChange, FileChangeKind, RunError,
+    RunErrorKind, SearchResult, SearchRunReport, SearchType, SqlCode, TagSource,
+};
+use chrono::{DateTime, Utc};
+use deadpool_sqlite::{Config, Pool, Runtime};
+use rusqlite::{params, Connection, OptionalExtension, Row, TransactionBehavior};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// database file used when sqlite_path is not set
+const DEFAULT_PATH: &str = "securelog.db";
+// sqlite allows a single writer, more connections only help readers
+const POOL_SIZE: usize = 8;
+// how long a query waits for another connection's write to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Sqlite {
+    pub(super) pool: Pool,
+}
+impl Sqlite {
+    /**
+     * Open the database file from the config, creating it if needed.
+     */
+    pub async fn connect() -> Result<Sqlite> {
+        let path = conf::get_sqlite_path().unwrap_or_else(|_| DEFAULT_PATH.to_string());
+
+        Sqlite::open(&path).await
+    }
+
+    pub async fn open(path: &str) -> Result<Sqlite> {
+        let pool = Config::new(path)
+            .builder(Runtime::Tokio1)
+            .map_err(|e| SqlError::Config(format!("sqlite_path {}: {}", path, e)))?
+            .max_size(POOL_SIZE)
+            .build()?;
+        let sqlite = Sqlite { pool };
+
+        let version = sqlite
+            .interact(|conn| {
+                // readers don't block the writer, and it persists in the file
+                conn.pragma_update(None, "journal_mode", "WAL")?;
+                Ok(conn.query_row("SELECT sqlite_version();", [], |row| {
+                    row.get::<usize, String>(0)
+                })?)
+            })
+            .await?;
+        info!("Opened SQLite {} database {}", version, path);
+
+        Ok(sqlite)
+    }
+
+    /**
+     * Run `f` with a connection from the pool, on the connection's thread.
+     */
+    pub(super) async fn interact<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.pool.get().await?;
+
+        conn.interact(move |conn| {
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            f(conn)
+        })
+        .await
+        .map_err(|e| SqlError::SqliteInteract(e.to_string()))?
+    }
+}
+
+// json array column, see migrations.rs
+fn json_column(row: &Row, column: &str) -> rusqlite::Result<Vec<String>> {
+    let text: Option<String> = row.get(column)?;
+
+    match text {
+        Some(text) => serde_json::from_str(&text).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+// json column that may be NULL
+fn json_option<T: serde::de::DeserializeOwned>(
+    row: &Row,
+    column: &str,
+) -> rusqlite::Result<Option<T>> {
+    let text: Option<String> = row.get(column)?;
+
+    text.map(|text| {
+        serde_json::from_str(&text).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+        })
+    })
+    .transpose()
+}
+
+// timestamps are compared as text, keep the precision Postgres has so
+// result cursors match exactly
+fn truncate_micros(ts: DateTime<Utc>) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(ts.timestamp_micros()).unwrap_or(ts)
+}
+
+fn segment_from_row(row: &Row) -> rusqlite::Result<ArchiveSegment> {
+    Ok(ArchiveSegment {
+        id: row.get("id")?,
+        client: row.get("client")?,
+        location: row.get("location")?,
+        start_offset: row.get("start_offset")?,
+        size: row.get("size")?,
+        stored_size: row.get("stored_size")?,
+        received: row.get("received")?,
+    })
+}
+
+fn storage_usage_from_row(row: &Row) -> rusqlite::Result<StorageUsage> {
+    Ok(StorageUsage {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        rows: row.get("rows")?,
+        bytes: row.get("bytes")?,
+    })
+}
+
+fn search_from_row(row: &Row) -> rusqlite::Result<Option<models::Search>> {
+    let stype = match SearchType::from_sql_code(row.get("type")?) {
+        Some(stype) => stype,
+        None => return Ok(None),
+    };
+
+    Ok(Some(models::Search {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        stype,
+        search: row.get("search")?,
+        locations: json_column(row, "locations")?,
+        enabled: row.get("enabled")?,
+        selector: row.get("selector")?,
+        realtime: row.get("realtime")?,
+    }))
+}
+
+fn search_version_from_row(row: &Row) -> rusqlite::Result<Option<models::SearchVersion>> {
+    let stype = match SearchType::from_sql_code(row.get("type")?) {
+        Some(stype) => stype,
+        None => return Ok(None),
+    };
+
+    Ok(Some(models::SearchVersion {
+        search_id: row.get("search")?,
+        version: row.get("version")?,
+        name: row.get("name")?,
+        stype,
+        search: row.get("search_text")?,
+        locations: json_column(row, "locations")?,
+        changed: row.get("changed")?,
+        changed_by: row.get("changed_by")?,
+        selector: row.get("selector")?,
+        realtime: row.get("realtime")?,
+    }))
+}
+
+fn integrity_change_from_row(row: &Row) -> rusqlite::Result<Option<IntegrityChange>> {
+    let Some(kind) = FileChangeKind::from_sql_code(row.get("kind")?) else {
+        return Ok(None);
+    };
+
+    Ok(Some(IntegrityChange {
+        id: row.get("id")?,
+        client_id: row.get("client")?,
+        client_name: row.get("client_name")?,
+        path: row.get("path")?,
+        kind,
+        changed: json_column(row, "changed")?,
+        before: json_option(row, "old_state")?,
+        after: json_option(row, "new_state")?,
+        detected: row.get("detected")?,
+    }))
+}
+
+fn enrollment_token_from_row(row: &Row) -> rusqlite::Result<EnrollmentToken> {
+    Ok(EnrollmentToken {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        tags: json_option(row, "tags")?.unwrap_or_default(),
+        max_uses: row.get("max_uses")?,
+        uses: row.get("uses")?,
+        expires: row.get("expires")?,
+        created: row.get("created")?,
+        created_by: row.get("created_by")?,
+    })
+}
+
+fn search_run_from_row(row: &Row) -> rusqlite::Result<SearchRun> {
+    let error = match row
+        .get::<&str, Option<i32>>("error_kind")?
+        .and_then(RunErrorKind::from_sql_code)
+    {
+        Some(kind) => Some(RunError {
+            kind,
+            message: row
+                .get::<&str, Option<String>>("error")?
+                .unwrap_or_default(),
+        }),
+        None => None,
+    };
+
+    Ok(SearchRun {
+        client_id: row.get("client")?,
+        client_name: row.get("client_name")?,
+        search_id: row.get("search")?,
+        location: row.get("location")?,
+        started: row.get("started")?,
+        duration_ms: row.get("duration_ms")?,
+        lines_scanned: row.get("lines_scanned")?,
+        bytes_read: row.get("bytes_read")?,
+        error,
+    })
+}
+
+fn client_status_from_row(row: &Row) -> rusqlite::Result<ClientStatus> {
+    Ok(ClientStatus {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        enabled: row.get("enabled")?,
+        created: row.get("created")?,
+        agent_version: row.get("agent_version")?,
+        last_seen: row.get("last_seen")?,
+        last_run: row.get("lastrun")?,
+        last_result: row.get("last_result")?,
+        last_error: row.get("last_error")?,
+        last_error_at: row.get("last_error_at")?,
+        missed_alert: row.get("missed_alert")?,
+        overdue: false,
+    })
+}
+
+fn client_tag_from_row(row: &Row) -> rusqlite::Result<Option<ClientTag>> {
+    let source = match TagSource::from_sql_code(row.get("source")?) {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+
+    Ok(Some(ClientTag {
+        key: row.get("key")?,
+        value: row.get("value")?,
+        source,
+    }))
+}
+
+// SearchEdit that can be moved to the connection's thread
+struct OwnedSearchEdit {
+    name: String,
+    stype: i32,
+    search: String,
+    // json array
+    locations: String,
+    selector: Option<String>,
+    realtime: bool,
+    changed_by: String,
+    changed: DateTime<Utc>,
+}
+impl OwnedSearchEdit {
+    fn new(search: &SearchEdit<'_>) -> Result<OwnedSearchEdit> {
+        Ok(OwnedSearchEdit {
+            name: search.name.to_string(),
+            stype: search.stype.sql_code(),
+            search: search.search.to_string(),
+            locations: serde_json::to_string(search.locations)?,
+            selector: search.selector.map(str::to_string),
+            realtime: search.realtime,
+            changed_by: search.changed_by.to_string(),
+            changed: truncate_micros(search.changed),
+        })
+    }
+}
+
+// add the next version of a search to its history, returns the version
+fn insert_search_version(
+    tran: &rusqlite::Transaction,
+    id: i32,
+    search: &OwnedSearchEdit,
+) -> rusqlite::Result<i32> {
+    tran.query_row(
+        "INSERT INTO search_history
+        (search, version, name, type, search_text, locations, changed, changed_by, selector,
+            realtime)
+        SELECT ?1, COALESCE(MAX(version), 0) + 1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9
+        FROM search_history WHERE search=?1
+        RETURNING version;",
+        params![
+            id,
+            search.name,
+            search.stype,
+            search.search,
+            search.locations,
+            search.changed,
+            search.changed_by,
+            search.selector,
+            search.realtime
+        ],
+        |row| row.get("version"),
+    )
+}
+
+#[async_trait]
+impl Storage for Sqlite {
+    fn backend(&self) -> Backend {
+        Backend::Sqlite
+    }
+
+    async fn db_version(&self) -> Result<i32> {
+        self.interact(|conn| {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type='table' AND name='dbinfo');",
+                [],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                return Ok(0);
+            }
+
+            let dbver: Option<Option<i32>> = conn
+                .query_row("SELECT dbver FROM dbinfo LIMIT 1;", [], |row| row.get(0))
+                .optional()?;
+
+            Ok(dbver.flatten().unwrap_or(0))
+        })
+        .await
+    }
+
+    async fn apply_migration(&self, sql: &str) -> Result<()> {
+        let sql = sql.to_string();
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+            tran.execute_batch(&sql)?;
+            tran.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_user(&self, username: &str) -> Result<Option<UserRow>> {
+        let username = username.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM auth WHERE username=?1 LIMIT 1;",
+                    params![username],
+                    |row| {
+                        Ok(UserRow {
+                            passwd: row.get("passwd")?,
+                            enabled: row.get("enabled")?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn insert_user(&self, username: &str, passwd: &str) -> Result<u64> {
+        let (username, passwd) = (username.to_string(), passwd.to_string());
+
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "INSERT INTO auth (username, passwd, enabled) VALUES(?1, ?2, ?3);",
+                params![username, passwd, true],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn set_user_last_login(&self, username: &str, ts: DateTime<Utc>) -> Result<u64> {
+        let username = username.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "UPDATE auth SET lastlogin=?1 WHERE username=?2;",
+                params![ts, username],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn has_users(&self) -> Result<bool> {
+        self.interact(|conn| {
+            Ok(conn
+                .query_row("SELECT username FROM auth LIMIT 1;", [], |_| Ok(()))
+                .optional()?
+                .is_some())
+        })
+        .await
+    }
+
+    async fn get_client_token(&self, id: &str) -> Result<Option<ClientTokenRow>> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT token, enabled FROM clients WHERE id=?1;",
+                    params![id],
+                    |row| {
+                        Ok(ClientTokenRow {
+                            token: row.get("token")?,
+                            enabled: row.get("enabled")?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_client_last_connect(&self, id: &str, ts: DateTime<Utc>) -> Result<u64> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "UPDATE clients SET lastconnect=?1 WHERE id=?2;",
+                params![ts, id],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn insert_client(&self, new: &NewClient<'_>) -> Result<()> {
+        let id = new.id.to_string();
+        let token = new.token.to_string();
+        let name = new.name.to_string();
+        let pubkey = new.pubkey.map(|pubkey| pubkey.to_string());
+        let created = new.created;
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            tran.execute(
+                "INSERT INTO clients
+                (id, token, name, enabled, created, lastconnect, pubkey)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                params![id, token, name, true, created, created, pubkey],
+            )?;
+            tran.execute(
+                "INSERT INTO client_schedule (id, lastrun, manualrun) VALUES(?1, ?2, ?3);",
+                params![id, created, false],
+            )?;
+
+            tran.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_client(&self, id: &str) -> Result<bool> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            let result = tran.execute("DELETE FROM clients WHERE id=?1;", params![id])?;
+            tran.execute("DELETE FROM client_tags WHERE client=?1;", params![id])?;
+            tran.execute("DELETE FROM search_runs WHERE client=?1;", params![id])?;
+            tran.execute("DELETE FROM client_settings WHERE client=?1;", params![id])?;
+            tran.execute(
+                "DELETE FROM integrity_baselines WHERE client=?1;",
+                params![id],
+            )?;
+            tran.execute(
+                "DELETE FROM integrity_changes WHERE client=?1;",
+                params![id],
+            )?;
+
+            tran.commit()?;
+            Ok(result > 0)
+        })
+        .await
+    }
+
+    async fn client_exists(&self, id: &str) -> Result<bool> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row("SELECT id FROM clients WHERE id=?1;", params![id], |_| {
+                    Ok(())
+                })
+                .optional()?
+                .is_some())
+        })
+        .await
+    }
+
+    async fn client_name_exists(&self, name: &str) -> Result<bool> {
+        let name = name.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT id FROM clients WHERE name=?1 LIMIT 1;",
+                    params![name],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+        })
+        .await
+    }
+
+    async fn set_client_enabled(&self, id: &str, enabled: bool) -> Result<()> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE clients SET enabled=?1 WHERE id=?2;",
+                params![enabled, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_client_pubkey(&self, id: &str) -> Result<Option<Option<String>>> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT pubkey FROM clients WHERE id=?1 LIMIT 1;",
+                    params![id],
+                    |row| row.get("pubkey"),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_client_pubkey(&self, id: &str, pubkey: &str) -> Result<bool> {
+        let (id, pubkey) = (id.to_string(), pubkey.to_string());
+
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "UPDATE clients SET pubkey=?1 WHERE id=?2 AND pubkey IS NULL;",
+                params![pubkey, id],
+            )? > 0)
+        })
+        .await
+    }
+
+    async fn get_clients(&self) -> Result<Vec<SLClient>> {
+        self.interact(|conn| {
+            let mut tags: HashMap<String, Vec<ClientTag>> = HashMap::new();
+            let mut stmt = conn.prepare("SELECT * FROM client_tags ORDER BY key, source;")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                if let Some(tag) = client_tag_from_row(row)? {
+                    tags.entry(row.get("client")?).or_default().push(tag);
+                }
+            }
+
+            let mut stmt = conn.prepare("SELECT * FROM clients;")?;
+            let clients = stmt
+                .query_map([], |row| {
+                    let id: String = row.get("id")?;
+                    Ok(SLClient {
+                        tags: tags.remove(&id).unwrap_or_default(),
+                        id,
+                        enabled: row.get("enabled")?,
+                        name: row.get("name")?,
+                        created: row.get("created")?,
+                        lastconnect: row.get("lastconnect")?,
+                        inventory: ClientInventory {
+                            agent_version: row.get("agent_version")?,
+                            git_sha: row.get("git_sha")?,
+                            os: row.get("os")?,
+                            hostname: row.get("hostname")?,
+                            capabilities: capabilities_from_sql(row.get("capabilities")?),
+                        },
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<SLClient>>>()?;
+
+            Ok(clients)
+        })
+        .await
+    }
+
+    async fn get_client_last_run(&self, id: &str) -> Result<Option<ClientLastRun>> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM client_schedule WHERE id=?1 LIMIT 1;",
+                    params![id],
+                    |row| {
+                        Ok(ClientLastRun {
+                            lastrun: row.get("lastrun")?,
+                            manualrun: row.get::<&str, Option<bool>>("manualrun")?.unwrap_or(false),
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_client_last_run(&self, id: &str, dt: DateTime<Utc>) -> Result<()> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE client_schedule SET lastrun=?1, manualrun=?3 WHERE id=?2;",
+                params![dt, id, false],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_client_tags(&self, id: &str) -> Result<Vec<ClientTag>> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT * FROM client_tags WHERE client=?1 ORDER BY key, source;")?;
+            let rows = stmt.query_map(params![id], client_tag_from_row)?;
+
+            Ok(rows
+                .collect::<rusqlite::Result<Vec<Option<ClientTag>>>>()?
+                .into_iter()
+                .flatten()
+                .collect())
+        })
+        .await
+    }
+
+    async fn set_client_tags(
+        &self,
+        id: &str,
+        source: TagSource,
+        tags: &[(String, String)],
+    ) -> Result<()> {
+        let id = id.to_string();
+        let source = source.sql_code();
+        let tags = tags.to_vec();
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            tran.execute(
+                "DELETE FROM client_tags WHERE client=?1 AND source=?2;",
+                params![id, source],
+            )?;
+            for (key, value) in tags {
+                tran.execute(
+                    "INSERT INTO client_tags (client, source, key, value) VALUES(?1, ?2, ?3, ?4);",
+                    params![id, source, key, value],
+                )?;
+            }
+
+            tran.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_client_last_seen(&self, id: &str, ts: DateTime<Utc>) -> Result<()> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE clients SET last_seen=?1 WHERE id=?2;",
+                params![ts, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_client_inventory(&self, id: &str, inventory: &ClientInventory) -> Result<()> {
+        let id = id.to_string();
+        let agent_version = inventory.agent_version.clone();
+        let git_sha = inventory.git_sha.clone();
+        let os = inventory.os.clone();
+        let hostname = inventory.hostname.clone();
+        let capabilities = capabilities_to_sql(&inventory.capabilities);
+
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE clients SET agent_version=?1, git_sha=?2, os=?3, hostname=?4, capabilities=?5
+                WHERE id=?6;",
+                params![agent_version, git_sha, os, hostname, capabilities, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_client_error(&self, id: &str, error: &str, ts: DateTime<Utc>) -> Result<()> {
+        let id = id.to_string();
+        let error = error.to_string();
+
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE clients SET last_error=?1, last_error_at=?2 WHERE id=?3;",
+                params![error, ts, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_client_missed_alert(&self, id: &str, ts: Option<DateTime<Utc>>) -> Result<()> {
+        let id = id.to_string();
+
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE clients SET missed_alert=?1 WHERE id=?2;",
+                params![ts, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_client_settings(&self, client: &str) -> Result<Option<ClientSettings>> {
+        let client = client.to_string();
+
+        let settings: Option<String> = self
+            .interact(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT settings FROM client_settings WHERE client=?1;",
+                        params![client],
+                        |row| row.get("settings"),
+                    )
+                    .optional()?)
+            })
+            .await?;
+
+        Ok(settings
+            .map(|settings| serde_json::from_str(&settings))
+            .transpose()?)
+    }
+
+    async fn set_client_settings(
+        &self,
+        client: &str,
+        settings: Option<&ClientSettings>,
+    ) -> Result<()> {
+        let client = client.to_string();
+        let settings = settings.map(serde_json::to_string).transpose()?;
+
+        self.interact(move |conn| {
+            match settings {
+                Some(settings) => conn.execute(
+                    "INSERT INTO client_settings (client, settings) VALUES(?1, ?2)
+                    ON CONFLICT (client) DO UPDATE SET settings=excluded.settings;",
+                    params![client, settings],
+                )?,
+                None => conn.execute(
+                    "DELETE FROM client_settings WHERE client=?1;",
+                    params![client],
+                )?,
+            };
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_client_statuses(&self) -> Result<Vec<ClientStatus>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT c.*, s.lastrun,
+                (SELECT MAX(r.started) FROM search_results r WHERE r.client=c.id) AS last_result
+            FROM clients c LEFT JOIN client_schedule s ON s.id=c.id
+            ORDER BY c.name;",
+            )?;
+            let statuses = stmt
+                .query_map([], client_status_from_row)?
+                .collect::<rusqlite::Result<Vec<ClientStatus>>>()?;
+
+            Ok(statuses)
+        })
+        .await
+    }
+
+    async fn get_search(&self, id: i32) -> Result<Option<models::Search>> {
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM searches WHERE id=?1 LIMIT 1;",
+                    params![id],
+                    search_from_row,
+                )
+                .optional()?
+                .flatten())
+        })
+        .await
+    }
+
+    async fn get_searches(&self) -> Result<Vec<models::Search>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare("SELECT * FROM searches WHERE enabled=1;")?;
+            let searches = stmt
+                .query_map([], search_from_row)?
+                .collect::<rusqlite::Result<Vec<Option<models::Search>>>>()?;
+
+            Ok(searches.into_iter().flatten().collect())
+        })
+        .await
+    }
+
+    async fn get_all_searches(&self) -> Result<Vec<models::Search>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare("SELECT * FROM searches ORDER BY id;")?;
+            let searches = stmt
+                .query_map([], search_from_row)?
+                .collect::<rusqlite::Result<Vec<Option<models::Search>>>>()?;
+
+            Ok(searches.into_iter().flatten().collect())
+        })
+        .await
+    }
+
+    async fn delete_search(&self, id: i32) -> Result<bool> {
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            let result = tran.execute("DELETE FROM searches WHERE id=?1;", params![id])?;
+            tran.execute("DELETE FROM search_history WHERE search=?1;", params![id])?;
+
+            tran.commit()?;
+
+            Ok(result > 0)
+        })
+        .await
+    }
+
+    async fn insert_search(&self, search: &SearchEdit<'_>, enabled: bool) -> Result<i32> {
+        let search = OwnedSearchEdit::new(search)?;
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            let id = tran.query_row(
+                "INSERT INTO searches
+                (name, type, search, locations, enabled, selector, realtime)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                RETURNING id;",
+                params![
+                    search.name,
+                    search.stype,
+                    search.search,
+                    search.locations,
+                    enabled,
+                    search.selector,
+                    search.realtime
+                ],
+                |row| row.get("id"),
+            )?;
+            insert_search_version(&tran, id, &search)?;
+
+            tran.commit()?;
+
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn update_search(&self, id: i32, search: &SearchEdit<'_>) -> Result<Option<i32>> {
+        let search = OwnedSearchEdit::new(search)?;
+
+        self.interact(move |conn| {
+            // takes the write lock now, versions of a search can't interleave
+            let tran = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let result = tran.execute(
+                "UPDATE searches SET name=?2, type=?3, search=?4, locations=?5, selector=?6,
+                realtime=?7
+                WHERE id=?1;",
+                params![
+                    id,
+                    search.name,
+                    search.stype,
+                    search.search,
+                    search.locations,
+                    search.selector,
+                    search.realtime
+                ],
+            )?;
+            if result < 1 {
+                return Ok(None);
+            }
+            let version = insert_search_version(&tran, id, &search)?;
+
+            tran.commit()?;
+
+            Ok(Some(version))
+        })
+        .await
+    }
+
+    async fn set_search_enabled(&self, id: i32, enabled: bool) -> Result<bool> {
+        self.interact(move |conn| {
+            let result = conn.execute(
+                "UPDATE searches SET enabled=?2 WHERE id=?1;",
+                params![id, enabled],
+            )?;
+
+            Ok(result > 0)
+        })
+        .await
+    }
+
+    async fn get_search_history(&self, id: i32) -> Result<Vec<models::SearchVersion>> {
+        self.interact(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT * FROM search_history WHERE search=?1 ORDER BY version DESC;")?;
+            let versions = stmt
+                .query_map(params![id], search_version_from_row)?
+                .collect::<rusqlite::Result<Vec<Option<models::SearchVersion>>>>()?;
+
+            Ok(versions.into_iter().flatten().collect())
+        })
+        .await
+    }
+
+    async fn get_search_version(
+        &self,
+        id: i32,
+        version: i32,
+    ) -> Result<Option<models::SearchVersion>> {
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM search_history WHERE search=?1 AND version=?2;",
+                    params![id, version],
+                    search_version_from_row,
+                )
+                .optional()?
+                .flatten())
+        })
+        .await
+    }
+
+    async fn insert_search_result(
+        &self,
+        clientid: &str,
+        result: &ClientSearchResult,
+        signature: Option<&str>,
+    ) -> Result<()> {
+        let clientid = clientid.to_string();
+        let search_id = result.search_id;
+        let location = result.location.clone();
+        let found = serde_json::to_string(&result.found)?;
+        let started = truncate_micros(result.started);
+        let signature = signature.map(|signature| signature.to_string());
+        let truncated = result.truncated;
+
+        self.interact(move |conn| {
+            conn.execute(
+                "INSERT INTO search_results
+                (client, search, location, found, started, signature, truncated)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                params![clientid, search_id, location, found, started, signature, truncated],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_search_runs(&self, clientid: &str, reports: &[SearchRunReport]) -> Result<()> {
+        let clientid = clientid.to_string();
+        let reports = reports.to_vec();
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            tran.execute(
+                "DELETE FROM search_runs WHERE client=?1;",
+                params![clientid],
+            )?;
+            for report in reports {
+                // a location listed twice in a search is reported twice, the last one wins
+                tran.execute(
+                    "INSERT INTO search_runs
+                    (client, search, location, started, duration_ms, lines_scanned, bytes_read, error_kind, error)
+                    VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                    ON CONFLICT (client, search, location) DO UPDATE SET
+                    started=excluded.started, duration_ms=excluded.duration_ms,
+                    lines_scanned=excluded.lines_scanned, bytes_read=excluded.bytes_read,
+                    error_kind=excluded.error_kind, error=excluded.error;",
+                    params![
+                        clientid,
+                        report.search_id,
+                        report.location,
+                        report.started,
+                        report.duration_ms as i64,
+                        report.lines_scanned as i64,
+                        report.bytes_read as i64,
+                        report.error.as_ref().map(|error| error.kind.sql_code()),
+                        report.error.as_ref().map(|error| error.message.as_str()),
+                    ],
+                )?;
+            }
+
+            tran.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_search_runs(&self, search: Option<i32>) -> Result<Vec<SearchRun>> {
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT r.*, c.name AS client_name FROM search_runs r
+                JOIN clients c ON c.id=r.client
+                WHERE ?1 IS NULL OR r.search=?1
+                ORDER BY r.search, c.name, r.location;",
+            )?;
+            let runs = stmt
+                .query_map(params![search], search_run_from_row)?
+                .collect::<rusqlite::Result<Vec<SearchRun>>>()?;
+
+            Ok(runs)
+        })
+        .await
+    }
+
+    async fn get_search_results(&self, filter: &SearchResultFilter) -> Result<SearchResultPage> {
+        let limit = filter
+            .limit
+            .unwrap_or(DEFAULT_RESULTS_PAGE)
+            .clamp(1, MAX_RESULTS_PAGE);
+        let client = filter.client.clone();
+        let search = filter.search;
+        let before = filter.before;
+        let after = filter.after;
+        let text = filter.like_text();
+        let cursor_started = filter.cursor.map(|cursor| cursor.started);
+        let cursor_id = filter.cursor.map(|cursor| cursor.id);
+
+        let rows = self
+            .interact(move |conn| {
+                // fetch one extra row to know if there is a next page
+                let mut stmt = conn.prepare(
+                    "SELECT r.*, COALESCE(s.name, '') AS search_name,
+                        COALESCE(c.name, '') AS client_name
+                    FROM search_results r
+                    LEFT JOIN searches s ON s.id=r.search
+                    LEFT JOIN clients c ON c.id=r.client
+                    WHERE (?1 IS NULL OR r.client=?1)
+                    AND (?2 IS NULL OR r.search=?2)
+                    AND (?3 IS NULL OR r.started<?3)
+                    AND (?4 IS NULL OR r.started>?4)
+                    AND (?5 IS NULL OR EXISTS (
+                        SELECT 1 FROM json_each(r.found)
+                        WHERE value LIKE '%' || ?5 || '%' ESCAPE '\\'
+                    ))
+                    AND (?6 IS NULL OR (r.started, r.id) < (?6, ?7))
+                    ORDER BY r.started DESC, r.id DESC
+                    LIMIT ?8;",
+                )?;
+                let rows = stmt
+                    .query_map(
+                        params![
+                            client,
+                            search,
+                            before,
+                            after,
+                            text,
+                            cursor_started,
+                            cursor_id,
+                            limit + 1
+                        ],
+                        |row| {
+                            let result = SearchResult {
+                                client_id: row.get("client")?,
+                                client_name: row.get("client_name")?,
+                                search_id: row.get("search")?,
+                                search_name: row.get("search_name")?,
+                                location: row.get("location")?,
+                                found: json_column(row, "found")?,
+                                started: row.get("started")?,
+                                signature: row.get("signature")?,
+                                truncated: row.get("truncated")?,
+                            };
+                            let cursor = ResultsCursor {
+                                started: result.started,
+                                id: row.get("id")?,
+                            };
+                            Ok((result, cursor))
+                        },
+                    )?
+                    .collect::<rusqlite::Result<Vec<(SearchResult, ResultsCursor)>>>()?;
+
+                Ok(rows)
+            })
+            .await?;
+
+        Ok(SearchResultPage::from_rows(rows, limit))
+    }
+
+    async fn search_matches(&self, query: &MatchQuery) -> Result<MatchPage> {
+        let fts5_query = match fulltext::fts5_query(&query.query) {
+            Some(fts5_query) => fts5_query,
+            None => {
+                return Ok(MatchPage {
+                    matches: Vec::new(),
+                    total: 0,
+                    clients: Vec::new(),
+                    searches: Vec::new(),
+                })
+            }
+        };
+        let (client, search) = (query.client.clone(), query.search);
+        let (before, after) = (query.before, query.after);
+        let (limit, offset) = (query.limit, query.offset);
+
+        self.interact(move |conn| {
+            // lines of the rows matching the filters, shared by the page and facet queries
+            let matched_lines = "
+                FROM search_result_lines_fts f
+                JOIN search_result_lines l ON l.id=f.rowid
+                JOIN search_results r ON r.id=l.result
+                LEFT JOIN searches s ON s.id=r.search
+                LEFT JOIN clients c ON c.id=r.client
+                WHERE search_result_lines_fts MATCH ?1
+                AND (?2 IS NULL OR r.client=?2)
+                AND (?3 IS NULL OR r.search=?3)
+                AND (?4 IS NULL OR r.started<?4)
+                AND (?5 IS NULL OR r.started>?5)";
+            let filters = params![fts5_query, client, search, before, after];
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT r.id, r.client, r.search, r.location, r.started,
+                    highlight(search_result_lines_fts, 0, ?6, ?7) AS headline,
+                    COALESCE(s.name, '') AS search_name, COALESCE(c.name, '') AS client_name
+                {}
+                ORDER BY r.started DESC, r.id DESC, l.ord
+                LIMIT ?8 OFFSET ?9;",
+                matched_lines
+            ))?;
+            let matches = stmt
+                .query_map(
+                    params![
+                        fts5_query,
+                        client,
+                        search,
+                        before,
+                        after,
+                        fulltext::HIGHLIGHT_START.to_string(),
+                        fulltext::HIGHLIGHT_STOP.to_string(),
+                        limit,
+                        offset
+                    ],
+                    |row| {
+                        let headline: String = row.get("headline")?;
+                        let (line, highlights) = fulltext::parse_headline(&headline);
+                        Ok(LineMatch {
+                            result_id: row.get("id")?,
+                            client_id: row.get("client")?,
+                            client_name: row.get("client_name")?,
+                            search_id: row.get("search")?,
+                            search_name: row.get("search_name")?,
+                            location: row.get("location")?,
+                            started: row.get("started")?,
+                            line,
+                            highlights,
+                        })
+                    },
+                )?
+                .collect::<rusqlite::Result<Vec<LineMatch>>>()?;
+
+            let facet = |row: &Row| -> rusqlite::Result<Facet> {
+                Ok(Facet {
+                    id: row.get("id")?,
+                    name: row.get("name")?,
+                    count: row.get("count")?,
+                })
+            };
+            let clients = conn
+                .prepare(&format!(
+                    "SELECT r.client AS id, COALESCE(c.name, '') AS name, COUNT(*) AS count
+                    {} GROUP BY r.client ORDER BY count DESC;",
+                    matched_lines
+                ))?
+                .query_map(filters, facet)?
+                .collect::<rusqlite::Result<Vec<Facet>>>()?;
+            let searches = conn
+                .prepare(&format!(
+                    "SELECT CAST(r.search AS TEXT) AS id, COALESCE(s.name, '') AS name,
+                        COUNT(*) AS count
+                    {} GROUP BY r.search ORDER BY count DESC;",
+                    matched_lines
+                ))?
+                .query_map(filters, facet)?
+                .collect::<rusqlite::Result<Vec<Facet>>>()?;
+
+            Ok(MatchPage {
+                matches,
+                total: clients.iter().map(|facet| facet.count).sum(),
+                clients,
+                searches,
+            })
+        })
+        .await
+    }
+
+    async fn get_integrity_baseline(&self, clientid: &str) -> Result<Option<IntegrityBaseline>> {
+        let clientid = clientid.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM integrity_baselines WHERE client=?1;",
+                    params![clientid],
+                    |row| {
+                        Ok(IntegrityBaseline {
+                            paths: json_column(row, "paths")?,
+                            files: json_option(row, "files")?.unwrap_or_default(),
+                            updated: row.get("updated")?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_integrity_baseline(
+        &self,
+        clientid: &str,
+        baseline: &IntegrityBaseline,
+        changes: &[FileChange],
+    ) -> Result<()> {
+        let clientid = clientid.to_string();
+        let paths = serde_json::to_string(&baseline.paths)?;
+        let files = serde_json::to_string(&baseline.files)?;
+        let updated = truncate_micros(baseline.updated);
+        let changes = changes
+            .iter()
+            .map(|change| {
+                Ok((
+                    change.path.clone(),
+                    change.kind.sql_code(),
+                    serde_json::to_string(&change.changed)?,
+                    change
+                        .before
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?,
+                    change
+                        .after
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+
+            tran.execute(
+                "INSERT INTO integrity_baselines (client, paths, files, updated)
+                VALUES(?1, ?2, ?3, ?4)
+                ON CONFLICT (client) DO UPDATE SET
+                paths=excluded.paths, files=excluded.files, updated=excluded.updated;",
+                params![clientid, paths, files, updated],
+            )?;
+            for (path, kind, changed, before, after) in changes {
+                tran.execute(
+                    "INSERT INTO integrity_changes
+                    (client, path, kind, changed, old_state, new_state, detected)
+                    VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                    params![clientid, path, kind, changed, before, after, updated],
+                )?;
+            }
+
+            tran.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_integrity_changes(
+        &self,
+        clientid: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<IntegrityChange>> {
+        let clientid = clientid.map(|clientid| clientid.to_string());
+
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT i.*, COALESCE(c.name, '') AS client_name FROM integrity_changes i
+                LEFT JOIN clients c ON c.id=i.client
+                WHERE ?1 IS NULL OR i.client=?1
+                ORDER BY i.detected DESC, i.id DESC
+                LIMIT ?2;",
+            )?;
+            let changes = stmt
+                .query_map(params![clientid, limit], integrity_change_from_row)?
+                .collect::<rusqlite::Result<Vec<Option<IntegrityChange>>>>()?;
+
+            Ok(changes.into_iter().flatten().collect())
+        })
+        .await
+    }
+
+    async fn insert_enrollment_token(&self, token: &NewEnrollmentToken<'_>) -> Result<i32> {
+        let name = token.name.to_string();
+        let hash = token.token.to_string();
+        let tags = serde_json::to_string(token.tags)?;
+        let (max_uses, expires, created) = (token.max_uses, token.expires, token.created);
+        let created_by = token.created_by.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn.query_row(
+                "INSERT INTO enrollment_tokens
+                (name, token, tags, max_uses, expires, created, created_by)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING id;",
+                params![name, hash, tags, max_uses, expires, created, created_by],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn get_enrollment_tokens(&self) -> Result<Vec<EnrollmentToken>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare("SELECT * FROM enrollment_tokens ORDER BY id;")?;
+            let tokens = stmt
+                .query_map([], enrollment_token_from_row)?
+                .collect::<rusqlite::Result<Vec<EnrollmentToken>>>()?;
+
+            Ok(tokens)
+        })
+        .await
+    }
+
+    async fn delete_enrollment_token(&self, id: i32) -> Result<bool> {
+        self.interact(move |conn| {
+            Ok(conn.execute("DELETE FROM enrollment_tokens WHERE id=?1;", params![id])? > 0)
+        })
+        .await
+    }
+
+    async fn enroll_client(
+        &self,
+        token: &str,
+        new: &NewClient<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<EnrollmentToken>> {
+        let hash = token.to_string();
+        let id = new.id.to_string();
+        let sqltoken = new.token.to_string();
+        let name = new.name.to_string();
+        let pubkey = new.pubkey.map(|pubkey| pubkey.to_string());
+        let created = new.created;
+        let source = TagSource::Admin.sql_code();
+
+        self.interact(move |conn| {
+            // takes the write lock now, two clients can't take the last use
+            let tran = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let used = tran
+                .query_row(
+                    "SELECT * FROM enrollment_tokens WHERE token=?1;",
+                    params![hash],
+                    enrollment_token_from_row,
+                )
+                .optional()?;
+            let Some(mut used) = used.filter(|used| used.usable(now)) else {
+                return Ok(None);
+            };
+            let taken = tran
+                .query_row(
+                    "SELECT id FROM clients WHERE name=?1 LIMIT 1;",
+                    params![name],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if taken.is_some() {
+                return Err(SqlError::ClientNameExists(name));
+            }
+
+            tran.execute(
+                "UPDATE enrollment_tokens SET uses=uses + 1 WHERE id=?1;",
+                params![used.id],
+            )?;
+            tran.execute(
+                "INSERT INTO clients
+                (id, token, name, enabled, created, lastconnect, pubkey)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                params![id, sqltoken, name, true, created, created, pubkey],
+            )?;
+            tran.execute(
+                "INSERT INTO client_schedule (id, lastrun, manualrun) VALUES(?1, ?2, ?3);",
+                params![id, created, false],
+            )?;
+            for (key, value) in &used.tags {
+                tran.execute(
+                    "INSERT INTO client_tags (client, source, key, value) VALUES(?1, ?2, ?3, ?4);",
+                    params![id, source, key, value],
+                )?;
+            }
+
+            tran.commit()?;
+            used.uses += 1;
+            Ok(Some(used))
+        })
+        .await
+    }
+
+    async fn get_scan_schedule(&self) -> Result<Option<ScanSchedule>> {
+        self.interact(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM scan_schedule WHERE searchid=?1 LIMIT 1;",
+                    params![0i32],
+                    |row| {
+                        let schedule: i32 = row.get("schedule")?;
+                        Ok(ScanSchedule {
+                            dur: Duration::from_secs((schedule as u64) * 60),
+                            manual: row.get("manual")?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_scan_schedule(&self, minutes: i32, manual: bool) -> Result<()> {
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE scan_schedule SET schedule=?1, manual=?3 WHERE searchid=?2;",
+                params![minutes, 0i32, manual],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn add_webhook(&self, name: &str, url: &str, username: &str) -> Result<()> {
+        let (name, url, username) = (name.to_string(), url.to_string(), username.to_string());
+
+        self.interact(move |conn| {
+            conn.execute(
+                "INSERT INTO webhooks (name, url, username) VALUES(?1, ?2, ?3);",
+                params![name, url, username],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare("SELECT * FROM webhooks;")?;
+            let hooks = stmt
+                .query_map([], |row| {
+                    Ok(Webhook {
+                        name: row.get("name")?,
+                        url: row.get("url")?,
+                        username: row.get("username")?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<Webhook>>>()?;
+
+            Ok(hooks)
+        })
+        .await
+    }
+
+    async fn delete_webhook(&self, name: &str) -> Result<bool> {
+        let name = name.to_string();
+
+        self.interact(move |conn| {
+            Ok(conn.execute("DELETE FROM webhooks WHERE name=?1;", params![name])? > 0)
+        })
+        .await
+    }
+
+    async fn insert_segment(&self, segment: &ArchiveSegment) -> Result<bool> {
+        let id = segment.id.clone();
+        let client = segment.client.clone();
+        let location = segment.location.clone();
+        let (start_offset, size, stored_size) =
+            (segment.start_offset, segment.size, segment.stored_size);
+        let received = truncate_micros(segment.received);
+
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "INSERT INTO archive_segments
+                (id, client, location, start_offset, size, stored_size, received)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT DO NOTHING;",
+                params![
+                    id,
+                    client,
+                    location,
+                    start_offset,
+                    size,
+                    stored_size,
+                    received
+                ],
+            )? > 0)
+        })
+        .await
+    }
+
+    async fn get_segment(&self, clientid: &str, id: &str) -> Result<Option<ArchiveSegment>> {
+        let (clientid, id) = (clientid.to_string(), id.to_string());
+
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT * FROM archive_segments WHERE client=?1 AND id=?2 LIMIT 1;",
+                    params![clientid, id],
+                    segment_from_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn get_segments(&self, filter: &SegmentFilter) -> Result<Vec<ArchiveSegment>> {
+        let (clientid, location) = (filter.client.clone(), filter.location.clone());
+        let (after, before) = (filter.after, filter.before);
+        let cursor = filter.cursor.clone();
+        // a negative limit is no limit
+        let limit = filter.limit.unwrap_or(-1);
+
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT * FROM archive_segments
+                WHERE (?1 IS NULL OR client=?1)
+                AND (?2 IS NULL OR location=?2)
+                AND (?3 IS NULL OR received>?3)
+                AND (?4 IS NULL OR received<?4)
+                AND (?5 IS NULL
+                    OR (received, start_offset, client, location, id) > (?5, ?6, ?7, ?8, ?9))
+                ORDER BY received, start_offset, client, location, id
+                LIMIT ?10;",
+            )?;
+            let cursor = cursor.as_ref();
+            let segments = stmt
+                .query_map(
+                    params![
+                        clientid,
+                        location,
+                        after,
+                        before,
+                        cursor.map(|cursor| cursor.received),
+                        cursor.map(|cursor| cursor.start_offset),
+                        cursor.map(|cursor| cursor.client.as_str()),
+                        cursor.map(|cursor| cursor.location.as_str()),
+                        cursor.map(|cursor| cursor.id.as_str()),
+                        limit
+                    ],
+                    segment_from_row,
+                )?
+                .collect::<rusqlite::Result<Vec<ArchiveSegment>>>()?;
+
+            Ok(segments)
+        })
+        .await
+    }
+
+    async fn delete_segments_before(&self, before: DateTime<Utc>) -> Result<Vec<ArchiveSegment>> {
+        self.interact(move |conn| {
+            let mut stmt =
+                conn.prepare("DELETE FROM archive_segments WHERE received<?1 RETURNING *;")?;
+            let segments = stmt
+                .query_map(params![before], segment_from_row)?
+                .collect::<rusqlite::Result<Vec<ArchiveSegment>>>()?;
+
+            Ok(segments)
+        })
+        .await
+    }
+
+    async fn get_search_retentions(&self) -> Result<Vec<SearchRetention>> {
+        self.interact(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT id, retention_days, retention_rows FROM searches;")?;
+            let retentions = stmt
+                .query_map([], |row| {
+                    Ok(SearchRetention {
+                        search_id: row.get("id")?,
+                        days: row.get("retention_days")?,
+                        rows: row.get("retention_rows")?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<SearchRetention>>>()?;
+
+            Ok(retentions)
+        })
+        .await
+    }
+
+    async fn set_search_retention(
+        &self,
+        id: i32,
+        days: Option<i32>,
+        rows: Option<i32>,
+    ) -> Result<()> {
+        self.interact(move |conn| {
+            conn.execute(
+                "UPDATE searches SET retention_days=?1, retention_rows=?2 WHERE id=?3;",
+                params![days, rows, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_expired_results(
+        &self,
+        target: &PruneTarget,
+        batch: i64,
+    ) -> Result<Vec<ExpiredResult>> {
+        let search = target.search;
+        let older_than = target.older_than;
+        let keep_rows = target.keep_rows;
+
+        self.interact(move |conn| {
+            // rows past keep_rows, newest first, and rows older than older_than
+            let mut stmt = conn.prepare(
+                "SELECT * FROM search_results
+                WHERE ((?1 IS NULL AND search NOT IN (SELECT id FROM searches))
+                    OR search=?1)
+                AND (
+                    (?2 IS NOT NULL AND started<?2)
+                    OR (?3 IS NOT NULL AND id IN (
+                        SELECT id FROM search_results
+                        WHERE ((?1 IS NULL AND search NOT IN (SELECT id FROM searches))
+                            OR search=?1)
+                        ORDER BY started DESC, id DESC
+                        LIMIT -1 OFFSET COALESCE(?3, 0)
+                    ))
+                )
+                ORDER BY id
+                LIMIT ?4;",
+            )?;
+            let rows = stmt.query_map(params![search, older_than, keep_rows, batch], |row| {
+                Ok(ExpiredResult {
+                    id: row.get("id")?,
+                    client: row.get("client")?,
+                    search: row.get("search")?,
+                    location: row.get("location")?,
+                    found: json_column(row, "found")?,
+                    started: row.get("started")?,
+                    signature: row.get("signature")?,
+                    truncated: row.get("truncated")?,
+                })
+            })?;
+
+            Ok(rows.collect::<rusqlite::Result<Vec<ExpiredResult>>>()?)
+        })
+        .await
+    }
+
+    async fn delete_search_results(&self, ids: &[i32]) -> Result<usize> {
+        let ids = ids.to_vec();
+
+        self.interact(move |conn| {
+            let tran = conn.transaction()?;
+            let mut deleted = 0;
+            {
+                let mut stmt = tran.prepare("DELETE FROM search_results WHERE id=?1;")?;
+                for id in ids {
+                    deleted += stmt.execute(params![id])?;
+                }
+            }
+            tran.commit()?;
+
+            Ok(deleted)
+        })
+        .await
+    }
+
+    // sqlite has no pg_column_size, bytes are the length of the stored values
+    async fn get_client_storage_usage(&self) -> Result<Vec<StorageUsage>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT r.client AS id, c.name, COUNT(*) AS rows,
+                    SUM(length(r.found) + COALESCE(length(r.location), 0)
+                        + COALESCE(length(r.signature), 0) + length(r.client)) AS bytes
+                FROM search_results r LEFT JOIN clients c ON c.id=r.client
+                GROUP BY r.client, c.name ORDER BY bytes DESC;",
+            )?;
+            let usage = stmt
+                .query_map([], storage_usage_from_row)?
+                .collect::<rusqlite::Result<Vec<StorageUsage>>>()?;
+
+            Ok(usage)
+        })
+        .await
+    }
+
+    async fn get_search_storage_usage(&self) -> Result<Vec<StorageUsage>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT CAST(r.search AS TEXT) AS id, s.name, COUNT(*) AS rows,
+                    SUM(length(r.found) + COALESCE(length(r.location), 0)
+                        + COALESCE(length(r.signature), 0) + length(r.client)) AS bytes
+                FROM search_results r LEFT JOIN searches s ON s.id=r.search
+                GROUP BY r.search, s.name ORDER BY bytes DESC;",
+            )?;
+            let usage = stmt
+                .query_map([], storage_usage_from_row)?
+                .collect::<rusqlite::Result<Vec<StorageUsage>>>()?;
+
+            Ok(usage)
+        })
+        .await
+    }
+}
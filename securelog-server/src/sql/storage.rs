@@ -0,0 +1,1132 @@
+/*
+Storage backends.
+
+Everything the server keeps in its database goes through Storage, the
+functions in sql::* pick the configured backend and keep any logic that does
+not depend on it, like hashing passwords. Postgres is the full featured
+backend, SQLite is meant for small deployments and tests and has no full
+text search.
+*/
+use super::archive::{ArchiveSegment, SegmentFilter};
+use super::client::{ClientInventory, ClientLastRun, ClientStatus, SLClient};
+use super::enrollment::EnrollmentToken;
+use super::fulltext::{MatchPage, MatchQuery};
+use super::integrity::{IntegrityBaseline, IntegrityChange};
+use super::retention::{ExpiredResult, PruneTarget, SearchRetention, StorageUsage};
+use super::runs::SearchRun;
+use super::webhooks::Webhook;
+use super::{Backend, Result, ScanSchedule, SearchResultFilter, SearchResultPage};
+use crate::models::{
+    ClientSearchResult, ClientSettings, ClientTag, FileChange, Search, SearchRunReport, SearchType,
+    SearchVersion, TagSource,
+};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+// a row of the auth table
+pub struct UserRow {
+    pub passwd: String,
+    pub enabled: bool,
+}
+
+// what a client authenticates with
+pub struct ClientTokenRow {
+    pub token: String,
+    pub enabled: bool,
+}
+
+pub struct NewClient<'a> {
+    pub id: &'a str,
+    // bcrypt hash of the token
+    pub token: &'a str,
+    pub name: &'a str,
+    pub pubkey: Option<&'a str>,
+    pub created: DateTime<Utc>,
+}
+
+pub struct NewEnrollmentToken<'a> {
+    pub name: &'a str,
+    // sha256 of the token, see enrollment::token_hash
+    pub token: &'a str,
+    pub tags: &'a BTreeMap<String, String>,
+    pub max_uses: i32,
+    pub expires: DateTime<Utc>,
+    pub created: DateTime<Utc>,
+    pub created_by: &'a str,
+}
+
+// a search as a user saved it, see SearchVersion
+pub struct SearchEdit<'a> {
+    pub name: &'a str,
+    pub stype: &'a SearchType,
+    pub search: &'a str,
+    pub locations: &'a [String],
+    pub selector: Option<&'a str>,
+    pub realtime: bool,
+    pub changed_by: &'a str,
+    pub changed: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    // migrations, see migrations.rs
+    async fn db_version(&self) -> Result<i32>;
+    // run the sql of one migration step in a transaction
+    async fn apply_migration(&self, sql: &str) -> Result<()>;
+
+    // users
+    async fn get_user(&self, username: &str) -> Result<Option<UserRow>>;
+    async fn insert_user(&self, username: &str, passwd: &str) -> Result<u64>;
+    async fn set_user_last_login(&self, username: &str, ts: DateTime<Utc>) -> Result<u64>;
+    async fn has_users(&self) -> Result<bool>;
+
+    // clients
+    async fn get_client_token(&self, id: &str) -> Result<Option<ClientTokenRow>>;
+    async fn set_client_last_connect(&self, id: &str, ts: DateTime<Utc>) -> Result<u64>;
+    // creates the clients and client_schedule rows
+    async fn insert_client(&self, client: &NewClient<'_>) -> Result<()>;
+    // deletes its tags, search runs, settings and integrity baseline too
+    async fn delete_client(&self, id: &str) -> Result<bool>;
+    async fn client_exists(&self, id: &str) -> Result<bool>;
+    async fn client_name_exists(&self, name: &str) -> Result<bool>;
+    async fn set_client_enabled(&self, id: &str, enabled: bool) -> Result<()>;
+    // None if the client does not exist
+    async fn get_client_pubkey(&self, id: &str) -> Result<Option<Option<String>>>;
+    // only when the client has none, false otherwise
+    async fn set_client_pubkey(&self, id: &str, pubkey: &str) -> Result<bool>;
+    // with their tags
+    async fn get_clients(&self) -> Result<Vec<SLClient>>;
+    async fn get_client_last_run(&self, id: &str) -> Result<Option<ClientLastRun>>;
+    async fn set_client_last_run(&self, id: &str, dt: DateTime<Utc>) -> Result<()>;
+    async fn get_client_tags(&self, id: &str) -> Result<Vec<ClientTag>>;
+    // replaces the client's tags from `source`
+    async fn set_client_tags(
+        &self,
+        id: &str,
+        source: TagSource,
+        tags: &[(String, String)],
+    ) -> Result<()>;
+    async fn set_client_last_seen(&self, id: &str, ts: DateTime<Utc>) -> Result<()>;
+    async fn set_client_inventory(&self, id: &str, inventory: &ClientInventory) -> Result<()>;
+    async fn set_client_error(&self, id: &str, error: &str, ts: DateTime<Utc>) -> Result<()>;
+    // None once the client checks in again
+    async fn set_client_missed_alert(&self, id: &str, ts: Option<DateTime<Utc>>) -> Result<()>;
+    // overdue is left false, see monitor::client_statuses
+    async fn get_client_statuses(&self) -> Result<Vec<ClientStatus>>;
+    // the defaults for every client are under client ""
+    async fn get_client_settings(&self, client: &str) -> Result<Option<ClientSettings>>;
+    // None deletes them
+    async fn set_client_settings(
+        &self,
+        client: &str,
+        settings: Option<&ClientSettings>,
+    ) -> Result<()>;
+
+    // searches
+    async fn get_search(&self, id: i32) -> Result<Option<Search>>;
+    // enabled searches only
+    async fn get_searches(&self) -> Result<Vec<Search>>;
+    async fn get_all_searches(&self) -> Result<Vec<Search>>;
+    // deletes its history too
+    async fn delete_search(&self, id: i32) -> Result<bool>;
+    // the search and its first version
+    async fn insert_search(&self, search: &SearchEdit<'_>, enabled: bool) -> Result<i32>;
+    // the new version, None if the search does not exist
+    async fn update_search(&self, id: i32, search: &SearchEdit<'_>) -> Result<Option<i32>>;
+    async fn set_search_enabled(&self, id: i32, enabled: bool) -> Result<bool>;
+    // newest first
+    async fn get_search_history(&self, id: i32) -> Result<Vec<SearchVersion>>;
+    async fn get_search_version(&self, id: i32, version: i32) -> Result<Option<SearchVersion>>;
+
+    // results
+    async fn insert_search_result(
+        &self,
+        clientid: &str,
+        result: &ClientSearchResult,
+        signature: Option<&str>,
+    ) -> Result<()>;
+    async fn get_search_results(&self, filter: &SearchResultFilter) -> Result<SearchResultPage>;
+    // replaces every search run of the client, a batch is a whole run
+    async fn set_search_runs(&self, clientid: &str, reports: &[SearchRunReport]) -> Result<()>;
+    // of one search, or all of them
+    async fn get_search_runs(&self, search: Option<i32>) -> Result<Vec<SearchRun>>;
+    async fn search_matches(&self, query: &MatchQuery) -> Result<MatchPage>;
+
+    // file integrity, see integrity.rs
+    async fn get_integrity_baseline(&self, clientid: &str) -> Result<Option<IntegrityBaseline>>;
+    // replaces the baseline and adds the changes from the old one
+    async fn set_integrity_baseline(
+        &self,
+        clientid: &str,
+        baseline: &IntegrityBaseline,
+        changes: &[FileChange],
+    ) -> Result<()>;
+    // of one client or all of them, newest first
+    async fn get_integrity_changes(
+        &self,
+        clientid: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<IntegrityChange>>;
+
+    // enrollment
+    async fn insert_enrollment_token(&self, token: &NewEnrollmentToken<'_>) -> Result<i32>;
+    async fn get_enrollment_tokens(&self) -> Result<Vec<EnrollmentToken>>;
+    async fn delete_enrollment_token(&self, id: i32) -> Result<bool>;
+    // uses the token with the hash to create the client with its tags, None
+    // and nothing created if it is unknown, used up or expired at `now`,
+    // ClientNameExists if it is usable but the name is taken
+    async fn enroll_client(
+        &self,
+        token: &str,
+        client: &NewClient<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<EnrollmentToken>>;
+
+    // schedules
+    async fn get_scan_schedule(&self) -> Result<Option<ScanSchedule>>;
+    async fn set_scan_schedule(&self, minutes: i32, manual: bool) -> Result<()>;
+
+    // webhooks
+    async fn add_webhook(&self, name: &str, url: &str, username: &str) -> Result<()>;
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>>;
+    async fn delete_webhook(&self, name: &str) -> Result<bool>;
+
+    // archive
+    async fn insert_segment(&self, segment: &ArchiveSegment) -> Result<bool>;
+    async fn get_segment(&self, clientid: &str, id: &str) -> Result<Option<ArchiveSegment>>;
+    async fn get_segments(&self, filter: &SegmentFilter) -> Result<Vec<ArchiveSegment>>;
+    async fn delete_segments_before(&self, before: DateTime<Utc>) -> Result<Vec<ArchiveSegment>>;
+
+    // retention
+    async fn get_search_retentions(&self) -> Result<Vec<SearchRetention>>;
+    async fn set_search_retention(
+        &self,
+        id: i32,
+        days: Option<i32>,
+        rows: Option<i32>,
+    ) -> Result<()>;
+    // at most `batch` rows the target says to delete, oldest ids first
+    async fn get_expired_results(
+        &self,
+        target: &PruneTarget,
+        batch: i64,
+    ) -> Result<Vec<ExpiredResult>>;
+    async fn delete_search_results(&self, ids: &[i32]) -> Result<usize>;
+    async fn get_client_storage_usage(&self) -> Result<Vec<StorageUsage>>;
+    async fn get_search_storage_usage(&self) -> Result<Vec<StorageUsage>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::archive::SegmentCursor;
+    use super::super::retention::PruneTarget;
+    use super::super::testdb::{add_client, result, TestDb};
+    use super::super::{ResultsCursor, SqlError};
+    use super::*;
+    use crate::models::{self, FileChangeKind, RunError, RunErrorKind};
+    use chrono::{Duration as ChronoDuration, SubsecRound};
+    use std::time::Duration;
+
+    fn edit<'a>(name: &'a str, search: &'a str, locations: &'a [String]) -> SearchEdit<'a> {
+        SearchEdit {
+            name,
+            stype: &SearchType::Contains,
+            search,
+            locations,
+            selector: None,
+            realtime: false,
+            changed_by: "admin",
+            changed: Utc::now(),
+        }
+    }
+
+    async fn users_and_clients(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        assert!(!storage.has_users().await.unwrap());
+        assert_eq!(storage.insert_user("admin", "hash").await.unwrap(), 1);
+        assert!(storage.has_users().await.unwrap());
+        assert!(storage.get_user("admin").await.unwrap().unwrap().enabled);
+        assert!(storage.get_user("nobody").await.unwrap().is_none());
+
+        add_client(storage, "c1", "web01").await;
+        assert!(storage.client_exists("c1").await.unwrap());
+        assert!(storage.client_name_exists("web01").await.unwrap());
+        assert_eq!(
+            storage.get_client_token("c1").await.unwrap().unwrap().token,
+            "hash"
+        );
+        assert_eq!(storage.get_client_pubkey("c1").await.unwrap(), Some(None));
+        assert!(storage.get_client_last_run("c1").await.unwrap().is_some());
+
+        storage.set_client_enabled("c1", false).await.unwrap();
+        assert!(
+            !storage
+                .get_client_token("c1")
+                .await
+                .unwrap()
+                .unwrap()
+                .enabled
+        );
+        assert_eq!(storage.get_clients().await.unwrap().len(), 1);
+
+        let tags = |tags: &[(&str, &str)]| -> Vec<(String, String)> {
+            tags.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        storage
+            .set_client_tags(
+                "c1",
+                TagSource::Client,
+                &tags(&[("role", "web"), ("env", "test")]),
+            )
+            .await
+            .unwrap();
+        storage
+            .set_client_tags("c1", TagSource::Admin, &tags(&[("env", "prod")]))
+            .await
+            .unwrap();
+        // replaces only the client's own tags
+        storage
+            .set_client_tags(
+                "c1",
+                TagSource::Client,
+                &tags(&[("role", "db"), ("env", "test")]),
+            )
+            .await
+            .unwrap();
+        let client_tags = storage.get_client_tags("c1").await.unwrap();
+        assert_eq!(client_tags.len(), 3);
+        assert_eq!(storage.get_clients().await.unwrap()[0].tags.len(), 3);
+        let effective = crate::sql::client::effective_tags(&client_tags);
+        assert_eq!(effective["env"], "prod");
+        assert_eq!(effective["role"], "db");
+
+        assert!(storage.delete_client("c1").await.unwrap());
+        assert!(!storage.client_exists("c1").await.unwrap());
+        assert!(storage.get_client_tags("c1").await.unwrap().is_empty());
+        assert!(storage.get_client_pubkey("c1").await.unwrap().is_none());
+
+        db.drop().await;
+    }
+
+    async fn client_status(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web01").await;
+        let status = &storage.get_client_statuses().await.unwrap()[0];
+        assert!(status.last_seen.is_none());
+        assert!(status.last_run.is_some());
+        assert!(status.last_result.is_none());
+
+        // both backends keep times to the microsecond
+        let seen = Utc::now().trunc_subsecs(6);
+        storage.set_client_last_seen("c1", seen).await.unwrap();
+        storage
+            .set_client_inventory(
+                "c1",
+                &ClientInventory {
+                    agent_version: Some("0.1.0".to_string()),
+                    capabilities: vec!["archive".to_string(), "settings".to_string()],
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        storage
+            .set_client_error("c1", "invalid signature", seen)
+            .await
+            .unwrap();
+        storage
+            .set_client_missed_alert("c1", Some(seen))
+            .await
+            .unwrap();
+        let id = storage
+            .insert_search(&edit("failed logins", "Failed", &[]), true)
+            .await
+            .unwrap();
+        let started = seen - ChronoDuration::minutes(5);
+        storage
+            .insert_search_result("c1", &result(id, &["a line"], started), None)
+            .await
+            .unwrap();
+
+        let status = &storage.get_client_statuses().await.unwrap()[0];
+        assert_eq!(status.last_seen, Some(seen));
+        assert_eq!(status.agent_version.as_deref(), Some("0.1.0"));
+        assert_eq!(status.last_error.as_deref(), Some("invalid signature"));
+        assert_eq!(status.missed_alert, Some(seen));
+        // results are kept to the microsecond
+        assert_eq!(
+            status.last_result.map(|ts| ts.timestamp_micros()),
+            Some(started.timestamp_micros())
+        );
+
+        storage.set_client_missed_alert("c1", None).await.unwrap();
+        assert!(storage.get_client_statuses().await.unwrap()[0]
+            .missed_alert
+            .is_none());
+
+        db.drop().await;
+    }
+
+    async fn client_settings_and_inventory(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web01").await;
+        assert_eq!(storage.get_clients().await.unwrap()[0].inventory.os, None);
+        assert_eq!(storage.get_client_settings("c1").await.unwrap(), None);
+
+        let inventory = ClientInventory {
+            agent_version: Some("0.1.0".to_string()),
+            git_sha: Some("1f09b90".to_string()),
+            os: Some("Debian GNU/Linux 12 (bookworm) linux x86_64".to_string()),
+            hostname: Some("web01.example.com".to_string()),
+            capabilities: vec!["archive".to_string(), "settings".to_string()],
+        };
+        storage
+            .set_client_inventory("c1", &inventory)
+            .await
+            .unwrap();
+        let client = &storage.get_clients().await.unwrap()[0];
+        assert_eq!(client.inventory.git_sha, inventory.git_sha);
+        assert_eq!(client.inventory.hostname, inventory.hostname);
+        assert_eq!(client.inventory.capabilities, inventory.capabilities);
+
+        let defaults = ClientSettings {
+            poll_interval: Some(300),
+            ..Default::default()
+        };
+        let mut own = ClientSettings {
+            log_level: Some("debug".to_string()),
+            ..Default::default()
+        };
+        storage
+            .set_client_settings("", Some(&defaults))
+            .await
+            .unwrap();
+        storage.set_client_settings("c1", Some(&own)).await.unwrap();
+        own.limits.max_run_secs = Some(600);
+        // replaces the client's settings
+        storage.set_client_settings("c1", Some(&own)).await.unwrap();
+        assert_eq!(
+            storage.get_client_settings("").await.unwrap(),
+            Some(defaults)
+        );
+        assert_eq!(storage.get_client_settings("c1").await.unwrap(), Some(own));
+
+        storage.set_client_settings("c1", None).await.unwrap();
+        assert_eq!(storage.get_client_settings("c1").await.unwrap(), None);
+
+        storage
+            .set_client_settings("c1", Some(&ClientSettings::default()))
+            .await
+            .unwrap();
+        assert!(storage.delete_client("c1").await.unwrap());
+        assert_eq!(storage.get_client_settings("c1").await.unwrap(), None);
+
+        db.drop().await;
+    }
+
+    async fn search_runs_are_replaced_and_summarized(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web01").await;
+        add_client(storage, "c2", "web02").await;
+
+        let report = |search: i32, location: &str, error: Option<RunErrorKind>| {
+            let mut report = SearchRunReport::new(search, location);
+            report.lines_scanned = 10;
+            report.bytes_read = 100;
+            report.error = error.map(|kind| RunError {
+                kind,
+                message: "No such file or directory".to_string(),
+            });
+            report
+        };
+        storage
+            .set_search_runs(
+                "c1",
+                &[
+                    report(1, "/var/log/auth.log", None),
+                    report(1, "/var/log/secure", Some(RunErrorKind::Missing)),
+                    report(2, "/var/log/syslog", None),
+                ],
+            )
+            .await
+            .unwrap();
+        storage
+            .set_search_runs("c2", &[report(1, "/var/log/auth.log", None)])
+            .await
+            .unwrap();
+
+        let runs = storage.get_search_runs(Some(1)).await.unwrap();
+        assert_eq!(runs.len(), 3);
+        assert_eq!(runs[0].client_name, "web01");
+        assert_eq!(
+            runs[1].error.as_ref().map(|error| error.kind),
+            Some(RunErrorKind::Missing)
+        );
+
+        let health = super::super::runs::summarize(&storage.get_search_runs(None).await.unwrap());
+        assert_eq!(health.len(), 2);
+        assert_eq!(health[0].clients, 2);
+        assert_eq!(health[0].failing_clients, 1);
+        assert_eq!(health[0].missing_files, 1);
+        assert_eq!(health[0].lines_scanned, 30);
+        assert_eq!(health[1].failing_clients, 0);
+
+        // the next run replaces the last one
+        storage
+            .set_search_runs("c1", &[report(1, "/var/log/auth.log", None)])
+            .await
+            .unwrap();
+        assert_eq!(storage.get_search_runs(None).await.unwrap().len(), 2);
+
+        assert!(storage.delete_client("c2").await.unwrap());
+        assert_eq!(storage.get_search_runs(None).await.unwrap().len(), 1);
+
+        db.drop().await;
+    }
+
+    async fn integrity_baselines_and_changes(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web01").await;
+        assert!(storage
+            .get_integrity_baseline("c1")
+            .await
+            .unwrap()
+            .is_none());
+
+        let passwd = models::FileState {
+            path: "/etc/passwd".to_string(),
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            size: 1024,
+            mtime: Utc::now(),
+            sha256: Some("aa".to_string()),
+            link: None,
+        };
+        let mut edited = passwd.clone();
+        edited.sha256 = Some("bb".to_string());
+        let baseline = IntegrityBaseline {
+            paths: vec!["/etc/passwd".to_string()],
+            files: vec![edited.clone()],
+            updated: Utc::now(),
+        };
+        let changes = [FileChange {
+            path: "/etc/passwd".to_string(),
+            kind: FileChangeKind::Modified,
+            changed: vec!["sha256".to_string()],
+            before: Some(passwd.clone()),
+            after: Some(edited.clone()),
+        }];
+        storage
+            .set_integrity_baseline("c1", &baseline, &changes)
+            .await
+            .unwrap();
+        storage
+            .set_integrity_baseline("c1", &baseline, &[])
+            .await
+            .unwrap();
+
+        let stored = storage.get_integrity_baseline("c1").await.unwrap().unwrap();
+        assert_eq!(stored.files, vec![edited.clone()]);
+        assert_eq!(stored.paths, baseline.paths);
+
+        let stored = storage.get_integrity_changes(None, 10).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].client_name, "web01");
+        assert_eq!(stored[0].kind, FileChangeKind::Modified);
+        assert_eq!(stored[0].before, Some(passwd));
+        assert_eq!(stored[0].after, Some(edited));
+        assert!(storage
+            .get_integrity_changes(Some("c2"), 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(storage.delete_client("c1").await.unwrap());
+        assert!(storage
+            .get_integrity_baseline("c1")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .get_integrity_changes(None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        db.drop().await;
+    }
+
+    async fn enrollment_tokens_create_clients(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+        let now = Utc::now();
+
+        let tags = [("role".to_string(), "web".to_string())].into();
+        let token = |token, expires| NewEnrollmentToken {
+            name: "web servers",
+            token,
+            tags: &tags,
+            max_uses: 1,
+            expires,
+            created: now,
+            created_by: "admin",
+        };
+        let id = storage
+            .insert_enrollment_token(&token("hash1", now + ChronoDuration::hours(1)))
+            .await
+            .unwrap();
+        storage
+            .insert_enrollment_token(&token("hash2", now - ChronoDuration::hours(1)))
+            .await
+            .unwrap();
+        let client = |id, name| NewClient {
+            id,
+            token: "hash",
+            name,
+            pubkey: None,
+            created: now,
+        };
+
+        // unknown and expired tokens create nothing
+        for hash in ["nope", "hash2"] {
+            let used = storage
+                .enroll_client(hash, &client("c1", "web01"), now)
+                .await
+                .unwrap();
+            assert!(used.is_none());
+        }
+        assert!(!storage.client_exists("c1").await.unwrap());
+
+        // a taken name does not use the token up
+        add_client(storage, "c0", "web00").await;
+        assert!(matches!(
+            storage
+                .enroll_client("hash1", &client("c1", "web00"), now)
+                .await,
+            Err(SqlError::ClientNameExists(_))
+        ));
+        assert!(!storage.client_exists("c1").await.unwrap());
+
+        let used = storage
+            .enroll_client("hash1", &client("c1", "web01"), now)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((used.id, used.uses), (id, 1));
+        assert!(storage.client_exists("c1").await.unwrap());
+        let client_tags = storage.get_client_tags("c1").await.unwrap();
+        assert_eq!(client_tags.len(), 1);
+        assert_eq!(client_tags[0].source, TagSource::Admin);
+        assert_eq!(client_tags[0].value, "web");
+
+        // used up
+        let used = storage
+            .enroll_client("hash1", &client("c2", "web02"), now)
+            .await
+            .unwrap();
+        assert!(used.is_none());
+        assert!(!storage.client_exists("c2").await.unwrap());
+
+        let tokens = storage.get_enrollment_tokens().await.unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].uses, 1);
+        assert_eq!(tokens[0].tags, tags);
+        assert!(storage.delete_enrollment_token(id).await.unwrap());
+        assert!(!storage.delete_enrollment_token(id).await.unwrap());
+        // clients the token created are kept
+        assert!(storage.client_exists("c1").await.unwrap());
+
+        db.drop().await;
+    }
+
+    async fn searches_schedules_and_webhooks(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        let locations = vec!["/var/log/auth.log".to_string()];
+        let id = storage
+            .insert_search(&edit("failed logins", "Failed", &locations), true)
+            .await
+            .unwrap();
+        let search = storage.get_search(id).await.unwrap().unwrap();
+        assert_eq!(search.locations, locations);
+        assert_eq!(storage.get_searches().await.unwrap().len(), 1);
+        assert!(storage.delete_search(id).await.unwrap());
+        assert!(!storage.delete_search(id).await.unwrap());
+        assert!(storage.get_search(id).await.unwrap().is_none());
+
+        let schedule = storage.get_scan_schedule().await.unwrap().unwrap();
+        assert_eq!(schedule.get_interval(), Duration::from_secs(30 * 60));
+        storage.set_scan_schedule(5, true).await.unwrap();
+        let schedule = storage.get_scan_schedule().await.unwrap().unwrap();
+        assert_eq!(schedule.get_interval(), Duration::from_secs(5 * 60));
+        assert!(schedule.is_manual());
+
+        storage
+            .add_webhook("ops", "https://example.com/hook", "admin")
+            .await
+            .unwrap();
+        assert_eq!(storage.get_webhooks().await.unwrap()[0].name, "ops");
+        assert!(storage.delete_webhook("ops").await.unwrap());
+        assert!(!storage.delete_webhook("ops").await.unwrap());
+
+        db.drop().await;
+    }
+
+    async fn search_edits_are_versioned(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        let locations = vec!["/var/log/auth.log".to_string()];
+        let id = storage
+            .insert_search(&edit("logins", "Failed", &locations), true)
+            .await
+            .unwrap();
+        let version = storage
+            .update_search(
+                id,
+                &SearchEdit {
+                    selector: Some("role=web"),
+                    realtime: true,
+                    ..edit("logins", "Accepted", &locations)
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(version, Some(2));
+        assert_eq!(
+            storage
+                .update_search(id + 1, &edit("x", "x", &locations))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            storage.get_search(id).await.unwrap().unwrap().search,
+            "Accepted"
+        );
+
+        let history = storage.get_search_history(id).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].version, 2);
+        assert_eq!(history[1].search, "Failed");
+        assert_eq!(history[1].changed_by.as_deref(), Some("admin"));
+        assert_eq!(history[0].selector.as_deref(), Some("role=web"));
+        assert_eq!(history[1].selector, None);
+        assert!(history[0].realtime);
+        assert!(!history[1].realtime);
+        assert!(storage.get_search(id).await.unwrap().unwrap().realtime);
+        assert_eq!(
+            storage
+                .get_search(id)
+                .await
+                .unwrap()
+                .unwrap()
+                .selector
+                .as_deref(),
+            Some("role=web")
+        );
+        let first = storage.get_search_version(id, 1).await.unwrap().unwrap();
+        assert_eq!(first.search, "Failed");
+        assert!(storage.get_search_version(id, 3).await.unwrap().is_none());
+
+        assert!(storage.set_search_enabled(id, false).await.unwrap());
+        assert!(storage.get_searches().await.unwrap().is_empty());
+        assert!(!storage.get_all_searches().await.unwrap()[0].enabled);
+        assert!(!storage.set_search_enabled(id + 1, false).await.unwrap());
+
+        storage.delete_search(id).await.unwrap();
+        assert!(storage.get_search_history(id).await.unwrap().is_empty());
+
+        db.drop().await;
+    }
+
+    async fn results_are_paged_and_filtered(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web01").await;
+        let id = storage
+            .insert_search(&edit("errors", "error", &[]), true)
+            .await
+            .unwrap();
+        let now = Utc::now();
+        for i in 0..5 {
+            let found = if i == 2 { "disk 100%_full" } else { "error" };
+            let mut result = result(id, &[found], now - ChronoDuration::minutes(i));
+            result.truncated = i == 2;
+            storage
+                .insert_search_result("c1", &result, None)
+                .await
+                .unwrap();
+        }
+
+        let mut filter = SearchResultFilter {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        loop {
+            let page = storage.get_search_results(&filter).await.unwrap();
+            seen.extend(page.results.into_iter().map(|result| result.started));
+            match page.next_cursor {
+                Some(cursor) => filter.cursor = ResultsCursor::decode(&cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 5);
+        assert!(seen.windows(2).all(|pair| pair[0] > pair[1]));
+
+        // wildcards are matched literally
+        let filter = SearchResultFilter {
+            text: Some("100%_F".to_string()),
+            ..Default::default()
+        };
+        let page = storage.get_search_results(&filter).await.unwrap();
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.results[0].client_name, "web01");
+        assert!(page.results[0].truncated);
+
+        let filter = SearchResultFilter {
+            text: Some("1%0".to_string()),
+            ..Default::default()
+        };
+        assert!(storage
+            .get_search_results(&filter)
+            .await
+            .unwrap()
+            .results
+            .is_empty());
+
+        db.drop().await;
+    }
+
+    async fn expired_results_are_deleted(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        add_client(storage, "c1", "web01").await;
+        let id = storage
+            .insert_search(&edit("errors", "error", &[]), true)
+            .await
+            .unwrap();
+        let now = Utc::now();
+        for i in 0..6 {
+            storage
+                .insert_search_result(
+                    "c1",
+                    &result(id, &["error"], now - ChronoDuration::days(i)),
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+
+        // the 2 rows older than 4 days, then all but the newest 3
+        let target = PruneTarget {
+            search: Some(id),
+            older_than: Some(now - ChronoDuration::hours(4 * 24 - 1)),
+            keep_rows: None,
+        };
+        let expired = storage.get_expired_results(&target, 100).await.unwrap();
+        assert_eq!(expired.len(), 2);
+        assert!(expired
+            .iter()
+            .all(|result| result.started < now - ChronoDuration::days(3)));
+        let ids: Vec<i32> = expired.iter().map(|result| result.id).collect();
+        assert_eq!(storage.delete_search_results(&ids).await.unwrap(), 2);
+
+        let target = PruneTarget {
+            search: Some(id),
+            older_than: None,
+            keep_rows: Some(3),
+        };
+        assert_eq!(
+            storage.get_expired_results(&target, 0).await.unwrap().len(),
+            0
+        );
+        let expired = storage.get_expired_results(&target, 100).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(
+            storage
+                .delete_search_results(&[expired[0].id])
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            storage
+                .delete_search_results(&[expired[0].id])
+                .await
+                .unwrap(),
+            0
+        );
+
+        let page = storage
+            .get_search_results(&SearchResultFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(page.results.len(), 3);
+        assert_eq!(storage.get_client_storage_usage().await.unwrap()[0].rows, 3);
+
+        db.drop().await;
+    }
+
+    async fn archive_segments_are_indexed_per_location(backend: Backend) {
+        let db = match TestDb::create_migrated(backend).await {
+            Some(db) => db,
+            None => return,
+        };
+        let storage = db.storage();
+
+        let received = Utc::now().trunc_subsecs(6);
+        let segment = |location: &str, start_offset: i64| ArchiveSegment {
+            id: "ab".repeat(32),
+            client: "c1".to_string(),
+            location: location.to_string(),
+            start_offset,
+            size: 100,
+            stored_size: 50,
+            received,
+        };
+        assert!(storage
+            .insert_segment(&segment("/var/log/syslog", 0))
+            .await
+            .unwrap());
+        assert!(!storage
+            .insert_segment(&segment("/var/log/syslog", 0))
+            .await
+            .unwrap());
+        // the same data copied to another file, or written twice
+        assert!(storage
+            .insert_segment(&segment("/var/log/syslog.1", 0))
+            .await
+            .unwrap());
+        assert!(storage
+            .insert_segment(&segment("/var/log/syslog", 100))
+            .await
+            .unwrap());
+
+        let segments = storage
+            .get_segments(&SegmentFilter {
+                client: Some("c1".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(
+            storage
+                .get_segments(&SegmentFilter {
+                    location: Some("/var/log/syslog".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // segments received at the same time still page one by one
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = storage
+                .get_segments(&SegmentFilter {
+                    cursor: cursor.clone(),
+                    limit: Some(1),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            let Some(segment) = page.first() else {
+                break;
+            };
+            let next = SegmentCursor::of(segment);
+            assert_eq!(SegmentCursor::decode(&next.encode()), Some(next.clone()));
+            paged.push((segment.location.clone(), segment.start_offset));
+            cursor = Some(next);
+        }
+        assert_eq!(
+            paged,
+            segments
+                .iter()
+                .map(|segment| (segment.location.clone(), segment.start_offset))
+                .collect::<Vec<_>>()
+        );
+        let stored = storage
+            .get_segment("c1", &"ab".repeat(32))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.received, received);
+
+        let expired = storage
+            .delete_segments_before(received + ChronoDuration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(expired.len(), 3);
+        assert!(storage
+            .get_segment("c1", &"ab".repeat(32))
+            .await
+            .unwrap()
+            .is_none());
+
+        db.drop().await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_archive_segments_are_indexed_per_location() {
+        archive_segments_are_indexed_per_location(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_users_and_clients() {
+        users_and_clients(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_client_status() {
+        client_status(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_client_settings_and_inventory() {
+        client_settings_and_inventory(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_search_runs_are_replaced_and_summarized() {
+        search_runs_are_replaced_and_summarized(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_integrity_baselines_and_changes() {
+        integrity_baselines_and_changes(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_enrollment_tokens_create_clients() {
+        enrollment_tokens_create_clients(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_searches_schedules_and_webhooks() {
+        searches_schedules_and_webhooks(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_search_edits_are_versioned() {
+        search_edits_are_versioned(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_results_are_paged_and_filtered() {
+        results_are_paged_and_filtered(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn postgres_expired_results_are_deleted() {
+        expired_results_are_deleted(Backend::Postgres).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_archive_segments_are_indexed_per_location() {
+        archive_segments_are_indexed_per_location(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_users_and_clients() {
+        users_and_clients(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_client_status() {
+        client_status(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_client_settings_and_inventory() {
+        client_settings_and_inventory(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_search_runs_are_replaced_and_summarized() {
+        search_runs_are_replaced_and_summarized(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_integrity_baselines_and_changes() {
+        integrity_baselines_and_changes(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_enrollment_tokens_create_clients() {
+        enrollment_tokens_create_clients(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_searches_schedules_and_webhooks() {
+        searches_schedules_and_webhooks(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_search_edits_are_versioned() {
+        search_edits_are_versioned(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_results_are_paged_and_filtered() {
+        results_are_paged_and_filtered(Backend::Sqlite).await;
+    }
+
+    #[actix_web::test]
+    async fn sqlite_expired_results_are_deleted() {
+        expired_results_are_deleted(Backend::Sqlite).await;
+    }
+}
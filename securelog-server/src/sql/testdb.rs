@@ -0,0 +1,161 @@
+/*
+Throwaway databases for the storage tests.
+
+SQLite databases always work. The Postgres ones need a real server, set
+SECURELOG_TEST_PG to connection params with permission to create
+databases, i.e.
+SECURELOG_TEST_PG="host=/var/run/postgresql user=postgres"
+Without it TestDb::create returns None and the test is skipped, except in
+CI (CI is set) where it fails so the Postgres tests can't be skipped by
+accident. .github/workflows/ci.yml runs them against a Postgres service.
+*/
+use super::postgres::Postgres;
+use super::random_string;
+use super::sqlite::Sqlite;
+use super::storage::{NewClient, Storage};
+use super::{migrations, Backend};
+use crate::models::ClientSearchResult;
+use chrono::{DateTime, Utc};
+use tokio_postgres::NoTls;
+
+pub enum TestDb {
+    Postgres {
+        admin: tokio_postgres::Client,
+        name: String,
+        storage: Postgres,
+    },
+    Sqlite {
+        path: std::path::PathBuf,
+        storage: Sqlite,
+    },
+}
+impl TestDb {
+    /**
+     * An empty database, without any tables.
+     */
+    pub async fn create(backend: Backend) -> Option<TestDb> {
+        match backend {
+            Backend::Postgres => TestDb::create_postgres().await,
+            Backend::Sqlite => {
+                let path =
+                    std::env::temp_dir().join(format!("securelog_test_{}.db", random_string(12)));
+                let storage = Sqlite::open(&path.to_string_lossy()).await.unwrap();
+                Some(TestDb::Sqlite { path, storage })
+            }
+        }
+    }
+
+    /**
+     * A database with every migration applied.
+     */
+    pub async fn create_migrated(backend: Backend) -> Option<TestDb> {
+        let db = TestDb::create(backend).await?;
+        migrations::migrate(db.storage(), None, false)
+            .await
+            .unwrap();
+        Some(db)
+    }
+
+    async fn create_postgres() -> Option<TestDb> {
+        let params = match std::env::var("SECURELOG_TEST_PG") {
+            Ok(params) => params,
+            Err(_) if std::env::var_os("CI").is_some() => {
+                panic!("SECURELOG_TEST_PG must be set in CI, the Postgres tests would be skipped")
+            }
+            Err(_) => {
+                eprintln!("SECURELOG_TEST_PG not set, skipping database test");
+                return None;
+            }
+        };
+
+        let (admin, connection) = tokio_postgres::connect(&params, NoTls).await.unwrap();
+        actix_web::rt::spawn(connection);
+
+        let name = format!("securelog_test_{}", random_string(12)).to_lowercase();
+        admin
+            .batch_execute(&format!("CREATE DATABASE {};", name))
+            .await
+            .unwrap();
+
+        let config: tokio_postgres::Config = format!("{} dbname={}", params, name).parse().unwrap();
+        let manager = deadpool_postgres::Manager::new(config, NoTls);
+        let pool = deadpool_postgres::Pool::builder(manager)
+            .max_size(2)
+            .build()
+            .unwrap();
+
+        Some(TestDb::Postgres {
+            admin,
+            name,
+            storage: Postgres { pool },
+        })
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        match self {
+            TestDb::Postgres { storage, .. } => storage,
+            TestDb::Sqlite { storage, .. } => storage,
+        }
+    }
+
+    pub async fn execute(&self, sql: &'static str) {
+        match self {
+            TestDb::Postgres { storage, .. } => {
+                let client = storage.pool.get().await.unwrap();
+                client.batch_execute(sql).await.unwrap();
+            }
+            TestDb::Sqlite { storage, .. } => {
+                storage
+                    .interact(move |conn| Ok(conn.execute_batch(sql)?))
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    pub async fn drop(self) {
+        match self {
+            TestDb::Postgres {
+                admin,
+                name,
+                storage,
+            } => {
+                storage.pool.close();
+                admin
+                    .batch_execute(&format!("DROP DATABASE {} WITH (FORCE);", name))
+                    .await
+                    .unwrap();
+            }
+            TestDb::Sqlite { path, storage } => {
+                storage.pool.close();
+                let _ = std::fs::remove_file(&path);
+                let _ = std::fs::remove_file(path.with_extension("db-wal"));
+                let _ = std::fs::remove_file(path.with_extension("db-shm"));
+            }
+        }
+    }
+}
+
+pub async fn add_client(storage: &dyn Storage, id: &str, name: &str) {
+    storage
+        .insert_client(&NewClient {
+            id,
+            token: "hash",
+            name,
+            pubkey: None,
+            created: Utc::now(),
+        })
+        .await
+        .unwrap();
+}
+
+pub fn result(search_id: i32, found: &[&str], started: DateTime<Utc>) -> ClientSearchResult {
+    ClientSearchResult {
+        search_id,
+        search_name: "search".to_string(),
+        found: found.iter().map(|line| line.to_string()).collect(),
+        location: "/var/log/syslog".to_string(),
+        started,
+        truncated: false,
+    }
+}
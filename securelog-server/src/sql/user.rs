@@ -1,101 +1,40 @@
-use super::{Result, SqlError, POOL};
+use super::{storage, Result, SqlError};
 use chrono::Utc;
 
 pub async fn user_login(username: &str, passwd: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query(
-            "SELECT * FROM auth WHERE username=$1 LIMIT 1;",
-            &[&username],
-        )
-        .await?;
-
-    if !rows.is_empty() {
-        let enabled: bool = rows[0].get("enabled");
-        if !enabled {
-            warn!("login for {} failed: account not enabled", username);
-            return Err(SqlError::UserDisabled);
-        }
+    match storage().get_user(username).await? {
+        Some(user) => {
+            if !user.enabled {
+                warn!("login for {} failed: account not enabled", username);
+                return Err(SqlError::UserDisabled);
+            }
 
-        let sqlpasswd: String = rows[0].get("passwd");
+            if bcrypt::verify(passwd, &user.passwd)? {
+                warn!("successful login for {}", username);
 
-        if bcrypt::verify(passwd, &sqlpasswd)? {
-            warn!("successful login for {}", username);
+                let result = storage().set_user_last_login(username, Utc::now()).await?;
 
-            let ts = Utc::now();
-            let result = client
-                .execute(
-                    "UPDATE auth SET lastlogin=$1 WHERE username=$2;",
-                    &[&ts, &username],
-                )
-                .await?;
+                if result < 1 {
+                    warn!("failed to update lastlogin for user {}", username);
+                }
 
-            if result < 1 {
-                warn!("failed to update lastlogin for user {}", username);
+                Ok(true)
+            } else {
+                warn!("login for {} failed: wrong password", username);
+                Ok(false)
             }
-
-            Ok(true)
-        } else {
-            warn!("login for {} failed: wrong password", username);
-            Ok(false)
         }
-    } else {
-        warn!("login for {} failed: account does not exist", username);
-        Err(SqlError::UserNotExist)
-    }
-}
-
-pub async fn user_set_enabled(username: &str, enabled: bool) -> Result<()> {
-    let client = POOL.get().await?;
-
-    let result = client
-        .execute(
-            "UPDATE auth SET enabled=$1 WHERE username=$2;",
-            &[&enabled, &username],
-        )
-        .await?;
-
-    if result < 1 {
-        Err(SqlError::UserNotExist)
-    } else {
-        Ok(())
-    }
-}
-
-pub async fn user_enabled(username: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query(
-            "SELECT enabled FROM auth WHERE username=$1 LIMIT 1;",
-            &[&username],
-        )
-        .await?;
-
-    if !rows.is_empty() {
-        let row = &rows[0];
-
-        let enabled: bool = row.get("enabled");
-
-        Ok(enabled)
-    } else {
-        Err(SqlError::UserNotExist)
+        None => {
+            warn!("login for {} failed: account does not exist", username);
+            Err(SqlError::UserNotExist)
+        }
     }
 }
 
 pub async fn user_create(username: &str, password: &str) -> Result<()> {
-    let client = POOL.get().await?;
-
     let sqlpasswd = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
 
-    let result = client
-        .execute(
-            "INSERT INTO auth (username, passwd, enabled)
-        VALUES($1, $2, $3);",
-            &[&username, &sqlpasswd, &true],
-        )
-        .await?;
+    let result = storage().insert_user(username, &sqlpasswd).await?;
 
     if result < 1 {
         return Err(SqlError::UserCreateFailed);
@@ -105,11 +44,5 @@ pub async fn user_create(username: &str, password: &str) -> Result<()> {
 }
 
 pub async fn has_users() -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let rows = client
-        .query("SELECT username FROM auth LIMIT 1;", &[])
-        .await?;
-
-    Ok(rows.len() > 0)
+    storage().has_users().await
 }
@@ -1,20 +1,11 @@
-use super::{random_string, Result, SqlError, POOL};
-use chrono::{DateTime, Utc};
+use super::{storage, Result};
+use utoipa::ToSchema;
 
 pub async fn add_webhook(name: &str, url: &str, username: &str) -> Result<()> {
-    let client = POOL.get().await?;
-
-    let _result = client
-        .execute(
-            "INSERT INTO webhooks (name, url, username) VALUES($1, $2, $3);",
-            &[&name, &url, &username],
-        )
-        .await?;
-
-    Ok(())
+    storage().add_webhook(name, url, username).await
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct Webhook {
     pub name: String,
     pub url: String,
@@ -28,39 +19,14 @@ impl Webhook {
             username: username.to_string(),
         }
     }
-    pub fn get_name(&self) -> String {
-        self.name.to_owned()
-    }
     pub fn get_url(&self) -> String {
         self.url.to_owned()
     }
-    pub fn get_username(&self) -> String {
-        self.username.to_owned()
-    }
 }
 pub async fn get_webhooks() -> Result<Vec<Webhook>> {
-    let client = POOL.get().await?;
-
-    let rows = client.query("SELECT * FROM webhooks;", &[]).await?;
-
-    let mut hooks: Vec<Webhook> = Vec::new();
-    for row in rows {
-        hooks.push(Webhook::new(
-            row.get("name"),
-            row.get("url"),
-            row.get("username"),
-        ));
-    }
-
-    Ok(hooks)
+    storage().get_webhooks().await
 }
 
 pub async fn delete_webhook(name: &str) -> Result<bool> {
-    let client = POOL.get().await?;
-
-    let result = client
-        .execute("DELETE FROM webhooks WHERE name=$1;", &[&name])
-        .await?;
-
-    Ok(result > 0)
+    storage().delete_webhook(name).await
 }
@@ -0,0 +1,587 @@
+/*
+Syslog receiver for devices that can not run a client.
+
+With syslog_udp, syslog_tcp or syslog_tls set to an address the server
+listens for syslog there: UDP one message a datagram, TCP as in RFC 6587
+and TLS as in RFC 5425 with the server's cert, both framed by octet counts
+or line ends. Every sending host is a client named syslog/<host>, created
+with the tag source=syslog on its first message. The host is the HOSTNAME
+of the message, so relays can forward for many hosts, or the address it
+came from if it has none; anything that can reach the listener can send as
+any host. Past syslog_max_hosts hosts, messages from hosts without a client
+share one client, see OTHER_HOSTS, so spoofed host names can not create
+clients without end.
+
+The enabled searches whose selector matches a host's client run over its
+messages through their syslog:// locations, see securelog_search::syslog.
+Matches are stored as search results every FLUSH_INTERVAL, as an agent
+sends them. A host that stops sending is alerted about like a client that
+missed its check-in, disabling its client ignores it.
+*/
+use crate::models::{ClientSearchResult, Search, TagSource};
+use crate::sql::client::ClientInventory;
+use crate::{conf, sql, web, webhooks};
+use securelog_search::{Matcher, SyslogMessage, SyslogQuery};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::TlsAcceptor;
+
+pub const CLIENT_PREFIX: &str = "syslog/";
+
+// RFC 5425 asks for 8192, UDP can not carry more than this
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+// digits of an octet count up to MAX_MESSAGE_SIZE
+const MAX_COUNT_DIGITS: u64 = 5;
+const MAX_HOST_LENGTH: usize = 255;
+// messages waiting to be searched, UDP drops what does not fit
+const QUEUE_SIZE: usize = 10_000;
+// how often matches are stored
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+// how often clients and searches are read again
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// hosts that get a client between refreshes, more go to OTHER_HOSTS until the next
+const MAX_NEW_CLIENTS: usize = 100;
+// hosts that get their own client, unless syslog_max_hosts says otherwise
+const DEFAULT_MAX_HOSTS: usize = 1000;
+// the host of the client shared by hosts past the limits, host_name never
+// returns it
+const OTHER_HOSTS: &str = "*";
+// open TCP and TLS connections, more are closed as they come in
+const MAX_CONNECTIONS: usize = 1000;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// a connection that sends no message for this long is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// lines in one result, past it the result is truncated
+const MAX_FOUND: usize = 1000;
+
+pub fn is_enabled() -> bool {
+    conf::get_syslog_udp().is_ok()
+        || conf::get_syslog_tcp().is_ok()
+        || conf::get_syslog_tls().is_ok()
+}
+
+#[derive(Debug)]
+struct Received {
+    peer: IpAddr,
+    text: String,
+}
+
+/**
+ * The host a message is from, its HOSTNAME if that looks like a host name.
+ */
+fn host_name(message: &SyslogMessage, peer: IpAddr) -> String {
+    message
+        .hostname
+        .as_deref()
+        .filter(|host| {
+            host.len() <= MAX_HOST_LENGTH
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':'))
+        })
+        .map(str::to_lowercase)
+        .unwrap_or_else(|| peer.to_string())
+}
+
+/**
+ * The next message of a stream, RFC 6587 octet counting ("12 <34>...") if
+ * it starts with a digit, ended by a line end otherwise. Longer lines are
+ * cut into messages of MAX_MESSAGE_SIZE. None at the end of the stream.
+ */
+async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let counted = match reader.fill_buf().await?.first() {
+        Some(first) => first.is_ascii_digit(),
+        None => return Ok(None),
+    };
+
+    let mut frame = Vec::new();
+    if counted {
+        let mut count = Vec::new();
+        (&mut *reader)
+            .take(MAX_COUNT_DIGITS + 1)
+            .read_until(b' ', &mut count)
+            .await?;
+        let count = std::str::from_utf8(&count)
+            .ok()
+            .and_then(|count| count.strip_suffix(' '))
+            .and_then(|count| count.parse::<usize>().ok())
+            .filter(|count| *count <= MAX_MESSAGE_SIZE)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "bad octet count"))?;
+        frame.resize(count, 0);
+        reader.read_exact(&mut frame).await?;
+    } else {
+        (&mut *reader)
+            .take(MAX_MESSAGE_SIZE as u64)
+            .read_until(b'\n', &mut frame)
+            .await?;
+    }
+
+    Ok(Some(frame))
+}
+
+async fn read_frames<S: AsyncRead + Unpin>(
+    stream: S,
+    peer: IpAddr,
+    sender: &mpsc::Sender<Received>,
+    idle: Duration,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let frame = match actix_web::rt::time::timeout(idle, read_frame(&mut reader)).await {
+            Ok(frame) => frame?,
+            Err(_) => return Err(io::Error::new(ErrorKind::TimedOut, "idle connection")),
+        };
+        let Some(frame) = frame else {
+            break;
+        };
+        let text = String::from_utf8_lossy(&frame).into_owned();
+        if text.trim().is_empty() {
+            continue;
+        }
+        sender
+            .send(Received { peer, text })
+            .await
+            .map_err(|_| io::Error::other("syslog receiver stopped"))?;
+    }
+
+    Ok(())
+}
+
+async fn receive_udp(socket: UdpSocket, sender: mpsc::Sender<Received>) {
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    let mut dropped: u64 = 0;
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("error receiving syslog: {}", e);
+                continue;
+            }
+        };
+        let received = Received {
+            peer: peer.ip(),
+            text: String::from_utf8_lossy(&buf[..len]).into_owned(),
+        };
+        if sender.try_send(received).is_err() {
+            dropped += 1;
+            if dropped.is_power_of_two() {
+                warn!("syslog queue is full, {} UDP messages dropped", dropped);
+            }
+        }
+    }
+}
+
+async fn accept_tcp(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    sender: mpsc::Sender<Received>,
+    connections: Arc<Semaphore>,
+) {
+    let mut refused: u64 = 0;
+    loop {
+        let (stream, peer): (_, SocketAddr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("error accepting syslog connection: {}", e);
+                continue;
+            }
+        };
+        // dropping the stream closes it
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            refused += 1;
+            if refused.is_power_of_two() {
+                warn!(
+                    "{} syslog connections open, {} refused",
+                    MAX_CONNECTIONS, refused
+                );
+            }
+            continue;
+        };
+        let tls = tls.clone();
+        let sender = sender.clone();
+        actix_web::rt::spawn(async move {
+            let _permit = permit;
+            let result = match tls {
+                Some(tls) => {
+                    match actix_web::rt::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            read_frames(stream, peer.ip(), &sender, IDLE_TIMEOUT).await
+                        }
+                        Ok(Err(e)) => Err(e),
+                        Err(_) => Err(io::Error::new(ErrorKind::TimedOut, "TLS handshake")),
+                    }
+                }
+                None => read_frames(stream, peer.ip(), &sender, IDLE_TIMEOUT).await,
+            };
+            if let Err(e) = result {
+                debug!("syslog connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+/**
+ * A search compiled for the messages of a client.
+ */
+struct SyslogSearch {
+    search: Search,
+    matcher: Matcher,
+    // location and what it selects
+    queries: Vec<(String, SyslogQuery)>,
+}
+impl SyslogSearch {
+    fn new(search: Search) -> Option<SyslogSearch> {
+        let matcher = match Matcher::new(&search.stype, &search.search) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                warn!("search {} does not compile: {}", search.id, e);
+                return None;
+            }
+        };
+        let queries = search
+            .locations
+            .iter()
+            .filter_map(|location| match SyslogQuery::parse(location) {
+                Ok(query) => Some((location.clone(), query)),
+                Err(e) => {
+                    warn!("search {} has a bad location: {}", search.id, e);
+                    None
+                }
+            })
+            .collect();
+
+        Some(SyslogSearch {
+            search,
+            matcher,
+            queries,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Receiver {
+    // hosts that get their own client, the rest share OTHER_HOSTS
+    max_hosts: usize,
+    // host to client id, None for disabled clients
+    clients: HashMap<String, Option<String>>,
+    new_clients: usize,
+    // by client id, read again after every refresh
+    searches: HashMap<String, Vec<SyslogSearch>>,
+    // matches not stored yet, by client, search and location
+    pending: BTreeMap<(String, i32, String), ClientSearchResult>,
+    // clients that sent something since the last flush
+    seen: BTreeSet<String>,
+}
+impl Receiver {
+    fn new(max_hosts: usize) -> Receiver {
+        Receiver {
+            max_hosts,
+            ..Default::default()
+        }
+    }
+
+    async fn refresh(&mut self) -> sql::Result<()> {
+        self.clients = sql::client::get_clients()
+            .await?
+            .into_iter()
+            .filter_map(|client| {
+                let host = client.name.strip_prefix(CLIENT_PREFIX)?.to_string();
+                Some((host, client.enabled.then_some(client.id)))
+            })
+            .collect();
+        self.new_clients = 0;
+        self.searches.clear();
+
+        Ok(())
+    }
+
+    async fn client(&mut self, host: &str) -> sql::Result<Option<String>> {
+        if let Some(client) = self.clients.get(host) {
+            return Ok(client.clone());
+        }
+
+        let hosts = self
+            .clients
+            .keys()
+            .filter(|known| *known != OTHER_HOSTS)
+            .count();
+        if hosts >= self.max_hosts || self.new_clients >= MAX_NEW_CLIENTS {
+            debug!(
+                "too many syslog hosts, {} goes to {}{}",
+                host, CLIENT_PREFIX, OTHER_HOSTS
+            );
+            match self.clients.get(OTHER_HOSTS) {
+                Some(client) => Ok(client.clone()),
+                None => self.create_client(OTHER_HOSTS).await,
+            }
+        } else {
+            self.new_clients += 1;
+            self.create_client(host).await
+        }
+    }
+
+    async fn create_client(&mut self, host: &str) -> sql::Result<Option<String>> {
+        let client = sql::client::client_auth_create(&format!("{}{}", CLIENT_PREFIX, host), None)
+            .await?
+            .id;
+        sql::client::set_client_tags(
+            &client,
+            TagSource::Client,
+            &[("source".to_string(), "syslog".to_string())],
+        )
+        .await?;
+        sql::client::set_client_inventory(
+            &client,
+            &ClientInventory {
+                hostname: (host != OTHER_HOSTS).then(|| host.to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        info!("created client {} for syslog host {}", client, host);
+
+        self.clients.insert(host.to_string(), Some(client.clone()));
+        Ok(Some(client))
+    }
+
+    async fn receive(&mut self, received: Received) -> sql::Result<()> {
+        let message = SyslogMessage::parse(&received.text);
+        let host = host_name(&message, received.peer);
+        let Some(client) = self.client(&host).await? else {
+            return Ok(());
+        };
+        self.seen.insert(client.clone());
+
+        if !self.searches.contains_key(&client) {
+            let searches = sql::get_syslog_searches(&client)
+                .await?
+                .into_iter()
+                .filter_map(SyslogSearch::new)
+                .collect();
+            self.searches.insert(client.clone(), searches);
+        }
+
+        for search in &self.searches[&client] {
+            if !search.matcher.is_match(&message.line) {
+                continue;
+            }
+            for (location, _) in search
+                .queries
+                .iter()
+                .filter(|(_, query)| query.matches(&message))
+            {
+                let result = self
+                    .pending
+                    .entry((client.clone(), search.search.id, location.clone()))
+                    .or_insert_with(|| {
+                        ClientSearchResult::new(search.search.id, &search.search.name, location)
+                    });
+                if result.found.len() < MAX_FOUND {
+                    result.found.push(message.line.clone());
+                } else {
+                    result.truncated = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Store the matches and check-ins. What fails to be stored is kept for
+     * the next flush.
+     */
+    async fn flush(&mut self) -> sql::Result<()> {
+        let mut seen = std::mem::take(&mut self.seen).into_iter();
+        while let Some(client) = seen.next() {
+            if let Err(e) = sql::client::client_seen(&client).await {
+                self.seen.insert(client);
+                self.seen.extend(seen);
+                return Err(e);
+            }
+        }
+
+        let mut pending = std::mem::take(&mut self.pending);
+        let mut clients = BTreeSet::new();
+        let mut stored = Ok(());
+        while let Some((key, result)) = pending.pop_first() {
+            if let Err(e) = sql::insert_client_search_result(&key.0, &result, None).await {
+                pending.insert(key, result);
+                self.pending.append(&mut pending);
+                stored = Err(e);
+                break;
+            }
+            clients.insert(key.0);
+        }
+        for client in clients {
+            let message = format!("New scan results for client {} received", client);
+            webhooks::send_message(&message).await?;
+        }
+
+        stored
+    }
+}
+
+async fn receiver_task(mut messages: mpsc::Receiver<Received>) {
+    let max_hosts = match conf::get_syslog_max_hosts() {
+        Ok(max) => max.max(0) as usize,
+        Err(_) => DEFAULT_MAX_HOSTS,
+    };
+    let mut receiver = Receiver::new(max_hosts);
+    let mut flush = actix_web::rt::time::interval(FLUSH_INTERVAL);
+    let mut refresh = actix_web::rt::time::interval(REFRESH_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            Some(received) = messages.recv() => receiver.receive(received).await,
+            _ = flush.tick() => receiver.flush().await,
+            _ = refresh.tick() => receiver.refresh().await,
+        };
+        if let Err(e) = result {
+            warn!("error handling syslog: {}", e);
+        }
+    }
+}
+
+// the address in bind errors, "address in use" alone does not say which
+fn bind_error(address: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", address, e))
+}
+
+/**
+ * Listen on the configured syslog addresses, the receiving runs in
+ * background tasks for the lifetime of the server.
+ */
+pub async fn start() -> io::Result<()> {
+    let (sender, messages) = mpsc::channel(QUEUE_SIZE);
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+
+    if let Ok(address) = conf::get_syslog_udp() {
+        let socket = UdpSocket::bind(&address)
+            .await
+            .map_err(|e| bind_error(&address, e))?;
+        info!("receiving syslog on udp {}", address);
+        actix_web::rt::spawn(receive_udp(socket, sender.clone()));
+    }
+    if let Ok(address) = conf::get_syslog_tcp() {
+        let listener = TcpListener::bind(&address)
+            .await
+            .map_err(|e| bind_error(&address, e))?;
+        info!("receiving syslog on tcp {}", address);
+        actix_web::rt::spawn(accept_tcp(
+            listener,
+            None,
+            sender.clone(),
+            connections.clone(),
+        ));
+    }
+    if let Ok(address) = conf::get_syslog_tls() {
+        let listener = TcpListener::bind(&address)
+            .await
+            .map_err(|e| bind_error(&address, e))?;
+        let acceptor = TlsAcceptor::from(Arc::new(web::tls_config()));
+        info!("receiving syslog on tls {}", address);
+        actix_web::rt::spawn(accept_tcp(listener, Some(acceptor), sender, connections));
+    }
+    actix_web::rt::spawn(receiver_task(messages));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn frames(mut stream: &[u8]) -> Vec<String> {
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame(&mut stream).await.unwrap() {
+            frames.push(String::from_utf8(frame).unwrap());
+        }
+        frames
+    }
+
+    #[actix_web::test]
+    async fn frames_are_counted_or_lines() {
+        assert_eq!(
+            frames(b"11 <13>1 - - x10 <13>hello\n<13>line one\n<13>line two").await,
+            vec![
+                "<13>1 - - x",
+                "<13>hello\n",
+                "<13>line one\n",
+                "<13>line two"
+            ]
+        );
+        assert!(frames(b"").await.is_empty());
+
+        let mut too_long: &[u8] = b"99999999 <13>x";
+        assert!(read_frame(&mut too_long).await.is_err());
+        let mut cut: &[u8] = b"20 <13>short";
+        assert!(read_frame(&mut cut).await.is_err());
+    }
+
+    #[test]
+    fn hosts_are_named() {
+        let peer: IpAddr = "10.0.0.9".parse().unwrap();
+
+        let message = SyslogMessage::parse("<34>Oct 11 22:14:15 SW01.example su: failed");
+        assert_eq!(host_name(&message, peer), "sw01.example");
+        let message = SyslogMessage::parse("<34>su: failed");
+        assert_eq!(host_name(&message, peer), "10.0.0.9");
+        let message = SyslogMessage::parse("<13>1 - ../etc/passwd app - - - x");
+        assert_eq!(host_name(&message, peer), "10.0.0.9");
+    }
+
+    #[actix_web::test]
+    async fn idle_connections_are_closed() {
+        use tokio::io::AsyncWriteExt;
+
+        let peer: IpAddr = "10.0.0.9".parse().unwrap();
+        let (sender, mut messages) = mpsc::channel(10);
+        let (mut device, stream) = tokio::io::duplex(1024);
+        device.write_all(b"<13>first\n<13>cut").await.unwrap();
+
+        let err = read_frames(stream, peer, &sender, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(messages.recv().await.unwrap().text, "<13>first\n");
+        assert!(messages.try_recv().is_err());
+        drop(device);
+    }
+
+    #[actix_web::test]
+    async fn hosts_past_the_limit_share_a_client() {
+        sql::connect_test().await;
+        let mut receiver = Receiver::new(2);
+
+        let sw01 = receiver.client("sw01").await.unwrap().unwrap();
+        let sw02 = receiver.client("sw02").await.unwrap().unwrap();
+        let sw03 = receiver.client("sw03").await.unwrap().unwrap();
+        assert_ne!(sw01, sw02);
+        assert_ne!(sw03, sw01);
+        assert_ne!(sw03, sw02);
+        assert_eq!(receiver.client("sw04").await.unwrap().unwrap(), sw03);
+        assert_eq!(receiver.client("sw01").await.unwrap().unwrap(), sw01);
+
+        // the limit holds across refreshes
+        receiver.refresh().await.unwrap();
+        assert_eq!(receiver.client("sw05").await.unwrap().unwrap(), sw03);
+        assert_eq!(receiver.client("sw02").await.unwrap().unwrap(), sw02);
+
+        let names: Vec<String> = sql::client::get_clients()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|client| client.name)
+            .filter(|name| name.starts_with(CLIENT_PREFIX))
+            .collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&format!("{}{}", CLIENT_PREFIX, OTHER_HOSTS)));
+    }
+}
@@ -1,27 +1,100 @@
 use super::{client_logged_in, user_logged_in};
-use crate::models::ClientSearchResult;
-use crate::sql;
+use crate::models::{ClientSearchResult, SearchRunReport, TagSource};
+use crate::sql::client::ClientInventory;
+use crate::{archive, conf, integrity, signing, sql};
 use actix_identity::Identity;
+use actix_web::http::StatusCode;
 use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Result};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
+use securelog_proto::{
+    negotiate, parse_tags, LoginResponse, ShouldRunResponse, SIGNATURE_HEADER,
+    SIGNATURE_TIME_HEADER,
+};
 
 #[derive(Debug, Deserialize)]
 struct ClientLogin {
     id: String,
     token: String,
+    // clients from before version negotiation speak version 1 and send neither
+    protocol: Option<u32>,
+    min_protocol: Option<u32>,
+    // comma separated key=value tags from the client config, older clients send none
+    tags: Option<String>,
+    // what the client runs, older clients send some or none of these
+    version: Option<String>,
+    git_sha: Option<String>,
+    os: Option<String>,
+    hostname: Option<String>,
+    // comma separated, see securelog_proto::capability
+    capabilities: Option<String>,
 }
+impl ClientLogin {
+    fn inventory(&self) -> Option<ClientInventory> {
+        // clients that send no version send none of it
+        let version = self.version.clone()?;
+
+        Some(ClientInventory {
+            agent_version: Some(version),
+            git_sha: self.git_sha.clone(),
+            os: self.os.clone(),
+            hostname: self.hostname.clone(),
+            capabilities: sql::client::capabilities_from_sql(self.capabilities.clone()),
+        })
+    }
+}
+
+/**
+ * Store what a client reports about itself at login.
+ */
+async fn record_login(
+    client_id: &str,
+    tags: Option<&[(String, String)]>,
+    inventory: Option<&ClientInventory>,
+) -> sql::Result<()> {
+    if let Some(tags) = tags {
+        sql::client::set_client_tags(client_id, TagSource::Client, tags).await?;
+    }
+    if let Some(inventory) = inventory {
+        sql::client::set_client_inventory(client_id, inventory).await?;
+    }
+
+    Ok(())
+}
+/**
+ * Log a client in and agree on the protocol version. A client that speaks
+ * no version the server does is refused with 426 and the reason.
+ */
 #[post("/api/client/login")]
 async fn api_client_login(
     request: HttpRequest,
     params: web::Form<ClientLogin>,
     id: Option<Identity>,
 ) -> actix_web::Result<HttpResponse> {
+    let peer = params.protocol.unwrap_or(1);
+    let protocol = match negotiate(peer, params.min_protocol.unwrap_or(peer)) {
+        Ok(protocol) => protocol,
+        Err(e) => {
+            warn!("refusing login from client {}: {}", params.id, e);
+            return Ok(HttpResponse::build(StatusCode::UPGRADE_REQUIRED).body(e.to_string()));
+        }
+    };
+    let tags = match params.tags.as_deref().map(parse_tags).transpose() {
+        Ok(tags) => tags,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+
     if let Some(client_id) = client_logged_in(id) {
-        Ok(HttpResponse::Ok().body("Client already logged in"))
+        sql::client::client_seen(&client_id).await?;
+        record_login(&client_id, tags.as_deref(), params.inventory().as_ref()).await?;
+
+        Ok(HttpResponse::Ok().json(LoginResponse { protocol }))
     } else if sql::client::client_authenticate(&params.id, &params.token).await? {
-        Identity::login(&request.extensions(), format!("user:{}", &params.id))?;
+        Identity::login(&request.extensions(), format!("client:{}", &params.id))?;
+        debug!("client {} logged in with protocol {}", params.id, protocol);
 
-        Ok(HttpResponse::Ok().body("Logged in successfully"))
+        record_login(&params.id, tags.as_deref(), params.inventory().as_ref()).await?;
+
+        Ok(HttpResponse::Ok().json(LoginResponse { protocol }))
     } else {
         Ok(HttpResponse::Unauthorized().body("Login failed"))
     }
@@ -42,11 +115,18 @@ struct ClientCreate {
     username: String,
     password: String,
     name: String,
+    pubkey: String,
 }
 #[post("/api/client/create")]
 async fn api_client_create(params: web::Form<ClientCreate>) -> Result<HttpResponse> {
     if sql::user::user_login(&params.username, &params.password).await? {
-        let client: sql::client::ClientAuth = sql::client::client_auth_create(&params.name).await?;
+        if let Err(e) = signing::parse_public_key(&params.pubkey) {
+            warn!("client {} sent an invalid public key: {}", params.name, e);
+            return Ok(HttpResponse::BadRequest().body("Invalid public key"));
+        }
+
+        let client: sql::client::ClientAuth =
+            sql::client::client_auth_create(&params.name, Some(&params.pubkey)).await?;
 
         Ok(HttpResponse::Ok().body(serde_json::to_string(&client)?))
     } else {
@@ -54,6 +134,76 @@ async fn api_client_create(params: web::Form<ClientCreate>) -> Result<HttpRespon
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct ClientRegisterKey {
+    id: String,
+    token: String,
+    pubkey: String,
+}
+/**
+ * Register a public key for a client created before result signing.
+ * Takes the client's token rather than its session, and a registered key
+ * is never replaced, so a stolen session cookie can't swap in its own key.
+ */
+#[post("/api/client/register_key")]
+async fn api_client_register_key(params: web::Form<ClientRegisterKey>) -> Result<HttpResponse> {
+    if !sql::client::client_authenticate(&params.id, &params.token).await? {
+        return Ok(HttpResponse::Unauthorized().body("Login failed"));
+    }
+    if let Err(e) = signing::parse_public_key(&params.pubkey) {
+        warn!("client {} sent an invalid public key: {}", params.id, e);
+        return Ok(HttpResponse::BadRequest().body("Invalid public key"));
+    }
+
+    if sql::client::set_client_pubkey(&params.id, &params.pubkey).await? {
+        info!("client {} registered its public key", params.id);
+        Ok(HttpResponse::Ok().body("Public key registered"))
+    } else {
+        warn!(
+            "refusing key of client {}, it already has one registered",
+            params.id
+        );
+        Ok(HttpResponse::Conflict().body("Client already has a public key"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientEnroll {
+    // an enrollment token an admin minted, see sql::enrollment
+    token: String,
+    name: String,
+    pubkey: String,
+}
+/**
+ * Create a client with an enrollment token instead of admin credentials,
+ * for `securelog-client enroll`. Answers like /api/client/create.
+ */
+#[post("/api/client/enroll")]
+async fn api_client_enroll(params: web::Form<ClientEnroll>) -> Result<HttpResponse> {
+    if params.name.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("Client name must not be empty"));
+    }
+    if let Err(e) = signing::parse_public_key(&params.pubkey) {
+        warn!("client {} sent an invalid public key: {}", params.name, e);
+        return Ok(HttpResponse::BadRequest().body("Invalid public key"));
+    }
+
+    match sql::client::client_enroll(&params.token, &params.name, Some(&params.pubkey)).await {
+        Ok(Some(client)) => Ok(HttpResponse::Ok().json(client)),
+        Ok(None) => {
+            warn!(
+                "refusing enrollment of client {}: unknown, used up or expired token",
+                params.name
+            );
+            Ok(HttpResponse::Unauthorized().body("Invalid or expired enrollment token"))
+        }
+        Err(sql::SqlError::ClientNameExists(name)) => {
+            Ok(HttpResponse::Conflict().body(format!("Client name {} already exists", name)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ClientSetEnabled {
     pub id: String,
@@ -80,8 +230,8 @@ async fn api_client_set_enabled(
 
 #[get("/api/client/get_searches")]
 async fn api_client_get_searches(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
-    if let Some(_username) = client_logged_in(id) {
-        let searches = sql::get_searches().await?;
+    if let Some(client_id) = client_logged_in(id) {
+        let searches = sql::get_client_searches(&client_id).await?;
 
         Ok(HttpResponse::Ok()
             .content_type("application/json")
@@ -91,9 +241,81 @@ async fn api_client_get_searches(id: Option<Identity>) -> actix_web::Result<Http
     }
 }
 
+/**
+ * The signature and its time sent in the headers of a request with a raw body.
+ */
+fn signature_headers(request: &HttpRequest) -> (Option<&str>, Option<i64>) {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    (
+        header(SIGNATURE_HEADER),
+        header(SIGNATURE_TIME_HEADER).and_then(|time| time.trim().parse().ok()),
+    )
+}
+
+/**
+ * Check the signature a client sent with a payload against its registered
+ * public key. Clients must sign everything they send along with the time,
+ * a session cookie alone is not enough to submit data for a client and a
+ * signed request can't be sent twice. Clients created before result signing
+ * have no key, their unsigned data is only taken with require_signatures
+ * turned off, until they register one.
+ * Returns the verified signature, or the response to reject the request with.
+ */
+async fn verify_client_signature<'a>(
+    client_id: &str,
+    data: &[u8],
+    signature: Option<&'a str>,
+    time: Option<i64>,
+) -> actix_web::Result<std::result::Result<Option<&'a str>, HttpResponse>> {
+    match sql::client::get_client_pubkey(client_id).await? {
+        Some(pubkey) => match signature {
+            Some(signature) => {
+                if let Err(e) = signing::check_signature(&pubkey, data, signature, time) {
+                    warn!("bad signature on data from client {}: {}", client_id, e);
+                    sql::client::set_client_error(client_id, &format!("invalid signature: {}", e))
+                        .await?;
+                    return Ok(Err(HttpResponse::Unauthorized().body("Invalid signature")));
+                }
+                Ok(Ok(Some(signature)))
+            }
+            None => {
+                warn!("unsigned data from client {}", client_id);
+                sql::client::set_client_error(client_id, "missing signature").await?;
+                Ok(Err(HttpResponse::Unauthorized().body("Missing signature")))
+            }
+        },
+        None if conf::get_require_signatures().unwrap_or(true) => {
+            warn!(
+                "client {} has no public key, refusing unsigned data",
+                client_id
+            );
+            sql::client::set_client_error(client_id, "missing signature, no public key registered")
+                .await?;
+            Ok(Err(HttpResponse::Unauthorized().body("Missing signature")))
+        }
+        None => {
+            warn!(
+                "client {} has no public key, accepting unsigned data",
+                client_id
+            );
+            Ok(Ok(None))
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ClientSendSearchResults {
     results: String,
+    // base64 Ed25519 signature over `signature_time` and `results`
+    signature: Option<String>,
+    // unix time the signature was made at
+    signature_time: Option<i64>,
 }
 #[post("/api/client/send_search_results")]
 async fn api_client_send_search_results(
@@ -101,9 +323,32 @@ async fn api_client_send_search_results(
     id: Option<Identity>,
 ) -> actix_web::Result<HttpResponse> {
     if let Some(client_id) = client_logged_in(id) {
-        let results: Vec<ClientSearchResult> = serde_json::from_str(&params.results)?;
+        let signature = match verify_client_signature(
+            &client_id,
+            params.results.as_bytes(),
+            params.signature.as_deref(),
+            params.signature_time,
+        )
+        .await?
+        {
+            Ok(signature) => signature,
+            Err(response) => return Ok(response),
+        };
+
+        let results: Vec<ClientSearchResult> = match serde_json::from_str(&params.results) {
+            Ok(results) => results,
+            Err(e) => {
+                warn!("invalid search results from client {}: {}", client_id, e);
+                sql::client::set_client_error(
+                    &client_id,
+                    &format!("invalid search results: {}", e),
+                )
+                .await?;
+                return Ok(HttpResponse::BadRequest().body("Invalid search results"));
+            }
+        };
         for result in &results {
-            sql::insert_client_search_result(&client_id, result).await?;
+            sql::insert_client_search_result(&client_id, result, signature).await?;
         }
 
         let message = format!("New scan results for client {} received", client_id);
@@ -115,6 +360,102 @@ async fn api_client_send_search_results(
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct ClientSendRunReports {
+    reports: String,
+    // base64 Ed25519 signature over `signature_time` and `reports`
+    signature: Option<String>,
+    // unix time the signature was made at
+    signature_time: Option<i64>,
+}
+/**
+ * Receive how every search went in a client run. The first failure is
+ * kept as the client's last error.
+ */
+#[post("/api/client/send_run_reports")]
+async fn api_client_send_run_reports(
+    params: web::Form<ClientSendRunReports>,
+    id: Option<Identity>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(client_id) = client_logged_in(id) {
+        if let Err(response) = verify_client_signature(
+            &client_id,
+            params.reports.as_bytes(),
+            params.signature.as_deref(),
+            params.signature_time,
+        )
+        .await?
+        {
+            return Ok(response);
+        }
+
+        let reports: Vec<SearchRunReport> = match serde_json::from_str(&params.reports) {
+            Ok(reports) => reports,
+            Err(e) => {
+                warn!("invalid run reports from client {}: {}", client_id, e);
+                return Ok(HttpResponse::BadRequest().body("Invalid run reports"));
+            }
+        };
+        sql::runs::set_search_runs(&client_id, &reports).await?;
+
+        if let Some((report, error)) = reports
+            .iter()
+            .find_map(|report| report.error.as_ref().map(|error| (report, error)))
+        {
+            let message = format!(
+                "search {} on {}: {}",
+                report.search_id, report.location, error.message
+            );
+            sql::client::set_client_error(&client_id, &message).await?;
+        }
+
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::Unauthorized().body("Unauthorized"))
+    }
+}
+
+/**
+ * Receive the files a client watches as zstd compressed json, see
+ * integrity.rs. The body is signed like archive segments, the signature and its
+ * time are sent in the X-Signature and X-Signature-Time headers.
+ */
+#[post("/api/client/send_integrity_snapshot")]
+async fn api_client_send_integrity_snapshot(
+    request: HttpRequest,
+    body: web::Bytes,
+    id: Option<Identity>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(client_id) = client_logged_in(id) {
+        let (signature, time) = signature_headers(&request);
+        if let Err(response) = verify_client_signature(&client_id, &body, signature, time).await? {
+            return Ok(response);
+        }
+
+        // a few MB of json for a large snapshot, decompressed off the workers
+        let snapshot = match web::block(move || integrity::decode_snapshot(&body)).await? {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!(
+                    "invalid integrity snapshot from client {}: {}",
+                    client_id, e
+                );
+                sql::client::set_client_error(
+                    &client_id,
+                    &format!("invalid integrity snapshot: {}", e),
+                )
+                .await?;
+                return Ok(HttpResponse::BadRequest().body("Invalid integrity snapshot"));
+            }
+        };
+        integrity::record_snapshot(&client_id, &snapshot).await?;
+
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::Unauthorized().body("Unauthorized"))
+    }
+}
+
 #[get("/api/client/notify_running")]
 async fn api_client_notify_running(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
     if let Some(client_id) = client_logged_in(id) {
@@ -129,30 +470,329 @@ async fn api_client_notify_running(id: Option<Identity>) -> actix_web::Result<Ht
 #[get("/api/client/should_run")]
 async fn api_client_should_run(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
     if let Some(client_id) = client_logged_in(id) {
+        // clients poll this every minute, it is their check-in
+        sql::client::client_seen(&client_id).await?;
         let schedule = sql::get_scan_schedule().await?;
         let lastrun = sql::client::get_client_last_run(&client_id).await?;
 
-        if lastrun.manualrun {
-            let data = json!({
-                "should_run": "true"
-            });
-            Ok(HttpResponse::Ok()
-                .content_type("application/json")
-                .body(serde_json::to_string(&data)?))
+        let should_run = if lastrun.manualrun {
+            true
         } else {
             let nextrun =
                 lastrun.lastrun + chrono::Duration::from_std(schedule.get_interval()).unwrap();
 
             // should_run should be false every time if in manual mode
-            let should_run = nextrun < Utc::now() && !schedule.is_manual();
+            nextrun < Utc::now() && !schedule.is_manual()
+        };
 
-            let data = json!({ "should_run": should_run }).to_string();
+        // settings ride along so clients pick up changes without a restart
+        let settings = sql::client::effective_settings(&client_id).await?;
 
-            Ok(HttpResponse::Ok()
-                .content_type("application/json")
-                .body(data))
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(ShouldRunResponse {
+                should_run,
+                settings: Some(settings),
+            }))
+    } else {
+        Ok(HttpResponse::Unauthorized().body("Unauthorized"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientArchiveSegment {
+    location: String,
+    offset: i64,
+}
+/**
+ * Receive a zstd compressed raw log segment from a client running with
+ * archive enabled. The body is signed like search results, the signature and its
+ * time are sent in the X-Signature and X-Signature-Time headers.
+ */
+#[post("/api/client/archive_segment")]
+async fn api_client_archive_segment(
+    request: HttpRequest,
+    params: web::Query<ClientArchiveSegment>,
+    body: web::Bytes,
+    id: Option<Identity>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(client_id) = client_logged_in(id) {
+        if !archive::is_enabled() {
+            return Ok(HttpResponse::NotFound().body("Archive not enabled"));
+        }
+
+        let (signature, time) = signature_headers(&request);
+        if let Err(response) = verify_client_signature(&client_id, &body, signature, time).await? {
+            return Ok(response);
         }
+
+        let segment =
+            archive::store_segment(&client_id, &params.location, params.offset, body).await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&segment))
     } else {
         Ok(HttpResponse::Unauthorized().body("Unauthorized"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{FileState, IntegritySnapshot};
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, test, App};
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use chrono::DateTime;
+    use ed25519_dalek::{Signer, SigningKey};
+    use sha2::{Digest, Sha256};
+
+    fn snapshot(files: usize, changed: &str) -> IntegritySnapshot {
+        IntegritySnapshot {
+            started: Utc::now(),
+            paths: vec!["/etc".to_string()],
+            files: (0..files)
+                .map(|i| {
+                    let path = format!("/etc/conf.d/file{}.conf", i);
+                    let content = if path == changed {
+                        "changed".to_string()
+                    } else {
+                        path.clone()
+                    };
+                    FileState {
+                        sha256: Some(format!("{:x}", Sha256::digest(content.as_bytes()))),
+                        path,
+                        mode: 0o100644,
+                        uid: 0,
+                        gid: 0,
+                        size: 100,
+                        mtime: DateTime::UNIX_EPOCH,
+                        link: None,
+                    }
+                })
+                .collect(),
+            unreadable: Vec::new(),
+        }
+    }
+
+    fn login(auth: &serde_json::Value) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/api/client/login")
+            .set_form([
+                ("id", auth["id"].as_str().unwrap()),
+                ("token", auth["token"].as_str().unwrap()),
+            ])
+    }
+
+    #[actix_web::test]
+    async fn large_integrity_snapshots_are_received() {
+        sql::connect_test().await;
+        let key = SigningKey::from_bytes(&[9; 32]);
+        let pubkey = BASE64.encode(key.verifying_key().to_bytes());
+        let auth = sql::client::client_auth_create("integrity_snapshot_test", Some(&pubkey))
+            .await
+            .unwrap();
+        let auth = serde_json::to_value(&auth).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(super::super::payload_config())
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(api_client_login)
+                .service(api_client_send_integrity_snapshot),
+        )
+        .await;
+
+        let response = test::call_service(&app, login(&auth).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let send = |body: Vec<u8>| {
+            let now = Utc::now().timestamp();
+            let signature = key.sign(&securelog_proto::signed_message(now, &body));
+            test::TestRequest::post()
+                .uri("/api/client/send_integrity_snapshot")
+                .cookie(cookie.clone())
+                .insert_header((SIGNATURE_HEADER, BASE64.encode(signature.to_bytes())))
+                .insert_header((SIGNATURE_TIME_HEADER, now.to_string()))
+                .set_payload(body)
+                .to_request()
+        };
+
+        // the first snapshot is the baseline, the second has one change
+        for (changed, changes) in [("", 0), ("/etc/conf.d/file1234.conf", 1)] {
+            let json = serde_json::to_vec(&snapshot(5000, changed)).unwrap();
+            // far past the 16 KiB actix takes in a form
+            assert!(json.len() > 512 * 1024);
+            let body = zstd::encode_all(json.as_slice(), 3).unwrap();
+
+            let response = test::call_service(&app, send(body)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let id = auth["id"].as_str().unwrap();
+            let baseline = sql::integrity::get_integrity_baseline(id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(baseline.files.len(), 5000);
+            let stored = sql::integrity::get_integrity_changes(Some(id), None)
+                .await
+                .unwrap();
+            assert_eq!(stored.len(), changes);
+        }
+
+        let response = test::call_service(&app, send(b"not zstd".to_vec())).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn keyed_clients_must_sign_every_post() {
+        sql::connect_test().await;
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let pubkey = BASE64.encode(key.verifying_key().to_bytes());
+        let auth = sql::client::client_auth_create("signed_reports_test", Some(&pubkey))
+            .await
+            .unwrap();
+        let auth = serde_json::to_value(&auth).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(api_client_login)
+                .service(api_client_send_run_reports),
+        )
+        .await;
+        let response = test::call_service(&app, login(&auth).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+
+        let now = Utc::now().timestamp();
+        let sign = |time: i64| {
+            BASE64.encode(
+                key.sign(&securelog_proto::signed_message(time, b"[]"))
+                    .to_bytes(),
+            )
+        };
+        let form = |time: Option<i64>, signature: Option<String>| {
+            let mut form = vec![("reports", "[]".to_string())];
+            form.extend(time.map(|time| ("signature_time", time.to_string())));
+            form.extend(signature.map(|signature| ("signature", signature)));
+            form
+        };
+        let stale = now - securelog_proto::MAX_SIGNATURE_AGE - 1;
+        let requests = [
+            (form(None, None), StatusCode::UNAUTHORIZED),
+            (form(None, Some(sign(now))), StatusCode::UNAUTHORIZED),
+            (
+                form(Some(stale), Some(sign(stale))),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (form(Some(now), Some(sign(now))), StatusCode::OK),
+            // replayed
+            (form(Some(now), Some(sign(now))), StatusCode::UNAUTHORIZED),
+        ];
+        for (form, status) in requests {
+            let response = test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri("/api/client/send_run_reports")
+                    .cookie(cookie.clone())
+                    .set_form(&form)
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), status, "{:?}", form);
+        }
+    }
+
+    #[actix_web::test]
+    async fn clients_without_a_key_must_register_one() {
+        sql::connect_test().await;
+        // as created before result signing
+        let auth = sql::client::client_auth_create("legacy_client_test", None)
+            .await
+            .unwrap();
+        let auth = serde_json::to_value(&auth).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(api_client_login)
+                .service(api_client_register_key)
+                .service(api_client_send_run_reports),
+        )
+        .await;
+        let response = test::call_service(&app, login(&auth).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+
+        // unsigned data is refused unless require_signatures is turned off
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/api/client/send_run_reports")
+                .cookie(cookie.clone())
+                .set_form([("reports", "[]")])
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let id = auth["id"].as_str().unwrap();
+        let key = SigningKey::from_bytes(&[8; 32]);
+        let pubkey = BASE64.encode(key.verifying_key().to_bytes());
+        let register = |token: &str, pubkey: &str| {
+            test::TestRequest::post()
+                .uri("/api/client/register_key")
+                .set_form([("id", id), ("token", token), ("pubkey", pubkey)])
+                .to_request()
+        };
+        let token = auth["token"].as_str().unwrap();
+        let other = BASE64.encode(SigningKey::from_bytes(&[6; 32]).verifying_key().to_bytes());
+        let requests = [
+            (register("wrong", &pubkey), StatusCode::UNAUTHORIZED),
+            (register(token, "not a key"), StatusCode::BAD_REQUEST),
+            (register(token, &pubkey), StatusCode::OK),
+            // a registered key is never replaced
+            (register(token, &other), StatusCode::CONFLICT),
+        ];
+        for (request, status) in requests {
+            assert_eq!(test::call_service(&app, request).await.status(), status);
+        }
+        assert_eq!(
+            sql::client::get_client_pubkey(id).await.unwrap(),
+            Some(pubkey)
+        );
+
+        let now = Utc::now().timestamp();
+        let signature = key.sign(&securelog_proto::signed_message(now, b"[]"));
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/api/client/send_run_reports")
+                .cookie(cookie)
+                .set_form([
+                    ("reports", "[]".to_string()),
+                    ("signature_time", now.to_string()),
+                    ("signature", BASE64.encode(signature.to_bytes())),
+                ])
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
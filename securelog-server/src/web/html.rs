@@ -106,7 +106,7 @@ pub async fn webhooks(id: Option<Identity>) -> HttpResponse {
 #[get("/js/{path}")]
 pub async fn js_file(path: web::Path<String>, id: Option<Identity>) -> HttpResponse {
     let path = path.into_inner();
-    if path == String::from("login.js") {
+    if path == "login.js" {
         return super::files::js_file_response(&path);
     }
     if let Some(_username) = user_logged_in(id) {
@@ -1,9 +1,7 @@
-use crate::conf;
-use crate::models::{SearchResult, SearchType};
-use crate::{constants, sql};
+use crate::{archive, conf};
 use actix_identity::{Identity, IdentityMiddleware};
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{cookie::Key, web, App, HttpServer};
 use rustls::{Certificate, PrivateKey, ServerConfig};
 use std::fs::File;
 use std::io::BufReader;
@@ -12,12 +10,22 @@ mod client;
 mod files;
 mod html;
 mod user;
+mod v1;
+
+/**
+ * Limit of the raw bodies taken by archive uploads and integrity snapshots,
+ * both zstd compressed.
+ */
+fn payload_config() -> web::PayloadConfig {
+    web::PayloadConfig::new(archive::MAX_SEGMENT_SIZE as usize)
+}
 
 pub async fn start() -> std::io::Result<()> {
     let secret_key = Key::generate();
 
     let server = HttpServer::new(move || {
         App::new()
+            .app_data(payload_config())
             .wrap(actix_web::middleware::Logger::default())
             // Install identity framework
             .wrap(IdentityMiddleware::default())
@@ -31,10 +39,18 @@ pub async fn start() -> std::io::Result<()> {
                     ("X-Frame-Options", "DENY")
                 )
             )
+            .service(v1::scope())
             .service(user::api_user_login)
             .service(user::api_user_logout)
             .service(user::api_user_username)
             .service(user::api_user_insert_search)
+            .service(user::api_user_update_search)
+            .service(user::api_user_set_search_enabled)
+            .service(user::api_user_clone_search)
+            .service(user::api_user_search_history)
+            .service(user::api_user_search_health)
+            .service(user::api_user_search_runs)
+            .service(user::api_user_revert_search)
             .service(user::api_user_delete_search)
             .service(user::api_user_get_search_results)
             .service(user::api_user_set_schedule)
@@ -43,15 +59,34 @@ pub async fn start() -> std::io::Result<()> {
             .service(user::api_user_webhooks_delete)
             .service(user::api_user_get_searches)
             .service(user::api_user_client_delete)
+            .service(user::api_user_client_set_tags)
+            .service(user::api_user_enrollment_fetch)
+            .service(user::api_user_enrollment_create)
+            .service(user::api_user_enrollment_delete)
+            .service(user::api_user_client_settings)
+            .service(user::api_user_client_set_settings)
             .service(user::api_fetch_clients)
+            .service(user::api_fetch_client_status)
+            .service(user::api_fetch_client_integrity)
+            .service(user::api_user_archive_segments)
+            .service(user::api_user_archive_download)
+            .service(user::api_user_archive_search)
+            .service(user::api_user_set_search_retention)
+            .service(user::api_user_storage_usage)
+            .service(user::api_user_search_matches)
             .service(client::api_client_login)
             .service(client::api_client_logout)
             .service(client::api_client_create)
+            .service(client::api_client_enroll)
+            .service(client::api_client_register_key)
             .service(client::api_client_set_enabled)
             .service(client::api_client_get_searches)
             .service(client::api_client_send_search_results)
+            .service(client::api_client_send_run_reports)
+            .service(client::api_client_send_integrity_snapshot)
             .service(client::api_client_should_run)
             .service(client::api_client_notify_running)
+            .service(client::api_client_archive_segment)
             .service(html::login)
             .service(html::index)
             .service(html::clients)
@@ -69,35 +104,42 @@ pub async fn start() -> std::io::Result<()> {
     if !conf::get_use_https().unwrap_or(false) {
         server.bind(listen_address)?.run().await?;
     } else {
-        let cert_file: String = conf::get_server_cert().unwrap();
-        let key_file: String = conf::get_server_cert_key().unwrap();
-
-        let config = ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth();
-        let cert_file = &mut BufReader::new(File::open(cert_file).unwrap());
-        let key_file = &mut BufReader::new(File::open(key_file).unwrap());
-        let cert_chain = rustls_pemfile::certs(cert_file)
-            .unwrap()
-            .into_iter()
-            .map(Certificate)
-            .collect();
-        let mut keys: Vec<PrivateKey> = rustls_pemfile::pkcs8_private_keys(key_file)
-            .unwrap()
-            .into_iter()
-            .map(PrivateKey)
-            .collect();
-        if keys.is_empty() {
-            eprintln!("Could not locate PKCS 8 private keys.");
-            std::process::exit(1);
-        }
-        let config = config.with_single_cert(cert_chain, keys.remove(0)).unwrap();
+        let config = tls_config();
         server.bind_rustls(listen_address, config)?.run().await?;
     }
 
     Ok(())
 }
 
+/**
+ * TLS config with the server's cert and key, exits if there is no key.
+ */
+pub fn tls_config() -> ServerConfig {
+    let cert_file: String = conf::get_server_cert().unwrap();
+    let key_file: String = conf::get_server_cert_key().unwrap();
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth();
+    let cert_file = &mut BufReader::new(File::open(cert_file).unwrap());
+    let key_file = &mut BufReader::new(File::open(key_file).unwrap());
+    let cert_chain = rustls_pemfile::certs(cert_file)
+        .unwrap()
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let mut keys: Vec<PrivateKey> = rustls_pemfile::pkcs8_private_keys(key_file)
+        .unwrap()
+        .into_iter()
+        .map(PrivateKey)
+        .collect();
+    if keys.is_empty() {
+        eprintln!("Could not locate PKCS 8 private keys.");
+        std::process::exit(1);
+    }
+    config.with_single_cert(cert_chain, keys.remove(0)).unwrap()
+}
+
 fn client_logged_in(user: Option<Identity>) -> Option<String> {
     debug!("client_logged_in: {}", user.is_some());
     if let Some(user) = user {
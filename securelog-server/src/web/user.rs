@@ -1,9 +1,10 @@
-use super::{client_logged_in, user_logged_in};
-use crate::models::{SearchResult, SearchType};
-use crate::sql;
+use super::user_logged_in;
+use crate::models::{ClientSettings, ResourceLimits, SearchType, TagSource};
+use crate::{archive, sql};
 use actix_identity::Identity;
-use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder, Result};
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use chrono::{DateTime, Utc};
+use securelog_proto::parse_tags;
 
 #[derive(Debug, Deserialize)]
 struct AuthLogin {
@@ -75,27 +76,167 @@ async fn api_fetch_clients(id: Option<Identity>) -> actix_web::Result<HttpRespon
     }
 }
 
+#[get("/api/user/client/status")]
+async fn api_fetch_client_status(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let statuses = crate::monitor::client_statuses().await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&statuses))
+    } else {
+        Ok(HttpResponse::Unauthorized().finish())
+    }
+}
+
+#[get("/api/user/client/integrity")]
+async fn api_fetch_client_integrity(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let changes = sql::integrity::get_integrity_changes(None, None).await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&changes))
+    } else {
+        Ok(HttpResponse::Unauthorized().finish())
+    }
+}
+
+// one location per line, blank lines are skipped
+fn parse_locations(locations: &str) -> Vec<String> {
+    locations
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
 #[derive(Debug, Deserialize)]
 struct UserInsertSearch {
     name: String,
     stype: SearchType,
     search: String,
     locations: String,
+    // empty for every client
+    selector: Option<String>,
+    // unchecked checkboxes are not sent
+    realtime: Option<bool>,
 }
 
 #[post("/api/user/create_search")]
 async fn api_user_insert_search(
     id: Option<Identity>,
     params: web::Form<UserInsertSearch>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(username) = user_logged_in(id) {
+        let locations = parse_locations(&params.locations);
+
+        let _id = sql::insert_search(
+            &username,
+            &sql::SearchFields {
+                name: &params.name,
+                stype: &params.stype,
+                search: &params.search,
+                locations: &locations,
+                selector: params.selector.as_deref(),
+                realtime: params.realtime.unwrap_or(false),
+            },
+        )
+        .await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/searches"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserUpdateSearch {
+    id: i32,
+    name: String,
+    stype: SearchType,
+    search: String,
+    locations: String,
+    // empty for every client
+    selector: Option<String>,
+    // unchecked checkboxes are not sent
+    realtime: Option<bool>,
+}
+#[post("/api/user/update_search")]
+async fn api_user_update_search(
+    id: Option<Identity>,
+    params: web::Form<UserUpdateSearch>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(username) = user_logged_in(id) {
+        let locations = parse_locations(&params.locations);
+
+        sql::update_search(
+            params.id,
+            &username,
+            &sql::SearchFields {
+                name: &params.name,
+                stype: &params.stype,
+                search: &params.search,
+                locations: &locations,
+                selector: params.selector.as_deref(),
+                realtime: params.realtime.unwrap_or(false),
+            },
+        )
+        .await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/searches"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserSetSearchEnabled {
+    id: i32,
+    // unchecked checkboxes are not sent
+    enabled: Option<bool>,
+}
+#[post("/api/user/set_search_enabled")]
+async fn api_user_set_search_enabled(
+    id: Option<Identity>,
+    params: web::Form<UserSetSearchEnabled>,
 ) -> actix_web::Result<HttpResponse> {
     if let Some(_username) = user_logged_in(id) {
-        let mut locations: Vec<String> = Vec::new();
-        for line in params.locations.lines() {
-            locations.push(line.to_string());
-        }
+        sql::set_search_enabled(params.id, params.enabled.unwrap_or(false)).await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/searches"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
 
-        let _id =
-            sql::insert_search(&params.name, &params.stype, &params.search, &locations).await?;
+#[derive(Debug, Deserialize)]
+struct UserCloneSearch {
+    id: i32,
+    // empty means "<name> (copy)"
+    name: Option<String>,
+}
+#[post("/api/user/clone_search")]
+async fn api_user_clone_search(
+    id: Option<Identity>,
+    params: web::Form<UserCloneSearch>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(username) = user_logged_in(id) {
+        let name = params.name.as_deref().filter(|name| !name.is_empty());
+        sql::clone_search(params.id, &username, name).await?;
 
         Ok(HttpResponse::Found()
             .insert_header(("location", "/searches"))
@@ -106,6 +247,89 @@ async fn api_user_insert_search(
             .finish())
     }
 }
+
+#[derive(Debug, Deserialize)]
+struct UserSearchHistory {
+    id: i32,
+}
+#[get("/api/user/search_history")]
+async fn api_user_search_history(
+    id: Option<Identity>,
+    params: web::Query<UserSearchHistory>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let history = sql::get_search_history(params.id).await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&history))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[get("/api/user/search_health")]
+async fn api_user_search_health(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let health = sql::runs::get_search_health().await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&health))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserSearchRuns {
+    id: i32,
+}
+#[get("/api/user/search_runs")]
+async fn api_user_search_runs(
+    id: Option<Identity>,
+    params: web::Query<UserSearchRuns>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let runs = sql::runs::get_search_runs(Some(params.id)).await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&runs))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserRevertSearch {
+    id: i32,
+    version: i32,
+}
+#[post("/api/user/revert_search")]
+async fn api_user_revert_search(
+    id: Option<Identity>,
+    params: web::Form<UserRevertSearch>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(username) = user_logged_in(id) {
+        sql::revert_search(params.id, params.version, &username).await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/searches"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct UserDeleteSearch {
     id: i32,
@@ -131,8 +355,13 @@ async fn api_user_delete_search(
 #[derive(Debug, Deserialize)]
 struct UserGetSearchResults {
     client: Option<String>,
+    search: Option<i32>,
     before: Option<DateTime<Utc>>,
     after: Option<DateTime<Utc>>,
+    text: Option<String>,
+    limit: Option<i64>,
+    // next_cursor of the previous page
+    cursor: Option<String>,
 }
 #[get("/api/user/get_search_results")]
 async fn api_user_get_search_results(
@@ -140,12 +369,30 @@ async fn api_user_get_search_results(
     params: web::Query<UserGetSearchResults>,
 ) -> actix_web::Result<HttpResponse> {
     if let Some(_username) = user_logged_in(id) {
-        let results =
-            sql::get_search_results(params.client.clone(), params.before, params.after).await?;
+        let cursor = match &params.cursor {
+            Some(token) => match sql::ResultsCursor::decode(token) {
+                Some(cursor) => Some(cursor),
+                None => return Ok(HttpResponse::BadRequest().body("Invalid cursor")),
+            },
+            None => None,
+        };
+        // empty form fields mean no filter
+        let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+
+        let filter = sql::SearchResultFilter {
+            client: non_empty(&params.client),
+            search: params.search,
+            before: params.before,
+            after: params.after,
+            text: non_empty(&params.text),
+            cursor,
+            limit: params.limit,
+        };
+        let page = sql::get_search_results(&filter).await?;
 
         Ok(HttpResponse::Ok()
             .content_type("application/json")
-            .json(&results))
+            .json(&page))
     } else {
         Ok(HttpResponse::Found()
             .insert_header(("location", "/login"))
@@ -241,7 +488,7 @@ async fn api_user_webhooks_delete(
 #[get("/api/user/get_searches")]
 async fn api_user_get_searches(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
     if let Some(_username) = user_logged_in(id) {
-        let searches = sql::get_searches().await?;
+        let searches = sql::get_all_searches().await?;
 
         Ok(HttpResponse::Ok()
             .content_type("application/json")
@@ -276,3 +523,444 @@ async fn api_user_client_delete(
             .finish())
     }
 }
+
+#[derive(Debug, Deserialize)]
+struct ClientSetTags {
+    id: String,
+    // one key=value per line
+    tags: String,
+}
+/**
+ * Replace the tags an admin gave a client, they override the tags the
+ * client reports with the same key.
+ */
+#[post("/api/user/client/set_tags")]
+async fn api_user_client_set_tags(
+    id: Option<Identity>,
+    params: web::Form<ClientSetTags>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let tags = match parse_tags(&params.tags.lines().collect::<Vec<&str>>().join(",")) {
+            Ok(tags) => tags,
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+        };
+        sql::client::set_client_tags(&params.id, TagSource::Admin, &tags).await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/clients"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[get("/api/user/enrollment/fetch")]
+async fn api_user_enrollment_fetch(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let tokens = sql::enrollment::get_enrollment_tokens().await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&tokens))
+    } else {
+        Ok(HttpResponse::Unauthorized().finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EnrollmentCreate {
+    name: String,
+    // one key=value per line
+    tags: String,
+    max_uses: i32,
+    hours: i64,
+}
+/**
+ * Mint an enrollment token, answered with the token as JSON since it is
+ * only shown once.
+ */
+#[post("/api/user/enrollment/create")]
+async fn api_user_enrollment_create(
+    id: Option<Identity>,
+    params: web::Form<EnrollmentCreate>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(username) = user_logged_in(id) {
+        let tags = match parse_tags(&params.tags.lines().collect::<Vec<&str>>().join(",")) {
+            Ok(tags) => tags.into_iter().collect(),
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+        };
+        let (token, enrollment_token) = sql::enrollment::create_enrollment_token(
+            &params.name,
+            &tags,
+            params.max_uses,
+            params.hours,
+            &username,
+        )
+        .await?;
+
+        Ok(HttpResponse::Ok().json(json!({
+            "token": token,
+            "enrollment_token": enrollment_token,
+        })))
+    } else {
+        Ok(HttpResponse::Unauthorized().finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EnrollmentDelete {
+    id: i32,
+}
+#[post("/api/user/enrollment/delete")]
+async fn api_user_enrollment_delete(
+    id: Option<Identity>,
+    params: web::Form<EnrollmentDelete>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        sql::enrollment::delete_enrollment_token(params.id).await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/clients"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientSettingsQuery {
+    // empty for the defaults every client gets
+    id: String,
+}
+#[get("/api/user/client/settings")]
+async fn api_user_client_settings(
+    id: Option<Identity>,
+    params: web::Query<ClientSettingsQuery>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let settings = if params.id.is_empty() {
+            sql::client::get_default_settings().await?
+        } else {
+            sql::client::get_client_settings(&params.id).await?
+        };
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&settings))
+    } else {
+        Ok(HttpResponse::Unauthorized().finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientSetSettings {
+    // empty for the defaults every client gets
+    id: String,
+    // empty fields are unset
+    log_level: String,
+    poll_interval: String,
+    max_run_bytes: String,
+    max_run_secs: String,
+    max_search_bytes: String,
+    max_search_lines: String,
+    max_search_secs: String,
+    max_read_rate: String,
+}
+impl ClientSetSettings {
+    fn settings(&self) -> std::result::Result<ClientSettings, String> {
+        let number = |name: &str, value: &str| -> std::result::Result<Option<u64>, String> {
+            match value.trim() {
+                "" => Ok(None),
+                value => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("{} must be a number", name)),
+            }
+        };
+
+        let settings = ClientSettings {
+            log_level: Some(self.log_level.trim().to_string()).filter(|level| !level.is_empty()),
+            poll_interval: number("poll interval", &self.poll_interval)?,
+            limits: ResourceLimits {
+                max_run_bytes: number("max run bytes", &self.max_run_bytes)?,
+                max_run_secs: number("max run seconds", &self.max_run_secs)?,
+                max_search_bytes: number("max search bytes", &self.max_search_bytes)?,
+                max_search_lines: number("max search lines", &self.max_search_lines)?,
+                max_search_secs: number("max search seconds", &self.max_search_secs)?,
+                max_read_rate: number("max read rate", &self.max_read_rate)?,
+            },
+        };
+        settings.validate().map_err(|e| e.to_string())?;
+
+        Ok(settings)
+    }
+}
+/**
+ * Set the settings pushed to a client, or to every client.
+ */
+#[post("/api/user/client/set_settings")]
+async fn api_user_client_set_settings(
+    id: Option<Identity>,
+    params: web::Form<ClientSetSettings>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let settings = match params.settings() {
+            Ok(settings) => settings,
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+        };
+        if params.id.is_empty() {
+            sql::client::set_default_settings(&settings).await?;
+        } else {
+            sql::client::set_client_settings(&params.id, &settings).await?;
+        }
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/clients"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserArchiveSegments {
+    client: Option<String>,
+    location: Option<String>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+}
+#[get("/api/user/archive/segments")]
+async fn api_user_archive_segments(
+    id: Option<Identity>,
+    params: web::Query<UserArchiveSegments>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let segments = sql::archive::get_segments(&sql::archive::SegmentFilter {
+            client: params.client.clone(),
+            location: params.location.clone(),
+            after: params.after,
+            before: params.before,
+            ..Default::default()
+        })
+        .await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&segments))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserArchiveDownload {
+    client: String,
+    id: String,
+}
+#[get("/api/user/archive/download")]
+async fn api_user_archive_download(
+    id: Option<Identity>,
+    params: web::Query<UserArchiveDownload>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        if sql::archive::get_segment(&params.client, &params.id)
+            .await?
+            .is_none()
+        {
+            return Ok(HttpResponse::NotFound().body("No such segment"));
+        }
+
+        let data = archive::read_segment(&params.client, &params.id).await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("text/plain")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.log\"", params.id),
+            ))
+            .body(data))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserArchiveSearch {
+    // run an existing search, or give stype and search
+    search_id: Option<i32>,
+    stype: Option<SearchType>,
+    search: Option<String>,
+    client: Option<String>,
+    location: Option<String>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+    // next_cursor of the previous page
+    cursor: Option<String>,
+}
+#[get("/api/user/archive/search")]
+async fn api_user_archive_search(
+    id: Option<Identity>,
+    params: web::Query<UserArchiveSearch>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let cursor = match &params.cursor {
+            Some(token) => match sql::archive::SegmentCursor::decode(token) {
+                Some(cursor) => Some(cursor),
+                None => return Ok(HttpResponse::BadRequest().body("Invalid cursor")),
+            },
+            None => None,
+        };
+        let saved = match params.search_id {
+            Some(search_id) => match sql::get_search(search_id).await? {
+                Some(search) => Some(search),
+                None => return Ok(HttpResponse::NotFound().body("No such search")),
+            },
+            None => None,
+        };
+
+        let (search_id, search_name, stype, search) = match (&saved, &params.stype, &params.search)
+        {
+            (Some(saved), _, _) => (saved.id, saved.name.as_str(), &saved.stype, &saved.search),
+            (None, Some(stype), Some(search)) => (0, "archive search", stype, search),
+            _ => {
+                return Ok(HttpResponse::BadRequest().body("Need search_id or stype and search"));
+            }
+        };
+
+        let page = archive::search_segments(&archive::ArchiveSearch {
+            search_id,
+            search_name,
+            stype,
+            search,
+            client: params.client.as_deref(),
+            location: params.location.as_deref(),
+            after: params.after,
+            before: params.before,
+            cursor,
+            limit: params.limit,
+        })
+        .await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&page))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserSetSearchRetention {
+    id: i32,
+    // empty means use the global setting
+    days: Option<String>,
+    rows: Option<String>,
+}
+#[post("/api/user/set_search_retention")]
+async fn api_user_set_search_retention(
+    id: Option<Identity>,
+    params: web::Form<UserSetSearchRetention>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let parse = |value: &Option<String>| -> Result<Option<i32>, std::num::ParseIntError> {
+            match value.as_deref().map(str::trim) {
+                None | Some("") => Ok(None),
+                Some(value) => value.parse().map(Some),
+            }
+        };
+        let (days, rows) = match (parse(&params.days), parse(&params.rows)) {
+            (Ok(days), Ok(rows)) => (days, rows),
+            _ => return Ok(HttpResponse::BadRequest().body("Invalid retention")),
+        };
+        if days.is_some_and(|days| days < 1) || rows.is_some_and(|rows| rows < 1) {
+            return Ok(HttpResponse::BadRequest().body("days and rows must be greater than 0"));
+        }
+
+        sql::retention::set_search_retention(params.id, days, rows).await?;
+
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/searches"))
+            .finish())
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[get("/api/user/storage_usage")]
+async fn api_user_storage_usage(id: Option<Identity>) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let clients = sql::retention::get_client_storage_usage().await?;
+        let searches = sql::retention::get_search_storage_usage().await?;
+
+        let data = json!({
+            "clients": clients,
+            "searches": searches,
+        });
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&data))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UserSearchMatches {
+    q: String,
+    client: Option<String>,
+    search: Option<i32>,
+    before: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+/**
+ * Full text search within the lines clients found, see sql/fulltext.rs
+ */
+#[get("/api/user/search_matches")]
+async fn api_user_search_matches(
+    id: Option<Identity>,
+    params: web::Query<UserSearchMatches>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(_username) = user_logged_in(id) {
+        let query = sql::fulltext::MatchQuery {
+            query: params.q.clone(),
+            client: params.client.clone().filter(|client| !client.is_empty()),
+            search: params.search,
+            before: params.before,
+            after: params.after,
+            limit: params
+                .limit
+                .unwrap_or(sql::DEFAULT_RESULTS_PAGE)
+                .clamp(1, sql::MAX_RESULTS_PAGE),
+            offset: params.offset.unwrap_or(0).max(0),
+        };
+        let page = sql::fulltext::search_matches(&query).await?;
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(&page))
+    } else {
+        Ok(HttpResponse::Found()
+            .insert_header(("location", "/login"))
+            .finish())
+    }
+}
@@ -0,0 +1,176 @@
+use super::error::{ApiError, ApiResult, ErrorBody};
+use super::require_user;
+use crate::archive::{self, ArchiveSearchPage};
+use crate::models::SearchType;
+use crate::sql::{
+    self,
+    archive::{ArchiveSegment, SegmentCursor},
+};
+use actix_identity::Identity;
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use utoipa::IntoParams;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SegmentsQuery {
+    /// Client id
+    client: Option<String>,
+    /// File the segment was read from
+    location: Option<String>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+}
+/**
+ * Raw log segments clients uploaded.
+ */
+#[utoipa::path(
+    tag = "archive",
+    params(SegmentsQuery),
+    responses(
+        (status = 200, body = Vec<ArchiveSegment>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/archive/segments")]
+async fn segments(
+    id: Option<Identity>,
+    params: web::Query<SegmentsQuery>,
+) -> ApiResult<web::Json<Vec<ArchiveSegment>>> {
+    require_user(id)?;
+
+    let segments = sql::archive::get_segments(&sql::archive::SegmentFilter {
+        client: params.client.clone(),
+        location: params.location.clone(),
+        after: params.after,
+        before: params.before,
+        ..Default::default()
+    })
+    .await?;
+
+    Ok(web::Json(segments))
+}
+
+#[utoipa::path(
+    tag = "archive",
+    params(
+        ("client" = String, Path, description = "Client id"),
+        ("id" = String, Path, description = "Segment id"),
+    ),
+    responses(
+        (status = 200, content_type = "text/plain", body = String),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/archive/segments/{client}/{id}")]
+async fn download(
+    id: Option<Identity>,
+    path: web::Path<(String, String)>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+    let (client, segment) = path.into_inner();
+
+    if sql::archive::get_segment(&client, &segment)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::NotFound(format!("no segment {}", segment)));
+    }
+    let data = archive::read_segment(&client, &segment).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.log\"", segment),
+        ))
+        .body(data))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ArchiveSearchQuery {
+    /// Run an existing search, or give stype and search
+    search_id: Option<i32>,
+    #[param(inline)]
+    stype: Option<SearchType>,
+    search: Option<String>,
+    /// Client id
+    client: Option<String>,
+    location: Option<String>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+    /// Results per page, at most 1000
+    limit: Option<i64>,
+    /// next_cursor of the previous page
+    cursor: Option<String>,
+}
+/**
+ * Run a search over archived segments, one result per segment with matches.
+ * A call reads a bounded part of the archive, when the response is truncated
+ * next_cursor continues the search.
+ */
+#[utoipa::path(
+    tag = "archive",
+    params(ArchiveSearchQuery),
+    responses(
+        (status = 200, body = ArchiveSearchPage),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/archive/search")]
+async fn search(
+    id: Option<Identity>,
+    params: web::Query<ArchiveSearchQuery>,
+) -> ApiResult<web::Json<ArchiveSearchPage>> {
+    require_user(id)?;
+
+    let cursor = match &params.cursor {
+        Some(token) => Some(
+            SegmentCursor::decode(token)
+                .ok_or_else(|| ApiError::BadRequest("invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    if params.limit.is_some_and(|limit| limit < 1) {
+        return Err(ApiError::BadRequest(
+            "limit must be greater than 0".to_string(),
+        ));
+    }
+
+    let saved = match params.search_id {
+        Some(search_id) => Some(
+            sql::get_search(search_id)
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("no search {}", search_id)))?,
+        ),
+        None => None,
+    };
+
+    let (search_id, search_name, stype, search) = match (&saved, &params.stype, &params.search) {
+        (Some(saved), _, _) => (saved.id, saved.name.as_str(), &saved.stype, &saved.search),
+        (None, Some(stype), Some(search)) => (0, "archive search", stype, search),
+        _ => {
+            return Err(ApiError::BadRequest(
+                "need search_id or stype and search".to_string(),
+            ))
+        }
+    };
+
+    let page = archive::search_segments(&archive::ArchiveSearch {
+        search_id,
+        search_name,
+        stype,
+        search,
+        client: params.client.as_deref(),
+        location: params.location.as_deref(),
+        after: params.after,
+        before: params.before,
+        cursor,
+        limit: params.limit,
+    })
+    .await?;
+
+    Ok(web::Json(page))
+}
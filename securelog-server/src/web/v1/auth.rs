@@ -0,0 +1,53 @@
+use super::error::{ApiError, ApiResult, ErrorBody};
+use crate::sql::{self, SqlError};
+use actix_identity::Identity;
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use utoipa::ToSchema;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Login {
+    username: String,
+    password: String,
+}
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LoggedIn {
+    username: String,
+}
+
+/**
+ * Log in, the session cookie in the response authenticates later requests.
+ */
+#[utoipa::path(
+    tag = "auth",
+    request_body = Login,
+    responses(
+        (status = 200, body = LoggedIn),
+        (status = 401, body = ErrorBody, description = "Wrong username or password"),
+    )
+)]
+#[post("/login")]
+async fn login(request: HttpRequest, params: web::Json<Login>) -> ApiResult<HttpResponse> {
+    // don't tell whether the user exists
+    match sql::user::user_login(&params.username, &params.password).await {
+        Ok(true) => (),
+        Ok(false) | Err(SqlError::UserNotExist) | Err(SqlError::UserDisabled) => {
+            return Err(ApiError::LoginFailed)
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Identity::login(&request.extensions(), format!("user:{}", &params.username))?;
+
+    Ok(HttpResponse::Ok().json(LoggedIn {
+        username: params.username.clone(),
+    }))
+}
+
+#[utoipa::path(tag = "auth", responses((status = 204)))]
+#[post("/logout")]
+async fn logout(id: Option<Identity>) -> HttpResponse {
+    if let Some(id) = id {
+        id.logout();
+    }
+
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,376 @@
+use super::error::{ApiError, ApiResult, ErrorBody};
+use super::require_user;
+use crate::models::{ClientSettings, ClientTag, Search, TagSource};
+use crate::monitor;
+use crate::sql::{
+    self, client::ClientStatus, client::SLClient, enrollment::EnrollmentToken,
+    integrity::IntegrityBaseline,
+};
+use actix_identity::Identity;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use securelog_proto::is_tag_word;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[utoipa::path(
+    tag = "clients",
+    responses(
+        (status = 200, body = Vec<SLClient>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/clients")]
+async fn list(id: Option<Identity>) -> ApiResult<web::Json<Vec<SLClient>>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::client::get_clients().await?))
+}
+
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    responses(
+        (status = 204, description = "Client and its results deleted"),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[delete("/clients/{id}")]
+async fn delete(id: Option<Identity>, path: web::Path<String>) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if sql::client::delete_client(&path).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::NotFound(format!("no client {}", path)))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetEnabled {
+    enabled: bool,
+}
+/**
+ * Disabled clients can not log in.
+ */
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    request_body = SetEnabled,
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[put("/clients/{id}/enabled")]
+async fn set_enabled(
+    id: Option<Identity>,
+    path: web::Path<String>,
+    params: web::Json<SetEnabled>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if !sql::client::client_exists(&path).await? {
+        return Err(ApiError::NotFound(format!("no client {}", path)));
+    }
+    sql::client::client_set_enabled(&path, params.enabled).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetTags {
+    // key to value, i.e. {"role": "web"}
+    tags: BTreeMap<String, String>,
+}
+/**
+ * Replace the tags an admin gave a client. They override the tags the
+ * client reports with the same key. Returns all the client's tags.
+ */
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    request_body = SetTags,
+    responses(
+        (status = 200, body = Vec<ClientTag>),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[put("/clients/{id}/tags")]
+async fn set_tags(
+    id: Option<Identity>,
+    path: web::Path<String>,
+    params: web::Json<SetTags>,
+) -> ApiResult<web::Json<Vec<ClientTag>>> {
+    require_user(id)?;
+
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for (key, value) in &params.tags {
+        if !is_tag_word(key) || !is_tag_word(value) {
+            return Err(ApiError::BadRequest(format!(
+                "invalid tag {}={}, tags are letters, digits and _-./:",
+                key, value
+            )));
+        }
+        tags.push((key.clone(), value.clone()));
+    }
+    sql::client::set_client_tags(&path, TagSource::Admin, &tags).await?;
+
+    Ok(web::Json(sql::client::get_client_tags(&path).await?))
+}
+
+/**
+ * The searches the client gets, the enabled ones whose selector matches
+ * its tags.
+ */
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    responses(
+        (status = 200, body = Vec<Search>),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/clients/{id}/searches")]
+async fn searches(
+    id: Option<Identity>,
+    path: web::Path<String>,
+) -> ApiResult<web::Json<Vec<Search>>> {
+    require_user(id)?;
+
+    if !sql::client::client_exists(&path).await? {
+        return Err(ApiError::NotFound(format!("no client {}", path)));
+    }
+
+    Ok(web::Json(sql::get_client_searches(&path).await?))
+}
+
+/**
+ * Last check-in, scan, result, error and agent version of every client.
+ */
+#[utoipa::path(
+    tag = "clients",
+    responses(
+        (status = 200, body = Vec<ClientStatus>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/clients/status")]
+async fn statuses(id: Option<Identity>) -> ApiResult<web::Json<Vec<ClientStatus>>> {
+    require_user(id)?;
+
+    Ok(web::Json(monitor::client_statuses().await?))
+}
+
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    responses(
+        (status = 200, body = ClientStatus),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/clients/{id}/status")]
+async fn status(
+    id: Option<Identity>,
+    path: web::Path<String>,
+) -> ApiResult<web::Json<ClientStatus>> {
+    require_user(id)?;
+
+    monitor::client_statuses()
+        .await?
+        .into_iter()
+        .find(|status| status.id == *path)
+        .map(web::Json)
+        .ok_or_else(|| ApiError::NotFound(format!("no client {}", path)))
+}
+
+/**
+ * The files of the client as of its last integrity snapshot.
+ */
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    responses(
+        (status = 200, body = IntegrityBaseline),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody, description = "No client, or it sent no snapshot"),
+    )
+)]
+#[get("/clients/{id}/baseline")]
+async fn baseline(
+    id: Option<Identity>,
+    path: web::Path<String>,
+) -> ApiResult<web::Json<IntegrityBaseline>> {
+    require_user(id)?;
+
+    sql::integrity::get_integrity_baseline(&path)
+        .await?
+        .map(web::Json)
+        .ok_or_else(|| ApiError::NotFound(format!("no integrity baseline for client {}", path)))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClientSettingsView {
+    // set for this client
+    own: ClientSettings,
+    // what the client gets, its own over the defaults
+    effective: ClientSettings,
+}
+async fn settings_view(client: &str) -> ApiResult<ClientSettingsView> {
+    Ok(ClientSettingsView {
+        own: sql::client::get_client_settings(client).await?,
+        effective: sql::client::effective_settings(client).await?,
+    })
+}
+
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    responses(
+        (status = 200, body = ClientSettingsView),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/clients/{id}/settings")]
+async fn settings(
+    id: Option<Identity>,
+    path: web::Path<String>,
+) -> ApiResult<web::Json<ClientSettingsView>> {
+    require_user(id)?;
+
+    if !sql::client::client_exists(&path).await? {
+        return Err(ApiError::NotFound(format!("no client {}", path)));
+    }
+
+    Ok(web::Json(settings_view(&path).await?))
+}
+
+/**
+ * Replace the settings of one client, unset ones fall back to the defaults.
+ */
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = String, Path, description = "Client id")),
+    request_body = ClientSettings,
+    responses(
+        (status = 200, body = ClientSettingsView),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[put("/clients/{id}/settings")]
+async fn set_settings(
+    id: Option<Identity>,
+    path: web::Path<String>,
+    params: web::Json<ClientSettings>,
+) -> ApiResult<web::Json<ClientSettingsView>> {
+    require_user(id)?;
+
+    params
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    sql::client::set_client_settings(&path, &params).await?;
+
+    Ok(web::Json(settings_view(&path).await?))
+}
+
+#[utoipa::path(
+    tag = "clients",
+    responses(
+        (status = 200, body = Vec<EnrollmentToken>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/enrollment_tokens")]
+async fn enrollment_tokens(id: Option<Identity>) -> ApiResult<web::Json<Vec<EnrollmentToken>>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::enrollment::get_enrollment_tokens().await?))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewEnrollmentToken {
+    // what the token is for
+    name: String,
+    // admin tags of the clients it creates, i.e. {"role": "web"}
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    // clients it can create, 1 unless set
+    max_uses: Option<i32>,
+    // hours until it expires, a day unless set
+    hours: Option<i64>,
+}
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedEnrollmentToken {
+    // for `securelog-client enroll --token`, it is not shown again
+    token: String,
+    enrollment_token: EnrollmentToken,
+}
+/**
+ * Mint a token `securelog-client enroll` creates clients with, without
+ * admin credentials on the host.
+ */
+#[utoipa::path(
+    tag = "clients",
+    request_body = NewEnrollmentToken,
+    responses(
+        (status = 201, body = CreatedEnrollmentToken),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[post("/enrollment_tokens")]
+async fn create_enrollment_token(
+    id: Option<Identity>,
+    params: web::Json<NewEnrollmentToken>,
+) -> ApiResult<HttpResponse> {
+    let username = require_user(id)?;
+
+    let (token, enrollment_token) = sql::enrollment::create_enrollment_token(
+        &params.name,
+        &params.tags,
+        params.max_uses.unwrap_or(1),
+        params.hours.unwrap_or(24),
+        &username,
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(CreatedEnrollmentToken {
+        token,
+        enrollment_token,
+    }))
+}
+
+/**
+ * Clients the token created are kept.
+ */
+#[utoipa::path(
+    tag = "clients",
+    params(("id" = i32, Path, description = "Enrollment token id")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[delete("/enrollment_tokens/{id}")]
+async fn delete_enrollment_token(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if sql::enrollment::delete_enrollment_token(*path).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::NotFound(format!("no enrollment token {}", path)))
+    }
+}
@@ -0,0 +1,127 @@
+use crate::archive::ArchiveError;
+use crate::sql::SqlError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use utoipa::ToSchema;
+
+/**
+ * Errors of the v1 API, always answered with an ErrorBody.
+ * Database and IO errors are logged, the caller only gets a generic message.
+ */
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("not logged in")]
+    Unauthorized,
+
+    #[error("login failed")]
+    LoginFailed,
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error(transparent)]
+    Sql(#[from] SqlError),
+
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+
+    #[error(transparent)]
+    Login(#[from] actix_identity::error::LoginError),
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    // machine readable, one of unauthorized, bad_request, not_found,
+    // conflict, payload_too_large, not_implemented or internal
+    #[schema(example = "not_found")]
+    pub error: &'static str,
+    pub message: String,
+}
+
+fn sql_status(e: &SqlError) -> StatusCode {
+    match e {
+        SqlError::ClientNotExist(_)
+        | SqlError::NoSuchSchedule(_)
+        | SqlError::NoSuchSearch(_)
+        | SqlError::NoSuchSearchVersion(_, _) => StatusCode::NOT_FOUND,
+        SqlError::InvalidSearch(_) | SqlError::InvalidEnrollmentToken(_) => StatusCode::BAD_REQUEST,
+        SqlError::ClientNameExists(_) => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized | ApiError::LoginFailed => StatusCode::UNAUTHORIZED,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Sql(e) => sql_status(e),
+            ApiError::Archive(ArchiveError::Sql(e)) => sql_status(e),
+            ApiError::Archive(e) => e.status_code(),
+            ApiError::Login(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let error = match status {
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::CONFLICT => "conflict",
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            _ => "internal",
+        };
+        let message = if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("api error: {}", self);
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+
+        HttpResponse::build(status).json(ErrorBody { error, message })
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    fn body(e: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = e.error_response();
+        let bytes = response.into_body().try_into_bytes().unwrap();
+        (e.status_code(), serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn errors_have_status_and_body() {
+        let (status, json) = body(ApiError::NotFound("no search 1".to_string()));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json["error"], "not_found");
+        assert_eq!(json["message"], "no search 1");
+
+        let (status, json) = body(ArchiveError::Sql(SqlError::ClientNameExists("a".into())).into());
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(json["error"], "conflict");
+    }
+
+    #[test]
+    fn internal_errors_are_not_leaked() {
+        let e: ApiError = SqlError::Config("pg_params: secret".to_string()).into();
+        let (status, json) = body(e);
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(json["error"], "internal");
+        assert_eq!(json["message"], "internal server error");
+    }
+}
@@ -0,0 +1,170 @@
+/*
+Version 1 of the user API, JSON in and out.
+
+Everything lives under /api/v1, errors are answered with an ErrorBody and
+a status code that matches the error. The OpenAPI description is generated
+from the handlers and served at /api/v1/openapi.json.
+*/
+use super::user_logged_in;
+use actix_identity::Identity;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use error::{ApiError, ApiResult, ErrorBody};
+use utoipa::OpenApi;
+
+mod archive;
+mod auth;
+mod clients;
+pub mod error;
+mod results;
+mod searches;
+mod settings;
+
+// largest JSON body accepted, sample text for dry runs is the biggest
+const MAX_JSON_BODY: usize = 1024 * 1024;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "securelog", description = "securelog server user API"),
+    servers((url = "/api/v1")),
+    paths(
+        auth::login,
+        auth::logout,
+        clients::list,
+        clients::delete,
+        clients::set_enabled,
+        clients::set_tags,
+        clients::searches,
+        clients::statuses,
+        clients::status,
+        clients::baseline,
+        clients::settings,
+        clients::set_settings,
+        clients::enrollment_tokens,
+        clients::create_enrollment_token,
+        clients::delete_enrollment_token,
+        searches::list,
+        searches::get,
+        searches::create,
+        searches::validate,
+        searches::test,
+        searches::update,
+        searches::set_enabled,
+        searches::clone,
+        searches::history,
+        searches::runs,
+        searches::health,
+        searches::revert,
+        searches::delete,
+        searches::retentions,
+        searches::set_retention,
+        results::list,
+        results::matches,
+        results::integrity,
+        settings::get_schedule,
+        settings::set_schedule,
+        settings::list_webhooks,
+        settings::add_webhook,
+        settings::delete_webhook,
+        settings::storage_usage,
+        settings::get_client_settings,
+        settings::set_client_settings,
+        archive::segments,
+        archive::download,
+        archive::search,
+        openapi_json,
+    ),
+    components(schemas(ErrorBody)),
+    tags(
+        (name = "auth"),
+        (name = "clients"),
+        (name = "searches"),
+        (name = "results"),
+        (name = "settings"),
+        (name = "archive"),
+    )
+)]
+pub struct ApiDoc;
+
+/**
+ * The logged in user, or Unauthorized.
+ */
+fn require_user(id: Option<Identity>) -> ApiResult<String> {
+    user_logged_in(id).ok_or(ApiError::Unauthorized)
+}
+
+// malformed bodies, queries and paths are answered like any other error
+fn bad_request<E: std::fmt::Display>(err: E, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
+
+pub fn scope() -> actix_web::Scope {
+    web::scope("/api/v1")
+        .app_data(
+            web::JsonConfig::default()
+                .limit(MAX_JSON_BODY)
+                .error_handler(bad_request),
+        )
+        .app_data(web::QueryConfig::default().error_handler(bad_request))
+        .app_data(web::PathConfig::default().error_handler(bad_request))
+        .service(auth::login)
+        .service(auth::logout)
+        .service(clients::statuses)
+        .service(clients::list)
+        .service(clients::delete)
+        .service(clients::set_enabled)
+        .service(clients::set_tags)
+        .service(clients::searches)
+        .service(clients::status)
+        .service(clients::baseline)
+        .service(clients::settings)
+        .service(clients::set_settings)
+        .service(clients::enrollment_tokens)
+        .service(clients::create_enrollment_token)
+        .service(clients::delete_enrollment_token)
+        .service(searches::list)
+        .service(searches::retentions)
+        .service(searches::health)
+        .service(searches::get)
+        .service(searches::create)
+        .service(searches::validate)
+        .service(searches::test)
+        .service(searches::update)
+        .service(searches::set_enabled)
+        .service(searches::clone)
+        .service(searches::history)
+        .service(searches::runs)
+        .service(searches::revert)
+        .service(searches::delete)
+        .service(searches::set_retention)
+        .service(results::list)
+        .service(results::matches)
+        .service(results::integrity)
+        .service(settings::get_schedule)
+        .service(settings::set_schedule)
+        .service(settings::list_webhooks)
+        .service(settings::add_webhook)
+        .service(settings::delete_webhook)
+        .service(settings::storage_usage)
+        .service(settings::get_client_settings)
+        .service(settings::set_client_settings)
+        .service(archive::segments)
+        .service(archive::download)
+        .service(archive::search)
+        .service(openapi_json)
+        .default_service(web::to(not_found))
+}
+
+async fn not_found() -> ApiResult<HttpResponse> {
+    Err(ApiError::NotFound("no such endpoint".to_string()))
+}
+
+/**
+ * This document.
+ */
+#[utoipa::path(
+    responses((status = 200, description = "OpenAPI description of the v1 API"))
+)]
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
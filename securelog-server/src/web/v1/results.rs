@@ -0,0 +1,146 @@
+use super::error::{ApiError, ApiResult, ErrorBody};
+use super::require_user;
+use crate::sql::{self, fulltext::MatchPage, integrity::IntegrityChange, SearchResultPage};
+use actix_identity::Identity;
+use actix_web::{get, web};
+use chrono::{DateTime, Utc};
+use utoipa::IntoParams;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ResultsQuery {
+    /// Client id
+    client: Option<String>,
+    /// Search id
+    search: Option<i32>,
+    before: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+    /// Case insensitive substring of any found line
+    text: Option<String>,
+    /// Page size, at most 1000
+    limit: Option<i64>,
+    /// next_cursor of the previous page
+    cursor: Option<String>,
+}
+/**
+ * Search results, newest first, one page at a time.
+ */
+#[utoipa::path(
+    tag = "results",
+    params(ResultsQuery),
+    responses(
+        (status = 200, body = SearchResultPage),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/results")]
+async fn list(
+    id: Option<Identity>,
+    params: web::Query<ResultsQuery>,
+) -> ApiResult<web::Json<SearchResultPage>> {
+    require_user(id)?;
+
+    let cursor = match &params.cursor {
+        Some(token) => Some(
+            sql::ResultsCursor::decode(token)
+                .ok_or_else(|| ApiError::BadRequest("invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    if params.limit.is_some_and(|limit| limit < 1) {
+        return Err(ApiError::BadRequest(
+            "limit must be greater than 0".to_string(),
+        ));
+    }
+
+    let filter = sql::SearchResultFilter {
+        client: params.client.clone(),
+        search: params.search,
+        before: params.before,
+        after: params.after,
+        text: params.text.clone(),
+        cursor,
+        limit: params.limit,
+    };
+
+    Ok(web::Json(sql::get_search_results(&filter).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct MatchesQuery {
+    /// Words to look for, "quoted phrases", -excluded and or
+    q: String,
+    /// Client id
+    client: Option<String>,
+    /// Search id
+    search: Option<i32>,
+    before: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+    /// Page size, at most 1000
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+/**
+ * Full text search within the lines clients found.
+ */
+#[utoipa::path(
+    tag = "results",
+    params(MatchesQuery),
+    responses(
+        (status = 200, body = MatchPage),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/matches")]
+async fn matches(
+    id: Option<Identity>,
+    params: web::Query<MatchesQuery>,
+) -> ApiResult<web::Json<MatchPage>> {
+    require_user(id)?;
+
+    let query = sql::fulltext::MatchQuery {
+        query: params.q.clone(),
+        client: params.client.clone(),
+        search: params.search,
+        before: params.before,
+        after: params.after,
+        limit: params
+            .limit
+            .unwrap_or(sql::DEFAULT_RESULTS_PAGE)
+            .clamp(1, sql::MAX_RESULTS_PAGE),
+        offset: params.offset.unwrap_or(0).max(0),
+    };
+
+    Ok(web::Json(sql::fulltext::search_matches(&query).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct IntegrityQuery {
+    /// Client id
+    client: Option<String>,
+    /// How many, at most 1000
+    limit: Option<i64>,
+}
+/**
+ * File integrity changes clients reported, newest first.
+ */
+#[utoipa::path(
+    tag = "results",
+    params(IntegrityQuery),
+    responses(
+        (status = 200, body = Vec<IntegrityChange>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/integrity")]
+async fn integrity(
+    id: Option<Identity>,
+    params: web::Query<IntegrityQuery>,
+) -> ApiResult<web::Json<Vec<IntegrityChange>>> {
+    require_user(id)?;
+
+    Ok(web::Json(
+        sql::integrity::get_integrity_changes(params.client.as_deref(), params.limit).await?,
+    ))
+}
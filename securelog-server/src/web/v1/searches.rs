@@ -0,0 +1,467 @@
+use super::error::{ApiError, ApiResult, ErrorBody};
+use super::require_user;
+use crate::models::{Search, SearchType, SearchVersion};
+use crate::sql::runs::{SearchHealth, SearchRun};
+use crate::sql::{self, retention::SearchRetention};
+use actix_identity::Identity;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use securelog_search::Matcher;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ListQuery {
+    /// Only enabled or only disabled searches
+    enabled: Option<bool>,
+}
+#[utoipa::path(
+    tag = "searches",
+    params(ListQuery),
+    responses(
+        (status = 200, body = Vec<Search>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/searches")]
+async fn list(
+    id: Option<Identity>,
+    params: web::Query<ListQuery>,
+) -> ApiResult<web::Json<Vec<Search>>> {
+    require_user(id)?;
+
+    let searches = sql::get_all_searches()
+        .await?
+        .into_iter()
+        .filter(|search| {
+            params
+                .enabled
+                .is_none_or(|enabled| search.enabled == enabled)
+        })
+        .collect();
+
+    Ok(web::Json(searches))
+}
+
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    responses(
+        (status = 200, body = Search),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/searches/{id}")]
+async fn get(id: Option<Identity>, path: web::Path<i32>) -> ApiResult<web::Json<Search>> {
+    require_user(id)?;
+
+    Ok(web::Json(find(*path).await?))
+}
+
+async fn find(id: i32) -> ApiResult<Search> {
+    sql::get_search(id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("no search {}", id)))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SearchBody {
+    name: String,
+    stype: SearchType,
+    search: String,
+    // files the search runs on, journal://, command:// or syslog:// locations, i.e. "journal://unit=sshd.service"
+    // command://name runs what the client has under that name in its own config, the
+    // server can't set up commands
+    locations: Vec<String>,
+    // client tags the search is sent to, i.e. "role=web,env=prod", every client if absent
+    selector: Option<String>,
+    // clients follow the locations and send matches within seconds
+    #[serde(default)]
+    realtime: bool,
+}
+impl SearchBody {
+    fn fields(&self) -> sql::SearchFields<'_> {
+        sql::SearchFields {
+            name: &self.name,
+            stype: &self.stype,
+            search: &self.search,
+            locations: &self.locations,
+            selector: self.selector.as_deref(),
+            realtime: self.realtime,
+        }
+    }
+}
+/**
+ * Create a search, it is enabled right away.
+ */
+#[utoipa::path(
+    tag = "searches",
+    request_body = SearchBody,
+    responses(
+        (status = 201, body = Search, headers(("location" = String))),
+        (status = 400, body = ErrorBody, description = "Invalid search, i.e. the regex does not compile"),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[post("/searches")]
+async fn create(id: Option<Identity>, params: web::Json<SearchBody>) -> ApiResult<HttpResponse> {
+    let username = require_user(id)?;
+
+    let search_id = sql::insert_search(&username, &params.fields()).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("location", format!("/api/v1/searches/{}", search_id)))
+        .json(find(search_id).await?))
+}
+
+/**
+ * Check a search would be accepted without saving it.
+ */
+#[utoipa::path(
+    tag = "searches",
+    request_body = SearchBody,
+    responses(
+        (status = 204, description = "The search is valid"),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[post("/searches/validate")]
+async fn validate(id: Option<Identity>, params: web::Json<SearchBody>) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    sql::validate_search(&params.fields())?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// most matching lines a dry run returns
+const MAX_TEST_MATCHES: usize = 1000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TestSearch {
+    stype: SearchType,
+    search: String,
+    // sample log lines
+    text: String,
+}
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TestMatch {
+    // 1 based line number in text
+    line: usize,
+    text: String,
+}
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TestResult {
+    matches: Vec<TestMatch>,
+    // lines in text
+    lines: usize,
+    // more lines matched than were returned
+    truncated: bool,
+}
+/**
+ * Dry run a search over sample text, lines match exactly as they would
+ * on a client.
+ */
+#[utoipa::path(
+    tag = "searches",
+    request_body = TestSearch,
+    responses(
+        (status = 200, body = TestResult),
+        (status = 400, body = ErrorBody, description = "Invalid search, i.e. the regex does not compile"),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[post("/searches/test")]
+async fn test(
+    id: Option<Identity>,
+    params: web::Json<TestSearch>,
+) -> ApiResult<web::Json<TestResult>> {
+    use std::io::BufRead;
+    require_user(id)?;
+
+    if params.search.is_empty() {
+        return Err(ApiError::BadRequest("search must not be empty".to_string()));
+    }
+    let matcher = Matcher::new(&params.stype, &params.search)
+        .map_err(|e| ApiError::BadRequest(format!("invalid regex: {}", e)))?;
+
+    let mut result = TestResult {
+        matches: Vec::new(),
+        lines: 0,
+        truncated: false,
+    };
+    // split like Matcher::matching_lines, reading from a str can't fail
+    for (number, line) in params
+        .text
+        .as_bytes()
+        .lines()
+        .map_while(|line| line.ok())
+        .enumerate()
+    {
+        result.lines += 1;
+        if matcher.is_match(&line) {
+            if result.matches.len() < MAX_TEST_MATCHES {
+                result.matches.push(TestMatch {
+                    line: number + 1,
+                    text: line,
+                });
+            } else {
+                result.truncated = true;
+            }
+        }
+    }
+
+    Ok(web::Json(result))
+}
+
+/**
+ * Replace a search, the previous version stays in its history.
+ */
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    request_body = SearchBody,
+    responses(
+        (status = 200, body = Search),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[put("/searches/{id}")]
+async fn update(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+    params: web::Json<SearchBody>,
+) -> ApiResult<web::Json<Search>> {
+    let username = require_user(id)?;
+
+    sql::update_search(*path, &username, &params.fields()).await?;
+
+    Ok(web::Json(find(*path).await?))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetEnabled {
+    enabled: bool,
+}
+/**
+ * Disabled searches are kept but not sent to clients.
+ */
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    request_body = SetEnabled,
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[put("/searches/{id}/enabled")]
+async fn set_enabled(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+    params: web::Json<SetEnabled>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    sql::set_search_enabled(*path, params.enabled).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Clone {
+    // "<name> (copy)" if not given
+    name: Option<String>,
+}
+/**
+ * Copy a search, the copy starts disabled.
+ */
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    request_body(content = Clone, description = "Optional, the body may be empty"),
+    responses(
+        (status = 201, body = Search, headers(("location" = String))),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[post("/searches/{id}/clone")]
+async fn clone(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+    params: Option<web::Json<Clone>>,
+) -> ApiResult<HttpResponse> {
+    let username = require_user(id)?;
+    let params = params.map(web::Json::into_inner).unwrap_or_default();
+
+    let search_id = sql::clone_search(*path, &username, params.name.as_deref()).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("location", format!("/api/v1/searches/{}", search_id)))
+        .json(find(search_id).await?))
+}
+
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    responses(
+        (status = 200, body = Vec<SearchVersion>, description = "Saved versions, newest first"),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/searches/{id}/history")]
+async fn history(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+) -> ApiResult<web::Json<Vec<SearchVersion>>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::get_search_history(*path).await?))
+}
+
+/**
+ * How the search went over each location on each client in their last
+ * runs, with the errors.
+ */
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    responses(
+        (status = 200, body = Vec<SearchRun>),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[get("/searches/{id}/runs")]
+async fn runs(id: Option<Identity>, path: web::Path<i32>) -> ApiResult<web::Json<Vec<SearchRun>>> {
+    require_user(id)?;
+
+    find(*path).await?;
+
+    Ok(web::Json(sql::runs::get_search_runs(Some(*path)).await?))
+}
+
+/**
+ * Per search summary of the clients' last runs, searches no client ran
+ * are left out.
+ */
+#[utoipa::path(
+    tag = "searches",
+    responses(
+        (status = 200, body = Vec<SearchHealth>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/searches/health")]
+async fn health(id: Option<Identity>) -> ApiResult<web::Json<Vec<SearchHealth>>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::runs::get_search_health().await?))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Revert {
+    version: i32,
+}
+/**
+ * Make an earlier version current again, saved as a new version.
+ */
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    request_body = Revert,
+    responses(
+        (status = 200, body = Search),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody, description = "No such search or version"),
+    )
+)]
+#[post("/searches/{id}/revert")]
+async fn revert(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+    params: web::Json<Revert>,
+) -> ApiResult<web::Json<Search>> {
+    let username = require_user(id)?;
+
+    sql::revert_search(*path, params.version, &username).await?;
+
+    Ok(web::Json(find(*path).await?))
+}
+
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[delete("/searches/{id}")]
+async fn delete(id: Option<Identity>, path: web::Path<i32>) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    sql::delete_search(*path).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/**
+ * Searches with their own retention, the others use the global setting.
+ */
+#[utoipa::path(
+    tag = "searches",
+    responses(
+        (status = 200, body = Vec<SearchRetention>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/searches/retention")]
+async fn retentions(id: Option<Identity>) -> ApiResult<web::Json<Vec<SearchRetention>>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::retention::get_search_retentions().await?))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetRetention {
+    // null means use the global setting
+    days: Option<i32>,
+    rows: Option<i32>,
+}
+#[utoipa::path(
+    tag = "searches",
+    params(("id" = i32, Path, description = "Search id")),
+    request_body = SetRetention,
+    responses(
+        (status = 204),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[put("/searches/{id}/retention")]
+async fn set_retention(
+    id: Option<Identity>,
+    path: web::Path<i32>,
+    params: web::Json<SetRetention>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if params.days.is_some_and(|days| days < 1) || params.rows.is_some_and(|rows| rows < 1) {
+        return Err(ApiError::BadRequest(
+            "days and rows must be greater than 0".to_string(),
+        ));
+    }
+    find(*path).await?;
+    sql::retention::set_search_retention(*path, params.days, params.rows).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
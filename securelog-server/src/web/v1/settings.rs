@@ -0,0 +1,209 @@
+use super::error::{ApiError, ApiResult, ErrorBody};
+use super::require_user;
+use crate::models::ClientSettings;
+use crate::sql::{self, retention::StorageUsage, webhooks::Webhook};
+use actix_identity::Identity;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Schedule {
+    // minutes between scans
+    minutes: i32,
+    // clients only scan when asked to
+    manual: bool,
+}
+#[utoipa::path(
+    tag = "settings",
+    responses(
+        (status = 200, body = Schedule),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/schedule")]
+async fn get_schedule(id: Option<Identity>) -> ApiResult<web::Json<Schedule>> {
+    require_user(id)?;
+
+    let schedule = sql::get_scan_schedule().await?;
+
+    Ok(web::Json(Schedule {
+        minutes: (schedule.get_interval().as_secs() / 60) as i32,
+        manual: schedule.is_manual(),
+    }))
+}
+
+#[utoipa::path(
+    tag = "settings",
+    request_body = Schedule,
+    responses(
+        (status = 204),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[put("/schedule")]
+async fn set_schedule(
+    id: Option<Identity>,
+    params: web::Json<Schedule>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if params.minutes < 1 {
+        return Err(ApiError::BadRequest(
+            "minutes must be greater than 0".to_string(),
+        ));
+    }
+    sql::set_search_schedule_minutes(params.minutes, params.manual).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    tag = "settings",
+    responses(
+        (status = 200, body = Vec<Webhook>),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/webhooks")]
+async fn list_webhooks(id: Option<Identity>) -> ApiResult<web::Json<Vec<Webhook>>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::webhooks::get_webhooks().await?))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhook {
+    name: String,
+    url: String,
+    // name messages are posted as
+    username: String,
+}
+#[utoipa::path(
+    tag = "settings",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, body = Webhook),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 409, body = ErrorBody, description = "A webhook with the name exists"),
+    )
+)]
+#[post("/webhooks")]
+async fn add_webhook(
+    id: Option<Identity>,
+    params: web::Json<NewWebhook>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if params.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("name must not be empty".to_string()));
+    }
+    if !params.url.starts_with("https://") && !params.url.starts_with("http://") {
+        return Err(ApiError::BadRequest(
+            "url must be a http or https url".to_string(),
+        ));
+    }
+    if sql::webhooks::get_webhooks()
+        .await?
+        .iter()
+        .any(|webhook| webhook.name == params.name)
+    {
+        return Err(ApiError::Conflict(format!(
+            "webhook {} already exists",
+            params.name
+        )));
+    }
+    sql::webhooks::add_webhook(&params.name, &params.url, &params.username).await?;
+
+    Ok(HttpResponse::Created().json(Webhook::new(&params.name, &params.url, &params.username)))
+}
+
+#[utoipa::path(
+    tag = "settings",
+    params(("name" = String, Path, description = "Webhook name")),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+#[delete("/webhooks/{name}")]
+async fn delete_webhook(id: Option<Identity>, path: web::Path<String>) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    if sql::webhooks::delete_webhook(&path).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::NotFound(format!("no webhook {}", path)))
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageUsageReport {
+    clients: Vec<StorageUsage>,
+    searches: Vec<StorageUsage>,
+}
+/**
+ * Rows and bytes of stored results by client and by search.
+ */
+#[utoipa::path(
+    tag = "settings",
+    responses(
+        (status = 200, body = StorageUsageReport),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/storage_usage")]
+async fn storage_usage(id: Option<Identity>) -> ApiResult<web::Json<StorageUsageReport>> {
+    require_user(id)?;
+
+    Ok(web::Json(StorageUsageReport {
+        clients: sql::retention::get_client_storage_usage().await?,
+        searches: sql::retention::get_search_storage_usage().await?,
+    }))
+}
+
+/**
+ * Settings every client gets unless its own say otherwise.
+ */
+#[utoipa::path(
+    tag = "settings",
+    responses(
+        (status = 200, body = ClientSettings),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[get("/client_settings")]
+async fn get_client_settings(id: Option<Identity>) -> ApiResult<web::Json<ClientSettings>> {
+    require_user(id)?;
+
+    Ok(web::Json(sql::client::get_default_settings().await?))
+}
+
+/**
+ * Clients pick the change up the next time they poll.
+ */
+#[utoipa::path(
+    tag = "settings",
+    request_body = ClientSettings,
+    responses(
+        (status = 204),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+    )
+)]
+#[put("/client_settings")]
+async fn set_client_settings(
+    id: Option<Identity>,
+    params: web::Json<ClientSettings>,
+) -> ApiResult<HttpResponse> {
+    require_user(id)?;
+
+    params
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    sql::client::set_default_settings(&params).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}